@@ -0,0 +1,200 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Decomposition of the addresses of loads and stores.
+//!
+//! An address is split into `base + offset + index * scale`, where the
+//! offset is a constant and the index is scaled by a multiplication or a
+//! left shift by a constant. The analyses that look into addresses, such as
+//! the recovery of layouts and of jump tables, share these helpers.
+
+use middle::ir::MOpcode;
+use middle::ssa::SSA;
+use middle::ssa::ssa_traits::NodeType;
+
+/// `base + offset + index * scale`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Address<V> {
+    /// The value the constant offset and the index are added to, if any.
+    pub base: Option<V>,
+    pub offset: i64,
+    /// The index and its scale, if any.
+    pub index: Option<(V, u64)>,
+}
+
+/// Decomposes the address `v`. Sums that are not of this form are bases.
+pub fn decompose<T: SSA>(ssa: &T, v: &T::ValueRef) -> Address<T::ValueRef> {
+    let plain = Address {
+        base: Some(*v),
+        offset: 0,
+        index: None,
+    };
+    if let Some(index) = scaled(ssa, v) {
+        return Address {
+            base: None,
+            offset: 0,
+            index: Some(index),
+        };
+    }
+    let opc = match opcode(ssa, v) {
+        Some(opc @ MOpcode::OpAdd) | Some(opc @ MOpcode::OpSub) => opc,
+        _ => return plain,
+    };
+    let (lhs, rhs) = match (operand(ssa, v, 0), operand(ssa, v, 1)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return plain,
+    };
+    if let Some(c) = constant(ssa, &rhs) {
+        let mut a = decompose(ssa, &lhs);
+        if opc == MOpcode::OpAdd {
+            a.offset = a.offset.wrapping_add(c as i64);
+        } else {
+            a.offset = a.offset.wrapping_sub(c as i64);
+        }
+        return a;
+    }
+    if opc == MOpcode::OpSub {
+        return plain;
+    }
+    if let Some(c) = constant(ssa, &lhs) {
+        let mut a = decompose(ssa, &rhs);
+        a.offset = a.offset.wrapping_add(c as i64);
+        return a;
+    }
+    for &(base, index) in &[(lhs, rhs), (rhs, lhs)] {
+        if let Some(scaled) = scaled(ssa, &index) {
+            let mut a = decompose(ssa, &base);
+            if a.index.is_none() {
+                a.index = Some(scaled);
+                return a;
+            }
+        }
+    }
+    plain
+}
+
+/// `v` as `index * scale`, if it is a multiplication or a left shift by a
+/// constant.
+pub fn scaled<T: SSA>(ssa: &T, v: &T::ValueRef) -> Option<(T::ValueRef, u64)> {
+    let (lhs, rhs) = match (operand(ssa, v, 0), operand(ssa, v, 1)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return None,
+    };
+    match opcode(ssa, v) {
+        Some(MOpcode::OpMul) => {
+            match (constant(ssa, &lhs), constant(ssa, &rhs)) {
+                (None, Some(c)) if c > 0 => Some((lhs, c)),
+                (Some(c), None) if c > 0 => Some((rhs, c)),
+                _ => None,
+            }
+        }
+        Some(MOpcode::OpLsl) => {
+            match constant(ssa, &rhs) {
+                Some(c) if c < 64 => Some((lhs, 1 << c)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The value `v` is an extension of, or `v` itself.
+pub fn extended<T: SSA>(ssa: &T, v: &T::ValueRef) -> T::ValueRef {
+    let mut v = *v;
+    while let Some(MOpcode::OpWiden(_)) = opcode(ssa, &v) {
+        match operand(ssa, &v, 0) {
+            Some(o) => v = o,
+            None => break,
+        }
+    }
+    v
+}
+
+pub fn opcode<T: SSA>(ssa: &T, v: &T::ValueRef) -> Option<MOpcode> {
+    match ssa.get_node_data(v).map(|nd| nd.nt) {
+        Ok(NodeType::Op(opc)) => Some(opc),
+        _ => None,
+    }
+}
+
+pub fn constant<T: SSA>(ssa: &T, v: &T::ValueRef) -> Option<u64> {
+    match opcode(ssa, v) {
+        Some(MOpcode::OpConst(c)) => Some(c),
+        _ => None,
+    }
+}
+
+/// The operand of `v` at `index`.
+pub fn operand<T: SSA>(ssa: &T, v: &T::ValueRef, index: u8) -> Option<T::ValueRef> {
+    ssa.get_sparse_operands(v)
+       .iter()
+       .find(|&&(i, _)| i == index)
+       .map(|&(_, o)| o)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use petgraph::graph::NodeIndex;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    fn binop(ssa: &mut SSAStorage,
+             block: NodeIndex,
+             opc: MOpcode,
+             lhs: NodeIndex,
+             rhs: NodeIndex)
+             -> NodeIndex {
+        let n = ssa.add_op(block, opc, From::from(64 as u16), None);
+        ssa.op_use(n, 0, lhs);
+        ssa.op_use(n, 1, rhs);
+        n
+    }
+
+    #[test]
+    fn decomposes() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let p = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let i = ssa.add_comment(entry, From::from(64 as u16), "rsi".to_owned());
+        let three = ssa.add_const(entry, 3);
+        let eight = ssa.add_const(entry, 8);
+        let table = ssa.add_const(entry, 0x601040);
+
+        // p + (i << 3) + 8
+        let shifted = binop(&mut ssa, entry, MOpcode::OpLsl, i, three);
+        let sum = binop(&mut ssa, entry, MOpcode::OpAdd, p, shifted);
+        let field = binop(&mut ssa, entry, MOpcode::OpAdd, sum, eight);
+        assert_eq!(decompose(&ssa, &field),
+                   Address {
+                       base: Some(p),
+                       offset: 8,
+                       index: Some((i, 8)),
+                   });
+
+        // 0x601040 + i * 8
+        let product = binop(&mut ssa, entry, MOpcode::OpMul, i, eight);
+        let entry_addr = binop(&mut ssa, entry, MOpcode::OpAdd, table, product);
+        assert_eq!(decompose(&ssa, &entry_addr),
+                   Address {
+                       base: None,
+                       offset: 0x601040,
+                       index: Some((i, 8)),
+                   });
+
+        // p - 8
+        let below = binop(&mut ssa, entry, MOpcode::OpSub, p, eight);
+        assert_eq!(decompose(&ssa, &below),
+                   Address {
+                       base: Some(p),
+                       offset: -8,
+                       index: None,
+                   });
+    }
+}
@@ -11,11 +11,14 @@
 //! perform the propagation. For more details, please refer:
 //!    * https://www.cs.utexas.edu/~lin/cs380c/wegman.pdf.
 //!
+//! The propagation itself is done by `analysis::propagate`; this module
+//! supplies the constant lattice.
 
 use std::collections::HashMap;
+use analysis::propagate::{Control, DataOperator, Propagator, forwarded_store};
 use middle::ssa::{SSA, SSAMod};
 use middle::ssa::ssa_traits::NodeType;
-use middle::ir::{MArity, MOpcode};
+use middle::ir::{MArity, MOpcode, WidthSpec};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ExprVal {
@@ -58,87 +61,13 @@ fn meet(v1: &ExprVal, v2: &ExprVal) -> ExprVal {
     }
 }
 
-pub struct Analyzer<T: SSAMod + SSA + Clone> {
-    ssa_worklist: Vec<T::ValueRef>,
-    cfg_worklist: Vec<T::CFEdgeRef>,
-    executable: HashMap<T::CFEdgeRef, bool>,
-    expr_val: HashMap<T::ValueRef, ExprVal>,
-    g: T,
-}
-
-impl<T: SSA + SSAMod + Clone> Analyzer<T> {
-    pub fn new(g: &mut T) -> Analyzer<T> {
-        Analyzer {
-            ssa_worklist: Vec::new(),
-            cfg_worklist: Vec::new(),
-            executable: HashMap::new(),
-            expr_val: HashMap::new(),
-            g: g.clone(),
-        }
-    }
-
-    pub fn dump(&self) {
-        println!("{:?}", self.expr_val);
-    }
-
-    fn visit_phi(&mut self, i: &T::ValueRef) -> ExprVal {
-        let operands = self.g.get_operands(i);
-        let mut phi_val = self.get_value(i);
-        let cur_block = self.g.get_block(i);
-        for op in operands.iter() {
-            let b = self.g.get_block(&op);
-            let edge = self.g.find_edge(&b, &cur_block);
-            if !self.is_executable(&edge) {
-                continue;
-            }
-            let val = self.get_value(&op);
-            phi_val = meet(&phi_val, &val);
-        }
-        phi_val
-    }
+/// The constant lattice, as a `DataOperator`.
+struct Constants;
 
-    fn evaluate_control_flow(&mut self, i: &T::ValueRef) {
-        assert!(self.g.is_selector(i));
-
-        let cond_val = self.get_value(i);
-        let block = self.g.selects_for(i);
-        let true_branch = self.g.true_edge_of(&block);
-        let false_branch = self.g.false_edge_of(&block);
-        match cond_val {
-            ExprVal::Bottom => {
-                self.cfg_worklist.push(true_branch);
-                self.cfg_worklist.push(false_branch);
-            }
-            ExprVal::Top => {
-                // TODO: Not really sure what to do here.
-            }
-            ExprVal::Const(cval) => {
-                if cval == 0 {
-                    self.cfg_worklist.push(true_branch);
-                } else {
-                    self.cfg_worklist.push(false_branch);
-                }
-            }
-        }
-    }
-
-    fn evaluate_unary_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> ExprVal {
-        let operand = self.g.get_operands(i);
-        let operand = if operand.len() > 0 {
-            operand[0]
-        } else {
-            // TODO: panic! or return correct error.
-            // panic!("Unary operation has less than one operand!\n");
-            return ExprVal::Top;
-        };
-
-        let val = self.get_value(&operand);
-        let const_val = if let ExprVal::Const(cval) = val {
-            cval
-        } else {
-            return val;
-        };
-        let _val = match opcode {
+impl Constants {
+    fn evaluate_unary_op(&self, opcode: MOpcode, width: WidthSpec, operand: ExprVal) -> ExprVal {
+        let const_val = exprval_match!(operand);
+        let val = match opcode {
             MOpcode::OpWiden(_) => {
                 // Nothing to do in case of widen as the value cannot change.
                 const_val
@@ -146,158 +75,210 @@ impl<T: SSA + SSAMod + Clone> Analyzer<T> {
             MOpcode::OpNarrow(size) => {
                 // Max size is 64. Therefore, we can _never_ narrow to 64.
                 assert!(size < 64);
-                let mask = (2 << (size + 1)) - 1;
+                let mask = (1 << size) - 1;
                 const_val & mask
             }
-            MOpcode::OpNot => {
-                !const_val as u64
-            }
-            _ => unreachable!(),
+            MOpcode::OpNot => !const_val,
+            _ => return ExprVal::Bottom,
         };
 
-        ExprVal::Const(_val)
+        ExprVal::Const(truncate(val, width))
     }
 
-    fn evaluate_binary_op(&mut self, i: &T::ValueRef, opcode: MOpcode) -> ExprVal {
-        let operands = self.g.get_operands(i).iter().map(|x| self.get_value(x)).collect::<Vec<_>>();
-        let lhs_val = exprval_match! (operands[0]);
-        let rhs_val = exprval_match! (operands[1]);
-
-        let _val = match opcode {
-            MOpcode::OpAdd => {
-                lhs_val + rhs_val
+    fn evaluate_binary_op(&self,
+                          opcode: MOpcode,
+                          width: WidthSpec,
+                          lhs: ExprVal,
+                          rhs: ExprVal)
+                          -> ExprVal {
+        let lhs_val = exprval_match!(lhs);
+        let rhs_val = exprval_match!(rhs);
+
+        let val = match opcode {
+            MOpcode::OpAdd => lhs_val.wrapping_add(rhs_val),
+            MOpcode::OpSub => lhs_val.wrapping_sub(rhs_val),
+            MOpcode::OpMul => lhs_val.wrapping_mul(rhs_val),
+            MOpcode::OpDiv if rhs_val != 0 => lhs_val / rhs_val,
+            MOpcode::OpMod if rhs_val != 0 => lhs_val % rhs_val,
+            MOpcode::OpAnd => lhs_val & rhs_val,
+            MOpcode::OpOr => lhs_val | rhs_val,
+            MOpcode::OpXor => lhs_val ^ rhs_val,
+            MOpcode::OpCmp => (lhs_val == rhs_val) as u64,
+            MOpcode::OpGt => (lhs_val > rhs_val) as u64,
+            MOpcode::OpLt => (lhs_val < rhs_val) as u64,
+            MOpcode::OpLteq => (lhs_val <= rhs_val) as u64,
+            MOpcode::OpGteq => (lhs_val >= rhs_val) as u64,
+            MOpcode::OpLsl if rhs_val < 64 => lhs_val << rhs_val,
+            MOpcode::OpLsr if rhs_val < 64 => lhs_val >> rhs_val,
+            MOpcode::OpLsl | MOpcode::OpLsr => 0,
+            // The signed operations and rotations depend on the width.
+            MOpcode::OpAsr => {
+                let shift = if rhs_val < 64 { rhs_val } else { 63 };
+                truncate((sign_extend(lhs_val, width) >> shift) as u64, width)
             }
-            MOpcode::OpSub => {
-                if lhs_val > rhs_val {
-                    lhs_val - rhs_val
-                } else {
-                    rhs_val - lhs_val
-                }
-            }
-            MOpcode::OpMul => {
-                lhs_val * rhs_val
-            }
-            MOpcode::OpDiv => {
-                lhs_val / rhs_val
-            }
-            MOpcode::OpMod => {
-                lhs_val % rhs_val
-            }
-            MOpcode::OpAnd => {
-                lhs_val & rhs_val
-            }
-            MOpcode::OpOr => {
-                lhs_val | rhs_val
-            }
-            MOpcode::OpXor => {
-                lhs_val ^ rhs_val
-            }
-            MOpcode::OpCmp => {
-                (lhs_val == rhs_val) as u64
+            MOpcode::OpSDiv if rhs_val != 0 => {
+                let q = sign_extend(lhs_val, width).wrapping_div(sign_extend(rhs_val, width));
+                truncate(q as u64, width)
             }
-            MOpcode::OpGt => {
-                (lhs_val > rhs_val) as u64
+            MOpcode::OpSMod if rhs_val != 0 => {
+                let r = sign_extend(lhs_val, width).wrapping_rem(sign_extend(rhs_val, width));
+                truncate(r as u64, width)
             }
-            MOpcode::OpLt => {
-                (lhs_val < rhs_val) as u64
-            }
-            MOpcode::OpLteq => {
-                (lhs_val <= rhs_val) as u64
-            }
-            MOpcode::OpGteq => {
-                (lhs_val >= rhs_val) as u64
-            }
-            MOpcode::OpLsl => {
-                lhs_val << rhs_val
-            }
-            MOpcode::OpLsr => {
-                lhs_val >> rhs_val
+            MOpcode::OpRol | MOpcode::OpRor if width > 0 && width <= 64 => {
+                let w = width as u64;
+                let k = if opcode == MOpcode::OpRol {
+                    rhs_val % w
+                } else {
+                    (w - rhs_val % w) % w
+                };
+                let v = truncate(lhs_val, width);
+                if k == 0 {
+                    v
+                } else {
+                    truncate((v << k) | (v >> (w - k)), width)
+                }
             }
-            _ => unreachable!(),
+            _ => return ExprVal::Bottom,
         };
 
-        ExprVal::Const(_val)
+        // The result wraps around at the width of the value.
+        ExprVal::Const(truncate(val, width))
+    }
+}
+
+impl<T: SSA> DataOperator<T> for Constants {
+    type Value = ExprVal;
+
+    fn initial(&self, ssa: &T, i: &T::ValueRef) -> ExprVal {
+        match ssa.get_node_data(i) {
+            Ok(node_data) => {
+                match node_data.nt {
+                    NodeType::Op(MOpcode::OpConst(v)) => ExprVal::Const(v),
+                    NodeType::Undefined => ExprVal::Bottom,
+                    _ => ExprVal::Top,
+                }
+            }
+            Err(_) => ExprVal::Bottom,
+        }
     }
 
-    fn visit_expression(&mut self, i: &T::ValueRef) -> ExprVal {
-        let expr = self.g.get_node_data(i).unwrap(); //"visit_expression() received invalid valueref");
-        let opcode = if let NodeType::Op(_opcode) = expr.nt {
-            _opcode.clone()
-        } else {
-            panic!("Found something other than an expression!");
+    fn transfer(&self, ssa: &T, i: &T::ValueRef, operands: &[(u8, ExprVal)]) -> ExprVal {
+        let (opcode, width) = match ssa.get_node_data(i) {
+            Ok(expr) => {
+                match expr.nt {
+                    NodeType::Op(opcode) => (opcode, expr.vt.width()),
+                    _ => return ExprVal::Bottom,
+                }
+            }
+            Err(_) => return ExprVal::Bottom,
         };
 
         if let MOpcode::OpConst(v) = opcode {
-            return ExprVal::Const(v as u64);
+            return ExprVal::Const(v);
         }
 
-        let val = match opcode.arity() {
-            MArity::Unary => self.evaluate_unary_op(i, opcode),
-            MArity::Binary => self.evaluate_binary_op(i, opcode),
-            _ => unimplemented!(),
+        let operand = |index: u8| {
+            operands.iter()
+                    .find(|&&(j, _)| j == index)
+                    .map(|&(_, val)| val)
+                    .unwrap_or(ExprVal::Top)
         };
-
-        // If expression is a `Selector` it means that it's value can affect the
-        // control flow.
-        // Hence evaluate the control flow to add edges to the cfgwl.
-        // TODO: Handle the case where the selector of a block may belong to a
-        // different block.
-        if self.g.is_selector(i) {
-            self.evaluate_control_flow(i);
+        match opcode {
+            // A store stands for the value it stores, which the loads of the
+            // same address read back.
+            MOpcode::OpStore(..) => return operand(1),
+            MOpcode::OpLoad(..) => {
+                let shift = match forwarded_store(ssa, i) {
+                    Some(shift) => shift,
+                    None => return ExprVal::Bottom,
+                };
+                let stored = exprval_match!(operand(3));
+                return ExprVal::Const(truncate(stored >> shift, width));
+            }
+            _ => {}
+        }
+        match opcode.arity() {
+            MArity::Unary => self.evaluate_unary_op(opcode, width, operand(0)),
+            MArity::Binary => self.evaluate_binary_op(opcode, width, operand(0), operand(1)),
+            _ => ExprVal::Bottom,
         }
+    }
 
-        return val;
+    fn join(&self, a: &ExprVal, b: &ExprVal) -> ExprVal {
+        meet(a, b)
     }
 
-    pub fn analyze(&mut self) {
+    fn branches(&self, sel: &ExprVal) -> (bool, bool) {
+        match *sel {
+            ExprVal::Top => (false, false),
+            ExprVal::Bottom => (true, true),
+            ExprVal::Const(0) => (true, false),
+            ExprVal::Const(_) => (false, true),
+        }
+    }
 
-        {
-            let start_node = self.g.start_node();
-            let edges = self.g.edges_of(&start_node);
-            for next in edges.iter() {
-                self.mark_executable(next);
-                self.cfgwl_push(next);
-            }
+    fn takes_case(&self, sel: &ExprVal, case: u64) -> bool {
+        match *sel {
+            ExprVal::Top => false,
+            ExprVal::Bottom => true,
+            ExprVal::Const(c) => c == case,
         }
+    }
 
-        while self.ssa_worklist.len() > 0 || self.cfg_worklist.len() > 0 {
-            while let Some(edge) = self.cfg_worklist.pop() {
-                let block = self.g.target_of(&edge);
-                self.mark_executable(&edge);
-                let phis = self.g.get_phis(&block);
-                for phi in phis.iter() {
-                    let v = self.visit_phi(phi);
-                    self.set_value(phi, v);
-                }
+    fn takes_default(&self, sel: &ExprVal, cases: &[u64]) -> bool {
+        match *sel {
+            ExprVal::Top => false,
+            ExprVal::Bottom => true,
+            ExprVal::Const(c) => !cases.contains(&c),
+        }
+    }
+}
 
-                let next_edge = self.g.next_edge_of(&block);
-                if next_edge != self.g.invalid_edge() {
-                    self.cfgwl_push(&next_edge);
-                    self.mark_executable(&next_edge);
-                }
+pub struct Analyzer<T: SSAMod + SSA + Clone> {
+    expr_val: HashMap<T::ValueRef, ExprVal>,
+    control: Control<T>,
+    g: T,
+}
 
-                for expr in self.g.exprs_in(&block) {
-                    let val = self.visit_expression(&expr);
-                    self.set_value(&expr, val);
-                    for _use in self.g.get_uses(&expr) {
-                        self.ssawl_push(&_use);
-                    }
-                }
-            }
-            while let Some(e) = self.ssa_worklist.pop() {
-                let t = self.visit_expression(&e);
-                if t != self.get_value(&e) {
-                    self.set_value(&e, t);
-                    for _use in self.g.get_uses(&e).iter() {
-                        self.ssawl_push(_use);
-                    }
-                }
-            }
+impl<T: SSA + SSAMod + Clone> Analyzer<T> {
+    pub fn new(g: &mut T) -> Analyzer<T> {
+        Analyzer {
+            expr_val: HashMap::new(),
+            control: Control::new(),
+            g: g.clone(),
         }
     }
 
+    pub fn dump(&self) {
+        println!("{:?}", self.expr_val);
+    }
+
+    pub fn analyze(&mut self) {
+        let (expr_val, control) = {
+            let mut propagator = Propagator::new(&self.g, Constants);
+            propagator.run();
+            propagator.into_parts()
+        };
+        self.expr_val = expr_val;
+        self.control = control;
+    }
+
     pub fn emit_ssa(&mut self) -> T {
         for (k, v) in self.expr_val.iter() {
+            // Stores and memory phis stand for the stored values, but cannot
+            // be replaced by them.
+            let replaceable = match self.g.get_node_data(k) {
+                Ok(nd) => {
+                    match nd.nt {
+                        NodeType::Op(MOpcode::OpStore(..)) => false,
+                        _ => nd.vt.width() > 0,
+                    }
+                }
+                Err(_) => false,
+            };
+            if !replaceable {
+                continue;
+            }
             if let ExprVal::Const(val) = *v {
                 let block = self.g.block_of(k);
                 let newnode = self.g.add_const(block, val);
@@ -315,7 +296,7 @@ impl<T: SSA + SSAMod + Clone> Analyzer<T> {
                 }
             }
             // TODO: Make this automatic in dce.
-            if !self.is_block_executable(block) {
+            if !self.control.is_block_executable(block) {
                 remove_blocks.push(*block);
             }
         }
@@ -332,72 +313,38 @@ impl<T: SSA + SSAMod + Clone> Analyzer<T> {
     /// / Helper functions.
     /// ////////////////////////////////////////////////////////////////////////
 
-    fn is_executable(&mut self, i: &T::CFEdgeRef) -> bool {
-        *(self.executable.entry(*i).or_insert(false))
-    }
-
-    fn mark_executable(&mut self, i: &T::CFEdgeRef) {
-        let n = self.executable.entry(*i).or_insert(false);
-        *n = true;
+    fn is_executable(&self, i: &T::CFEdgeRef) -> bool {
+        let (source, target) = self.g.info(i);
+        self.control.is_edge_executable(&source, &target)
     }
+}
 
-    // Determines the Initial value
-    fn init_val(&self, i: &T::ValueRef) -> ExprVal {
-        let node_data = self.g.get_node_data(i).unwrap();
-        match node_data.nt {
-            NodeType::Op(MOpcode::OpConst(v)) => ExprVal::Const(v),
-            NodeType::Undefined => ExprVal::Bottom,
-            _ => ExprVal::Top,
-        }
-    }
-
-    fn get_value(&mut self, i: &T::ValueRef) -> ExprVal {
-        if self.expr_val.contains_key(i) {
-            return self.expr_val[i];
-        }
-
-        let v = self.init_val(i);
-        self.expr_val.insert(*i, v);
+// The low `width` bits of `v`. Values of unknown width are left as they are.
+fn truncate(v: u64, width: WidthSpec) -> u64 {
+    if width == 0 || width >= 64 {
         v
+    } else {
+        v & ((1 << width) - 1)
     }
+}
 
-    fn set_value(&mut self, i: &T::ValueRef, v: ExprVal) {
-        let n = self.expr_val.entry(*i).or_insert(ExprVal::Top);
-        *n = v;
-    }
-
-    fn is_block_executable(&mut self, i: &T::ActionRef) -> bool {
-        // start_node is always reachable.
-        if *i == self.g.start_node() {
-            return true;
-        }
-        let incoming = self.g.incoming_edges(i);
-        for edge in incoming.iter() {
-            if self.is_executable(edge) {
-                return true;
-            }
-        }
-        return false;
-    }
-
-    fn ssawl_push(&mut self, i: &T::ValueRef) {
-        if !self.g.is_expr(i) {
-            return;
-        }
-        let owner_block = self.g.get_block(&i);
-        if self.is_block_executable(&owner_block) {
-            self.ssa_worklist.push(*i);
-        }
-    }
-
-    fn cfgwl_push(&mut self, i: &T::CFEdgeRef) {
-        self.cfg_worklist.push(*i);
+// The low `width` bits of `v` as a signed integer.
+fn sign_extend(v: u64, width: WidthSpec) -> i64 {
+    if width == 0 || width >= 64 {
+        return v as i64;
     }
+    let shift = 64 - width as u32;
+    ((v << shift) as i64) >> shift
 }
 
 #[cfg(test)]
 mod test {
-    use super::{ExprVal, meet};
+    use super::{Analyzer, Constants, ExprVal, meet};
+    use middle::arch::Endian;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+    use middle::ssa::ssa_traits::NodeType;
 
     #[test]
     fn test_meet() {
@@ -414,4 +361,117 @@ mod test {
         assert_eq!(meet(&c2, &c1), b);
         assert_eq!(meet(&c1, &c1), c1);
     }
+
+    #[test]
+    fn taken_branch() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let dead = ssa.add_block(BBInfo { addr: 0x10 });
+        let live = ssa.add_block(BBInfo { addr: 0x20 });
+        ssa.add_control_edge(entry, dead, 0);
+        ssa.add_control_edge(entry, live, 1);
+        ssa.add_control_edge(dead, exit, 2);
+        ssa.add_control_edge(live, exit, 2);
+
+        let two = ssa.add_const(entry, 2);
+        let three = ssa.add_const(entry, 3);
+        let sub = ssa.add_op(entry, MOpcode::OpSub, From::from(64 as u16), None);
+        ssa.op_use(sub, 0, two);
+        ssa.op_use(sub, 1, three);
+        let lt = ssa.add_op(entry, MOpcode::OpLt, From::from(1 as u16), None);
+        ssa.op_use(lt, 0, three);
+        ssa.op_use(lt, 1, sub);
+        ssa.mark_selector(lt, entry);
+
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&sub], ExprVal::Const(!0));
+        assert_eq!(analyzer.expr_val[&lt], ExprVal::Const(1));
+        assert!(analyzer.control.is_block_executable(&live));
+        assert!(!analyzer.control.is_block_executable(&dead));
+    }
+
+    #[test]
+    fn signed_ops_and_rotations() {
+        let c = |v| ExprVal::Const(v);
+        let eval = |opc, width, a, b| Constants.evaluate_binary_op(opc, width, c(a), c(b));
+        assert_eq!(eval(MOpcode::OpAsr, 8, 0x80, 4), c(0xf8));
+        assert_eq!(eval(MOpcode::OpAsr, 64, !0, 63), c(!0));
+        assert_eq!(eval(MOpcode::OpSDiv, 32, 0xfffffff9, 2), c(0xfffffffd));
+        assert_eq!(eval(MOpcode::OpSMod, 32, 0xfffffff9, 2), c(0xffffffff));
+        assert_eq!(eval(MOpcode::OpSDiv, 64, 1, 0), ExprVal::Bottom);
+        assert_eq!(eval(MOpcode::OpRol, 8, 0x81, 1), c(0x03));
+        assert_eq!(eval(MOpcode::OpRor, 16, 0x0001, 4), c(0x1000));
+        assert_eq!(eval(MOpcode::OpRol, 64, 1 << 63, 65), c(1));
+    }
+
+    #[test]
+    fn wrap_at_width() {
+        let c = |v| ExprVal::Const(v);
+        let eval = |opc, width, a, b| Constants.evaluate_binary_op(opc, width, c(a), c(b));
+        assert_eq!(eval(MOpcode::OpAdd, 8, 0xff, 1), c(0));
+        assert_eq!(eval(MOpcode::OpSub, 32, 0, 1), c(0xffffffff));
+        assert_eq!(eval(MOpcode::OpMul, 16, 0x100, 0x100), c(0));
+        assert_eq!(eval(MOpcode::OpLsl, 8, 0x81, 1), c(0x02));
+        assert_eq!(eval(MOpcode::OpAdd, 64, !0, 2), c(1));
+        assert_eq!(Constants.evaluate_unary_op(MOpcode::OpNot, 8, c(0x0f)), c(0xf0));
+    }
+
+    #[test]
+    fn forwarded_stores() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        ssa.add_control_edge(entry, exit, 2);
+
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let addr = ssa.add_undefined(entry, From::from(64 as u16));
+        let value = ssa.add_const(entry, 0x11223344);
+        let mut stores = Vec::new();
+        let mut loads = Vec::new();
+        for &endian in &[Endian::Little, Endian::Big] {
+            let store = ssa.add_op(entry,
+                                   MOpcode::OpStore(32, endian),
+                                   From::from(0 as u16),
+                                   None);
+            ssa.op_use(store, 0, addr);
+            ssa.op_use(store, 1, value);
+            ssa.op_use(store, 3, mem);
+            let load = ssa.add_op(entry,
+                                  MOpcode::OpLoad(16, endian),
+                                  From::from(16 as u16),
+                                  None);
+            ssa.op_use(load, 0, addr);
+            ssa.op_use(load, 3, store);
+            stores.push(store);
+            loads.push(load);
+        }
+        // A load in another byte order than the store is unknown.
+        let other = ssa.add_op(entry,
+                               MOpcode::OpLoad(16, Endian::Big),
+                               From::from(16 as u16),
+                               None);
+        ssa.op_use(other, 0, addr);
+        ssa.op_use(other, 3, stores[0]);
+
+        let mut analyzer = Analyzer::new(&mut ssa);
+        analyzer.analyze();
+        assert_eq!(analyzer.expr_val[&loads[0]], ExprVal::Const(0x3344));
+        assert_eq!(analyzer.expr_val[&loads[1]], ExprVal::Const(0x1122));
+        assert_eq!(analyzer.expr_val[&other], ExprVal::Bottom);
+
+        // The stores are kept.
+        let ssa = analyzer.emit_ssa();
+        for store in &stores {
+            match ssa.get_node_data(store).map(|nd| nd.nt) {
+                Ok(NodeType::Op(MOpcode::OpStore(..))) => {}
+                _ => panic!("store replaced"),
+            }
+        }
+    }
 }
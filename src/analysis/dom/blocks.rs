@@ -0,0 +1,117 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Dominance between the blocks of an SSA graph.
+//!
+//! Unlike `DomTree`, which works on any graph, this follows only the control
+//! edges of the SSA, and leaves out the blocks that cannot be reached.
+
+use std::collections::{HashMap, HashSet};
+
+use middle::ssa::SSAMod;
+
+/// The blocks reachable from the start node in reverse postorder.
+pub fn reverse_post_order<T: SSAMod>(ssa: &T) -> Vec<T::ActionRef> {
+    let start = ssa.start_node();
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    let mut stack = vec![(start, ssa.succs_of(start), 0)];
+    visited.insert(start);
+    while let Some((n, succs, i)) = stack.pop() {
+        if i < succs.len() {
+            let s = succs[i];
+            stack.push((n, succs, i + 1));
+            if visited.insert(s) {
+                stack.push((s, ssa.succs_of(s), 0));
+            }
+        } else {
+            order.push(n);
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// Immediate dominators of the blocks in `order`, which is in reverse
+/// postorder, as indices into `order`. The start node is its own immediate
+/// dominator, and blocks without one have `order.len()`.
+pub fn dominators<T: SSAMod>(ssa: &T, order: &[T::ActionRef]) -> Vec<usize> {
+    let index = order.iter()
+                     .enumerate()
+                     .map(|(i, b)| (*b, i))
+                     .collect::<HashMap<_, _>>();
+    immediate_dominators(order.len(), |n| {
+        ssa.preds_of(order[n])
+           .iter()
+           .filter_map(|p| index.get(p).cloned())
+           .collect()
+    })
+}
+
+/// Immediate dominators of the nodes `0..n` of any graph, numbered in reverse
+/// postorder from the start node `0`, given the predecessors of every node.
+/// The start node is its own immediate dominator, and nodes without one have
+/// `n`.
+///
+/// After Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm".
+pub fn immediate_dominators<F>(n: usize, preds: F) -> Vec<usize>
+    where F: Fn(usize) -> Vec<usize>
+{
+    let preds = (0..n).map(preds).collect::<Vec<_>>();
+    let undefined = n;
+    let mut idom = vec![undefined; n];
+    if n == 0 {
+        return idom;
+    }
+    idom[0] = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 1..n {
+            let mut new_idom = undefined;
+            for &p in &preds[i] {
+                if idom[p] == undefined {
+                    continue;
+                }
+                if new_idom == undefined {
+                    new_idom = p;
+                    continue;
+                }
+                let (mut a, mut b) = (p, new_idom);
+                while a != b {
+                    while a > b {
+                        a = idom[a];
+                    }
+                    while b > a {
+                        b = idom[b];
+                    }
+                }
+                new_idom = a;
+            }
+            if new_idom != idom[i] {
+                idom[i] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+/// Whether the node `a` dominates the node `b`, given the immediate
+/// dominators `idom` of all nodes. The start node is its own immediate
+/// dominator, and nodes without one have `idom.len()`.
+pub fn dominates(idom: &[usize], a: usize, mut b: usize) -> bool {
+    loop {
+        if a == b {
+            return true;
+        }
+        if b >= idom.len() || idom[b] == b {
+            return false;
+        }
+        b = idom[b];
+    }
+}
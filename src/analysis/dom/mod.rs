@@ -8,6 +8,7 @@
 /// Module for computing dominance and post-dominance information
 
 mod index;
+pub mod blocks;
 pub mod domtree;
 
 pub use self::domtree::DomTree;
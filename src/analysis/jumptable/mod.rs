@@ -0,0 +1,341 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Resolution of indirect jumps through jump tables.
+//!
+//! A switch is commonly compiled into a check of the bounds of the index
+//! followed by a jump to an address loaded from a table, as in
+//! `jmp [table + index * 8]`. Position independent code stores the offsets
+//! of the targets from the table instead, and jumps to
+//! `table + sext(load(table + index * 4))`. The index is bounded by the
+//! value set analysis, which takes the compare dominating the jump into
+//! account, and the entries are read from the memory of the binary.
+//!
+//! Jumps to a value the function received, as the return address in the
+//! link register or on the stack, are returns. Other jumps that cannot be
+//! resolved are reported in `CFG::unresolved`.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use r2pipe::structs::LRegInfo;
+
+use analysis::address::{constant, decompose, extended, opcode, operand};
+use analysis::stack::{StackFrame, find_register};
+use analysis::valueset::{Analyzer, ValueSet};
+use frontend::esilssa::SSAConstruction;
+use frontend::memory::Memory;
+use middle::arch::{Architecture, Endian};
+use middle::cfg::{CFG, JumpTable, Unresolved};
+use middle::ir::{Address, MInst, MOpcode, WidthSpec};
+use middle::ssa::{SSA, SSAStorage};
+use middle::ssa::ssa_traits::NodeType;
+
+/// Tables with more entries are not read.
+pub const MAX_ENTRIES: u64 = 1024;
+
+/// A jump target loaded from `base + index * scale`.
+pub struct TableAccess<T: SSA> {
+    pub index: T::ValueRef,
+    pub base: u64,
+    /// Distance of the entries in bytes.
+    pub scale: u64,
+    /// Size of an entry in bits.
+    pub entry_size: WidthSpec,
+    pub endian: Endian,
+    /// The address the sign extended entries are added to, if they are
+    /// offsets rather than addresses.
+    pub origin: Option<u64>,
+}
+
+/// Jump tables and unresolved jumps, by the address of the jump.
+#[derive(Clone, Debug, Default)]
+pub struct Resolution {
+    pub tables: BTreeMap<Address, JumpTable>,
+    pub unresolved: BTreeMap<Address, Unresolved>,
+}
+
+/// Builds the CFG of `insts` with the tables of their indirect jumps. The
+/// targets of a table may contain further indirect jumps, so the CFG is
+/// rebuilt until no new table is found. Calls to the functions at the
+/// addresses in `noreturn` end their blocks.
+pub fn build_cfg(insts: &mut Vec<MInst>,
+                 reg_info: &LRegInfo,
+                 memory: &mut Memory,
+                 noreturn: &BTreeSet<Address>)
+                 -> CFG {
+    let sp = Architecture::detect(reg_info).sp;
+    let mut tables = BTreeMap::new();
+    loop {
+        let mut cfg = CFG::new();
+        cfg.jump_tables = tables.clone();
+        cfg.noreturn = noreturn.clone();
+        cfg.build(insts);
+
+        let mut ssa = SSAStorage::new();
+        let jumps = {
+            let mut con = SSAConstruction::new(&mut ssa, reg_info);
+            con.construct(&cfg);
+            con.indirect_jumps.clone()
+        };
+        if jumps.is_empty() {
+            return cfg;
+        }
+        let resolution = resolve(&ssa, &jumps, find_register(&ssa, &sp), memory);
+
+        let mut found = false;
+        for (addr, table) in resolution.tables {
+            if !tables.contains_key(&addr) {
+                tables.insert(addr, table);
+                found = true;
+            }
+        }
+        if !found {
+            cfg.unresolved = resolution.unresolved
+                                       .into_iter()
+                                       .filter(|&(addr, _)| !tables.contains_key(&addr))
+                                       .collect();
+            return cfg;
+        }
+    }
+}
+
+/// Reads the tables of the indirect `jumps` in `ssa`, given by the address
+/// of the jump, its block and its target, as `SSAConstruction` records
+/// them. `sp` is the stack pointer at the entry of the function.
+pub fn resolve<T: SSA>(ssa: &T,
+                       jumps: &BTreeMap<Address, (T::ActionRef, T::ValueRef)>,
+                       sp: Option<T::ValueRef>,
+                       memory: &mut Memory)
+                       -> Resolution {
+    let mut analyzer = Analyzer::new(ssa);
+    analyzer.analyze();
+    let frame = sp.map(|sp| StackFrame::recover(ssa, sp));
+
+    let mut resolution = Resolution::default();
+    for (&addr, &(block, target)) in jumps {
+        if !analyzer.is_executable(&block) || is_return(ssa, frame.as_ref(), &target) {
+            continue;
+        }
+        match read_table(ssa, &analyzer, &block, &target, memory) {
+            Ok(table) => {
+                resolution.tables.insert(addr, table);
+            }
+            Err(reason) => {
+                resolution.unresolved.insert(addr, reason);
+            }
+        }
+    }
+    resolution
+}
+
+/// Matches the target of a jump with a load from a table, or with the sum
+/// of a constant and a load from a table.
+pub fn table_access<T: SSA>(ssa: &T, target: &T::ValueRef) -> Option<TableAccess<T>> {
+    let target = extended(ssa, target);
+    let relative = decompose(ssa, &target);
+    let (load, origin) = match relative.base.map(|b| extended(ssa, &b)) {
+        Some(load) if relative.offset != 0 && relative.index.is_none() &&
+                      is_load(ssa, &load) => (load, Some(relative.offset as u64)),
+        _ => (target, None),
+    };
+    let (entry_size, endian) = match opcode(ssa, &load) {
+        Some(MOpcode::OpLoad(width, endian)) => (width, endian),
+        _ => return None,
+    };
+    let addr = match operand(ssa, &load, 0) {
+        Some(addr) => decompose(ssa, &extended(ssa, &addr)),
+        None => return None,
+    };
+    let (index, scale) = match (addr.base, addr.index) {
+        (None, Some(index)) => index,
+        (Some(index), None) if constant(ssa, &index).is_none() => (index, 1),
+        _ => return None,
+    };
+    if addr.offset == 0 {
+        return None;
+    }
+    Some(TableAccess {
+        index: extended(ssa, &index),
+        base: addr.offset as u64,
+        scale: scale,
+        entry_size: entry_size,
+        endian: endian,
+        origin: origin,
+    })
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+fn read_table<T: SSA>(ssa: &T,
+                      analyzer: &Analyzer<T>,
+                      block: &T::ActionRef,
+                      target: &T::ValueRef,
+                      memory: &mut Memory)
+                      -> Result<JumpTable, Unresolved> {
+    let access = match table_access(ssa, target) {
+        Some(access) => access,
+        None => return Err(Unresolved::NoTable),
+    };
+    let index = analyzer.value_in(&access.index, block);
+    if index.is_empty() || index.count() > MAX_ENTRIES {
+        return Err(Unresolved::Unbounded);
+    }
+
+    let step = if index.multiple.modulus > 1 {
+        index.multiple.modulus
+    } else {
+        1
+    };
+    let mut targets = BTreeMap::new();
+    let mut i = index.urange.min;
+    while i <= index.urange.max {
+        if index.contains(i) {
+            let entry = access.base.wrapping_add(i.wrapping_mul(access.scale));
+            match memory.read_word(entry, access.entry_size, access.endian) {
+                Some(word) => {
+                    let target = match access.origin {
+                        Some(origin) => origin.wrapping_add(sign_extend(word, access.entry_size)),
+                        None => word,
+                    };
+                    targets.insert(i, target);
+                }
+                None => return Err(Unresolved::Unreadable(entry)),
+            }
+        }
+        i = match i.checked_add(step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    Ok(JumpTable {
+        base: access.base,
+        entry_size: access.entry_size,
+        targets: targets,
+    })
+}
+
+// Whether `target` was received by the function, in a register or on the
+// stack.
+fn is_return<T: SSA>(ssa: &T, frame: Option<&StackFrame<T>>, target: &T::ValueRef) -> bool {
+    let target = extended(ssa, target);
+    match ssa.get_node_data(&target).map(|nd| nd.nt) {
+        // The registers at the entry of the function.
+        Ok(NodeType::Undefined) => true,
+        Ok(NodeType::Op(MOpcode::OpLoad(..))) => {
+            match (frame, operand(ssa, &target, 0)) {
+                (Some(frame), Some(addr)) => frame.variable_at(&addr).is_some(),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn is_load<T: SSA>(ssa: &T, v: &T::ValueRef) -> bool {
+    match opcode(ssa, v) {
+        Some(MOpcode::OpLoad(..)) => true,
+        _ => false,
+    }
+}
+
+// `word` of `width` bits, sign extended to 64 bits.
+fn sign_extend(word: u64, width: WidthSpec) -> u64 {
+    if width == 0 || width >= 64 {
+        return word;
+    }
+    let shift = 64 - width as u32;
+    (((word << shift) as i64) >> shift) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+    use frontend::memory::MemoryMap;
+    use petgraph::graph::NodeIndex;
+    use frontend::offline::{self, Recording};
+    use middle::cfg::{EdgeType, Unresolved};
+    use middle::ssa::{BBInfo, SSAMod};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    fn binop(ssa: &mut SSAStorage,
+             block: NodeIndex,
+             opc: MOpcode,
+             lhs: NodeIndex,
+             rhs: NodeIndex)
+             -> NodeIndex {
+        let n = ssa.add_op(block, opc, From::from(64 as u16), None);
+        ssa.op_use(n, 0, lhs);
+        ssa.op_use(n, 1, rhs);
+        n
+    }
+
+    #[test]
+    fn arm_switch() {
+        let recording = Recording::load("./ex-bins/json/arm", "sym.switch").unwrap();
+        let cfg = recording.cfg();
+        let table = &cfg.jump_tables[&0x8104];
+        assert_eq!(table.base, 0x8200);
+        assert_eq!(table.entry_size, 32);
+        let targets = table.targets.iter().map(|(&i, &t)| (i, t)).collect::<Vec<_>>();
+        assert_eq!(targets, vec![(0, 0x8108), (1, 0x8110), (2, 0x8118), (3, 0x8110)]);
+        assert!(cfg.unresolved.is_empty());
+        let cases = cfg.g.raw_edges().iter().filter(|e| {
+            match e.weight.edge_type {
+                EdgeType::Case(_) => true,
+                _ => false,
+            }
+        });
+        assert_eq!(cases.count(), 4);
+    }
+
+    #[test]
+    fn unreadable_table() {
+        let recording = Recording::load("./ex-bins/json/arm", "sym.switch").unwrap();
+        let mut memory = MemoryMap::new();
+        let cfg = offline::build_cfg(&recording.ops(),
+                                     &recording.reg_info,
+                                     &recording.architecture(),
+                                     &mut memory,
+                                     &BTreeSet::new());
+        assert!(cfg.jump_tables.is_empty());
+        assert_eq!(cfg.unresolved.get(&0x8104), Some(&Unresolved::Unreadable(0x8200)));
+    }
+
+    #[test]
+    fn relative_table() {
+        // table + sext(load(table + i * 4))
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let i = ssa.add_comment(entry, From::from(64 as u16), "rax".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let table = ssa.add_const(entry, 0x4006f0);
+        let four = ssa.add_const(entry, 4);
+        let offset = binop(&mut ssa, entry, MOpcode::OpMul, i, four);
+        let addr = binop(&mut ssa, entry, MOpcode::OpAdd, table, offset);
+        let load = ssa.add_op(entry,
+                              MOpcode::OpLoad(32, Endian::Little),
+                              From::from(32 as u16),
+                              None);
+        ssa.op_use(load, 0, addr);
+        ssa.op_use(load, 3, mem);
+        let word = ssa.add_op(entry, MOpcode::OpWiden(64), From::from(64 as u16), None);
+        ssa.op_use(word, 0, load);
+        let target = binop(&mut ssa, entry, MOpcode::OpAdd, word, table);
+
+        let access = table_access(&ssa, &target).unwrap();
+        assert_eq!(access.index, i);
+        assert_eq!(access.base, 0x4006f0);
+        assert_eq!(access.scale, 4);
+        assert_eq!(access.entry_size, 32);
+        assert_eq!(access.origin, Some(0x4006f0));
+        assert_eq!(0x4006f0u64.wrapping_add(sign_extend(0xfffffe10, 32)), 0x400500);
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recovery of the layout of structs and arrays.
+//!
+//! The address of every load and store is split into a base pointer, a
+//! constant offset and optionally an index scaled by a constant stride, as
+//! in `base + i * stride + k`. The accesses through a common base make up
+//! its layout: Unindexed accesses are the fields of a struct, indexed
+//! accesses the elements of an array field, whose elements are in turn
+//! structs with a field for every `k % stride`.
+//!
+//! Bases that are constants or that are accessed at a negative offset, such
+//! as the stack pointer, are not given a layout.
+
+use std::collections::{BTreeMap, HashMap};
+
+use analysis::address::{Address, constant, decompose, operand};
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::SSA;
+use middle::ssa::ssa_traits::NodeType;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldType {
+    /// A value of the given width in bits.
+    Scalar(WidthSpec),
+    /// An array of unknown length.
+    Array {
+        stride: u64,
+        element: Layout,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub offset: u64,
+    pub ty: FieldType,
+}
+
+impl Field {
+    pub fn name(&self) -> String {
+        field_name(self.offset)
+    }
+
+    /// Size in bytes. An array counts as a single element.
+    pub fn size(&self) -> u64 {
+        match self.ty {
+            FieldType::Scalar(width) => width as u64 / 8,
+            FieldType::Array { stride, .. } => stride,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub name: String,
+    /// Fields ordered by offset. Fields may overlap.
+    pub fields: Vec<Field>,
+}
+
+impl Layout {
+    /// Size in bytes, up to the end of the last field.
+    pub fn size(&self) -> u64 {
+        self.fields.iter().map(|f| f.offset + f.size()).max().unwrap_or(0)
+    }
+
+    pub fn field_at(&self, offset: u64) -> Option<&Field> {
+        self.fields.iter().find(|f| f.offset == offset)
+    }
+
+    /// The element type if the layout is just an array, so that the base
+    /// points to its first element.
+    pub fn as_array(&self) -> Option<(u64, &Layout)> {
+        if self.fields.len() != 1 || self.fields[0].offset != 0 {
+            return None;
+        }
+        match self.fields[0].ty {
+            FieldType::Array { stride, ref element } => Some((stride, element)),
+            FieldType::Scalar(_) => None,
+        }
+    }
+
+    /// The width of the value if the layout is just a scalar at offset 0
+    /// filling `size` bytes.
+    pub fn as_scalar(&self, size: u64) -> Option<WidthSpec> {
+        if self.fields.len() != 1 || self.fields[0].offset != 0 {
+            return None;
+        }
+        match self.fields[0].ty {
+            FieldType::Scalar(width) if width as u64 == size * 8 => Some(width),
+            _ => None,
+        }
+    }
+}
+
+/// A load or store through a base with a layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Access<V> {
+    pub base: V,
+    /// Offset of the accessed field from the base.
+    pub offset: u64,
+    /// Index into the array field, if any, and the offset of the accessed
+    /// field in its element.
+    pub index: Option<(V, u64)>,
+}
+
+pub struct Layouts<T: SSA> {
+    layouts: Vec<Layout>,
+    bases: HashMap<T::ValueRef, usize>,
+    accesses: HashMap<T::ValueRef, Access<T::ValueRef>>,
+}
+
+impl<T: SSA> Layouts<T> {
+    /// Recovers the layouts pointed to by the values of `ssa`.
+    pub fn recover(ssa: &T) -> Layouts<T> {
+        // Accesses through each base, in the order of the bases.
+        let mut order = Vec::new();
+        let mut by_base: HashMap<_, Vec<_>> = HashMap::new();
+        for n in ssa.nodes() {
+            let nd = match ssa.get_node_data(&n) {
+                Ok(nd) => nd,
+                Err(_) => continue,
+            };
+            let width = match nd.nt {
+                NodeType::Op(MOpcode::OpLoad(width, _)) |
+                NodeType::Op(MOpcode::OpStore(width, _)) => width,
+                _ => continue,
+            };
+            if width == 0 || width % 8 != 0 {
+                continue;
+            }
+            let address = match operand(ssa, &n, 0) {
+                Some(a) => decompose(ssa, &a),
+                None => continue,
+            };
+            let base = match address.base {
+                Some(base) if constant(ssa, &base).is_none() => base,
+                _ => continue,
+            };
+            if !by_base.contains_key(&base) {
+                order.push(base);
+            }
+            by_base.entry(base).or_insert_with(Vec::new).push((n, address, width));
+        }
+
+        let mut layouts = Layouts {
+            layouts: Vec::new(),
+            bases: HashMap::new(),
+            accesses: HashMap::new(),
+        };
+        for base in order {
+            let accesses = &by_base[&base];
+            if accesses.iter().any(|&(_, ref a, _)| a.offset < 0) ||
+               accesses.iter().all(|&(_, ref a, _)| a.offset == 0 && a.index.is_none()) {
+                continue;
+            }
+            let name = format!("struct_{}", layouts.layouts.len());
+            let (layout, recorded) = build(name, base, accesses);
+            layouts.bases.insert(base, layouts.layouts.len());
+            layouts.layouts.push(layout);
+            for (n, access) in recorded {
+                layouts.accesses.insert(n, access);
+            }
+        }
+        layouts
+    }
+
+    pub fn layouts(&self) -> &[Layout] {
+        &self.layouts
+    }
+
+    /// The layout pointed to by `base`.
+    pub fn layout_of(&self, base: &T::ValueRef) -> Option<&Layout> {
+        self.bases.get(base).map(|&i| &self.layouts[i])
+    }
+
+    /// The field accessed by the load or store `n`.
+    pub fn access(&self, n: &T::ValueRef) -> Option<&Access<T::ValueRef>> {
+        self.accesses.get(n)
+    }
+}
+
+/// Name of the field at `offset`.
+pub fn field_name(offset: u64) -> String {
+    format!("field_{:x}", offset)
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// The layout of a base from its accesses, and the accesses that match a
+// field of the layout.
+fn build<T: SSA>(name: String,
+                 base: T::ValueRef,
+                 accesses: &[(T::ValueRef, Address<T::ValueRef>, WidthSpec)])
+                 -> (Layout, Vec<(T::ValueRef, Access<T::ValueRef>)>) {
+    let mut scalars = BTreeMap::<u64, WidthSpec>::new();
+    // Stride and fields of the element of each array, by the offset of the
+    // array.
+    let mut arrays = BTreeMap::<u64, (u64, BTreeMap<u64, WidthSpec>)>::new();
+    for &(_, ref address, width) in accesses {
+        let offset = address.offset as u64;
+        match address.index {
+            None => {
+                let w = scalars.entry(offset).or_insert(width);
+                *w = ::std::cmp::max(*w, width);
+            }
+            Some((_, stride)) => {
+                let start = offset - offset % stride;
+                let array = arrays.entry(start).or_insert((stride, BTreeMap::new()));
+                if array.0 == stride {
+                    let w = array.1.entry(offset % stride).or_insert(width);
+                    *w = ::std::cmp::max(*w, width);
+                }
+            }
+        }
+    }
+
+    let mut recorded = Vec::new();
+    for &(n, ref address, width) in accesses {
+        let offset = address.offset as u64;
+        let access = match address.index {
+            None if scalars[&offset] == width => {
+                Access {
+                    base: base,
+                    offset: offset,
+                    index: None,
+                }
+            }
+            Some((index, stride)) => {
+                let start = offset - offset % stride;
+                let (s, ref element) = arrays[&start];
+                if s != stride || element[&(offset % stride)] != width {
+                    continue;
+                }
+                Access {
+                    base: base,
+                    offset: start,
+                    index: Some((index, offset % stride)),
+                }
+            }
+            None => continue,
+        };
+        recorded.push((n, access));
+    }
+
+    let mut fields = scalars.into_iter()
+                            .map(|(offset, width)| {
+                                Field {
+                                    offset: offset,
+                                    ty: FieldType::Scalar(width),
+                                }
+                            })
+                            .collect::<Vec<_>>();
+    for (offset, (stride, element)) in arrays {
+        let element = Layout {
+            name: format!("{}_{:x}", name, offset),
+            fields: element.into_iter()
+                           .map(|(o, w)| {
+                               Field {
+                                   offset: o,
+                                   ty: FieldType::Scalar(w),
+                               }
+                           })
+                           .collect(),
+        };
+        fields.push(Field {
+            offset: offset,
+            ty: FieldType::Array {
+                stride: stride,
+                element: element,
+            },
+        });
+    }
+    fields.sort_by_key(|f| f.offset);
+    let layout = Layout {
+        name: name,
+        fields: fields,
+    };
+    (layout, recorded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use petgraph::graph::NodeIndex;
+    use middle::arch::Endian;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    fn binop(ssa: &mut SSAStorage,
+             block: NodeIndex,
+             opc: MOpcode,
+             lhs: NodeIndex,
+             rhs: NodeIndex)
+             -> NodeIndex {
+        let n = ssa.add_op(block, opc, From::from(64 as u16), None);
+        ssa.op_use(n, 0, lhs);
+        ssa.op_use(n, 1, rhs);
+        n
+    }
+
+    fn load(ssa: &mut SSAStorage,
+            block: NodeIndex,
+            width: u16,
+            addr: NodeIndex,
+            mem: NodeIndex)
+            -> NodeIndex {
+        let n = ssa.add_op(block, MOpcode::OpLoad(width, Endian::Little), From::from(width), None);
+        ssa.op_use(n, 0, addr);
+        ssa.op_use(n, 3, mem);
+        n
+    }
+
+    #[test]
+    fn fields_and_arrays() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let p = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let i = ssa.add_comment(entry, From::from(64 as u16), "rsi".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+
+        // p->field_8
+        let eight = ssa.add_const(entry, 8);
+        let a = binop(&mut ssa, entry, MOpcode::OpAdd, p, eight);
+        let x = load(&mut ssa, entry, 32, a, mem);
+        // p->field_0
+        let y = load(&mut ssa, entry, 64, p, mem);
+        // p->field_10[i].field_4, with elements of 8 bytes.
+        let three = ssa.add_const(entry, 3);
+        let scaled = binop(&mut ssa, entry, MOpcode::OpLsl, i, three);
+        let elements = binop(&mut ssa, entry, MOpcode::OpAdd, p, scaled);
+        let c = ssa.add_const(entry, 0x14);
+        let b = binop(&mut ssa, entry, MOpcode::OpAdd, elements, c);
+        let z = load(&mut ssa, entry, 32, b, mem);
+
+        let layouts = Layouts::recover(&ssa);
+        assert_eq!(layouts.layouts().len(), 1);
+        let layout = layouts.layout_of(&p).unwrap();
+        assert_eq!(layout.fields.len(), 3);
+        assert_eq!(layout.field_at(8).unwrap().ty, FieldType::Scalar(32));
+        assert_eq!(layout.size(), 0x18);
+        match layout.field_at(0x10).unwrap().ty {
+            FieldType::Array { stride, ref element } => {
+                assert_eq!(stride, 8);
+                assert_eq!(element.field_at(4).unwrap().ty, FieldType::Scalar(32));
+            }
+            _ => panic!("Expected an array"),
+        }
+
+        assert_eq!(layouts.access(&x).unwrap().offset, 8);
+        assert_eq!(layouts.access(&y).unwrap().index, None);
+        let access = layouts.access(&z).unwrap();
+        assert_eq!(access.offset, 0x10);
+        assert_eq!(access.index, Some((i, 4)));
+    }
+
+    #[test]
+    fn stack_is_not_a_struct() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let rsp = ssa.add_comment(entry, From::from(64 as u16), "rsp".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let eight = ssa.add_const(entry, 8);
+        let a = binop(&mut ssa, entry, MOpcode::OpSub, rsp, eight);
+        load(&mut ssa, entry, 64, a, mem);
+        let b = binop(&mut ssa, entry, MOpcode::OpAdd, rsp, eight);
+        load(&mut ssa, entry, 64, b, mem);
+
+        let layouts = Layouts::recover(&ssa);
+        assert!(layouts.layouts().is_empty());
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Loops of a control flow graph and their nesting.
+//!
+//! Loops are found with the algorithm of Havlak, "Nesting of Reducible and
+//! Irreducible Loops" (TOPLAS 1997), as corrected by Ramalingam,
+//! "Identifying Loops in Almost Linear Time" (TOPLAS 1999). The blocks are
+//! numbered in depth-first preorder from the start node. An edge to an
+//! ancestor in the spanning tree closes a loop whose header is the
+//! ancestor. Visiting the headers innermost first, the body of a loop is
+//! collected backwards from the sources of these edges and collapsed into
+//! its header, so that the loops form a forest.
+//!
+//! The body of a reducible loop is dominated by its header, and is the
+//! natural loop of the edges back to the header. An irreducible loop can
+//! also be entered at other blocks, which are listed in `Loop::entries`. Its
+//! header is the block of the loop the depth-first search reaches first.
+//!
+//! Only the blocks reachable from the start node are in a loop.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use middle::ssa::cfg_traits::CFG;
+
+/// A loop of a `LoopForest`. Loops are referred to by their index in
+/// `LoopForest::loops`.
+#[derive(Clone, Debug)]
+pub struct Loop<B> {
+    pub header: B,
+    /// Blocks of the loop with an edge to the header, in depth-first
+    /// preorder.
+    pub latches: Vec<B>,
+    /// Blocks of the loop, including those of nested loops, in depth-first
+    /// preorder. The header comes first.
+    pub body: Vec<B>,
+    /// Edges leaving the loop, from a block in the loop to one outside.
+    pub exits: Vec<(B, B)>,
+    /// Blocks other than the header that are entered from outside the loop.
+    pub entries: Vec<B>,
+    /// The innermost loop containing this one.
+    pub parent: Option<usize>,
+    /// The loops directly nested in this one.
+    pub children: Vec<usize>,
+    /// Number of loops containing this one.
+    pub depth: usize,
+}
+
+/// The loops of a CFG. Outer loops come before the loops nested in them.
+#[derive(Clone, Debug)]
+pub struct LoopForest<B> {
+    pub loops: Vec<Loop<B>>,
+    // The innermost loop of every block in a loop.
+    innermost: HashMap<B, usize>,
+}
+
+impl<B> Loop<B> {
+    /// Whether the loop is only entered at its header.
+    pub fn is_reducible(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<B: Eq + Hash + Copy> LoopForest<B> {
+    /// Finds the loops of `cfg`.
+    pub fn new<T: CFG<ActionRef = B>>(cfg: &T) -> LoopForest<B> {
+        let (order, last) = preorder(cfg);
+        let n = order.len();
+        let number = order.iter()
+                          .enumerate()
+                          .map(|(i, b)| (*b, i))
+                          .collect::<HashMap<_, _>>();
+        let is_ancestor = |w: usize, v: usize| w <= v && v <= last[w];
+
+        let mut back_preds = vec![Vec::new(); n];
+        let mut other_preds = vec![Vec::new(); n];
+        for (w, b) in order.iter().enumerate() {
+            for p in cfg.preds_of(*b) {
+                let v = match number.get(&p) {
+                    Some(&v) => v,
+                    None => continue,
+                };
+                if is_ancestor(w, v) {
+                    back_preds[w].push(v);
+                } else {
+                    other_preds[w].push(v);
+                }
+            }
+        }
+
+        // Blocks are collapsed into the header of the outermost loop found
+        // so far that contains them.
+        let mut rep = (0..n).collect::<Vec<_>>();
+        let mut header = vec![None; n];
+        let mut is_header = vec![false; n];
+        for w in (0..n).rev() {
+            let mut body = Vec::new();
+            let mut in_body = HashSet::new();
+            for &v in &back_preds[w] {
+                if v == w {
+                    is_header[w] = true;
+                    continue;
+                }
+                let v = find(&mut rep, v);
+                if in_body.insert(v) {
+                    body.push(v);
+                }
+            }
+            let mut worklist = body.clone();
+            while let Some(x) = worklist.pop() {
+                for y in other_preds[x].clone() {
+                    let y = find(&mut rep, y);
+                    if !is_ancestor(w, y) {
+                        // `y` enters the loop below its header, so the
+                        // loops enclosing it are entered at `w`.
+                        other_preds[w].push(y);
+                    } else if y != w && in_body.insert(y) {
+                        body.push(y);
+                        worklist.push(y);
+                    }
+                }
+            }
+            if !body.is_empty() {
+                is_header[w] = true;
+            }
+            for x in body {
+                header[x] = Some(w);
+                rep[x] = w;
+            }
+        }
+
+        // A header is visited before the headers of the loops nested in it.
+        let mut index = vec![None; n];
+        let mut loops: Vec<Loop<B>> = Vec::new();
+        for w in 0..n {
+            if !is_header[w] {
+                continue;
+            }
+            let parent = header[w].and_then(|h| index[h]);
+            // A latch may have several edges to the header.
+            let mut latches = back_preds[w].clone();
+            latches.sort();
+            latches.dedup();
+            let latches = latches.into_iter().map(|v| order[v]).collect::<Vec<_>>();
+            let depth = parent.map_or(0, |p| loops[p].depth + 1);
+            let l = loops.len();
+            index[w] = Some(l);
+            loops.push(Loop {
+                header: order[w],
+                latches: latches,
+                body: Vec::new(),
+                exits: Vec::new(),
+                entries: Vec::new(),
+                parent: parent,
+                children: Vec::new(),
+                depth: depth,
+            });
+            if let Some(p) = parent {
+                loops[p].children.push(l);
+            }
+        }
+
+        let mut innermost = HashMap::new();
+        for v in 0..n {
+            let l = if is_header[v] {
+                index[v]
+            } else {
+                header[v].and_then(|h| index[h])
+            };
+            if let Some(l) = l {
+                innermost.insert(order[v], l);
+            }
+            let mut l = l;
+            while let Some(i) = l {
+                loops[i].body.push(order[v]);
+                l = loops[i].parent;
+            }
+        }
+
+        for l in &mut loops {
+            let members = l.body.iter().cloned().collect::<HashSet<_>>();
+            for &b in &l.body {
+                for s in cfg.succs_of(b) {
+                    if !members.contains(&s) {
+                        l.exits.push((b, s));
+                    }
+                }
+                let entered = b != l.header &&
+                              cfg.preds_of(b)
+                                 .iter()
+                                 .any(|p| number.contains_key(p) && !members.contains(p));
+                if entered {
+                    l.entries.push(b);
+                }
+            }
+        }
+
+        LoopForest {
+            loops: loops,
+            innermost: innermost,
+        }
+    }
+
+    /// The innermost loop `block` is in.
+    pub fn loop_of(&self, block: &B) -> Option<usize> {
+        self.innermost.get(block).cloned()
+    }
+
+    /// The loop `block` is the header of.
+    pub fn loop_headed_by(&self, block: &B) -> Option<usize> {
+        self.loop_of(block).and_then(|l| {
+            if self.loops[l].header == *block {
+                Some(l)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether `block` is in the loop `l`, or in a loop nested in it.
+    pub fn contains(&self, l: usize, block: &B) -> bool {
+        let mut inner = self.loop_of(block);
+        while let Some(i) = inner {
+            if i == l {
+                return true;
+            }
+            inner = self.loops[i].parent;
+        }
+        false
+    }
+
+    /// Number of loops `block` is in.
+    pub fn depth_of(&self, block: &B) -> usize {
+        self.loop_of(block).map_or(0, |l| self.loops[l].depth + 1)
+    }
+
+    /// The loops that are not nested in another.
+    pub fn roots(&self) -> Vec<usize> {
+        (0..self.loops.len()).filter(|&l| self.loops[l].parent.is_none()).collect()
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// The blocks reachable from the start node in depth-first preorder and, for
+// every block, the number of its last descendant in the spanning tree.
+fn preorder<T: CFG>(cfg: &T) -> (Vec<T::ActionRef>, Vec<usize>) {
+    let start = cfg.start_node();
+    let mut visited = HashSet::new();
+    let mut order = vec![start];
+    let mut last = vec![0];
+    let mut stack = vec![(0, cfg.succs_of(start), 0)];
+    visited.insert(start);
+    while let Some((n, succs, i)) = stack.pop() {
+        if i < succs.len() {
+            let s = succs[i];
+            stack.push((n, succs, i + 1));
+            if visited.insert(s) {
+                order.push(s);
+                last.push(0);
+                stack.push((order.len() - 1, cfg.succs_of(s), 0));
+            }
+        } else {
+            last[n] = order.len() - 1;
+        }
+    }
+    (order, last)
+}
+
+// The header `x` is collapsed into, with path compression.
+fn find(rep: &mut [usize], x: usize) -> usize {
+    let mut root = x;
+    while rep[root] != root {
+        root = rep[root];
+    }
+    let mut x = x;
+    while rep[x] != root {
+        let next = rep[x];
+        rep[x] = root;
+        x = next;
+    }
+    root
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use analysis::dom::DomTree;
+    use middle::ssa::{BBInfo, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+    use petgraph::graph::{Graph, NodeIndex};
+
+    // Blocks 0 to n - 1, starting at 0, followed by the exit.
+    fn graph(n: usize, edges: &[(usize, usize)]) -> (SSAStorage, Vec<NodeIndex>) {
+        let mut ssa = SSAStorage::new();
+        let mut blocks = (0..n)
+                             .map(|i| ssa.add_block(BBInfo { addr: i as u64 * 0x10 }))
+                             .collect::<Vec<_>>();
+        ssa.mark_start_node(&blocks[0]);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        blocks.push(exit);
+        for &(a, b) in edges {
+            ssa.add_control_edge(blocks[a], blocks[b], 2);
+        }
+        (ssa, blocks)
+    }
+
+    // The dominator tree of the blocks of `graph(n - 1, edges)`, whose
+    // nodes are the indices of the blocks.
+    fn dom_tree(n: usize, edges: &[(usize, usize)]) -> DomTree {
+        let mut g = Graph::<(), ()>::new();
+        let nodes = (0..n).map(|_| g.add_node(())).collect::<Vec<_>>();
+        for &(a, b) in edges {
+            g.add_edge(nodes[a], nodes[b], ());
+        }
+        DomTree::build_dom_tree(&g, nodes[0])
+    }
+
+    #[test]
+    fn nested() {
+        // 1 is the header of the outer loop with the latch 4, 2 the header
+        // of the inner loop with the latch 3.
+        let edges = [(0, 1), (1, 2), (1, 5), (2, 3), (3, 2), (3, 4), (4, 1)];
+        let (ssa, b) = graph(5, &edges);
+        let forest = LoopForest::new(&ssa);
+        assert_eq!(forest.loops.len(), 2);
+        assert_eq!(forest.roots(), vec![0]);
+        let (outer, inner) = (&forest.loops[0], &forest.loops[1]);
+        assert_eq!(outer.header, b[1]);
+        assert_eq!(outer.latches, vec![b[4]]);
+        assert_eq!(outer.exits, vec![(b[1], b[5])]);
+        assert_eq!(outer.children, vec![1]);
+        assert_eq!(inner.header, b[2]);
+        assert_eq!(inner.latches, vec![b[3]]);
+        assert_eq!(inner.exits, vec![(b[3], b[4])]);
+        assert_eq!(inner.parent, Some(0));
+        assert_eq!(inner.depth, 1);
+
+        let mut body = outer.body.clone();
+        body.sort();
+        assert_eq!(body, vec![b[1], b[2], b[3], b[4]]);
+        assert_eq!(forest.loop_of(&b[0]), None);
+        assert_eq!(forest.loop_of(&b[3]), Some(1));
+        assert_eq!(forest.loop_of(&b[4]), Some(0));
+        assert_eq!(forest.loop_headed_by(&b[2]), Some(1));
+        assert_eq!(forest.loop_headed_by(&b[3]), None);
+        assert!(forest.contains(0, &b[3]));
+        assert!(!forest.contains(1, &b[4]));
+        assert_eq!(forest.depth_of(&b[3]), 2);
+
+        // Both are natural loops.
+        let dom = dom_tree(6, &edges);
+        let pos = |x: &NodeIndex| NodeIndex::new(b.iter().position(|y| y == x).unwrap());
+        for l in &forest.loops {
+            assert!(l.is_reducible());
+            assert!(l.body.iter().all(|x| dom.doms(pos(x)).contains(&pos(&l.header))));
+        }
+    }
+
+    #[test]
+    fn irreducible() {
+        // 1 and 2 jump to each other and are both entered from 0.
+        let edges = [(0, 1), (0, 2), (1, 2), (2, 1), (1, 3)];
+        let (ssa, b) = graph(3, &edges);
+        let forest = LoopForest::new(&ssa);
+        assert_eq!(forest.loops.len(), 1);
+        let l = &forest.loops[0];
+        assert!(!l.is_reducible());
+        assert_eq!(l.body.len(), 2);
+        assert_eq!(l.entries.len(), 1);
+        assert!(l.entries[0] != l.header);
+        assert_eq!(forest.loop_of(&b[1]), Some(0));
+        assert_eq!(forest.loop_of(&b[2]), Some(0));
+        assert_eq!(forest.loop_of(&b[0]), None);
+
+        // Neither block dominates the other.
+        let dom = dom_tree(4, &edges);
+        let h = b.iter().position(|x| *x == l.header).unwrap();
+        let e = b.iter().position(|x| *x == l.entries[0]).unwrap();
+        assert!(!dom.doms(NodeIndex::new(e)).contains(&NodeIndex::new(h)));
+    }
+
+    #[test]
+    fn repeated_latch() {
+        // 2 has two edges back to 1, with 3 latching in between.
+        let (mut ssa, b) = graph(4, &[(0, 1), (1, 2), (2, 1), (2, 3), (3, 1), (3, 4)]);
+        ssa.add_control_edge(b[2], b[1], 0);
+        let forest = LoopForest::new(&ssa);
+        assert_eq!(forest.loops.len(), 1);
+        assert_eq!(forest.loops[0].header, b[1]);
+        assert_eq!(forest.loops[0].latches, vec![b[2], b[3]]);
+    }
+
+    #[test]
+    fn self_loop() {
+        let (ssa, b) = graph(2, &[(0, 1), (1, 1), (1, 2)]);
+        let forest = LoopForest::new(&ssa);
+        assert_eq!(forest.loops.len(), 1);
+        assert_eq!(forest.loops[0].body, vec![b[1]]);
+        assert_eq!(forest.loops[0].latches, vec![b[1]]);
+        assert_eq!(forest.loops[0].exits, vec![(b[1], b[2])]);
+    }
+}
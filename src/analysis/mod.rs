@@ -11,6 +11,13 @@
 
 #[allow(dead_code)]
 pub mod valueset;
-// pub mod propagate;
+pub mod propagate;
+pub mod address;
+pub mod layout;
+pub mod stack;
+pub mod types;
 pub mod dom;
+pub mod jumptable;
+pub mod loops;
+pub mod noreturn;
 pub mod constant_propagation;
@@ -0,0 +1,139 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Detection of functions that do not return.
+//!
+//! The instructions after a call to such a function are not executed by
+//! the path through the call, so the call ends its block, which leads to the
+//! exit (see `CFG::noreturn`). Otherwise the registers the function leaves
+//! behind would flow into code that is only reachable along other paths.
+//!
+//! Library functions are known by name. Other functions are found bottom-up
+//! over the call graph, as those whose exit cannot be reached without
+//! calling a function that does not return (see `CFG::returns` and
+//! `utils::program`).
+
+use std::collections::BTreeSet;
+
+use frontend::offline::FunctionEntry;
+
+/// Library functions that do not return.
+pub const NORETURN: &'static [&'static str] = &["_exit",
+                                                "_Exit",
+                                                "__assert_fail",
+                                                "__assert_rtn",
+                                                "__chk_fail",
+                                                "__cxa_bad_cast",
+                                                "__cxa_bad_typeid",
+                                                "__cxa_call_unexpected",
+                                                "__cxa_pure_virtual",
+                                                "__cxa_rethrow",
+                                                "__cxa_throw",
+                                                "__fortify_fail",
+                                                "__libc_fatal",
+                                                "__longjmp_chk",
+                                                "__stack_chk_fail",
+                                                "_Unwind_Resume",
+                                                "abort",
+                                                "err",
+                                                "errx",
+                                                "exit",
+                                                "ExitProcess",
+                                                "ExitThread",
+                                                "longjmp",
+                                                "pthread_exit",
+                                                "quick_exit",
+                                                "siglongjmp",
+                                                "verr",
+                                                "verrx"];
+
+/// Prefixes r2 gives the names of imports and symbols.
+const PREFIXES: &'static [&'static str] = &["sym.imp.", "sym.", "imp.", "reloc."];
+
+/// Whether `name`, as listed by r2, is a library function that does not
+/// return.
+pub fn is_noreturn(name: &str) -> bool {
+    let name = PREFIXES.iter()
+                       .find(|p| name.starts_with(*p))
+                       .map_or(name, |p| &name[p.len()..]);
+    NORETURN.contains(&name)
+}
+
+/// Addresses of the library functions in `functions` that do not return.
+pub fn known(functions: &[FunctionEntry]) -> BTreeSet<u64> {
+    functions.iter().filter(|f| is_noreturn(&f.name)).map(|f| f.offset).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+    use frontend::offline::FunctionEntry;
+    use middle::cfg::{CFG, NodeData};
+    use middle::ir::{MAddr, MInst, MOpcode, MVal, MValType};
+
+    fn literal(value: u64) -> MVal {
+        let mut val = MVal::new(format!("0x{:x}", value), 64, MValType::Internal, 0, None);
+        val.as_literal = Some(value);
+        val
+    }
+
+    fn inst(opcode: MOpcode, addr: u64, operand: MVal) -> MInst {
+        MInst::new(opcode, MVal::null(), operand, MVal::null(), Some(MAddr::new(addr)))
+    }
+
+    // 0x10: call 0x100
+    // 0x14: call 0x200
+    // 0x18: nop
+    fn build(noreturn: &[u64]) -> CFG {
+        let mut insts = vec![inst(MOpcode::OpCall, 0x10, literal(0x100)),
+                             inst(MOpcode::OpCall, 0x14, literal(0x200)),
+                             inst(MOpcode::OpNop, 0x18, MVal::null())];
+        let mut cfg = CFG::new();
+        cfg.noreturn = noreturn.iter().cloned().collect::<BTreeSet<_>>();
+        cfg.build(&mut insts);
+        cfg
+    }
+
+    #[test]
+    fn names() {
+        assert!(is_noreturn("exit"));
+        assert!(is_noreturn("sym.imp.__stack_chk_fail"));
+        assert!(is_noreturn("reloc.abort"));
+        assert!(!is_noreturn("sym.imp.printf"));
+        assert!(!is_noreturn("sym.exit_handler"));
+
+        let functions = vec![FunctionEntry {
+                                 name: "sym.main".to_owned(),
+                                 offset: 0x400,
+                                 size: 0x20,
+                             },
+                             FunctionEntry {
+                                 name: "sym.imp.abort".to_owned(),
+                                 offset: 0x300,
+                                 size: 0x6,
+                             }];
+        assert_eq!(known(&functions).into_iter().collect::<Vec<_>>(), vec![0x300]);
+    }
+
+    #[test]
+    fn call_ends_block() {
+        let cfg = build(&[0x200]);
+        let block = cfg.bbs[&0x10];
+        let after = cfg.bbs[&0x18];
+        assert!(block != after);
+        let succs = cfg.g.neighbors(block).collect::<Vec<_>>();
+        assert_eq!(succs, vec![cfg.exit]);
+        match cfg.g[after] {
+            NodeData::Block(ref bb) => assert!(!bb.reachable),
+            _ => panic!("0x18 does not start a block"),
+        }
+        assert!(!cfg.returns());
+        assert!(build(&[]).returns());
+        assert!(build(&[0x100]).bbs.contains_key(&0x14));
+    }
+}
@@ -5,3 +5,55 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
+//! Tracks the blocks and control flow edges found executable.
+
+use std::collections::HashSet;
+
+use middle::ssa::cfg_traits::CFG;
+
+pub struct Control<T: CFG> {
+    blocks: HashSet<T::ActionRef>,
+    edges: HashSet<(T::ActionRef, T::ActionRef)>,
+    worklist: Vec<(T::ActionRef, T::ActionRef)>,
+}
+
+impl<T: CFG> Control<T> {
+    pub fn new() -> Control<T> {
+        Control {
+            blocks: HashSet::new(),
+            edges: HashSet::new(),
+            worklist: Vec::new(),
+        }
+    }
+
+    pub fn is_block_executable(&self, block: &T::ActionRef) -> bool {
+        self.blocks.contains(block)
+    }
+
+    pub fn is_edge_executable(&self, source: &T::ActionRef, target: &T::ActionRef) -> bool {
+        self.edges.contains(&(*source, *target))
+    }
+
+    /// Marks `block` executable. Returns true if it was not before.
+    pub fn mark_block(&mut self, block: T::ActionRef) -> bool {
+        self.blocks.insert(block)
+    }
+
+    /// Queues the edge from `source` to `target` to be marked executable.
+    pub fn push(&mut self, source: T::ActionRef, target: T::ActionRef) {
+        if !self.edges.contains(&(source, target)) {
+            self.worklist.push((source, target));
+        }
+    }
+
+    /// Returns the next queued edge that is not executable yet and marks it
+    /// executable.
+    pub fn pop(&mut self) -> Option<(T::ActionRef, T::ActionRef)> {
+        while let Some(edge) = self.worklist.pop() {
+            if self.edges.insert(edge) {
+                return Some(edge);
+            }
+        }
+        None
+    }
+}
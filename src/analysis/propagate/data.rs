@@ -5,6 +5,83 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-pub trait DataOperator<T> {
-	fn join(inputs: &[T]) -> T;
+//! The lattice and transfer functions an analysis supplies to `Propagator`.
+
+use std::fmt::Debug;
+
+use middle::ir::{self, WidthSpec};
+use middle::ssa::SSA;
+use middle::ssa::ssa_traits::NodeType;
+
+/// Describes a sparse dataflow analysis over values of type `Self::Value`.
+///
+/// Values only ever move up the lattice: The engine joins every newly
+/// computed value with the previous one.
+pub trait DataOperator<T: SSA> {
+    type Value: Clone + Debug + PartialEq;
+
+    /// Value of `v` before it is evaluated. Nodes other than expressions and
+    /// phis are never evaluated and keep this value.
+    ///
+    /// For a phi, this must be the identity of `join`.
+    fn initial(&self, ssa: &T, v: &T::ValueRef) -> Self::Value;
+
+    /// Computes the expression `v` from the values of its operands, given as
+    /// in `SSA::get_sparse_operands`.
+    fn transfer(&self, ssa: &T, v: &T::ValueRef, operands: &[(u8, Self::Value)]) -> Self::Value;
+
+    /// Least upper bound of two values.
+    fn join(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+
+    /// Used instead of `join` once a value has changed a few times, so that
+    /// lattices of great height converge.
+    fn widen(&self, old: &Self::Value, new: &Self::Value) -> Self::Value {
+        self.join(old, new)
+    }
+
+    /// Whether the false and true edge of a block are executable when its
+    /// selector has value `sel`.
+    fn branches(&self, sel: &Self::Value) -> (bool, bool);
+
+    /// Whether the edge of a switch for `case` is executable when its
+    /// selector has value `sel`.
+    fn takes_case(&self, _sel: &Self::Value, _case: u64) -> bool {
+        true
+    }
+
+    /// Whether the default edge of a switch over `cases` is executable when
+    /// its selector has value `sel`.
+    fn takes_default(&self, _sel: &Self::Value, _cases: &[u64]) -> bool {
+        true
+    }
+}
+
+/// If the load `v` reads back bytes written by the store that is its memory
+/// operand, the number of bits the stored value is shifted right by to give
+/// the loaded value. Transfer functions use it to forward stored values, for
+/// which stores stand in the lattice.
+pub fn forwarded_store<T: SSA>(ssa: &T, v: &T::ValueRef) -> Option<WidthSpec> {
+    let operands = ssa.get_sparse_operands(v);
+    let operand = |of: &[(u8, T::ValueRef)], index: u8| {
+        of.iter().find(|&&(j, _)| j == index).map(|&(_, o)| o)
+    };
+    let opcode = |n: &T::ValueRef| {
+        match ssa.get_node_data(n).map(|nd| nd.nt) {
+            Ok(NodeType::Op(opc)) => Some(opc),
+            _ => None,
+        }
+    };
+    let store = match operand(&operands[..], 3) {
+        Some(store) => store,
+        None => return None,
+    };
+    let store_operands = ssa.get_sparse_operands(&store);
+    let address = operand(&operands[..], 0);
+    if address.is_none() || operand(&store_operands[..], 0) != address {
+        return None;
+    }
+    match (opcode(&store), opcode(v)) {
+        (Some(s), Some(l)) => ir::forwarded_shift(s, l),
+        _ => None,
+    }
 }
@@ -5,4 +5,186 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
+//! Sparse dataflow propagation over the SSA IR.
+//!
+//! `Propagator` runs the worklist algorithm of sparse conditional constant
+//! propagation (Wegman and Zadeck) for any lattice described by a
+//! `DataOperator`. Control flow edges become executable as the selectors of
+//! their blocks allow, and only expressions in executable blocks are
+//! evaluated. Phis join the operands defined in executable blocks.
+
 pub mod data;
+pub mod control;
+
+pub use self::data::{DataOperator, forwarded_store};
+pub use self::control::Control;
+
+use std::collections::HashMap;
+
+use middle::ssa::SSA;
+
+/// Number of changes of a value after which it is widened.
+const WIDEN_AFTER: usize = 3;
+
+pub struct Propagator<'a, T: SSA + 'a, O: DataOperator<T>> {
+    ssa: &'a T,
+    operator: O,
+    values: HashMap<T::ValueRef, O::Value>,
+    changes: HashMap<T::ValueRef, usize>,
+    control: Control<T>,
+    ssa_worklist: Vec<T::ValueRef>,
+}
+
+impl<'a, T: SSA + 'a, O: DataOperator<T>> Propagator<'a, T, O> {
+    pub fn new(ssa: &'a T, operator: O) -> Propagator<'a, T, O> {
+        Propagator {
+            ssa: ssa,
+            operator: operator,
+            values: HashMap::new(),
+            changes: HashMap::new(),
+            control: Control::new(),
+            ssa_worklist: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        let start = self.ssa.start_node();
+        self.control.mark_block(start);
+        self.visit_block(start);
+        loop {
+            if let Some((_, target)) = self.control.pop() {
+                for phi in self.ssa.get_phis(&target) {
+                    self.update(phi);
+                }
+                if self.control.mark_block(target) {
+                    self.visit_block(target);
+                }
+            } else if let Some(v) = self.ssa_worklist.pop() {
+                self.update(v);
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn value_of(&self, v: &T::ValueRef) -> O::Value {
+        match self.values.get(v) {
+            Some(value) => value.clone(),
+            None => self.operator.initial(self.ssa, v),
+        }
+    }
+
+    /// Values of all evaluated nodes.
+    pub fn values(&self) -> &HashMap<T::ValueRef, O::Value> {
+        &self.values
+    }
+
+    pub fn control(&self) -> &Control<T> {
+        &self.control
+    }
+
+    pub fn operator(&self) -> &O {
+        &self.operator
+    }
+
+    /// Consumes the propagator, returning the values of all evaluated nodes
+    /// and the executable control flow.
+    pub fn into_parts(self) -> (HashMap<T::ValueRef, O::Value>, Control<T>) {
+        (self.values, self.control)
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Helper functions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn evaluate(&self, v: &T::ValueRef) -> O::Value {
+        if self.ssa.is_expr(v) {
+            let operands = self.ssa
+                               .get_sparse_operands(v)
+                               .into_iter()
+                               .map(|(i, op)| (i, self.value_of(&op)))
+                               .collect::<Vec<_>>();
+            return self.operator.transfer(self.ssa, v, &operands);
+        }
+        // A phi.
+        let mut value = self.operator.initial(self.ssa, v);
+        for op in self.ssa.get_operands(v) {
+            if self.control.is_block_executable(&self.ssa.get_block(&op)) {
+                value = self.operator.join(&value, &self.value_of(&op));
+            }
+        }
+        value
+    }
+
+    fn update(&mut self, v: T::ValueRef) {
+        let old = self.value_of(&v);
+        let new = self.evaluate(&v);
+        let changes = self.changes.get(&v).cloned().unwrap_or(0);
+        let new = if changes >= WIDEN_AFTER {
+            self.operator.widen(&old, &new)
+        } else {
+            self.operator.join(&old, &new)
+        };
+        if new == old && self.values.contains_key(&v) {
+            return;
+        }
+        self.values.insert(v, new);
+        self.changes.insert(v, changes + 1);
+        for user in self.ssa.get_uses(&v) {
+            if self.control.is_block_executable(&self.ssa.get_block(&user)) {
+                self.ssa_worklist.push(user);
+            }
+        }
+        if self.ssa.is_selector(&v) {
+            let block = self.ssa.selects_for(&v);
+            if self.control.is_block_executable(&block) {
+                self.visit_control(block);
+            }
+        }
+    }
+
+    fn visit_block(&mut self, block: T::ActionRef) {
+        for phi in self.ssa.get_phis(&block) {
+            self.update(phi);
+        }
+        for expr in self.ssa.exprs_in(&block) {
+            self.update(expr);
+        }
+        self.visit_control(block);
+    }
+
+    fn visit_control(&mut self, block: T::ActionRef) {
+        let sel = match self.ssa.selector_of(&block) {
+            Some(sel) => sel,
+            None => {
+                for succ in self.ssa.succs_of(block) {
+                    self.control.push(block, succ);
+                }
+                return;
+            }
+        };
+        let (cases, default) = self.ssa.get_cases(&sel);
+        if !cases.is_empty() {
+            let value = self.value_of(&sel);
+            for &(case, target) in &cases {
+                if self.operator.takes_case(&value, case) {
+                    self.control.push(block, target);
+                }
+            }
+            let values = cases.iter().map(|&(case, _)| case).collect::<Vec<_>>();
+            if default != self.ssa.invalid_action() &&
+               self.operator.takes_default(&value, &values) {
+                self.control.push(block, default);
+            }
+            return;
+        }
+        let (false_branch, true_branch) = self.ssa.get_branches(&sel);
+        let (takes_false, takes_true) = self.operator.branches(&self.value_of(&sel));
+        if takes_true {
+            self.control.push(block, true_branch);
+        }
+        if takes_false {
+            self.control.push(block, false_branch);
+        }
+    }
+}
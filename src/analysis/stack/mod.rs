@@ -0,0 +1,420 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recovery of stack variables.
+//!
+//! Tracks the offset of every value from the stack pointer at function
+//! entry, through arithmetic and through the register phis. Loads and stores
+//! at a constant offset are grouped into stack variables.
+//!
+//! A pointer into the stack that is used for anything but addressing a load
+//! or store may be used to reach any variable above it. Variables below all
+//! such pointers, which are accessed with a single size and do not overlap
+//! others, can be promoted into SSA values by `StackFrame::promote`.
+
+mod promote;
+
+use std::collections::{BTreeMap, HashMap};
+
+use analysis::address::operand;
+use analysis::propagate::{DataOperator, Propagator};
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::{SSA, SSAStorage};
+use middle::ssa::ssa_traits::NodeType;
+use middle::ssa::ssastorage::NodeData;
+use petgraph::graph::NodeIndex;
+
+#[derive(Clone, Debug)]
+pub struct StackVariable {
+    pub name: String,
+    /// Offset from the stack pointer at function entry.
+    pub offset: i64,
+    pub width: WidthSpec,
+    /// Whether a pointer to the variable may be used other than to load
+    /// or store it.
+    pub escapes: bool,
+    /// Whether the loads and stores were replaced by SSA values.
+    pub promoted: bool,
+}
+
+pub struct StackFrame<T: SSA> {
+    variables: Vec<StackVariable>,
+    promotable: Vec<bool>,
+    /// Variable accessed by each load and store.
+    accesses: HashMap<T::ValueRef, usize>,
+    /// Variable each address points to.
+    addresses: HashMap<T::ValueRef, usize>,
+}
+
+impl<T: SSA> StackFrame<T> {
+    /// Finds the stack variables of the function in `ssa`, given the value
+    /// of the stack pointer at its entry.
+    pub fn recover(ssa: &T, sp: T::ValueRef) -> StackFrame<T> {
+        let mut propagator = Propagator::new(ssa, Offsets::<T> { sp: sp });
+        propagator.run();
+        let offset_of = |v: &T::ValueRef| {
+            match propagator.value_of(v) {
+                Offset::At(o) => Some(o),
+                _ => None,
+            }
+        };
+
+        let mut slots = BTreeMap::<i64, Slot<T>>::new();
+        let mut escape: Option<i64> = None;
+        for v in ssa.nodes() {
+            let nd = match ssa.get_node_data(&v) {
+                Ok(nd) => nd,
+                Err(_) => continue,
+            };
+            let access = match nd.nt {
+                NodeType::Op(MOpcode::OpLoad(width, _)) => Some((width, true)),
+                NodeType::Op(MOpcode::OpStore(width, _)) => Some((width, false)),
+                _ => None,
+            };
+            if let Some((width, load)) = access {
+                let addr = operand(ssa, &v, 0);
+                if let Some(o) = addr.as_ref().and_then(|a| offset_of(a)) {
+                    let slot = slots.entry(o).or_insert_with(Slot::new);
+                    slot.widths.push(width);
+                    slot.accesses.push(v);
+                    slot.addresses.push(addr.unwrap());
+                    if load {
+                        slot.loaded = true;
+                    } else {
+                        slot.stored = true;
+                    }
+                }
+            }
+            if let Some(o) = offset_of(&v) {
+                let escapes = ssa.get_uses(&v).iter().any(|u| {
+                    !addresses_only(ssa, &v, u, offset_of(u).is_some())
+                });
+                if escapes {
+                    escape = Some(escape.map_or(o, |e| if o < e { o } else { e }));
+                }
+            }
+        }
+
+        let mut frame = StackFrame {
+            variables: Vec::new(),
+            promotable: Vec::new(),
+            accesses: HashMap::new(),
+            addresses: HashMap::new(),
+        };
+        let offsets = slots.keys().cloned().collect::<Vec<_>>();
+        let mut end = i64::min_value();
+        for (n, (&offset, slot)) in slots.iter().enumerate() {
+            let width = slot.widths.iter().cloned().max().unwrap_or(0);
+            let uniform = width > 0 && width % 8 == 0 && slot.widths.iter().all(|&w| w == width);
+            let size = (width / 8) as i64;
+            let overlaps = offset < end || offsets.get(n + 1).map_or(false, |&o| o < offset + size);
+            end = ::std::cmp::max(end, offset + size);
+            let escapes = escape.map_or(false, |e| offset >= e);
+            let name = if offset < 0 {
+                format!("local_{:x}", -offset)
+            } else {
+                format!("arg_{:x}", offset)
+            };
+            let index = frame.variables.len();
+            frame.variables.push(StackVariable {
+                name: name,
+                offset: offset,
+                width: width,
+                escapes: escapes,
+                promoted: false,
+            });
+            frame.promotable.push(offset < 0 && uniform && !overlaps && !escapes &&
+                                  slot.loaded && slot.stored);
+            for a in &slot.accesses {
+                frame.accesses.insert(*a, index);
+            }
+            for a in &slot.addresses {
+                frame.addresses.insert(*a, index);
+            }
+        }
+        frame
+    }
+
+    pub fn variables(&self) -> &[StackVariable] {
+        &self.variables
+    }
+
+    /// The variable `addr` points to, if any.
+    pub fn variable_at(&self, addr: &T::ValueRef) -> Option<&StackVariable> {
+        self.addresses.get(addr).map(|&i| &self.variables[i])
+    }
+}
+
+/// Finds the argument node holding the initial value of register `name`.
+pub fn find_register(ssa: &SSAStorage, name: &str) -> Option<NodeIndex> {
+    let mut nodes = ssa.nodes();
+    nodes.sort();
+    nodes.into_iter().find(|n| {
+        match ssa.g[ssa.internal(n)] {
+            NodeData::Comment(_, ref c) => c == name,
+            _ => false,
+        }
+    })
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+struct Slot<T: SSA> {
+    widths: Vec<WidthSpec>,
+    accesses: Vec<T::ValueRef>,
+    addresses: Vec<T::ValueRef>,
+    loaded: bool,
+    stored: bool,
+}
+
+impl<T: SSA> Slot<T> {
+    fn new() -> Slot<T> {
+        Slot {
+            widths: Vec::new(),
+            accesses: Vec::new(),
+            addresses: Vec::new(),
+            loaded: false,
+            stored: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Offset {
+    Top,
+    At(i64),
+    Bottom,
+}
+
+/// Offsets from the initial stack pointer, as a `DataOperator`.
+struct Offsets<T: SSA> {
+    sp: T::ValueRef,
+}
+
+impl<T: SSA> DataOperator<T> for Offsets<T> {
+    type Value = Offset;
+
+    fn initial(&self, ssa: &T, v: &T::ValueRef) -> Offset {
+        if *v == self.sp {
+            return Offset::At(0);
+        }
+        match ssa.get_node_data(v).map(|nd| nd.nt) {
+            Ok(NodeType::Op(_)) | Ok(NodeType::Phi) => Offset::Top,
+            _ => Offset::Bottom,
+        }
+    }
+
+    fn transfer(&self, ssa: &T, v: &T::ValueRef, operands: &[(u8, Offset)]) -> Offset {
+        let opc = match ssa.get_node_data(v).map(|nd| nd.nt) {
+            Ok(NodeType::Op(opc)) => opc,
+            _ => return Offset::Bottom,
+        };
+        let value = |i: u8| {
+            operands.iter()
+                    .find(|&&(j, _)| j == i)
+                    .map(|&(_, o)| o)
+                    .unwrap_or(Offset::Bottom)
+        };
+        let constant = |i: u8| {
+            match operand(ssa, v, i).and_then(|o| ssa.get_node_data(&o).ok()).map(|nd| nd.nt) {
+                Some(NodeType::Op(MOpcode::OpConst(c))) => Some(c as i64),
+                _ => None,
+            }
+        };
+        match (opc, value(0), value(1)) {
+            (MOpcode::OpAdd, Offset::At(o), _) if constant(1).is_some() => {
+                Offset::At(o.wrapping_add(constant(1).unwrap()))
+            }
+            (MOpcode::OpAdd, _, Offset::At(o)) if constant(0).is_some() => {
+                Offset::At(o.wrapping_add(constant(0).unwrap()))
+            }
+            (MOpcode::OpSub, Offset::At(o), _) if constant(1).is_some() => {
+                Offset::At(o.wrapping_sub(constant(1).unwrap()))
+            }
+            (MOpcode::OpAdd, Offset::Top, _) |
+            (MOpcode::OpAdd, _, Offset::Top) |
+            (MOpcode::OpSub, Offset::Top, _) => Offset::Top,
+            _ => Offset::Bottom,
+        }
+    }
+
+    fn join(&self, a: &Offset, b: &Offset) -> Offset {
+        match (*a, *b) {
+            (Offset::Top, x) | (x, Offset::Top) => x,
+            (Offset::At(x), Offset::At(y)) if x == y => Offset::At(x),
+            _ => Offset::Bottom,
+        }
+    }
+
+    fn branches(&self, _: &Offset) -> (bool, bool) {
+        (true, true)
+    }
+}
+
+// Whether `user` only uses the stack address `v` to access memory, or to
+// compute another address with a known offset.
+fn addresses_only<T: SSA>(ssa: &T, v: &T::ValueRef, user: &T::ValueRef, known: bool) -> bool {
+    match ssa.get_node_data(user).map(|nd| nd.nt) {
+        Ok(NodeType::Op(MOpcode::OpLoad(..))) => true,
+        Ok(NodeType::Op(MOpcode::OpStore(..))) => operand(ssa, user, 1) != Some(*v),
+        Ok(NodeType::Op(MOpcode::OpAdd)) |
+        Ok(NodeType::Op(MOpcode::OpSub)) |
+        Ok(NodeType::Phi) => known,
+        Ok(_) => false,
+        // The register state.
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::arch::Endian;
+    use middle::ir::{MOpcode, WidthSpec};
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::{CFG, CFGMod};
+    use middle::ssa::ssa_traits::NodeType;
+
+    fn frame() -> (SSAStorage, <SSAStorage as SSA>::ActionRef) {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        (ssa, entry)
+    }
+
+    // `rbp - offset`, with rbp = rsp - 8.
+    fn local(ssa: &mut SSAStorage,
+             block: <SSAStorage as SSA>::ActionRef,
+             rbp: <SSAStorage as SSA>::ValueRef,
+             offset: u64)
+             -> <SSAStorage as SSA>::ValueRef {
+        let c = ssa.add_const(block, offset);
+        let addr = ssa.add_op(block, MOpcode::OpSub, From::from(64 as u16), None);
+        ssa.op_use(addr, 0, rbp);
+        ssa.op_use(addr, 1, c);
+        addr
+    }
+
+    fn access(ssa: &mut SSAStorage,
+              block: <SSAStorage as SSA>::ActionRef,
+              opcode: fn(WidthSpec, Endian) -> MOpcode,
+              width: u16,
+              operands: &[<SSAStorage as SSA>::ValueRef])
+              -> <SSAStorage as SSA>::ValueRef {
+        let opc = opcode(width, Endian::Little);
+        let vt = match opc {
+            MOpcode::OpStore(..) => 0,
+            _ => width,
+        };
+        let n = ssa.add_op(block, opc, From::from(vt), None);
+        ssa.op_use(n, 0, operands[0]);
+        if operands.len() > 2 {
+            ssa.op_use(n, 1, operands[1]);
+        }
+        ssa.op_use(n, 3, operands[operands.len() - 1]);
+        n
+    }
+
+    #[test]
+    fn promote_local() {
+        let (mut ssa, entry) = frame();
+        let body = ssa.add_block(BBInfo { addr: 0x10 });
+        let exit = ssa.exit_node();
+        ssa.add_control_edge(entry, body, 2);
+        ssa.add_control_edge(body, exit, 2);
+
+        let rsp = ssa.add_comment(entry, From::from(64 as u16), "rsp".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let x = ssa.add_comment(entry, From::from(32 as u16), "edi".to_owned());
+        let rbp = local(&mut ssa, body, rsp, 8);
+        let a = local(&mut ssa, body, rbp, 0x14);
+        let store = access(&mut ssa, body, MOpcode::OpStore, 32, &[a, x, mem]);
+        let b = local(&mut ssa, body, rbp, 0x14);
+        let load = access(&mut ssa, body, MOpcode::OpLoad, 32, &[b, store]);
+        let not = ssa.add_op(body, MOpcode::OpNot, From::from(32 as u16), None);
+        ssa.op_use(not, 0, load);
+
+        assert_eq!(find_register(&ssa, "rsp"), Some(rsp));
+        let mut frame = StackFrame::recover(&ssa, rsp);
+        assert_eq!(frame.variables().len(), 1);
+        assert_eq!(frame.variables()[0].name, "local_1c");
+        assert_eq!(frame.variables()[0].offset, -0x1c);
+        assert_eq!(frame.variables()[0].width, 32);
+        assert!(frame.variable_at(&b).is_some());
+        assert_eq!(frame.promote(&mut ssa), 1);
+        assert!(frame.variables()[0].promoted);
+        assert_eq!(ssa.get_operands(&not), vec![x]);
+    }
+
+    #[test]
+    fn phi_at_join() {
+        let (mut ssa, entry) = frame();
+        let left = ssa.add_block(BBInfo { addr: 0x10 });
+        let right = ssa.add_block(BBInfo { addr: 0x20 });
+        let join = ssa.add_block(BBInfo { addr: 0x30 });
+        let exit = ssa.exit_node();
+        ssa.add_control_edge(entry, left, 0);
+        ssa.add_control_edge(entry, right, 1);
+        ssa.add_control_edge(left, join, 2);
+        ssa.add_control_edge(right, join, 2);
+        ssa.add_control_edge(join, exit, 2);
+
+        let rsp = ssa.add_comment(entry, From::from(64 as u16), "rsp".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let one = ssa.add_const(left, 1);
+        let two = ssa.add_const(right, 2);
+        let a = local(&mut ssa, left, rsp, 0x10);
+        let s1 = access(&mut ssa, left, MOpcode::OpStore, 64, &[a, one, mem]);
+        let b = local(&mut ssa, right, rsp, 0x10);
+        let s2 = access(&mut ssa, right, MOpcode::OpStore, 64, &[b, two, mem]);
+        let mphi = ssa.add_phi(join, From::from(0 as u16));
+        ssa.phi_use(mphi, s1);
+        ssa.phi_use(mphi, s2);
+        let c = local(&mut ssa, join, rsp, 0x10);
+        let load = access(&mut ssa, join, MOpcode::OpLoad, 64, &[c, mphi]);
+        let not = ssa.add_op(join, MOpcode::OpNot, From::from(64 as u16), None);
+        ssa.op_use(not, 0, load);
+
+        let mut frame = StackFrame::recover(&ssa, rsp);
+        assert_eq!(frame.promote(&mut ssa), 1);
+        let value = ssa.get_operands(&not)[0];
+        match ssa.get_node_data(&value).unwrap().nt {
+            NodeType::Phi => {}
+            ref nt => panic!("Expected a phi, found {:?}", nt),
+        }
+        let mut sources = ssa.get_operands(&value);
+        sources.sort();
+        let mut expected = vec![one, two];
+        expected.sort();
+        assert_eq!(sources, expected);
+    }
+
+    #[test]
+    fn escaping_address() {
+        let (mut ssa, entry) = frame();
+        let rsp = ssa.add_comment(entry, From::from(64 as u16), "rsp".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let x = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let a = local(&mut ssa, entry, rsp, 0x10);
+        let store = access(&mut ssa, entry, MOpcode::OpStore, 64, &[a, x, mem]);
+        // The address itself is stored, so it may be used elsewhere.
+        let b = local(&mut ssa, entry, rsp, 0x20);
+        let leak = access(&mut ssa, entry, MOpcode::OpStore, 64, &[b, a, store]);
+        let load = access(&mut ssa, entry, MOpcode::OpLoad, 64, &[a, leak]);
+        let not = ssa.add_op(entry, MOpcode::OpNot, From::from(64 as u16), None);
+        ssa.op_use(not, 0, load);
+
+        let mut frame = StackFrame::recover(&ssa, rsp);
+        assert!(frame.variable_at(&a).unwrap().escapes);
+        assert_eq!(frame.promote(&mut ssa), 0);
+        assert_eq!(ssa.get_operands(&not), vec![load]);
+    }
+}
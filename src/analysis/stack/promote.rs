@@ -0,0 +1,164 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Promotion of stack variables into SSA values.
+
+use std::collections::{HashMap, HashSet};
+
+use analysis::address;
+use middle::ir::MOpcode;
+use middle::phiplacement::PhiPlacer;
+use middle::ssa::{BBInfo, SSA, SSAMod};
+use middle::ssa::ssa_traits::NodeType;
+
+use super::StackFrame;
+
+const VALUE: u8 = 1;
+const MEMORY: u8 = 3;
+
+impl<T: SSAMod<BBInfo=BBInfo>> StackFrame<T> {
+    /// Replaces the loads of every promotable variable by the value last
+    /// stored to it, and removes the stores. Returns the number of variables
+    /// promoted.
+    pub fn promote(&mut self, ssa: &mut T) -> usize {
+        // Variable of the `PhiPlacer` for each promotable stack variable.
+        let mut ids = HashMap::new();
+        let mut types = Vec::new();
+        for (i, variable) in self.variables.iter().enumerate() {
+            if self.promotable[i] {
+                ids.insert(i, types.len());
+                types.push(From::from(variable.width));
+            }
+        }
+        if types.is_empty() {
+            return 0;
+        }
+
+        let mut loads = Vec::new();
+        let mut stores = Vec::new();
+        {
+            let mut placer = PhiPlacer::new(ssa);
+            placer.add_variables(types.clone());
+            let blocks = placer.ssa.blocks();
+            for block in &blocks {
+                placer.add_existing_block(*block);
+            }
+            // Reading a variable before any store yields an undefined value.
+            let start = placer.ssa.start_node();
+            for (id, vt) in types.iter().enumerate() {
+                let undef = placer.ssa.add_undefined(start, *vt);
+                placer.write_variable(start, id, undef);
+            }
+
+            for block in &blocks {
+                let (chain, readers) = memory_order(&*placer.ssa, block);
+                let entry = Vec::new();
+                let mut states = vec![None];
+                states.extend(chain.iter().map(|s| Some(*s)));
+                for state in states {
+                    if let Some(writer) = state {
+                        if let Some(&id) = self.accesses.get(&writer).and_then(|i| ids.get(i)) {
+                            let value = operand(&*placer.ssa, &writer, VALUE);
+                            placer.write_variable(*block, id, value);
+                            stores.push(writer);
+                        }
+                        // An instruction that could not be parsed may have
+                        // written any variable. Calls cannot, as the address
+                        // of a promotable variable does not escape.
+                        if let Ok(NodeType::Op(MOpcode::OpUnknown)) =
+                               placer.ssa.get_node_data(&writer).map(|nd| nd.nt) {
+                            for (id, vt) in types.iter().enumerate() {
+                                let undef = placer.ssa.add_undefined(*block, *vt);
+                                placer.write_variable(*block, id, undef);
+                            }
+                        }
+                    }
+                    let reading = match state {
+                        Some(ref s) => readers.get(s).unwrap_or(&entry),
+                        None => readers.get(&placer.ssa.invalid_value()).unwrap_or(&entry),
+                    };
+                    for load in reading {
+                        if let Some(&id) = self.accesses.get(load).and_then(|i| ids.get(i)) {
+                            let value = placer.read_variable(*block, id);
+                            loads.push((*load, value));
+                        }
+                    }
+                }
+            }
+
+            for block in &blocks {
+                placer.seal_block(*block);
+            }
+        }
+
+        // Phis created above may since have been replaced, which `replace`
+        // resolves.
+        for (load, value) in loads {
+            ssa.replace(load, value);
+        }
+        for store in stores {
+            let memory = operand(ssa, &store, MEMORY);
+            ssa.replace(store, memory);
+        }
+        for (&i, _) in &ids {
+            self.variables[i].promoted = true;
+        }
+        ids.len()
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// The nodes of `block` that change the memory, which are stores, the memory
+// after calls and unknown instructions, in the order they do so, and the
+// loads reading each memory state. Loads of the memory state at block entry
+// are keyed by `invalid_value`.
+fn memory_order<T: SSA>(ssa: &T,
+                        block: &T::ActionRef)
+                        -> (Vec<T::ValueRef>, HashMap<T::ValueRef, Vec<T::ValueRef>>) {
+    let mut writers = HashSet::new();
+    let mut loads = Vec::new();
+    for expr in ssa.exprs_in(block) {
+        match ssa.get_node_data(&expr).map(|nd| nd.nt) {
+            Ok(NodeType::Op(MOpcode::OpStore(..))) |
+            Ok(NodeType::Op(MOpcode::OpMemory)) |
+            Ok(NodeType::Op(MOpcode::OpUnknown)) => {
+                writers.insert(expr);
+            }
+            Ok(NodeType::Op(MOpcode::OpLoad(..))) => loads.push(expr),
+            _ => {}
+        }
+    }
+
+    let entry = ssa.invalid_value();
+    let previous = |v: &T::ValueRef| {
+        let m = operand(ssa, v, MEMORY);
+        if writers.contains(&m) { m } else { entry }
+    };
+    let mut next = HashMap::new();
+    for w in &writers {
+        next.insert(previous(w), *w);
+    }
+    let mut chain = Vec::new();
+    let mut state = entry;
+    while let Some(&w) = next.get(&state) {
+        chain.push(w);
+        state = w;
+    }
+
+    let mut readers = HashMap::new();
+    for l in loads {
+        readers.entry(previous(&l)).or_insert_with(Vec::new).push(l);
+    }
+    (chain, readers)
+}
+
+fn operand<T: SSA>(ssa: &T, v: &T::ValueRef, index: u8) -> T::ValueRef {
+    address::operand(ssa, v, index).unwrap_or_else(|| ssa.invalid_value())
+}
@@ -0,0 +1,361 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Recovery of the types of SSA values.
+//!
+//! Values are constructed as `ValueType::Integer`s. How a value is used
+//! constrains its type further:
+//!
+//!  * The address of a load or store is a pointer. So is the sum of a
+//!    pointer and an integer, and a value that gives a pointer when the
+//!    offset of a field is added to it, unless it is a scaled index.
+//!  * Comparisons and the selectors of conditional branches are booleans.
+//!  * A value whose sign bit is extracted, by a right shift by its width
+//!    minus one as in ESIL's `$s`, is signed.
+//!  * The operands and results of floating point operations are floats.
+//!
+//! A phi and its operands share their type. A value with conflicting
+//! constraints stays an integer.
+
+use std::collections::HashMap;
+
+use analysis::address;
+use middle::ir::MOpcode;
+use middle::ssa::{SSA, SSAMod, ValueType};
+use middle::ssa::ssa_traits::NodeType;
+
+/// Largest constant taken for the offset of a field rather than an address.
+const MAX_OFFSET: u64 = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Pointer,
+    Bool,
+    Signed,
+    Float,
+    Conflict,
+}
+
+impl Kind {
+    fn join(old: Option<Kind>, new: Kind) -> Kind {
+        match old {
+            None => new,
+            Some(old) if old == new => old,
+            Some(_) => Kind::Conflict,
+        }
+    }
+}
+
+pub struct TypeInference<T: SSA> {
+    types: HashMap<T::ValueRef, ValueType>,
+}
+
+impl<T: SSA> TypeInference<T> {
+    /// Infers the types of the values in `ssa`.
+    pub fn infer(ssa: &T) -> TypeInference<T> {
+        let mut solver = Solver::<T> {
+            ssa: ssa,
+            kinds: HashMap::new(),
+            worklist: Vec::new(),
+        };
+        solver.collect();
+        solver.solve();
+
+        let mut types = HashMap::new();
+        for (v, kind) in solver.kinds {
+            let width = match ssa.get_node_data(&v) {
+                Ok(nd) => nd.vt.width(),
+                Err(_) => continue,
+            };
+            let vt = match kind {
+                Kind::Pointer => ValueType::Pointer { width: width },
+                Kind::Bool if width == 1 => ValueType::Bool,
+                Kind::Signed => ValueType::Signed { width: width },
+                Kind::Float => ValueType::Float { width: width },
+                _ => continue,
+            };
+            types.insert(v, vt);
+        }
+        TypeInference { types: types }
+    }
+
+    /// The type inferred for `v`, if it is more specific than an integer.
+    pub fn type_of(&self, v: &T::ValueRef) -> Option<ValueType> {
+        self.types.get(v).cloned()
+    }
+}
+
+impl<T: SSAMod> TypeInference<T> {
+    /// Sets the inferred types on the nodes of `ssa`. Returns the number of
+    /// nodes changed.
+    pub fn apply(&self, ssa: &mut T) -> usize {
+        for (v, vt) in &self.types {
+            ssa.set_value_type(*v, *vt);
+        }
+        self.types.len()
+    }
+}
+
+struct Solver<'a, T: SSA + 'a> {
+    ssa: &'a T,
+    kinds: HashMap<T::ValueRef, Kind>,
+    worklist: Vec<T::ValueRef>,
+}
+
+impl<'a, T: SSA + 'a> Solver<'a, T> {
+    fn constrain(&mut self, v: T::ValueRef, kind: Kind) {
+        // Memory states and calls without result have no type.
+        if self.width(&v) == Some(0) {
+            return;
+        }
+        let old = self.kinds.get(&v).cloned();
+        let new = Kind::join(old, kind);
+        if old != Some(new) {
+            self.kinds.insert(v, new);
+            self.worklist.push(v);
+        }
+    }
+
+    // Constraints of the individual nodes.
+    fn collect(&mut self) {
+        let ssa = self.ssa;
+        for v in ssa.nodes() {
+            let opc = match ssa.get_node_data(&v).map(|nd| nd.nt) {
+                Ok(NodeType::Op(opc)) => opc,
+                Ok(_) => continue,
+                Err(_) => continue,
+            };
+            match opc {
+                MOpcode::OpLoad(..) | MOpcode::OpStore(..) => {
+                    if let Some(addr) = self.operand(&v, 0) {
+                        self.constrain(addr, Kind::Pointer);
+                    }
+                }
+                MOpcode::OpCmp | MOpcode::OpGt | MOpcode::OpLt | MOpcode::OpLteq |
+                MOpcode::OpGteq => self.constrain(v, Kind::Bool),
+                MOpcode::OpFAdd | MOpcode::OpFSub | MOpcode::OpFMul | MOpcode::OpFDiv |
+                MOpcode::OpFCmp | MOpcode::OpFLt | MOpcode::OpFLteq | MOpcode::OpFtoI(_) |
+                MOpcode::OpFConv(_) => {
+                    for o in ssa.get_operands(&v) {
+                        self.constrain(o, Kind::Float);
+                    }
+                }
+                _ => {}
+            }
+            if opc.is_float() {
+                self.constrain(v, Kind::Float);
+            }
+            match opc {
+                MOpcode::OpLsr => {
+                    let value = self.operand(&v, 0);
+                    let shift = self.operand(&v, 1).and_then(|s| self.constant(&s));
+                    if let (Some(value), Some(shift)) = (value, shift) {
+                        if self.width(&value).map(|w| w as u64) == Some(shift + 1) {
+                            self.constrain(value, Kind::Signed);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if ssa.is_selector(&v) && self.width(&v) == Some(1) {
+                self.constrain(v, Kind::Bool);
+            }
+        }
+    }
+
+    // Propagates the kinds through phis and pointer arithmetic.
+    fn solve(&mut self) {
+        let ssa = self.ssa;
+        while let Some(v) = self.worklist.pop() {
+            let kind = self.kinds[&v];
+            if self.is_phi(&v) {
+                for o in ssa.get_operands(&v) {
+                    self.constrain(o, kind);
+                }
+            }
+            for u in ssa.get_uses(&v) {
+                if self.is_phi(&u) {
+                    self.constrain(u, kind);
+                } else if kind == Kind::Pointer {
+                    // `p + x`, `x + p` and `p - x` are pointers.
+                    match self.opcode(&u) {
+                        Some(MOpcode::OpAdd) => self.constrain(u, Kind::Pointer),
+                        Some(MOpcode::OpSub) if self.operand(&u, 0) == Some(v) => {
+                            self.constrain(u, Kind::Pointer)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // If `x + c` or `x - c` is a pointer, so is `x`. In `i * 8 + c`
+            // or `x + c` with a large `c`, `c` is the address instead.
+            if kind == Kind::Pointer {
+                let opc = self.opcode(&v);
+                if opc == Some(MOpcode::OpAdd) || opc == Some(MOpcode::OpSub) {
+                    let lhs = self.operand(&v, 0);
+                    let rhs = self.operand(&v, 1);
+                    if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                        if self.is_offset(&rhs) {
+                            if !self.is_scaled(&lhs) {
+                                self.constrain(lhs, Kind::Pointer);
+                            }
+                        } else if opc == Some(MOpcode::OpAdd) && self.is_offset(&lhs) &&
+                                  !self.is_scaled(&rhs) {
+                            self.constrain(rhs, Kind::Pointer);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Helper functions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn operand(&self, v: &T::ValueRef, index: u8) -> Option<T::ValueRef> {
+        address::operand(self.ssa, v, index)
+    }
+
+    fn width(&self, v: &T::ValueRef) -> Option<u16> {
+        self.ssa.get_node_data(v).ok().map(|nd| nd.vt.width())
+    }
+
+    fn opcode(&self, v: &T::ValueRef) -> Option<MOpcode> {
+        address::opcode(self.ssa, v)
+    }
+
+    fn constant(&self, v: &T::ValueRef) -> Option<u64> {
+        address::constant(self.ssa, v)
+    }
+
+    // Whether `v` is a constant small enough to be the offset of a field.
+    fn is_offset(&self, v: &T::ValueRef) -> bool {
+        self.constant(v).map_or(false, |c| c <= MAX_OFFSET)
+    }
+
+    // Whether `v` is an index multiplied by the size of an element.
+    fn is_scaled(&self, v: &T::ValueRef) -> bool {
+        address::scaled(self.ssa, v).is_some()
+    }
+
+    fn is_phi(&self, v: &T::ValueRef) -> bool {
+        match self.ssa.get_node_data(v).map(|nd| nd.nt) {
+            Ok(NodeType::Phi) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::arch::Endian;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage, ValueType};
+    use middle::ssa::cfg_traits::{CFG, CFGMod};
+
+    #[test]
+    fn pointers_bools_and_signed() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let rdi = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let rsi = ssa.add_comment(entry, From::from(64 as u16), "rsi".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+
+        // load(rdi + 8)
+        let eight = ssa.add_const(entry, 8);
+        let addr = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(addr, 0, rdi);
+        ssa.op_use(addr, 1, eight);
+        let load = ssa.add_op(entry,
+                              MOpcode::OpLoad(64, Endian::Little),
+                              From::from(64 as u16),
+                              None);
+        ssa.op_use(load, 0, addr);
+        ssa.op_use(load, 3, mem);
+
+        // (rsi >> 63) selects the branch taken on rsi < load.
+        let sign = ssa.add_const(entry, 63);
+        let shift = ssa.add_op(entry, MOpcode::OpLsr, From::from(64 as u16), None);
+        ssa.op_use(shift, 0, rsi);
+        ssa.op_use(shift, 1, sign);
+        let cmp = ssa.add_op(entry, MOpcode::OpLt, From::from(1 as u16), None);
+        ssa.op_use(cmp, 0, rsi);
+        ssa.op_use(cmp, 1, load);
+        ssa.mark_selector(cmp, entry);
+
+        let types = TypeInference::infer(&ssa);
+        assert_eq!(types.type_of(&addr), Some(ValueType::Pointer { width: 64 }));
+        assert_eq!(types.type_of(&rdi), Some(ValueType::Pointer { width: 64 }));
+        assert_eq!(types.type_of(&rsi), Some(ValueType::Signed { width: 64 }));
+        assert_eq!(types.type_of(&cmp), Some(ValueType::Bool));
+        assert_eq!(types.type_of(&load), None);
+        assert_eq!(types.type_of(&mem), None);
+
+        assert_eq!(types.apply(&mut ssa), 4);
+        assert_eq!(ssa.get_node_data(&rdi).unwrap().vt, ValueType::Pointer { width: 64 });
+    }
+
+    #[test]
+    fn phi_shares_type() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let join = ssa.add_block(BBInfo { addr: 0x10 });
+        ssa.add_control_edge(entry, join, 2);
+        let a = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let b = ssa.add_comment(entry, From::from(64 as u16), "rsi".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let phi = ssa.add_phi(join, From::from(64 as u16));
+        ssa.phi_use(phi, a);
+        ssa.phi_use(phi, b);
+        let store = ssa.add_op(join,
+                               MOpcode::OpStore(64, Endian::Little),
+                               From::from(0 as u16),
+                               None);
+        ssa.op_use(store, 0, phi);
+        ssa.op_use(store, 1, a);
+        ssa.op_use(store, 3, mem);
+
+        let types = TypeInference::infer(&ssa);
+        assert_eq!(types.type_of(&phi), Some(ValueType::Pointer { width: 64 }));
+        assert_eq!(types.type_of(&b), Some(ValueType::Pointer { width: 64 }));
+        assert_eq!(types.type_of(&a), Some(ValueType::Pointer { width: 64 }));
+    }
+
+    #[test]
+    fn scaled_index() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let i = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+
+        // load(i * 8 + 0x601040)
+        let eight = ssa.add_const(entry, 8);
+        let index = ssa.add_op(entry, MOpcode::OpMul, From::from(64 as u16), None);
+        ssa.op_use(index, 0, i);
+        ssa.op_use(index, 1, eight);
+        let table = ssa.add_const(entry, 0x601040);
+        let addr = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(addr, 0, index);
+        ssa.op_use(addr, 1, table);
+        let load = ssa.add_op(entry,
+                              MOpcode::OpLoad(64, Endian::Little),
+                              From::from(64 as u16),
+                              None);
+        ssa.op_use(load, 0, addr);
+        ssa.op_use(load, 3, mem);
+
+        let types = TypeInference::infer(&ssa);
+        assert_eq!(types.type_of(&addr), Some(ValueType::Pointer { width: 64 }));
+        assert_eq!(types.type_of(&index), None);
+        assert_eq!(types.type_of(&i), None);
+    }
+}
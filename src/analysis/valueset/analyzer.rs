@@ -0,0 +1,343 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Value set analysis on the SSA IR.
+//!
+//! Computes a `ValueInfo` for every value, in the manner of sparse
+//! conditional constant propagation: Only blocks reachable through
+//! executable edges are evaluated, and a branch whose selector is known
+//! marks only one of its edges executable. Values that keep changing are
+//! widened so that loops converge. The propagation itself is done by
+//! `analysis::propagate`.
+
+use std::collections::HashSet;
+
+use analysis::propagate::{DataOperator, Propagator, forwarded_store};
+use middle::ir::MOpcode;
+use middle::ssa::SSA;
+use middle::ssa::ssa_traits::NodeType;
+use super::ValueSet;
+use super::product::ValueInfo;
+
+/// The value set lattice, as a `DataOperator`.
+pub struct ValueSets;
+
+impl<T: SSA> DataOperator<T> for ValueSets {
+    type Value = ValueInfo;
+
+    fn initial(&self, ssa: &T, v: &T::ValueRef) -> ValueInfo {
+        match ssa.get_node_data(v) {
+            Ok(nd) => {
+                let width = nd.vt.width();
+                match nd.nt {
+                    // Arguments and undefined values.
+                    NodeType::Undefined => ValueInfo::full(width),
+                    _ => ValueInfo::empty(width),
+                }
+            }
+            Err(_) => ValueInfo::full(64),
+        }
+    }
+
+    fn transfer(&self, ssa: &T, v: &T::ValueRef, operands: &[(u8, ValueInfo)]) -> ValueInfo {
+        let nd = match ssa.get_node_data(v) {
+            Ok(nd) => nd,
+            Err(_) => return ValueInfo::full(64),
+        };
+        let width = nd.vt.width();
+        let operand = |index: u8| {
+            operands.iter()
+                    .find(|&&(j, _)| j == index)
+                    .map(|&(_, info)| info)
+                    .unwrap_or(ValueInfo::full(64))
+        };
+        match nd.nt {
+            // A store stands for the value it stores, which the loads of the
+            // same address read back.
+            NodeType::Op(MOpcode::OpStore(..)) => operand(1),
+            NodeType::Op(MOpcode::OpLoad(..)) => {
+                match forwarded_store(ssa, v) {
+                    Some(shift) => {
+                        let stored = operand(3);
+                        let amount = ValueInfo::constant(stored.width, shift as u64);
+                        let shifted = ValueInfo::transfer(MOpcode::OpLsr,
+                                                          stored.width,
+                                                          &[stored, amount]);
+                        ValueInfo::transfer(MOpcode::OpNarrow(width), width, &[shifted])
+                    }
+                    None => ValueInfo::full(width),
+                }
+            }
+            NodeType::Op(opc) => ValueInfo::transfer(opc, width, &[operand(0), operand(1)]),
+            _ => ValueInfo::full(width),
+        }
+    }
+
+    fn join(&self, a: &ValueInfo, b: &ValueInfo) -> ValueInfo {
+        a.join(b)
+    }
+
+    fn widen(&self, old: &ValueInfo, new: &ValueInfo) -> ValueInfo {
+        old.widen(new)
+    }
+
+    fn branches(&self, sel: &ValueInfo) -> (bool, bool) {
+        if sel.is_empty() {
+            (false, false)
+        } else if !sel.contains(0) {
+            (false, true)
+        } else if sel.as_const() == Some(0) {
+            (true, false)
+        } else {
+            (true, true)
+        }
+    }
+
+    fn takes_case(&self, sel: &ValueInfo, case: u64) -> bool {
+        sel.contains(case)
+    }
+
+    fn takes_default(&self, sel: &ValueInfo, cases: &[u64]) -> bool {
+        // Unless every value of the selector is one of the cases.
+        let matched = cases.iter().filter(|&&case| sel.contains(case)).count() as u64;
+        !sel.is_empty() && matched < sel.count()
+    }
+}
+
+pub struct Analyzer<'a, T: SSA + 'a> {
+    ssa: &'a T,
+    propagator: Propagator<'a, T, ValueSets>,
+}
+
+impl<'a, T: SSA + 'a> Analyzer<'a, T> {
+    pub fn new(ssa: &'a T) -> Analyzer<'a, T> {
+        Analyzer {
+            ssa: ssa,
+            propagator: Propagator::new(ssa, ValueSets),
+        }
+    }
+
+    pub fn analyze(&mut self) {
+        self.propagator.run();
+    }
+
+    /// Values `v` may take. Values in blocks found unreachable are empty.
+    pub fn value_of(&self, v: &T::ValueRef) -> ValueInfo {
+        self.propagator.value_of(v)
+    }
+
+    /// Values `v` may take in `block`, refined by the branches leading there.
+    ///
+    /// Walks up from `block` as long as there is a single predecessor and
+    /// applies every comparison of `v` with another value that selected the
+    /// branch taken, as well as the cases of a switch over `v`.
+    pub fn value_in(&self, v: &T::ValueRef, block: &T::ActionRef) -> ValueInfo {
+        let mut info = self.value_of(v);
+        let def = self.ssa.get_block(v);
+        let mut visited = HashSet::new();
+        let mut cur = *block;
+        while cur != def && visited.insert(cur) {
+            let preds = self.ssa.preds_of(cur);
+            if preds.len() != 1 {
+                break;
+            }
+            if let Some(sel) = self.ssa.selector_of(&preds[0]) {
+                let (cases, default) = self.ssa.get_cases(&sel);
+                if !cases.is_empty() {
+                    if sel == *v && cur != default {
+                        info = self.refine_cases(info, &cases, &cur);
+                    }
+                } else {
+                    let (_, true_branch) = self.ssa.get_branches(&sel);
+                    info = self.refine(info, v, &sel, cur == true_branch);
+                }
+            }
+            cur = preds[0];
+        }
+        info
+    }
+
+    pub fn is_executable(&self, block: &T::ActionRef) -> bool {
+        self.propagator.control().is_block_executable(block)
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Helper functions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn operand(&self, v: &T::ValueRef, index: u8) -> Option<T::ValueRef> {
+        self.ssa
+            .get_sparse_operands(v)
+            .iter()
+            .find(|&&(i, _)| i == index)
+            .map(|&(_, o)| o)
+    }
+
+    // Restricts `info` to the cases of a switch over it that lead to `block`.
+    fn refine_cases(&self,
+                    info: ValueInfo,
+                    cases: &[(u64, T::ActionRef)],
+                    block: &T::ActionRef)
+                    -> ValueInfo {
+        let taken = cases.iter()
+                         .filter(|&&(_, target)| target == *block)
+                         .fold(ValueInfo::empty(info.width), |taken, &(case, _)| {
+                             taken.join(&ValueInfo::constant(info.width, case))
+                         });
+        info.meet(&taken)
+    }
+
+    fn refine(&self,
+              info: ValueInfo,
+              v: &T::ValueRef,
+              sel: &T::ValueRef,
+              taken: bool)
+              -> ValueInfo {
+        let opc = match self.ssa.get_node_data(sel) {
+            Ok(nd) => {
+                match nd.nt {
+                    NodeType::Op(opc) => opc,
+                    _ => return info,
+                }
+            }
+            Err(_) => return info,
+        };
+        match (self.operand(sel, 0), self.operand(sel, 1)) {
+            (Some(a), Some(b)) if a == *v => info.assume(opc, &self.value_of(&b), taken),
+            (Some(a), Some(b)) if b == *v => {
+                let mirrored = match opc {
+                    MOpcode::OpLt => MOpcode::OpGt,
+                    MOpcode::OpGt => MOpcode::OpLt,
+                    MOpcode::OpLteq => MOpcode::OpGteq,
+                    MOpcode::OpGteq => MOpcode::OpLteq,
+                    _ => opc,
+                };
+                info.assume(mirrored, &self.value_of(&a), taken)
+            }
+            _ => info,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use analysis::valueset::ValueSet;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    // for (i = 0; i < 10; i++) with the check in the header and the bounded
+    // use of `i` in the body.
+    #[test]
+    fn bounded_loop() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let head = ssa.add_block(BBInfo { addr: 0x10 });
+        let body = ssa.add_block(BBInfo { addr: 0x20 });
+        ssa.add_control_edge(entry, head, 2);
+        ssa.add_control_edge(head, exit, 0);
+        ssa.add_control_edge(head, body, 1);
+        ssa.add_control_edge(body, head, 2);
+
+        let zero = ssa.add_const(entry, 0);
+        let i = ssa.add_phi(head, From::from(64 as u16));
+        let ten = ssa.add_const(head, 10);
+        let lt = ssa.add_op(head, MOpcode::OpLt, From::from(1 as u16), None);
+        ssa.op_use(lt, 0, i);
+        ssa.op_use(lt, 1, ten);
+        ssa.mark_selector(lt, head);
+        let one = ssa.add_const(body, 1);
+        let next = ssa.add_op(body, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(next, 0, i);
+        ssa.op_use(next, 1, one);
+        ssa.phi_use(i, zero);
+        ssa.phi_use(i, next);
+
+        let mut analyzer = Analyzer::new(&ssa);
+        analyzer.analyze();
+        assert!(analyzer.is_executable(&body));
+        assert_eq!(analyzer.value_of(&zero).as_const(), Some(0));
+        assert_eq!(analyzer.value_of(&i).urange.min, 0);
+        let in_body = analyzer.value_in(&i, &body);
+        assert_eq!(in_body.urange.min, 0);
+        assert_eq!(in_body.urange.max, 9);
+        assert!(analyzer.value_of(&lt).as_const().is_none());
+    }
+
+    #[test]
+    fn dead_branch() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let dead = ssa.add_block(BBInfo { addr: 0x10 });
+        ssa.add_control_edge(entry, exit, 0);
+        ssa.add_control_edge(entry, dead, 1);
+        ssa.add_control_edge(dead, exit, 2);
+
+        let four = ssa.add_const(entry, 4);
+        let masked = ssa.add_op(entry, MOpcode::OpAnd, From::from(64 as u16), None);
+        ssa.op_use(masked, 0, four);
+        ssa.op_use(masked, 1, four);
+        let three = ssa.add_const(entry, 3);
+        let gt = ssa.add_op(entry, MOpcode::OpGt, From::from(1 as u16), None);
+        ssa.op_use(gt, 0, three);
+        ssa.op_use(gt, 1, masked);
+        ssa.mark_selector(gt, entry);
+        let unused = ssa.add_const(dead, 7);
+
+        let mut analyzer = Analyzer::new(&ssa);
+        analyzer.analyze();
+        assert_eq!(analyzer.value_of(&gt).as_const(), Some(0));
+        assert!(!analyzer.is_executable(&dead));
+        assert!(analyzer.value_of(&unused).is_empty());
+    }
+
+    // switch (x & 3) with the cases 0 and 2 leading to the same block. As
+    // every value has a case, the default is dead.
+    #[test]
+    fn switch_cases() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let even = ssa.add_block(BBInfo { addr: 0x10 });
+        let odd = ssa.add_block(BBInfo { addr: 0x20 });
+        let dead = ssa.add_block(BBInfo { addr: 0x30 });
+        ssa.add_case_edge(entry, even, 0);
+        ssa.add_case_edge(entry, odd, 1);
+        ssa.add_case_edge(entry, even, 2);
+        ssa.add_case_edge(entry, odd, 3);
+        ssa.add_default_edge(entry, dead);
+        ssa.add_control_edge(even, exit, 2);
+        ssa.add_control_edge(odd, exit, 2);
+        ssa.add_control_edge(dead, exit, 2);
+
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let three = ssa.add_const(entry, 3);
+        let index = ssa.add_op(entry, MOpcode::OpAnd, From::from(64 as u16), None);
+        ssa.op_use(index, 0, x);
+        ssa.op_use(index, 1, three);
+        ssa.mark_selector(index, entry);
+
+        let mut analyzer = Analyzer::new(&ssa);
+        analyzer.analyze();
+        assert!(analyzer.is_executable(&even));
+        assert!(analyzer.is_executable(&odd));
+        assert!(!analyzer.is_executable(&dead));
+        let in_even = analyzer.value_in(&index, &even);
+        assert_eq!(in_even.urange.max, 2);
+        assert!(in_even.contains(0));
+        assert!(!in_even.contains(1));
+    }
+}
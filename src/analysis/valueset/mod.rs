@@ -9,7 +9,9 @@
 //! of 64 bit integers
 //! using different compact representations, including methods for intersection
 //! and union.
-//! (Currently unused)
+//!
+//! `ValueInfo` combines them into a reduced product, which `Analyzer`
+//! computes for every value of a function in SSA form.
 
 use std::fmt::Debug;
 use std::ops::{BitAnd, BitOr};
@@ -25,8 +27,14 @@ mod sintrange;
 mod uintmultiple;
 mod sintmultiple;
 
+pub mod product;
+pub mod analyzer;
+
+pub use self::product::ValueInfo;
+pub use self::analyzer::Analyzer;
+
 /// Value set of u64 integers with certain bits set/cleared.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct KnownBits {
     /// Bits that are cleared
     pub zerobits: u64,
@@ -35,21 +43,21 @@ pub struct KnownBits {
 }
 
 /// Value set of unsigned values that satisfy value % modulus = residue.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct UIntMultiple {
     pub modulus: u64,
     pub residue: u64,
 }
 
 /// Value set of unsigned values between min and max.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct UIntRange {
     pub min: u64,
     pub max: u64,
 }
 
 /// Value set of signed values between min and max.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SIntRange {
     pub min: i64,
     pub max: i64,
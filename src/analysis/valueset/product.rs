@@ -0,0 +1,698 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reduced product of the value set domains and transfer functions for the
+//! operations of the IR.
+//!
+//! A `ValueInfo` describes the values a SSA value of a given width may take.
+//! All values are zero extended to 64 bits. Every operation returns a
+//! reduced value, i.e. each component is refined by what the others know.
+
+use std::cmp::{max, min};
+
+use middle::ir::{MOpcode, WidthSpec};
+use super::{KnownBits, SIntRange, ScannableSet, UIntMultiple, UIntRange, ValueSet};
+use super::{EMPTY_SINTRANGE, EMPTY_UINTMULTIPLE, EMPTY_UINTRANGE};
+
+/// Mask of the bits that a value of `width` may have set.
+pub fn mask(width: WidthSpec) -> u64 {
+    if width == 0 || width >= 64 {
+        u64::max_value()
+    } else {
+        (1 << width) - 1
+    }
+}
+
+fn full_srange(width: WidthSpec) -> SIntRange {
+    if width == 0 || width >= 64 {
+        SIntRange {
+            min: i64::min_value(),
+            max: i64::max_value(),
+        }
+    } else {
+        SIntRange {
+            min: 0,
+            max: mask(width) as i64,
+        }
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+// Only exact if one modulus divides the other, which holds for the powers of
+// two coming from known bits. Otherwise the finer one is kept. BitAnd would
+// overflow for large moduli.
+fn meet_multiple(a: &UIntMultiple, b: &UIntMultiple) -> UIntMultiple {
+    if a.modulus == 0 || b.modulus == 0 {
+        return EMPTY_UINTMULTIPLE;
+    }
+    let (small, large) = if a.modulus <= b.modulus { (a, b) } else { (b, a) };
+    if large.modulus % small.modulus != 0 {
+        return *large;
+    }
+    if large.residue % small.modulus == small.residue {
+        *large
+    } else {
+        EMPTY_UINTMULTIPLE
+    }
+}
+
+fn join_multiple(a: &UIntMultiple, b: &UIntMultiple) -> UIntMultiple {
+    if a.modulus == 0 {
+        return *b;
+    }
+    if b.modulus == 0 {
+        return *a;
+    }
+    a | b
+}
+
+// Known bits of `a + b + carry`, after LLVM's `KnownBits::computeForAddCarry`.
+fn add_bits(a: &KnownBits, b: &KnownBits, carry: bool) -> KnownBits {
+    let c = carry as u64;
+    let sum_zero = (!a.zerobits).wrapping_add(!b.zerobits).wrapping_add(c);
+    let sum_one = a.onebits.wrapping_add(b.onebits).wrapping_add(c);
+    let carry_zero = !(sum_zero ^ a.zerobits ^ b.zerobits);
+    let carry_one = sum_one ^ a.onebits ^ b.onebits;
+    let known = (a.zerobits | a.onebits) & (b.zerobits | b.onebits) & (carry_zero | carry_one);
+    KnownBits {
+        zerobits: !sum_zero & known,
+        onebits: sum_one & known,
+    }
+}
+
+// Number of low bits known to be zero.
+fn trailing_zeros(bits: &KnownBits) -> u32 {
+    (!bits.zerobits).trailing_zeros()
+}
+
+/// Values a SSA value may take, as a reduced product of `KnownBits`,
+/// `UIntRange`, `SIntRange` and `UIntMultiple`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueInfo {
+    pub width: WidthSpec,
+    pub bits: KnownBits,
+    pub urange: UIntRange,
+    pub srange: SIntRange,
+    pub multiple: UIntMultiple,
+}
+
+impl ValueSet<u64> for ValueInfo {
+    fn contains(&self, value: u64) -> bool {
+        value & !mask(self.width) == 0 && self.bits.contains(value) &&
+        self.urange.contains(value) && self.srange.contains(value) &&
+        self.multiple.contains(value)
+    }
+}
+
+impl ValueInfo {
+    /// The value set containing no values, i.e. the value is not computed.
+    pub fn empty(width: WidthSpec) -> ValueInfo {
+        ValueInfo {
+            width: width,
+            bits: KnownBits {
+                zerobits: !0,
+                onebits: !0,
+            },
+            urange: EMPTY_UINTRANGE,
+            srange: EMPTY_SINTRANGE,
+            multiple: EMPTY_UINTMULTIPLE,
+        }
+    }
+
+    /// The value set containing every value of `width`.
+    pub fn full(width: WidthSpec) -> ValueInfo {
+        ValueInfo {
+            width: width,
+            bits: KnownBits {
+                zerobits: !mask(width),
+                onebits: 0,
+            },
+            urange: UIntRange {
+                min: 0,
+                max: mask(width),
+            },
+            srange: full_srange(width),
+            multiple: UIntMultiple {
+                modulus: 1,
+                residue: 0,
+            },
+        }
+    }
+
+    pub fn constant(width: WidthSpec, value: u64) -> ValueInfo {
+        let value = value & mask(width);
+        ValueInfo {
+            width: width,
+            bits: KnownBits {
+                zerobits: !value,
+                onebits: value,
+            },
+            urange: UIntRange {
+                min: value,
+                max: value,
+            },
+            srange: SIntRange {
+                min: value as i64,
+                max: value as i64,
+            },
+            multiple: UIntMultiple {
+                modulus: 1,
+                residue: 0,
+            },
+        }
+    }
+
+    /// Returns the unsigned range `[min, max]` of `width`, reduced.
+    pub fn range(width: WidthSpec, min: u64, max: u64) -> ValueInfo {
+        let mut v = ValueInfo::full(width);
+        v.urange = UIntRange {
+            min: min,
+            max: max,
+        };
+        v.reduce()
+    }
+
+    fn boolean() -> ValueInfo {
+        ValueInfo::range(1, 0, 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.zerobits & self.bits.onebits != 0 || self.urange.is_empty() ||
+        self.srange.is_empty() || self.multiple.modulus == 0
+    }
+
+    pub fn as_const(&self) -> Option<u64> {
+        if !self.is_empty() && self.urange.min == self.urange.max {
+            Some(self.urange.min)
+        } else {
+            None
+        }
+    }
+
+    /// Number of values in the set, saturating at `u64::max_value()`.
+    pub fn count(&self) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let span = self.urange.max - self.urange.min;
+        if self.multiple.modulus > 1 {
+            span / self.multiple.modulus + 1
+        } else {
+            span.saturating_add(1)
+        }
+    }
+
+    /// Refines every component by the information of the others until
+    /// nothing changes, giving up after a few rounds.
+    pub fn reduce(self) -> ValueInfo {
+        let mut v = self;
+        v.bits.zerobits |= !mask(v.width);
+        for _ in 0..8 {
+            if v.is_empty() {
+                return ValueInfo::empty(v.width);
+            }
+            let old = v;
+            v.urange = &v.urange & &v.bits.as_urange();
+            v.urange = &v.urange & &v.srange.as_urange();
+            v.srange = &v.srange & &v.urange.as_srange();
+            v.srange = &v.srange & &v.bits.as_srange();
+            v.bits = &v.bits & &v.urange.as_knownbits();
+            // Not a multiple of anything if all bits are known.
+            if v.bits.zerobits | v.bits.onebits != !0 {
+                v.multiple = meet_multiple(&v.multiple, &v.bits.as_umultiple());
+            }
+            if v.multiple.modulus > 1 && !v.urange.is_empty() {
+                let lo = v.multiple.scan_up(v.urange.min);
+                let hi = v.multiple.scan_dn(v.urange.max);
+                match (lo, hi) {
+                    (Some(lo), Some(hi)) => {
+                        v.urange = UIntRange {
+                            min: lo,
+                            max: hi,
+                        }
+                    }
+                    _ => v.urange = EMPTY_UINTRANGE,
+                }
+                v.bits = &v.bits & &v.multiple.as_knownbits();
+            }
+            if v == old {
+                break;
+            }
+        }
+        v
+    }
+
+    /// Intersection of both sets.
+    pub fn meet(&self, other: &ValueInfo) -> ValueInfo {
+        ValueInfo {
+            width: self.width,
+            bits: &self.bits & &other.bits,
+            urange: &self.urange & &other.urange,
+            srange: &self.srange & &other.srange,
+            multiple: meet_multiple(&self.multiple, &other.multiple),
+        }
+        .reduce()
+    }
+
+    /// Union of both sets, possibly including values of neither.
+    pub fn join(&self, other: &ValueInfo) -> ValueInfo {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        ValueInfo {
+            width: max(self.width, other.width),
+            bits: &self.bits | &other.bits,
+            urange: &self.urange | &other.urange,
+            srange: &self.srange | &other.srange,
+            multiple: join_multiple(&self.multiple, &other.multiple),
+        }
+        .reduce()
+    }
+
+    /// Joins `other` into `self`, moving any bound that grew to the limit of
+    /// its range. Used to make loops converge.
+    pub fn widen(&self, other: &ValueInfo) -> ValueInfo {
+        let mut v = self.join(other);
+        if self.is_empty() {
+            return v;
+        }
+        let full = ValueInfo::full(v.width);
+        if v.urange.min < self.urange.min {
+            v.urange.min = full.urange.min;
+        }
+        if v.urange.max > self.urange.max {
+            v.urange.max = full.urange.max;
+        }
+        if v.srange.min < self.srange.min {
+            v.srange.min = full.srange.min;
+        }
+        if v.srange.max > self.srange.max {
+            v.srange.max = full.srange.max;
+        }
+        v.reduce()
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Transfer functions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    /// Computes the result of `opc` of `width` applied to `operands`. Missing
+    /// operands are unknown.
+    pub fn transfer(opc: MOpcode, width: WidthSpec, operands: &[ValueInfo]) -> ValueInfo {
+        let unknown = ValueInfo::full(64);
+        let a = operands.get(0).cloned().unwrap_or(unknown);
+        let b = operands.get(1).cloned().unwrap_or(unknown);
+        if a.is_empty() || b.is_empty() && opc.is_binary() {
+            return ValueInfo::empty(width);
+        }
+        let result = match opc {
+            MOpcode::OpConst(v) => return ValueInfo::constant(width, v),
+            MOpcode::OpAdd => a.add(&b, width),
+            MOpcode::OpSub => a.sub(&b, width),
+            MOpcode::OpMul => a.mul(&b, width),
+            MOpcode::OpDiv => a.div(&b, width),
+            MOpcode::OpMod => a.rem(&b, width),
+            MOpcode::OpAnd => a.and(&b, width),
+            MOpcode::OpOr => a.or(&b, width),
+            MOpcode::OpXor => a.xor(&b, width),
+            MOpcode::OpNot => a.not(width),
+            MOpcode::OpLsl => a.shl(&b, width),
+            MOpcode::OpLsr => a.shr(&b, width),
+            MOpcode::OpInc => a.add(&ValueInfo::constant(width, 1), width),
+            MOpcode::OpDec => a.sub(&ValueInfo::constant(width, 1), width),
+            MOpcode::OpNarrow(w) | MOpcode::OpWiden(w) => a.resize(w),
+            MOpcode::OpCmp | MOpcode::OpEq => a.cmp_eq(&b),
+            MOpcode::OpLt => a.cmp_lt(&b),
+            MOpcode::OpGt => b.cmp_lt(&a),
+            MOpcode::OpLteq => b.cmp_lt(&a).not(1),
+            MOpcode::OpGteq => a.cmp_lt(&b).not(1),
+            MOpcode::OpIf => a,
+            _ => ValueInfo::full(width),
+        };
+        // Comparisons are booleans no matter the width of the node.
+        ValueInfo { width: width, ..result }.reduce()
+    }
+
+    fn add(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        if let (Some(x), Some(y)) = (self.as_const(), b.as_const()) {
+            return ValueInfo::constant(width, x.wrapping_add(y));
+        }
+        let mut r = ValueInfo::full(width);
+        r.bits = add_bits(&self.bits, &b.bits, false);
+        match self.urange.max.checked_add(b.urange.max) {
+            Some(hi) if hi <= mask(width) => {
+                r.urange = UIntRange {
+                    min: self.urange.min + b.urange.min,
+                    max: hi,
+                };
+                // No wrap around, so the residues add up.
+                r.multiple = match (self.as_const(), b.as_const()) {
+                    (_, Some(c)) => self.multiple.offset(c),
+                    (Some(c), _) => b.multiple.offset(c),
+                    _ => {
+                        let g = gcd(self.multiple.modulus, b.multiple.modulus);
+                        UIntMultiple {
+                            modulus: g,
+                            residue: (self.multiple.residue + b.multiple.residue) % g,
+                        }
+                    }
+                };
+            }
+            _ => {}
+        }
+        r
+    }
+
+    fn sub(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        if let (Some(x), Some(y)) = (self.as_const(), b.as_const()) {
+            return ValueInfo::constant(width, x.wrapping_sub(y));
+        }
+        let mut r = ValueInfo::full(width);
+        let not_b = KnownBits {
+            zerobits: b.bits.onebits,
+            onebits: b.bits.zerobits,
+        };
+        r.bits = add_bits(&self.bits, &not_b, true);
+        if self.urange.min >= b.urange.max {
+            r.urange = UIntRange {
+                min: self.urange.min - b.urange.max,
+                max: self.urange.max - b.urange.min,
+            };
+        }
+        r
+    }
+
+    fn mul(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        if let (Some(x), Some(y)) = (self.as_const(), b.as_const()) {
+            return ValueInfo::constant(width, x.wrapping_mul(y));
+        }
+        let mut r = ValueInfo::full(width);
+        let tz = trailing_zeros(&self.bits) + trailing_zeros(&b.bits);
+        if tz >= 64 {
+            return ValueInfo::constant(width, 0);
+        }
+        r.bits.zerobits |= (1 << tz) - 1;
+        match self.urange.max.checked_mul(b.urange.max) {
+            Some(hi) if hi <= mask(width) => {
+                r.urange = UIntRange {
+                    min: self.urange.min * b.urange.min,
+                    max: hi,
+                };
+                let scaled = match (self.as_const(), b.as_const()) {
+                    (_, Some(c)) => self.multiple.scale(c),
+                    (Some(c), _) => b.multiple.scale(c),
+                    _ => None,
+                };
+                if let Some(m) = scaled {
+                    r.multiple = m;
+                }
+            }
+            _ => {}
+        }
+        r
+    }
+
+    fn div(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        if b.urange.min > 0 {
+            r.urange = UIntRange {
+                min: self.urange.min / b.urange.max,
+                max: self.urange.max / b.urange.min,
+            };
+        }
+        r
+    }
+
+    fn rem(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        if b.urange.min > 0 {
+            if self.urange.max < b.urange.min {
+                return *self;
+            }
+            r.urange = UIntRange {
+                min: 0,
+                max: min(self.urange.max, b.urange.max - 1),
+            };
+        }
+        r
+    }
+
+    fn and(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        r.bits = KnownBits {
+            zerobits: self.bits.zerobits | b.bits.zerobits,
+            onebits: self.bits.onebits & b.bits.onebits,
+        };
+        r.urange.max = min(self.urange.max, b.urange.max);
+        r
+    }
+
+    fn or(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        r.bits = KnownBits {
+            zerobits: self.bits.zerobits & b.bits.zerobits,
+            onebits: self.bits.onebits | b.bits.onebits,
+        };
+        r.urange.min = max(self.urange.min, b.urange.min);
+        r
+    }
+
+    fn xor(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        r.bits = KnownBits {
+            zerobits: (self.bits.zerobits & b.bits.zerobits) | (self.bits.onebits & b.bits.onebits),
+            onebits: (self.bits.zerobits & b.bits.onebits) | (self.bits.onebits & b.bits.zerobits),
+        };
+        r
+    }
+
+    fn not(&self, width: WidthSpec) -> ValueInfo {
+        let m = mask(width);
+        let mut r = ValueInfo::full(width);
+        r.bits = KnownBits {
+            zerobits: self.bits.onebits,
+            onebits: self.bits.zerobits & m,
+        };
+        if self.urange.max <= m {
+            r.urange = UIntRange {
+                min: m - self.urange.max,
+                max: m - self.urange.min,
+            };
+        }
+        r
+    }
+
+    fn shl(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        let k = match b.as_const() {
+            Some(k) if k < 64 => k as u32,
+            Some(_) => return ValueInfo::constant(width, 0),
+            None => {
+                // At least the smallest shift worth of zeros.
+                let tz = (trailing_zeros(&self.bits) as u64).saturating_add(b.urange.min);
+                if tz >= 64 {
+                    return ValueInfo::constant(width, 0);
+                }
+                r.bits.zerobits |= (1 << tz) - 1;
+                return r;
+            }
+        };
+        r.bits = KnownBits {
+            zerobits: (self.bits.zerobits << k) | ((1 << k) - 1),
+            onebits: self.bits.onebits << k,
+        };
+        if self.urange.max.leading_zeros() >= k && (self.urange.max << k) <= mask(width) {
+            r.urange = UIntRange {
+                min: self.urange.min << k,
+                max: self.urange.max << k,
+            };
+        }
+        r
+    }
+
+    fn shr(&self, b: &ValueInfo, width: WidthSpec) -> ValueInfo {
+        let mut r = ValueInfo::full(width);
+        match b.as_const() {
+            Some(k) if k < 64 => {
+                let k = k as u32;
+                r.bits = KnownBits {
+                    zerobits: (self.bits.zerobits >> k) | !(u64::max_value() >> k),
+                    onebits: self.bits.onebits >> k,
+                };
+                r.urange = UIntRange {
+                    min: self.urange.min >> k,
+                    max: self.urange.max >> k,
+                };
+            }
+            Some(_) => return ValueInfo::constant(width, 0),
+            None => {
+                let k = min(b.urange.min, 63) as u32;
+                r.urange = UIntRange {
+                    min: 0,
+                    max: self.urange.max >> k,
+                };
+            }
+        }
+        r
+    }
+
+    // Truncates or zero extends the value to `width` bits.
+    fn resize(&self, width: WidthSpec) -> ValueInfo {
+        if self.urange.max <= mask(width) {
+            return ValueInfo { width: width, ..*self };
+        }
+        let mut r = ValueInfo::full(width);
+        r.bits = KnownBits {
+            zerobits: self.bits.zerobits | !mask(width),
+            onebits: self.bits.onebits & mask(width),
+        };
+        r
+    }
+
+    fn cmp_eq(&self, b: &ValueInfo) -> ValueInfo {
+        if let (Some(x), Some(y)) = (self.as_const(), b.as_const()) {
+            return ValueInfo::constant(1, (x == y) as u64);
+        }
+        if self.meet(b).is_empty() {
+            return ValueInfo::constant(1, 0);
+        }
+        ValueInfo::boolean()
+    }
+
+    fn cmp_lt(&self, b: &ValueInfo) -> ValueInfo {
+        if self.urange.max < b.urange.min {
+            ValueInfo::constant(1, 1)
+        } else if self.urange.min >= b.urange.max {
+            ValueInfo::constant(1, 0)
+        } else {
+            ValueInfo::boolean()
+        }
+    }
+
+    /// Refines `self` knowing that `self opc other` evaluated to `taken`.
+    /// Only unsigned comparisons are understood.
+    pub fn assume(&self, opc: MOpcode, other: &ValueInfo, taken: bool) -> ValueInfo {
+        let m = mask(self.width);
+        let (lo, hi) = (other.urange.min, other.urange.max);
+        let bound = match (opc, taken) {
+            (MOpcode::OpCmp, true) | (MOpcode::OpEq, true) => *other,
+            (MOpcode::OpLt, true) | (MOpcode::OpGteq, false) => {
+                if hi == 0 {
+                    return ValueInfo::empty(self.width);
+                }
+                ValueInfo::range(self.width, 0, hi - 1)
+            }
+            (MOpcode::OpLt, false) | (MOpcode::OpGteq, true) => ValueInfo::range(self.width, lo, m),
+            (MOpcode::OpGt, true) | (MOpcode::OpLteq, false) => {
+                if lo == m {
+                    return ValueInfo::empty(self.width);
+                }
+                ValueInfo::range(self.width, lo + 1, m)
+            }
+            (MOpcode::OpGt, false) | (MOpcode::OpLteq, true) => ValueInfo::range(self.width, 0, hi),
+            _ => return *self,
+        };
+        self.meet(&ValueInfo { width: self.width, ..bound })
+    }
+}
+
+impl UIntMultiple {
+    // The multiple shifted by `c`, assuming no wrap around.
+    fn offset(&self, c: u64) -> UIntMultiple {
+        if self.modulus <= 1 {
+            return *self;
+        }
+        UIntMultiple {
+            modulus: self.modulus,
+            residue: (self.residue + c % self.modulus) % self.modulus,
+        }
+    }
+
+    // The multiple scaled by `c`, assuming no wrap around.
+    fn scale(&self, c: u64) -> Option<UIntMultiple> {
+        if c == 0 {
+            return None;
+        }
+        self.modulus.checked_mul(c).map(|m| {
+            UIntMultiple {
+                modulus: m,
+                residue: self.residue * c,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MOpcode;
+    use analysis::valueset::ValueSet;
+
+    #[test]
+    fn scaled_index() {
+        // (i & 7) * 8 + 0x1000
+        let i = ValueInfo::full(64);
+        let masked = ValueInfo::transfer(MOpcode::OpAnd, 64, &[i, ValueInfo::constant(64, 7)]);
+        assert_eq!(masked.urange.max, 7);
+        let scaled = ValueInfo::transfer(MOpcode::OpMul, 64, &[masked, ValueInfo::constant(64, 8)]);
+        let addr = ValueInfo::transfer(MOpcode::OpAdd,
+                                       64,
+                                       &[scaled, ValueInfo::constant(64, 0x1000)]);
+        assert_eq!(addr.urange.min, 0x1000);
+        assert_eq!(addr.urange.max, 0x1038);
+        assert_eq!(addr.count(), 8);
+        assert!(addr.contains(0x1010));
+        assert!(!addr.contains(0x1011));
+    }
+
+    #[test]
+    fn compare_and_assume() {
+        let x = ValueInfo::range(32, 0, 100);
+        let five = ValueInfo::constant(32, 5);
+        let lt = ValueInfo::transfer(MOpcode::OpLt, 1, &[x, five]);
+        assert_eq!(lt.as_const(), None);
+        let below = x.assume(MOpcode::OpLt, &five, true);
+        assert_eq!(below.urange.max, 4);
+        let lt = ValueInfo::transfer(MOpcode::OpLt, 1, &[below, five]);
+        assert_eq!(lt.as_const(), Some(1));
+        let above = x.assume(MOpcode::OpLt, &five, false);
+        assert_eq!(above.urange.min, 5);
+    }
+
+    #[test]
+    fn narrow_and_widen() {
+        let x = ValueInfo::range(64, 0x1f0, 0x1ff);
+        let low = ValueInfo::transfer(MOpcode::OpNarrow(8), 8, &[x]);
+        assert_eq!(low.urange.min, 0xf0);
+        assert_eq!(low.urange.max, 0xff);
+        let wide = ValueInfo::transfer(MOpcode::OpWiden(64), 64, &[low]);
+        assert_eq!(wide.urange.max, 0xff);
+    }
+
+    #[test]
+    fn widening_terminates() {
+        let mut v = ValueInfo::constant(64, 0);
+        let one = ValueInfo::constant(64, 1);
+        for _ in 0..3 {
+            let next = ValueInfo::transfer(MOpcode::OpAdd, 64, &[v, one]);
+            v = v.widen(&next);
+        }
+        assert_eq!(v.urange.max, u64::max_value());
+        assert_eq!(v.urange.min, 0);
+    }
+}
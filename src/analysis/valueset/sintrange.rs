@@ -6,6 +6,7 @@
 // except according to those terms.
 
 use super::{KnownBits, SIntRange, UIntMultiple, UIntRange, ValueSet};
+use super::EMPTY_UINTRANGE;
 use std::cmp::{max, min};
 use std::ops::{BitAnd, BitOr};
 
@@ -17,21 +18,30 @@ impl ValueSet<u64> for SIntRange {
 
 /// A value set that includes all i64 values between a minimum and a maximum
 impl SIntRange {
-    // TODO
-    fn as_knownbits(&self) -> KnownBits {
-        KnownBits {
-            zerobits: 0,
-            onebits: 0,
-        }
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
     }
-    fn as_umultiple(&self) -> UIntMultiple {
-        UIntMultiple {
-            modulus: 0,
-            residue: 0,
-        }
+    pub fn as_knownbits(&self) -> KnownBits {
+        self.as_urange().as_knownbits()
+    }
+    pub fn as_umultiple(&self) -> UIntMultiple {
+        self.as_urange().as_umultiple()
     }
-    fn as_urange(&self) -> UIntRange {
-        UIntRange { min: 0, max: 0 }
+    pub fn as_urange(&self) -> UIntRange {
+        if self.is_empty() {
+            return EMPTY_UINTRANGE;
+        }
+        if (self.min >= 0) == (self.max >= 0) {
+            UIntRange {
+                min: self.min as u64,
+                max: self.max as u64,
+            }
+        } else {
+            UIntRange {
+                min: u64::min_value(),
+                max: u64::max_value(),
+            }
+        }
     }
 }
 
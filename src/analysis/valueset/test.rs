@@ -111,6 +111,18 @@ fn test_srange_membership() {
                                     6]);
 }
 
+#[test]
+fn test_srange_conversions() {
+    // Nothing is known of a range that crosses zero.
+    let s1_1 = SIntRange { min: -1, max: 1 }.as_knownbits();
+    assert_eq!(s1_1.zerobits, 0);
+    assert_eq!(s1_1.onebits, 0);
+
+    let s8_5 = SIntRange { min: -8, max: -5 }.as_knownbits();
+    assert_eq!(s8_5.zerobits, 0x4);
+    assert_eq!(s8_5.onebits, 0xfffffffffffffff8);
+}
+
 #[test]
 fn test_uintmultiple_conversions() {
 
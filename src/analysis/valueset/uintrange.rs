@@ -6,7 +6,9 @@
 // except according to those terms.
 
 use super::{KnownBits, SIntRange, UIntMultiple, UIntRange, ValueSet};
+use super::{EMPTY_SINTRANGE, EMPTY_UINTMULTIPLE};
 use std::cmp::{max, min};
+use super::math::bitsmear;
 use std::ops::{BitAnd, BitOr};
 
 impl ValueSet<u64> for UIntRange {
@@ -17,21 +19,48 @@ impl ValueSet<u64> for UIntRange {
 
 /// A value set that includes all u64 values between a minimum and a maximum
 impl UIntRange {
-    // TODO
-    fn as_knownbits(&self) -> KnownBits {
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+    /// The bits above the highest bit in which min and max differ are shared
+    /// by all values of the range.
+    pub fn as_knownbits(&self) -> KnownBits {
+        if self.is_empty() {
+            return KnownBits {
+                zerobits: !0,
+                onebits: !0,
+            };
+        }
+        let fixedbits = !bitsmear(self.min ^ self.max);
         KnownBits {
-            zerobits: 0,
-            onebits: 0,
+            zerobits: !self.min & fixedbits,
+            onebits: self.min & fixedbits,
         }
     }
-    fn as_umultiple(&self) -> UIntMultiple {
+    pub fn as_umultiple(&self) -> UIntMultiple {
+        if self.is_empty() {
+            return EMPTY_UINTMULTIPLE;
+        }
         UIntMultiple {
-            modulus: 0,
+            modulus: 1,
             residue: 0,
         }
     }
-    fn as_srange(&self) -> SIntRange {
-        SIntRange { min: 0, max: 0 }
+    pub fn as_srange(&self) -> SIntRange {
+        if self.is_empty() {
+            return EMPTY_SINTRANGE;
+        }
+        if (self.min as i64 >= 0) == (self.max as i64 >= 0) {
+            SIntRange {
+                min: self.min as i64,
+                max: self.max as i64,
+            }
+        } else {
+            SIntRange {
+                min: i64::min_value(),
+                max: i64::max_value(),
+            }
+        }
     }
 }
 
@@ -0,0 +1,1067 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Walks a structured control flow tree and prints the basic blocks it
+//! references as C.
+//!
+//! Values are either folded into the expression that uses them or stored in
+//! a local declared at the top of the function. A value is folded if it is
+//! free of side effects and has exactly one user in the same basic block.
+//! Phi nodes become locals that are assigned at the end of every predecessor.
+
+use std::collections::{HashMap, HashSet};
+use petgraph::graph::NodeIndex;
+
+use analysis::layout::{self, FieldType, Layout, Layouts};
+use analysis::stack::{StackFrame, StackVariable};
+use backend::scf::{AST, Condition, LoopType};
+use frontend::esilssa::ARGUMENTS;
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::{SSA, SSAStorage, ValueType};
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssastorage::NodeData;
+
+const INDENT: &'static str = "    ";
+
+/// Returns the C type used to declare a value of type `vt`.
+pub fn c_type(vt: ValueType) -> String {
+    match vt {
+        ValueType::Integer { width: 0 } => "void".to_owned(),
+        ValueType::Integer { width: 1 } | ValueType::Bool => "bool".to_owned(),
+        ValueType::Integer { width } => format!("uint{}_t", c_width(width)),
+        ValueType::Signed { width } => format!("int{}_t", c_width(width)),
+        // The type pointed to is not known.
+        ValueType::Pointer { .. } => "void *".to_owned(),
+        ValueType::Float { width: 32 } => "float".to_owned(),
+        ValueType::Float { width: 80 } => "long double".to_owned(),
+        ValueType::Float { .. } => "double".to_owned(),
+    }
+}
+
+// Declaration of `name` with the C type `ty`.
+fn c_decl(ty: String, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+// Type of a pointer to a value of type `vt`.
+fn c_pointer(vt: ValueType) -> String {
+    let t = c_type(vt);
+    if t.ends_with('*') {
+        format!("{}*", t)
+    } else {
+        format!("{} *", t)
+    }
+}
+
+// Type of the elements of an array, which are `stride` bytes apart.
+fn element_type(element: &Layout, stride: u64) -> String {
+    match element.as_scalar(stride) {
+        Some(width) => c_type(From::from(width)),
+        None => format!("struct {}", element.name),
+    }
+}
+
+// Definition of the struct `layout`, padded to `size` bytes. Fields that
+// overlap a previous one are left as comments.
+fn c_struct(layout: &Layout, size: u64) -> String {
+    let mut s = format!("struct {} {{\n", layout.name);
+    let mut end = 0;
+    for (i, field) in layout.fields.iter().enumerate() {
+        let (decl, extent) = match field.ty {
+            FieldType::Scalar(width) => {
+                (format!("{} {};", c_type(From::from(width)), field.name()), field.size())
+            }
+            FieldType::Array { stride, ref element } => {
+                // The array extends up to the next field, or is the last.
+                let length = match layout.fields.get(i + 1) {
+                    Some(next) if next.offset > field.offset => {
+                        Some(::std::cmp::max(1, (next.offset - field.offset) / stride))
+                    }
+                    _ => None,
+                };
+                let decl = format!("{} {}[{}];",
+                                   element_type(element, stride),
+                                   field.name(),
+                                   length.map_or(String::new(), |l| format!("{}", l)));
+                (decl, length.unwrap_or(1) * stride)
+            }
+        };
+        if field.offset < end {
+            s.push_str(&format!("{}/* {} */\n", INDENT, decl));
+            continue;
+        }
+        if field.offset > end {
+            s.push_str(&format!("{}uint8_t pad_{:x}[{}];\n", INDENT, end, field.offset - end));
+        }
+        s.push_str(&format!("{}{}\n", INDENT, decl));
+        end = field.offset + extent;
+    }
+    if size > end {
+        s.push_str(&format!("{}uint8_t pad_{:x}[{}];\n", INDENT, end, size - end));
+    }
+    s.push_str("};\n");
+    s
+}
+
+// Width of the smallest standard C integer type that holds `width` bits.
+fn c_width(width: WidthSpec) -> WidthSpec {
+    match width {
+        2...8 => 8,
+        9...16 => 16,
+        17...32 => 32,
+        33...64 => 64,
+        _ => width,
+    }
+}
+
+fn c_const(v: u64) -> String {
+    if v < 10 {
+        format!("{}", v)
+    } else {
+        format!("0x{:x}", v)
+    }
+}
+
+// Removes one pair of parentheses enclosing the whole of `s`.
+fn strip_parens(s: String) -> String {
+    if !s.starts_with('(') || !s.ends_with(')') {
+        return s;
+    }
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            if i == s.len() - 1 {
+                return s[1..i].to_owned();
+            }
+            break;
+        }
+    }
+    s
+}
+
+pub struct CWriter<'a> {
+    ssa: &'a SSAStorage,
+    name: String,
+    /// Values that are printed inline at their use.
+    folded: HashSet<NodeIndex>,
+    /// Blocks that are the target of a `goto`.
+    labels: HashSet<NodeIndex>,
+    /// Number of times each selector is tested by the control flow.
+    tests: HashMap<NodeIndex, usize>,
+    /// Stack variables of the function, if recovered.
+    frame: Option<&'a StackFrame<SSAStorage>>,
+    /// Layouts of the structs and arrays pointed to, if recovered.
+    layouts: Option<&'a Layouts<SSAStorage>>,
+    out: String,
+    depth: usize,
+}
+
+impl<'a> CWriter<'a> {
+    pub fn new(ssa: &'a SSAStorage, name: String) -> CWriter<'a> {
+        let name = name.chars()
+                       .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                       .collect();
+        CWriter {
+            ssa: ssa,
+            name: name,
+            folded: HashSet::new(),
+            labels: HashSet::new(),
+            tests: HashMap::new(),
+            frame: None,
+            layouts: None,
+            out: String::new(),
+            depth: 0,
+        }
+    }
+
+    /// Print stack accesses as the variables of `frame`.
+    pub fn set_frame(&mut self, frame: &'a StackFrame<SSAStorage>) {
+        self.frame = Some(frame);
+    }
+
+    /// Print accesses through pointers to the structs and arrays of
+    /// `layouts`, and define their types.
+    pub fn set_layouts(&mut self, layouts: &'a Layouts<SSAStorage>) {
+        self.layouts = Some(layouts);
+    }
+
+    /// Emit the function described by the SSA, structured as `ast`.
+    pub fn emit(&mut self, ast: &AST<NodeIndex, NodeIndex>) -> String {
+        self.out = String::new();
+        self.depth = 0;
+        self.labels = HashSet::new();
+        self.tests = HashMap::new();
+        self.collect_labels(ast);
+        self.compute_folded();
+
+        for definition in self.definitions() {
+            self.out.push_str(&definition);
+            self.out.push('\n');
+        }
+        let header = format!("void {}({}) {{", self.name, self.params().join(", "));
+        self.line(header);
+        self.depth += 1;
+        let locals = self.locals();
+        for l in locals.iter() {
+            self.line(l.clone());
+        }
+        if !locals.is_empty() {
+            self.out.push('\n');
+        }
+        self.emit_ast(ast);
+        self.depth -= 1;
+        self.line("}".to_owned());
+        self.out.clone()
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Value helpers.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn node(&self, n: NodeIndex) -> &NodeData {
+        &self.ssa.g[self.ssa.internal(&n)]
+    }
+
+    fn value_type(&self, n: NodeIndex) -> ValueType {
+        self.ssa
+            .get_node_data(&n)
+            .map(|nd| nd.vt)
+            .unwrap_or(ValueType::Integer { width: 0 })
+    }
+
+    fn has_value(&self, n: NodeIndex) -> bool {
+        self.value_type(n).width() > 0
+    }
+
+    fn operand(&self, n: NodeIndex, index: u8) -> Option<NodeIndex> {
+        self.ssa
+            .get_sparse_operands(&n)
+            .iter()
+            .find(|&&(i, _)| i == index)
+            .map(|&(_, v)| v)
+    }
+
+    // The stack variable accessed by a load or store, if it can be named.
+    fn stack_variable(&self, n: NodeIndex, vt: ValueType) -> Option<&'a StackVariable> {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return None,
+        };
+        let width = vt.width();
+        self.operand(n, 0)
+            .and_then(|addr| frame.variable_at(&addr))
+            .and_then(|var| if !var.escapes && var.width == width { Some(var) } else { None })
+    }
+
+    // Uses of a value by other values. The register state is not printed.
+    fn users(&self, n: NodeIndex) -> Vec<NodeIndex> {
+        self.ssa
+            .uses_of(n)
+            .into_iter()
+            .filter(|u| {
+                match *self.node(*u) {
+                    NodeData::RegisterState => false,
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+
+    fn compute_folded(&mut self) {
+        let mut folded = HashSet::new();
+        for n in self.ssa.nodes() {
+            let opc = match *self.node(n) {
+                NodeData::Op(opc, _) => opc,
+                _ => continue,
+            };
+            let foldable = match opc {
+                MOpcode::OpConst(_) => true,
+                MOpcode::OpStore(..) | MOpcode::OpCall | MOpcode::OpLoad(..) |
+                MOpcode::OpUnknown => false,
+                // A selector tested more than once is evaluated only once,
+                // before the phi copies of its block may change its operands.
+                _ if self.ssa.is_selector(&n) => {
+                    let block = self.ssa.selects_for(&n);
+                    self.users(n).is_empty() && self.phi_copies(block).is_empty() &&
+                    self.tests.get(&n).cloned().unwrap_or(0) <= 1
+                }
+                _ => {
+                    let users = self.users(n);
+                    users.len() == 1 &&
+                    self.ssa.get_block(&users[0]) == self.ssa.get_block(&n) &&
+                    match *self.node(users[0]) {
+                        NodeData::Op(_, _) => true,
+                        _ => false,
+                    }
+                }
+            };
+            if foldable {
+                folded.insert(n);
+            }
+        }
+        self.folded = folded;
+    }
+
+    fn var_name(&self, n: NodeIndex) -> String {
+        match *self.node(n) {
+            NodeData::Phi(_, _) => format!("phi_{}", n.index()),
+            _ => format!("tmp_{}", n.index()),
+        }
+    }
+
+    fn params(&self) -> Vec<String> {
+        let mut params = Vec::new();
+        let mut nodes = self.ssa.nodes();
+        nodes.sort();
+        for n in nodes {
+            if let NodeData::Comment(vt, ref name) = *self.node(n) {
+                let used = self.users(n).iter().any(|u| {
+                    match *self.node(*u) {
+                        NodeData::Op(_, _) | NodeData::Phi(_, _) => true,
+                        _ => false,
+                    }
+                });
+                if used && self.has_value(n) {
+                    params.push(c_decl(self.declared_type(n, vt), name));
+                }
+            }
+        }
+        params
+    }
+
+    fn locals(&self) -> Vec<String> {
+        let mut locals = Vec::new();
+        let mut nodes = self.ssa.nodes();
+        nodes.sort();
+        for n in nodes {
+            let vt = match *self.node(n) {
+                NodeData::Op(MOpcode::OpStore(..), _) => continue,
+                NodeData::Op(_, vt) if !self.folded.contains(&n) => vt,
+                NodeData::Phi(vt, _) => vt,
+                _ => continue,
+            };
+            if self.has_value(n) {
+                locals.push(format!("{};", c_decl(self.declared_type(n, vt), &self.var_name(n))));
+            }
+        }
+        if let Some(frame) = self.frame {
+            for var in frame.variables().iter().filter(|v| !v.escapes && !v.promoted) {
+                locals.push(format!("{} {};", c_type(From::from(var.width)), var.name));
+            }
+        }
+        locals
+    }
+
+    // Type of a value, which points to a struct or array if it is the base of
+    // a layout.
+    fn declared_type(&self, n: NodeIndex, vt: ValueType) -> String {
+        match self.layouts.and_then(|l| l.layout_of(&n)) {
+            Some(layout) => {
+                match layout.as_array() {
+                    Some((stride, element)) => format!("{} *", element_type(element, stride)),
+                    None => format!("struct {} *", layout.name),
+                }
+            }
+            None => c_type(vt),
+        }
+    }
+
+    // Definitions of the structs of the layouts.
+    fn definitions(&self) -> Vec<String> {
+        let layouts = match self.layouts {
+            Some(layouts) => layouts.layouts(),
+            None => return Vec::new(),
+        };
+        let mut definitions = Vec::new();
+        for layout in layouts {
+            // Element types are defined before the struct holding them.
+            for field in &layout.fields {
+                if let FieldType::Array { stride, ref element } = field.ty {
+                    if element.as_scalar(stride).is_none() {
+                        definitions.push(c_struct(element, stride));
+                    }
+                }
+            }
+            if layout.as_array().is_none() {
+                definitions.push(c_struct(layout, layout.size()));
+            }
+        }
+        definitions
+    }
+
+    // The field accessed by a load or store, if it can be named.
+    fn field_access(&self, n: NodeIndex) -> Option<String> {
+        let layouts = match self.layouts {
+            Some(layouts) => layouts,
+            None => return None,
+        };
+        let access = match layouts.access(&n) {
+            Some(access) => access,
+            None => return None,
+        };
+        let layout = match layouts.layout_of(&access.base) {
+            Some(layout) => layout,
+            None => return None,
+        };
+        let base = self.expr(access.base);
+        let (index, offset) = match access.index {
+            Some(index) => index,
+            None => return Some(format!("{}->{}", base, layout::field_name(access.offset))),
+        };
+        let index = strip_parens(self.expr(index));
+        let (element, stride, array) = match layout.as_array() {
+            Some((stride, element)) => (element, stride, format!("{}[{}]", base, index)),
+            None => {
+                match layout.field_at(access.offset).map(|f| &f.ty) {
+                    Some(&FieldType::Array { stride, ref element }) => {
+                        let field = layout::field_name(access.offset);
+                        (element, stride, format!("{}->{}[{}]", base, field, index))
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        if element.as_scalar(stride).is_some() {
+            Some(array)
+        } else {
+            Some(format!("{}.{}", array, layout::field_name(offset)))
+        }
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Expressions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn expr(&self, n: NodeIndex) -> String {
+        match *self.node(n) {
+            NodeData::Op(MOpcode::OpConst(v), _) => c_const(v),
+            NodeData::Op(opc, vt) => {
+                if self.folded.contains(&n) {
+                    self.op_expr(n, opc, vt)
+                } else {
+                    self.var_name(n)
+                }
+            }
+            NodeData::Phi(_, _) => self.var_name(n),
+            NodeData::Comment(_, ref name) => name.clone(),
+            NodeData::Undefined(_) => "0 /* undefined */".to_owned(),
+            _ => "0 /* invalid */".to_owned(),
+        }
+    }
+
+    fn arg(&self, n: NodeIndex, index: u8) -> String {
+        self.operand(n, index)
+            .map(|a| self.expr(a))
+            .unwrap_or("0 /* missing */".to_owned())
+    }
+
+    fn call_target(&self, n: NodeIndex) -> String {
+        let target = self.operand(n, 0);
+        if let Some(t) = target {
+            if let NodeData::Op(MOpcode::OpConst(addr), _) = *self.node(t) {
+                return format!("fcn_{:08x}", addr);
+            }
+        }
+        format!("((void (*)()){})", self.arg(n, 0))
+    }
+
+    fn op_expr(&self, n: NodeIndex, opc: MOpcode, vt: ValueType) -> String {
+        match opc {
+            MOpcode::OpNot => format!("!{}", self.arg(n, 0)),
+            MOpcode::OpIf => self.arg(n, 0),
+            MOpcode::OpNarrow(_) | MOpcode::OpWiden(_) | MOpcode::OpItoF(_) |
+            MOpcode::OpFtoI(_) | MOpcode::OpFConv(_) => {
+                format!("({}){}", c_type(vt), self.arg(n, 0))
+            }
+            MOpcode::OpSItoF(_) => {
+                let width = self.operand(n, 0).map_or(64, |o| self.value_type(o).width());
+                let signed = c_type(ValueType::Signed { width: width });
+                format!("({})({}){}", c_type(vt), signed, self.arg(n, 0))
+            }
+            MOpcode::OpFAdd | MOpcode::OpFSub | MOpcode::OpFMul | MOpcode::OpFDiv |
+            MOpcode::OpFCmp | MOpcode::OpFLt | MOpcode::OpFLteq => {
+                let op = match opc {
+                    MOpcode::OpFAdd => "+",
+                    MOpcode::OpFSub => "-",
+                    MOpcode::OpFMul => "*",
+                    MOpcode::OpFDiv => "/",
+                    MOpcode::OpFCmp => "==",
+                    MOpcode::OpFLt => "<",
+                    _ => "<=",
+                };
+                format!("({} {} {})", self.arg(n, 0), op, self.arg(n, 1))
+            }
+            MOpcode::OpAsr | MOpcode::OpSDiv | MOpcode::OpSMod => {
+                let op = match opc {
+                    MOpcode::OpAsr => ">>",
+                    MOpcode::OpSDiv => "/",
+                    _ => "%",
+                };
+                let signed = c_type(ValueType::Signed { width: vt.width() });
+                let rhs = if opc == MOpcode::OpAsr {
+                    self.arg(n, 1)
+                } else {
+                    format!("({}){}", signed, self.arg(n, 1))
+                };
+                format!("(({}){} {} {})", signed, self.arg(n, 0), op, rhs)
+            }
+            MOpcode::OpRol | MOpcode::OpRor => {
+                let (op, back) = if opc == MOpcode::OpRol {
+                    ("<<", ">>")
+                } else {
+                    (">>", "<<")
+                };
+                let value = self.arg(n, 0);
+                let amount = self.arg(n, 1);
+                format!("(({} {} {}) | ({} {} ({} - {})))",
+                        value,
+                        op,
+                        amount,
+                        value,
+                        back,
+                        vt.width(),
+                        amount)
+            }
+            MOpcode::OpLoad(..) => {
+                if let Some(var) = self.stack_variable(n, vt) {
+                    return var.name.clone();
+                }
+                if let Some(field) = self.field_access(n) {
+                    return field;
+                }
+                format!("*({})({})", c_pointer(vt), strip_parens(self.arg(n, 0)))
+            }
+            MOpcode::OpStore(..) => {
+                let vt = self.operand(n, 1)
+                             .map(|v| self.value_type(v))
+                             .unwrap_or(vt);
+                if let Some(var) = self.stack_variable(n, vt) {
+                    return format!("{} = {}", var.name, strip_parens(self.arg(n, 1)));
+                }
+                if let Some(field) = self.field_access(n) {
+                    return format!("{} = {}", field, strip_parens(self.arg(n, 1)));
+                }
+                format!("*({})({}) = {}",
+                        c_pointer(vt),
+                        strip_parens(self.arg(n, 0)),
+                        strip_parens(self.arg(n, 1)))
+            }
+            MOpcode::OpCall => {
+                let mut args = self.ssa.get_sparse_operands(&n);
+                args.sort_by_key(|&(i, _)| i);
+                let args = args.iter()
+                               .filter(|&&(i, _)| i >= ARGUMENTS)
+                               .map(|&(_, a)| strip_parens(self.expr(a)))
+                               .collect::<Vec<_>>();
+                format!("{}({})", self.call_target(n), args.join(", "))
+            }
+            _ if opc.is_binary() => {
+                format!("({} {} {})", self.arg(n, 0), opc.to_string(), self.arg(n, 1))
+            }
+            _ => format!("0 /* {} */", opc.to_string()),
+        }
+    }
+
+    fn statement(&self, n: NodeIndex) -> Option<String> {
+        match *self.node(n) {
+            NodeData::Op(MOpcode::OpConst(_), _) |
+            NodeData::Op(MOpcode::OpMemory, _) => None,
+            NodeData::Op(_, _) if self.folded.contains(&n) => None,
+            NodeData::Op(MOpcode::OpUnknown, _) => Some("/* unknown effect */".to_owned()),
+            NodeData::Op(opc, vt) => {
+                let e = self.op_expr(n, opc, vt);
+                let store = match opc {
+                    MOpcode::OpStore(..) => true,
+                    _ => false,
+                };
+                if store || !self.has_value(n) {
+                    Some(format!("{};", e))
+                } else {
+                    Some(format!("{} = {};", self.var_name(n), strip_parens(e)))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Blocks and phi resolution.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    // Expressions of the block ordered so that operands come before their
+    // users.
+    fn block_order(&self, b: NodeIndex) -> Vec<NodeIndex> {
+        let mut exprs = self.ssa.exprs_in(&b);
+        exprs.sort();
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for e in exprs {
+            self.visit(e, b, &mut visited, &mut order);
+        }
+        order
+    }
+
+    fn visit(&self,
+             n: NodeIndex,
+             b: NodeIndex,
+             visited: &mut HashSet<NodeIndex>,
+             order: &mut Vec<NodeIndex>) {
+        if !visited.insert(n) {
+            return;
+        }
+        let mut args = self.ssa.args_of(n);
+        args.sort();
+        for arg in args {
+            if self.ssa.is_expr(&arg) && self.ssa.get_block(&arg) == b {
+                self.visit(arg, b, visited, order);
+            }
+        }
+        order.push(n);
+    }
+
+    // Finds the operand of `phi` that reaches the end of `pred` by walking
+    // backwards from `pred` until a block defining one of the operands is
+    // found.
+    fn phi_source(&self, phi: NodeIndex, pred: NodeIndex) -> Option<NodeIndex> {
+        let operands = self.ssa.args_of(phi);
+        let mut visited = HashSet::new();
+        let mut queue = vec![pred];
+        while !queue.is_empty() {
+            let b = queue.remove(0);
+            if !visited.insert(b) {
+                continue;
+            }
+            if let Some(op) = operands.iter().find(|op| self.ssa.get_block(op) == b) {
+                return Some(*op);
+            }
+            queue.extend(self.ssa.preds_of(b));
+        }
+        None
+    }
+
+    // Assignments to the phis of the successors of `b` as (phi, source).
+    fn phi_copies(&self, b: NodeIndex) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut copies = Vec::new();
+        for succ in self.ssa.succs_of(b) {
+            let mut phis = self.ssa.get_phis(&succ);
+            phis.sort();
+            for phi in phis {
+                if let Some(src) = self.phi_source(phi, b) {
+                    if src != phi && self.has_value(phi) {
+                        copies.push((phi, src));
+                    }
+                }
+            }
+        }
+        copies
+    }
+
+    // The copies happen in parallel. Order them so that no phi is
+    // overwritten before it is read, and break cycles with a temporary.
+    fn emit_copies(&mut self, copies: Vec<(NodeIndex, NodeIndex)>) {
+        let mut pending = copies.iter()
+                                .map(|&(dst, src)| {
+                                    let reads = match *self.node(src) {
+                                        NodeData::Phi(_, _) => Some(src),
+                                        _ => None,
+                                    };
+                                    (dst, self.expr(src), reads)
+                                })
+                                .collect::<Vec<_>>();
+        while !pending.is_empty() {
+            let ready = (0..pending.len()).find(|&i| {
+                let dst = pending[i].0;
+                !pending.iter().any(|&(_, _, reads)| reads == Some(dst))
+            });
+            match ready {
+                Some(i) => {
+                    let (dst, src, _) = pending.remove(i);
+                    let l = format!("{} = {};", self.var_name(dst), strip_parens(src));
+                    self.line(l);
+                }
+                None => {
+                    let dst = pending[0].0;
+                    let save = format!("swp_{}", dst.index());
+                    let l = format!("{} {} = {};",
+                                    c_type(self.value_type(dst)),
+                                    save,
+                                    self.var_name(dst));
+                    self.line(l);
+                    for copy in pending.iter_mut() {
+                        if copy.2 == Some(dst) {
+                            copy.1 = save.clone();
+                            copy.2 = None;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn label(&self, b: NodeIndex) -> String {
+        match *self.node(b) {
+            NodeData::BasicBlock(ref info) => format!("bb_{:x}", info.addr),
+            _ => format!("bb_n{}", b.index()),
+        }
+    }
+
+    fn emit_block(&mut self, b: NodeIndex) {
+        if self.labels.contains(&b) {
+            let l = format!("{}: ;", self.label(b));
+            self.depth -= 1;
+            self.line(l);
+            self.depth += 1;
+        }
+        for n in self.block_order(b) {
+            if let Some(s) = self.statement(n) {
+                self.line(s);
+            }
+        }
+        let copies = self.phi_copies(b);
+        self.emit_copies(copies);
+        if self.ssa.get_unconditional(&b) == self.ssa.exit_node() {
+            self.line("return;".to_owned());
+        }
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Control flow.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn count_tests(&mut self, c: &Condition<NodeIndex>) {
+        match *c {
+            Condition::True => {}
+            Condition::Value(sel) => *self.tests.entry(sel).or_insert(0) += 1,
+            Condition::Not(ref c) => self.count_tests(c),
+            Condition::And(ref cs) | Condition::Or(ref cs) => {
+                for c in cs {
+                    self.count_tests(c);
+                }
+            }
+        }
+    }
+
+    // Collects the targets of gotos and counts the tests of selectors.
+    fn collect_labels(&mut self, ast: &AST<NodeIndex, NodeIndex>) {
+        match *ast {
+            AST::Goto(b) => {
+                if b != self.ssa.exit_node() {
+                    self.labels.insert(b);
+                }
+            }
+            AST::Seq(ref v) => {
+                for a in v {
+                    self.collect_labels(a);
+                }
+            }
+            AST::Cond(ref c, ref t, ref f) => {
+                self.count_tests(c);
+                self.collect_labels(t);
+                self.collect_labels(f);
+            }
+            AST::Loop(lt, ref c, ref body) => {
+                if lt != LoopType::ForEver {
+                    self.count_tests(c);
+                }
+                self.collect_labels(body);
+            }
+            AST::Switch(sel, ref cases, ref default) => {
+                *self.tests.entry(sel).or_insert(0) += 1;
+                for case in cases.values() {
+                    self.collect_labels(case);
+                }
+                self.collect_labels(default);
+            }
+            AST::Unit(_) | AST::Break => {}
+        }
+    }
+
+    fn cond(&self, c: &Condition<NodeIndex>) -> String {
+        strip_parens(self.cond_expr(c))
+    }
+
+    fn cond_expr(&self, c: &Condition<NodeIndex>) -> String {
+        match *c {
+            Condition::True => "1".to_owned(),
+            Condition::Value(sel) => self.expr(sel),
+            Condition::Not(ref c) => {
+                let e = self.cond_expr(c);
+                if e.starts_with('(') {
+                    format!("!{}", e)
+                } else {
+                    format!("!({})", e)
+                }
+            }
+            Condition::And(ref cs) => {
+                let es = cs.iter().map(|c| self.cond_expr(c)).collect::<Vec<_>>();
+                format!("({})", es.join(" && "))
+            }
+            Condition::Or(ref cs) => {
+                let es = cs.iter().map(|c| self.cond_expr(c)).collect::<Vec<_>>();
+                format!("({})", es.join(" || "))
+            }
+        }
+    }
+
+    // A block that prints nothing.
+    fn is_silent(&self, b: NodeIndex) -> bool {
+        !self.labels.contains(&b) && self.ssa.get_unconditional(&b) != self.ssa.exit_node() &&
+        self.phi_copies(b).is_empty() &&
+        self.block_order(b).into_iter().all(|n| self.statement(n).is_none())
+    }
+
+    // An endless loop starting with a silent block followed by a conditional
+    // break is printed as a while loop. Returns the loop condition and the
+    // rest of the body.
+    fn while_form<'b>(&self,
+                      body: &'b AST<NodeIndex, NodeIndex>)
+                      -> Option<(Condition<NodeIndex>, &'b [Box<AST<NodeIndex, NodeIndex>>])> {
+        let v = match *body {
+            AST::Seq(ref v) if v.len() >= 2 => v,
+            _ => return None,
+        };
+        match (&*v[0], &*v[1]) {
+            (&AST::Unit(b), &AST::Cond(ref c, ref t, ref f)) => {
+                let breaks = match **t {
+                    AST::Break => true,
+                    _ => false,
+                };
+                if breaks && f.is_empty() && self.is_silent(b) {
+                    Some((c.clone().negate(), &v[2..]))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn emit_nested(&mut self, ast: &AST<NodeIndex, NodeIndex>) {
+        self.depth += 1;
+        self.emit_ast(ast);
+        self.depth -= 1;
+    }
+
+    fn emit_ast(&mut self, ast: &AST<NodeIndex, NodeIndex>) {
+        match *ast {
+            AST::Unit(b) => self.emit_block(b),
+            AST::Seq(ref v) => {
+                for a in v {
+                    self.emit_ast(a);
+                }
+            }
+            AST::Cond(ref c, ref t, ref f) => {
+                if t.is_empty() {
+                    let c = self.cond(&c.clone().negate());
+                    self.line(format!("if ({}) {{", c));
+                    self.emit_nested(f);
+                } else {
+                    let c = self.cond(c);
+                    self.line(format!("if ({}) {{", c));
+                    self.emit_nested(t);
+                    if !f.is_empty() {
+                        self.line("} else {".to_owned());
+                        self.emit_nested(f);
+                    }
+                }
+                self.line("}".to_owned());
+            }
+            AST::Loop(LoopType::ForEver, _, ref body) if self.while_form(body).is_some() => {
+                let (c, rest) = self.while_form(body).unwrap();
+                let c = self.cond(&c);
+                self.line(format!("while ({}) {{", c));
+                self.depth += 1;
+                for a in rest {
+                    self.emit_ast(a);
+                }
+                self.depth -= 1;
+                self.line("}".to_owned());
+            }
+            AST::Loop(lt, ref c, ref body) => {
+                let c = self.cond(c);
+                match lt {
+                    LoopType::While => self.line(format!("while ({}) {{", c)),
+                    LoopType::DoWhile => self.line("do {".to_owned()),
+                    LoopType::ForEver => self.line("for (;;) {".to_owned()),
+                }
+                self.emit_nested(body);
+                match lt {
+                    LoopType::DoWhile => self.line(format!("}} while ({});", c)),
+                    _ => self.line("}".to_owned()),
+                }
+            }
+            AST::Switch(sel, ref cases, ref default) => {
+                let c = strip_parens(self.expr(sel));
+                self.line(format!("switch ({}) {{", c));
+                for (value, case) in cases.iter() {
+                    self.line(format!("case {}:", c_const(*value)));
+                    self.emit_nested(case);
+                    self.depth += 1;
+                    self.line("break;".to_owned());
+                    self.depth -= 1;
+                }
+                self.line("default:".to_owned());
+                self.emit_nested(default);
+                self.line("}".to_owned());
+            }
+            AST::Break => self.line("break;".to_owned()),
+            AST::Goto(b) => {
+                if b == self.ssa.exit_node() {
+                    self.line("return;".to_owned());
+                } else {
+                    let l = format!("goto {};", self.label(b));
+                    self.line(l);
+                }
+            }
+        }
+    }
+
+    fn line(&mut self, l: String) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(&l);
+        self.out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use analysis::layout::Layouts;
+    use backend::scf::linearize;
+    use backend::scf::dream::Dreamer;
+    use middle::arch::Endian;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage, ValueType};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    #[test]
+    fn value_types() {
+        assert_eq!(c_type(ValueType::Integer { width: 32 }), "uint32_t");
+        assert_eq!(c_type(ValueType::Signed { width: 7 }), "int8_t");
+        assert_eq!(c_type(ValueType::Bool), "bool");
+        assert_eq!(c_type(ValueType::Float { width: 64 }), "double");
+        assert_eq!(c_pointer(ValueType::Pointer { width: 64 }), "void **");
+        assert_eq!(c_pointer(ValueType::Integer { width: 16 }), "uint16_t *");
+    }
+
+    #[test]
+    fn struct_fields() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        ssa.add_control_edge(entry, exit, 2);
+
+        let arg = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let mem = ssa.add_comment(entry, From::from(0 as u16), "mem".to_owned());
+        let eight = ssa.add_const(entry, 8);
+        let addr = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(addr, 0, arg);
+        ssa.op_use(addr, 1, eight);
+        let load = ssa.add_op(entry,
+                              MOpcode::OpLoad(32, Endian::Little),
+                              From::from(32 as u16),
+                              None);
+        ssa.op_use(load, 0, addr);
+        ssa.op_use(load, 3, mem);
+        let store = ssa.add_op(entry,
+                               MOpcode::OpStore(32, Endian::Little),
+                               From::from(0 as u16),
+                               None);
+        ssa.op_use(store, 0, arg);
+        ssa.op_use(store, 1, load);
+        ssa.op_use(store, 3, mem);
+
+        let layouts = Layouts::recover(&ssa);
+        let ast = linearize(&ssa);
+        let code = {
+            let mut writer = CWriter::new(&ssa, "sym.main".to_owned());
+            writer.set_layouts(&layouts);
+            writer.emit(&ast)
+        };
+        assert!(code.starts_with("struct struct_0 {\n    uint32_t field_0;\n    \
+                                  uint8_t pad_4[4];\n    uint32_t field_8;\n};\n"));
+        assert!(code.contains("void sym_main(struct struct_0 *rdi) {\n"));
+        assert!(code.contains(" = rdi->field_8;\n"));
+        assert!(code.contains("    rdi->field_0 = tmp_"));
+    }
+
+    #[test]
+    fn fold_and_store() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        ssa.add_control_edge(entry, exit, 2);
+
+        let arg = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let five = ssa.add_const(entry, 5);
+        let sum = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(sum, 0, arg);
+        ssa.op_use(sum, 1, five);
+        let store = ssa.add_op(entry,
+                               MOpcode::OpStore(64, Endian::Little),
+                               From::from(0 as u16),
+                               None);
+        ssa.op_use(store, 0, arg);
+        ssa.op_use(store, 1, sum);
+
+        let ast = linearize(&ssa);
+        let code = CWriter::new(&ssa, "sym.main".to_owned()).emit(&ast);
+        assert!(code.starts_with("void sym_main(uint64_t rdi) {\n"));
+        assert!(code.contains("    *(uint64_t *)(rdi) = rdi + 5;\n"));
+        assert!(code.contains("    return;\n"));
+    }
+
+    #[test]
+    fn while_loop() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let head = ssa.add_block(BBInfo { addr: 0x10 });
+        let body = ssa.add_block(BBInfo { addr: 0x20 });
+        let tail = ssa.add_block(BBInfo { addr: 0x30 });
+        ssa.add_control_edge(entry, head, 2);
+        ssa.add_control_edge(head, tail, 0);
+        ssa.add_control_edge(head, body, 1);
+        ssa.add_control_edge(body, head, 2);
+        ssa.add_control_edge(tail, exit, 2);
+
+        let arg = ssa.add_comment(entry, From::from(64 as u16), "rdi".to_owned());
+        let five = ssa.add_const(head, 5);
+        let lt = ssa.add_op(head, MOpcode::OpLt, From::from(1 as u16), None);
+        ssa.op_use(lt, 0, arg);
+        ssa.op_use(lt, 1, five);
+        ssa.mark_selector(lt, head);
+        let store = ssa.add_op(body,
+                               MOpcode::OpStore(64, Endian::Little),
+                               From::from(0 as u16),
+                               None);
+        ssa.op_use(store, 0, arg);
+        ssa.op_use(store, 1, arg);
+
+        let ast = Dreamer::new(&ssa).run();
+        let code = CWriter::new(&ssa, "f".to_owned()).emit(&ast);
+        assert!(code.contains("    while (rdi < 5) {\n        *(uint64_t *)(rdi) = rdi;\n    }\n"));
+        assert!(!code.contains("goto"));
+    }
+}
@@ -5,3 +5,8 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
+//! Emits C pseudo-code for functions in SSA form.
+
+pub mod cwriter;
+
+pub use self::cwriter::CWriter;
@@ -7,5 +7,5 @@
 
 //! Components converting SSA to more high level representations.
 
-// pub mod lang_c;
+pub mod lang_c;
 pub mod scf;
@@ -5,131 +5,926 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use petgraph::Graph;
-use petgraph::graph::{NodeIndex, EdgeIndex};
-
-use super::{AST, AST_};
-use analysis::dom::DomInfo;
-
-struct GraphSlice<T> {
-	source: NodeIndex,
-	sink:   NodeIndex,
-	graph:  Graph<NodeIndex. EdgeIndex>
-}
-
-impl GraphSlice {
-	fn from()
-}
-
-enum Marker {
-	Unvisited,
-	Visited,
-	VisitedOnStack,
-	VisitedCycle,
-	VisitedOnStackCycle,
-}
-
-impl Marker {
-	fn cycle(&self) -> Self { match *self {
-		VisitedOnStack | VisitedOnStackCycle => true,
-		_ => false
-	}}
-	fn visited(&self) -> Self { match *self {
-		Unvisited => false,
-		_ => true
-	}}
-	fn push(&mut self) -> bool {
-		let descend = !self.visited();
-		*self = match *self {
-			Unvisited           => VisitedOnStack,
-			Visited             => VisitedOnStack,
-			VisitedOnStack      => VisitedOnStackCycle,
-			VisitedCycle        => VisitedCycle,
-			VisitedOnStackCycle => VisitedOnStackCycle,
-		};
-		descend
-	}
-	fn pop(&mut self) {
-		*self = match *self {
-			Unvisited           => panic!(),
-			Visited             => panic!(),
-			VisitedOnStack      => Visited,
-			VisitedCycle        => VisitedCycle,
-			VisitedOnStackCycle => VisitedCycle,
-		};
-	}
+//! Goto-free control flow structuring.
+//!
+//! Implements the approach of "No More Gotos: Decompilation Using
+//! Pattern-Independent Control-Flow Structuring and Semantics-Preserving
+//! Transformations" (Yakdan et al., NDSS 2015).
+//!
+//! The CFG is copied into a graph of regions, each holding the `AST` of the
+//! code it covers. Regions are visited in post order. A loop header has its
+//! natural loop collapsed into a single `Loop` region whose exits become
+//! `Break`s. Any other node that dominates an acyclic region with at most
+//! one successor has the region collapsed into a sequence in which every
+//! node is guarded by its reaching condition, i.e. the condition under which
+//! it is reached from the header. The sequence is then refined by merging
+//! nodes with equal conditions and turning complementary conditions into
+//! if-else. This is repeated until one region is left.
+//!
+//! A switch is collapsed with its cases if every case is reached from the
+//! switch alone and all of them continue to the same successor.
+//!
+//! Edges to the exit node become returns. If the graph cannot be reduced
+//! further, e.g. because it is irreducible, the remaining regions are
+//! connected with `Goto`s.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{AST, AST_, Condition, LoopType};
+use analysis::dom::blocks::{dominates, immediate_dominators};
+use middle::ssa::SSA;
+use middle::ssa::cfg_traits::CFG;
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Reaching conditions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// Conditions are kept in disjunctive normal form while structuring. A
+// literal `(v, b)` holds if the selector `v` evaluates to `b`. The empty
+// disjunction is false, a disjunction containing the empty term is true.
+type Lit<V> = (V, bool);
+type Term<V> = Vec<Lit<V>>;
+type DNF<V> = Vec<Term<V>>;
+
+fn dnf_true<V>() -> DNF<V> {
+    vec![Vec::new()]
+}
+
+fn dnf_lit<V>(v: V, b: bool) -> DNF<V> {
+    vec![vec![(v, b)]]
+}
+
+fn is_true<V>(d: &DNF<V>) -> bool {
+    d.iter().any(|t| t.is_empty())
+}
+
+fn term_subset<V: Copy + Eq>(a: &Term<V>, b: &Term<V>) -> bool {
+    a.iter().all(|l| b.contains(l))
+}
+
+fn term_eq<V: Copy + Eq>(a: &Term<V>, b: &Term<V>) -> bool {
+    term_subset(a, b) && term_subset(b, a)
+}
+
+fn dnf_eq<V: Copy + Eq>(a: &DNF<V>, b: &DNF<V>) -> bool {
+    a.iter().all(|t| b.iter().any(|u| term_eq(t, u))) &&
+    b.iter().all(|t| a.iter().any(|u| term_eq(t, u)))
+}
+
+// If `a` and `b` differ only in the polarity of a single literal, returns
+// the literals they have in common.
+fn resolve<V: Copy + Eq>(a: &Term<V>, b: &Term<V>) -> Option<Term<V>> {
+    if a.len() != b.len() {
+        return None;
+    }
+    let only_a = a.iter().filter(|l| !b.contains(l)).cloned().collect::<Vec<_>>();
+    if only_a.len() != 1 || !b.contains(&(only_a[0].0, !only_a[0].1)) {
+        return None;
+    }
+    Some(a.iter().filter(|l| **l != only_a[0]).cloned().collect())
+}
+
+fn simplify<V: Copy + Eq>(mut d: DNF<V>) -> DNF<V> {
+    loop {
+        if is_true(&d) {
+            return dnf_true();
+        }
+        let mut changed = false;
+        'outer: for i in 0..d.len() {
+            for j in 0..d.len() {
+                if i == j {
+                    continue;
+                }
+                // Absorption: a | (a & b) = a
+                if term_subset(&d[i], &d[j]) {
+                    d.remove(j);
+                    changed = true;
+                    break 'outer;
+                }
+                // Resolution: (a & c) | (a & !c) = a
+                if let Some(t) = resolve(&d[i], &d[j]) {
+                    let (lo, hi) = if i < j { (i, j) } else { (j, i) };
+                    d.remove(hi);
+                    d.remove(lo);
+                    d.push(t);
+                    changed = true;
+                    break 'outer;
+                }
+            }
+        }
+        if !changed {
+            return d;
+        }
+    }
+}
+
+fn dnf_or<V: Copy + Eq>(mut a: DNF<V>, b: DNF<V>) -> DNF<V> {
+    a.extend(b);
+    simplify(a)
+}
+
+fn dnf_and<V: Copy + Eq>(a: &DNF<V>, b: &DNF<V>) -> DNF<V> {
+    let mut d = Vec::new();
+    for ta in a {
+        'terms: for tb in b {
+            let mut t = ta.clone();
+            for &(v, pol) in tb {
+                if t.contains(&(v, !pol)) {
+                    continue 'terms;
+                }
+                if !t.contains(&(v, pol)) {
+                    t.push((v, pol));
+                }
+            }
+            d.push(t);
+        }
+    }
+    simplify(d)
+}
+
+// Simplifies `d` knowing that `lit` holds.
+fn dnf_assume<V: Copy + Eq>(d: DNF<V>, lit: Lit<V>) -> DNF<V> {
+    let d: DNF<V> = d.into_iter()
+                     .filter(|t| !t.contains(&(lit.0, !lit.1)))
+                     .map(|t| t.into_iter().filter(|l| *l != lit).collect())
+                     .collect();
+    simplify(d)
 }
 
-struct BB<T: SSA> {
-	index:    T::ActionRef,
-	ast:      Option<AST_<T::ActionRef, T::ValueRef>>,
-	selector: Option<T::ValueRef>,
+fn to_condition<V: Copy>(d: &DNF<V>) -> Condition<V> {
+    if is_true(d) {
+        return Condition::True;
+    }
+    let mut terms = d.iter()
+                     .map(|t| {
+                         let mut lits = t.iter()
+                                         .map(|&(v, b)| {
+                                             if b {
+                                                 Condition::Value(v)
+                                             } else {
+                                                 Condition::Not(Box::new(Condition::Value(v)))
+                                             }
+                                         })
+                                         .collect::<Vec<_>>();
+                         if lits.len() == 1 {
+                             lits.remove(0)
+                         } else {
+                             Condition::And(lits)
+                         }
+                     })
+                     .collect::<Vec<_>>();
+    match terms.len() {
+        0 => Condition::Not(Box::new(Condition::True)),
+        1 => terms.remove(0),
+        _ => Condition::Or(terms),
+    }
 }
 
-type CFG<T: SSA> = Graph<BB, u8>;
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Region graph.
+/// ////////////////////////////////////////////////////////////////////////////
+
+fn seq<B, V>(items: Vec<AST_<B, V>>) -> AST<B, V> {
+    let mut flat = Vec::new();
+    for item in items {
+        match *item {
+            AST::Seq(v) => flat.extend(v),
+            a => flat.push(Box::new(a)),
+        }
+    }
+    if flat.len() == 1 {
+        *flat.remove(0)
+    } else {
+        AST::Seq(flat)
+    }
+}
 
-struct CFG2<T: SSA> {
-	// TODO: better name
-	cfg:        CFG<T>
-	markers:    Vec<Marker>,
-	post_order: Vec<NodeIndex>
+fn empty<B, V>() -> AST_<B, V> {
+    Box::new(AST::Seq(Vec::new()))
 }
 
-impl CFG2<T: SSA> {
-	fn build_cfg<T: SSA>(ssa: &T) -> CFG<T> {
-		let cfg = Graph::new();
+// Merges neighbouring nodes with equal reaching conditions and turns pairs
+// of complementary conditions into if-else.
+fn refine<B, V: Copy + Eq>(items: Vec<(DNF<V>, AST<B, V>)>) -> Vec<AST_<B, V>> {
+    let mut groups: Vec<(DNF<V>, Vec<AST_<B, V>>)> = Vec::new();
+    for (cond, ast) in items {
+        let merge = groups.last().map(|g| dnf_eq(&g.0, &cond)).unwrap_or(false);
+        if merge {
+            groups.last_mut().unwrap().1.push(Box::new(ast));
+        } else {
+            groups.push((cond, vec![Box::new(ast)]));
+        }
+    }
 
-	}
+    let mut out = Vec::new();
+    let mut groups = groups.into_iter().peekable();
+    while let Some((cond, body)) = groups.next() {
+        if is_true(&cond) {
+            out.extend(body);
+            continue;
+        }
+        let complement = match (cond.len(), groups.peek()) {
+            (1, Some(&(ref next, _))) if cond[0].len() == 1 => {
+                let (v, b) = cond[0][0];
+                dnf_eq(next, &dnf_lit(v, !b))
+            }
+            _ => false,
+        };
+        if complement {
+            let (_, other) = groups.next().unwrap();
+            let (v, b) = cond[0][0];
+            // Prefer the positive condition in the `if`.
+            let (t, f) = if b { (body, other) } else { (other, body) };
+            out.push(Box::new(AST::Cond(Condition::Value(v),
+                                        Box::new(seq(t)),
+                                        Box::new(seq(f)))));
+        } else {
+            out.push(Box::new(AST::Cond(to_condition(&cond), Box::new(seq(body)), empty())));
+        }
+    }
+    out
+}
 
-	fn dfs<T: SSA>(cfg: &mut CFG<T>, i: NodeIndex) {
-		let descend = (&mut cfg[i]).push();
-		if descend {
-			self.post_order.push(i);
+// A switch that has not been structured yet. Its edges are kept in the
+// successors of its region without conditions.
+#[derive(Clone)]
+struct Switch<B, V> {
+    sel: V,
+    cases: Vec<(u64, B)>,
+    default: Option<B>,
+}
 
-		}
-		(&mut cfg[i]).pop();
-	}
+struct Region<B, V> {
+    /// Block the region starts with.
+    block: B,
+    ast: AST<B, V>,
+    succs: Vec<(usize, DNF<V>)>,
+    switch: Option<Switch<B, V>>,
 }
 
-struct Dreamer<T: SSA> {
-	ssa:     &T,
-	cfg:     &mut CFG<T>,
-	dominfo: DomInfo,
+pub struct Dreamer<'a, T: SSA + 'a> {
+    ssa: &'a T,
+    regions: Vec<Region<T::ActionRef, T::ValueRef>>,
+    /// Region of every block.
+    index: HashMap<T::ActionRef, usize>,
+    entry: usize,
 }
 
-impl<T: SSA> Dreamer<T> {
-	pub fn new(ssa: &T) -> Self {
-		let mut d = Dreamer {
-			ssa:     ssa,
-			cfg:     build_cfg(ssa),
-			domtree: DomInfo::new(),
-		};
-		d.build_dom_tree(ssa, ssa.start_node())
-	}
+impl<'a, T: SSA + 'a> Dreamer<'a, T> {
+    pub fn new(ssa: &'a T) -> Dreamer<'a, T> {
+        Dreamer {
+            ssa: ssa,
+            regions: Vec::new(),
+            index: HashMap::new(),
+            entry: 0,
+        }
+    }
+
+    /// Structure the control flow of the SSA.
+    pub fn run(&mut self) -> AST<T::ActionRef, T::ValueRef> {
+        self.build();
+        loop {
+            let post = self.post_order();
+            if post.len() <= 1 {
+                break;
+            }
+            let idom = self.dominators(&post);
+            let preds = self.preds();
+            let mut reduced = false;
+            for &n in &post {
+                reduced = if self.regions[n].switch.is_some() {
+                    self.reduce_switch(n, &preds)
+                } else if self.is_loop_header(n, &idom, &preds) {
+                    self.reduce_cyclic(n, &idom, &preds, &post)
+                } else {
+                    self.reduce_acyclic(n, &idom, &preds, &post)
+                };
+                if reduced {
+                    break;
+                }
+            }
+            if !reduced {
+                return self.fallback(&post);
+            }
+        }
+        self.regions[self.entry].ast.clone()
+    }
+
+    // Copies the blocks reachable from the start node into the region
+    // graph. Edges to the exit node become returns.
+    fn build(&mut self) {
+        let start = self.ssa.start_node();
+        let exit = self.ssa.exit_node();
+        let mut index = HashMap::new();
+        let mut blocks = Vec::new();
+        let mut stack = vec![start];
+        while let Some(b) = stack.pop() {
+            if b == exit || index.contains_key(&b) {
+                continue;
+            }
+            index.insert(b, blocks.len());
+            blocks.push(b);
+            stack.extend(self.ssa.succs_of(b));
+        }
+
+        self.regions = Vec::new();
+        self.entry = 0;
+        for &b in &blocks {
+            let mut switch = None;
+            let edges = match self.ssa.selector_of(&b) {
+                Some(sel) => {
+                    let (cases, default) = self.ssa.get_cases(&sel);
+                    if cases.is_empty() {
+                        let (f, t) = self.ssa.get_branches(&sel);
+                        vec![(t, dnf_lit(sel, true)), (f, dnf_lit(sel, false))]
+                    } else {
+                        let default = if default == self.ssa.invalid_action() {
+                            None
+                        } else {
+                            Some(default)
+                        };
+                        // The cases are told apart by `reduce_switch`.
+                        let mut edges: Vec<(T::ActionRef, DNF<T::ValueRef>)> = Vec::new();
+                        for target in cases.iter().map(|&(_, t)| t).chain(default) {
+                            if !edges.iter().any(|&(t, _)| t == target) {
+                                edges.push((target, dnf_true()));
+                            }
+                        }
+                        switch = Some(Switch {
+                            sel: sel,
+                            cases: cases,
+                            default: default,
+                        });
+                        edges
+                    }
+                }
+                None => {
+                    let succ = self.ssa.get_unconditional(&b);
+                    if succ == self.ssa.invalid_action() {
+                        Vec::new()
+                    } else {
+                        vec![(succ, dnf_true())]
+                    }
+                }
+            };
+
+            let mut ast = AST::Unit(b);
+            let mut succs = Vec::new();
+            let mut returns = None;
+            for (target, cond) in edges {
+                if target != exit {
+                    succs.push((index[&target], cond));
+                } else if !is_true(&cond) {
+                    returns = Some(cond);
+                }
+            }
+            // An unconditional return is printed along with the block.
+            if let Some(cond) = returns {
+                ast = seq(vec![Box::new(ast),
+                               Box::new(AST::Cond(to_condition(&cond),
+                                                  Box::new(AST::Goto(exit)),
+                                                  empty()))]);
+                for s in &mut succs {
+                    s.1 = dnf_true();
+                }
+            }
+            self.regions.push(Region {
+                block: b,
+                ast: ast,
+                succs: succs,
+                switch: switch,
+            });
+        }
+        self.index = index;
+    }
+
+    fn preds(&self) -> Vec<Vec<usize>> {
+        let mut preds = vec![Vec::new(); self.regions.len()];
+        for (n, r) in self.regions.iter().enumerate() {
+            for &(s, _) in &r.succs {
+                preds[s].push(n);
+            }
+        }
+        preds
+    }
+
+    fn post_order(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.regions.len()];
+        let mut order = Vec::new();
+        let mut stack = vec![(self.entry, 0)];
+        visited[self.entry] = true;
+        while let Some((n, i)) = stack.pop() {
+            if i < self.regions[n].succs.len() {
+                stack.push((n, i + 1));
+                let s = self.regions[n].succs[i].0;
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            } else {
+                order.push(n);
+            }
+        }
+        order
+    }
+
+    // Immediate dominators of the regions in `post`, indexed by region. The
+    // entry is its own immediate dominator, and unreachable regions have
+    // `self.regions.len()`.
+    fn dominators(&self, post: &[usize]) -> Vec<usize> {
+        let undefined = self.regions.len();
+        let rpo = post.iter().rev().cloned().collect::<Vec<_>>();
+        let mut number = vec![undefined; self.regions.len()];
+        for (i, &n) in rpo.iter().enumerate() {
+            number[n] = i;
+        }
+        let preds = self.preds();
+        let order = immediate_dominators(rpo.len(), |i| {
+            preds[rpo[i]]
+                .iter()
+                .map(|&p| number[p])
+                .filter(|&p| p != undefined)
+                .collect()
+        });
+        let mut idom = vec![undefined; self.regions.len()];
+        for (i, &d) in order.iter().enumerate() {
+            if d < rpo.len() {
+                idom[rpo[i]] = rpo[d];
+            }
+        }
+        idom
+    }
+
+    fn is_loop_header(&self, h: usize, idom: &[usize], preds: &[Vec<usize>]) -> bool {
+        preds[h].iter().any(|&p| dominates(idom, h, p))
+    }
+
+    // Checks that `order` is a topological order of `region`, ignoring edges
+    // to `h`.
+    fn is_acyclic(&self, h: usize, order: &[usize], region: &HashSet<usize>) -> bool {
+        let position = order.iter()
+                            .enumerate()
+                            .map(|(i, &n)| (n, i))
+                            .collect::<HashMap<_, _>>();
+        order.iter().all(|&n| {
+            self.regions[n].succs.iter().all(|&(s, _)| {
+                s == h || !region.contains(&s) || position[&n] < position[&s]
+            })
+        })
+    }
+
+    // Orders the nodes of the acyclic `region` and guards each by its
+    // reaching condition from `h`. Inside a loop, edges leaving the region
+    // become breaks.
+    fn structure(&self,
+                 h: usize,
+                 order: &[usize],
+                 region: &HashSet<usize>,
+                 preds: &[Vec<usize>],
+                 is_loop: bool)
+                 -> Vec<AST_<T::ActionRef, T::ValueRef>> {
+        let mut reaching: HashMap<usize, DNF<T::ValueRef>> = HashMap::new();
+        let mut known: Vec<Lit<T::ValueRef>> = Vec::new();
+        let mut items = Vec::new();
+        for &n in order {
+            let mut cond = if n == h {
+                dnf_true()
+            } else {
+                let mut cond = Vec::new();
+                for &p in preds[n].iter().filter(|&&p| region.contains(&p)) {
+                    for &(s, ref edge) in &self.regions[p].succs {
+                        if s == n {
+                            cond = dnf_or(cond, dnf_and(&reaching[&p], edge));
+                        }
+                    }
+                }
+                cond
+            };
+            for &lit in &known {
+                cond = dnf_assume(cond, lit);
+            }
+            reaching.insert(n, cond.clone());
+            if cond.is_empty() {
+                continue;
+            }
+
+            let mut ast = vec![Box::new(self.regions[n].ast.clone())];
+            if is_loop {
+                for &(s, ref edge) in &self.regions[n].succs {
+                    if region.contains(&s) {
+                        continue;
+                    }
+                    if is_true(edge) {
+                        ast.push(Box::new(AST::Break));
+                    } else {
+                        ast.push(Box::new(AST::Cond(to_condition(edge),
+                                                    Box::new(AST::Break),
+                                                    empty())));
+                        // Past an unconditional test, the loop was not left.
+                        if is_true(&cond) && edge.len() == 1 && edge[0].len() == 1 {
+                            let (v, b) = edge[0][0];
+                            known.push((v, !b));
+                        }
+                    }
+                }
+            }
+            items.push((cond, seq(ast)));
+        }
+        refine(items)
+    }
+
+    // Replaces the nodes of `region` with `h`, now holding `ast`.
+    fn collapse(&mut self,
+                h: usize,
+                region: &HashSet<usize>,
+                ast: AST<T::ActionRef, T::ValueRef>,
+                succ: Option<usize>) {
+        for &n in region {
+            self.regions[n].succs = Vec::new();
+        }
+        self.regions[h].ast = ast;
+        if let Some(s) = succ {
+            self.regions[h].succs = vec![(s, dnf_true())];
+        }
+    }
+
+    fn successors(&self, region: &HashSet<usize>) -> Vec<usize> {
+        let mut succs = Vec::new();
+        for &n in region {
+            for &(s, _) in &self.regions[n].succs {
+                if !region.contains(&s) && !succs.contains(&s) {
+                    succs.push(s);
+                }
+            }
+        }
+        succs
+    }
+
+    fn reduce_acyclic(&mut self,
+                      h: usize,
+                      idom: &[usize],
+                      preds: &[Vec<usize>],
+                      post: &[usize])
+                      -> bool {
+        let order = post.iter()
+                        .rev()
+                        .filter(|&&n| dominates(idom, h, n))
+                        .cloned()
+                        .collect::<Vec<_>>();
+        if order.len() < 2 {
+            return false;
+        }
+        let region = order.iter().cloned().collect::<HashSet<_>>();
+        let succs = self.successors(&region);
+        if succs.len() > 1 || self.has_switch(&order) ||
+           !self.is_acyclic(usize::max_value(), &order, &region) {
+            return false;
+        }
+        let ast = seq(self.structure(h, &order, &region, preds, false));
+        self.collapse(h, &region, ast, succs.first().cloned());
+        true
+    }
+
+    fn reduce_cyclic(&mut self,
+                     h: usize,
+                     idom: &[usize],
+                     preds: &[Vec<usize>],
+                     post: &[usize])
+                     -> bool {
+        // The natural loop of `h`.
+        let mut body = HashSet::new();
+        body.insert(h);
+        let mut stack = preds[h].iter()
+                                .filter(|&&p| dominates(idom, h, p))
+                                .cloned()
+                                .collect::<Vec<_>>();
+        while let Some(n) = stack.pop() {
+            if body.insert(n) {
+                stack.extend(preds[n].iter().filter(|&&p| idom[p] < idom.len()));
+            }
+        }
+        let entered = body.iter().any(|&n| {
+            n != h && preds[n].iter().any(|p| idom[*p] < idom.len() && !body.contains(p))
+        });
+        if entered {
+            return false;
+        }
+
+        // Pull nodes into the loop until at most one successor is left. A
+        // node qualifies if it is only reached from the loop and either
+        // returns or continues to another successor of the loop.
+        let mut succs = self.successors(&body);
+        while succs.len() > 1 {
+            let pulled = succs.iter().cloned().find(|&s| {
+                dominates(idom, h, s) && preds[s].iter().all(|p| body.contains(p)) &&
+                self.regions[s].succs.iter().all(|&(t, _)| t != s && succs.contains(&t))
+            });
+            match pulled {
+                Some(s) => {
+                    body.insert(s);
+                    succs = self.successors(&body);
+                }
+                None => return false,
+            }
+        }
+
+        let order = post.iter()
+                        .rev()
+                        .filter(|&&n| body.contains(&n))
+                        .cloned()
+                        .collect::<Vec<_>>();
+        if order.first() != Some(&h) || self.has_switch(&order) ||
+           !self.is_acyclic(h, &order, &body) {
+            return false;
+        }
+        let mut items = match seq(self.structure(h, &order, &body, preds, true)) {
+            AST::Seq(v) => v,
+            a => vec![Box::new(a)],
+        };
+
+        // A loop whose only latch ends the body with a conditional break is
+        // a do-while loop.
+        let last = *order.last().unwrap();
+        let single_latch = preds[h].iter().filter(|p| body.contains(p)).count() == 1 &&
+                           self.regions[last].succs.iter().any(|&(s, _)| s == h);
+        let mut lt = LoopType::ForEver;
+        let mut cond = Condition::True;
+        if single_latch && items.len() > 1 {
+            let tail = match **items.last().unwrap() {
+                AST::Cond(ref c, ref t, ref f) if f.is_empty() => {
+                    match **t {
+                        AST::Break => Some(c.clone()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(c) = tail {
+                items.pop();
+                lt = LoopType::DoWhile;
+                cond = c.negate();
+            }
+        }
+        let ast = AST::Loop(lt, cond, Box::new(seq(items)));
+        self.collapse(h, &body, ast, succs.first().cloned());
+        true
+    }
+
+    // Collapses the switch `n` with its cases, provided that every case is
+    // only reached from the switch and all of them continue to one successor,
+    // which then follows the switch. A case may not share its code with
+    // another, as the AST has no fallthrough.
+    fn reduce_switch(&mut self, n: usize, preds: &[Vec<usize>]) -> bool {
+        let switch = match self.regions[n].switch {
+            Some(ref switch) => switch.clone(),
+            None => return false,
+        };
+        let exit = self.ssa.exit_node();
+        let mut targets = switch.cases.iter().map(|&(_, t)| t).collect::<Vec<_>>();
+        targets.extend(switch.default);
+
+        let mut bodies = HashSet::new();
+        let mut follows = HashSet::new();
+        for &t in &targets {
+            if t == exit {
+                continue;
+            }
+            let r = self.index[&t];
+            let succs = &self.regions[r].succs;
+            if r != n && preds[r].iter().all(|&p| p == n) && self.regions[r].switch.is_none() &&
+               succs.len() <= 1 {
+                if targets.iter().filter(|&&u| u == t).count() > 1 {
+                    return false;
+                }
+                bodies.insert(r);
+                follows.extend(succs.iter().map(|&(s, _)| s));
+            } else {
+                follows.insert(r);
+            }
+        }
+        if follows.len() > 1 || follows.contains(&n) || follows.iter().any(|f| bodies.contains(f)) {
+            return false;
+        }
+
+        let ast = {
+            // Cases that are not bodies continue to the successor directly.
+            let arm = |t: T::ActionRef| -> AST_<T::ActionRef, T::ValueRef> {
+                if t == exit {
+                    return Box::new(AST::Goto(exit));
+                }
+                let r = self.index[&t];
+                if bodies.contains(&r) {
+                    Box::new(self.regions[r].ast.clone())
+                } else {
+                    empty()
+                }
+            };
+            let cases = switch.cases
+                              .iter()
+                              .map(|&(case, t)| (case, arm(t)))
+                              .collect::<BTreeMap<_, _>>();
+            let default = switch.default.map(|t| arm(t)).unwrap_or_else(empty);
+            seq(vec![Box::new(self.regions[n].ast.clone()),
+                     Box::new(AST::Switch(switch.sel, cases, default))])
+        };
+        let mut region = bodies;
+        region.insert(n);
+        self.regions[n].switch = None;
+        self.collapse(n, &region, ast, follows.into_iter().next());
+        true
+    }
+
+    fn has_switch(&self, order: &[usize]) -> bool {
+        order.iter().any(|&n| self.regions[n].switch.is_some())
+    }
+
+    // Connects the remaining regions with gotos.
+    fn fallback(&self, post: &[usize]) -> AST<T::ActionRef, T::ValueRef> {
+        let order = post.iter().rev().cloned().collect::<Vec<_>>();
+        let mut items = Vec::new();
+        for (i, &n) in order.iter().enumerate() {
+            let next = order.get(i + 1).cloned();
+            items.push(Box::new(self.regions[n].ast.clone()));
+            if let Some(ref switch) = self.regions[n].switch {
+                let cases = switch.cases
+                                  .iter()
+                                  .map(|&(case, t)| (case, Box::new(AST::Goto(t))))
+                                  .collect::<BTreeMap<_, _>>();
+                let default = switch.default.map(|t| Box::new(AST::Goto(t))).unwrap_or_else(empty);
+                items.push(Box::new(AST::Switch(switch.sel, cases, default)));
+                continue;
+            }
+            let succs = &self.regions[n].succs;
+            for (j, &(s, ref cond)) in succs.iter().enumerate() {
+                let jump = Box::new(AST::Goto(self.regions[s].block));
+                if j + 1 == succs.len() {
+                    // The other edges were not taken.
+                    if Some(s) != next {
+                        items.push(jump);
+                    }
+                } else {
+                    items.push(Box::new(AST::Cond(to_condition(cond), jump, empty())));
+                }
+            }
+        }
+        seq(items)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use petgraph::graph::NodeIndex;
+    use backend::scf::{AST, LoopType};
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    fn has_goto<B, V>(ast: &AST<B, V>) -> bool {
+        match *ast {
+            AST::Goto(_) => true,
+            AST::Seq(ref v) => v.iter().any(|a| has_goto(a)),
+            AST::Cond(_, ref t, ref f) => has_goto(t) || has_goto(f),
+            AST::Loop(_, _, ref body) => has_goto(body),
+            AST::Switch(_, ref cases, ref default) => {
+                cases.values().any(|a| has_goto(a)) || has_goto(default)
+            }
+            AST::Unit(_) | AST::Break => false,
+        }
+    }
+
+    fn has_loop<B, V>(ast: &AST<B, V>) -> bool {
+        match *ast {
+            AST::Loop(_, _, _) => true,
+            AST::Seq(ref v) => v.iter().any(|a| has_loop(a)),
+            AST::Cond(_, ref t, ref f) => has_loop(t) || has_loop(f),
+            _ => false,
+        }
+    }
+
+    fn function() -> SSAStorage {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        ssa
+    }
+
+    fn branch(ssa: &mut SSAStorage, b: NodeIndex, t: NodeIndex, f: NodeIndex) {
+        let sel = ssa.add_comment(b, From::from(1 as u16), "c".to_owned());
+        ssa.mark_selector(sel, b);
+        ssa.add_control_edge(b, f, 0);
+        ssa.add_control_edge(b, t, 1);
+    }
+
+    #[test]
+    fn diamond() {
+        let mut ssa = function();
+        let (entry, exit) = (ssa.start_node(), ssa.exit_node());
+        let a = ssa.add_block(BBInfo { addr: 1 });
+        let b = ssa.add_block(BBInfo { addr: 2 });
+        let c = ssa.add_block(BBInfo { addr: 3 });
+        let d = ssa.add_block(BBInfo { addr: 4 });
+        ssa.add_control_edge(entry, a, 2);
+        branch(&mut ssa, a, b, c);
+        ssa.add_control_edge(b, d, 2);
+        ssa.add_control_edge(c, d, 2);
+        ssa.add_control_edge(d, exit, 2);
+
+        let ast = Dreamer::new(&ssa).run();
+        assert!(!has_goto(&ast));
+        match ast {
+            AST::Seq(ref v) => {
+                assert_eq!(v.len(), 4);
+                match *v[2] {
+                    AST::Cond(_, ref t, ref f) => {
+                        assert!(!t.is_empty());
+                        assert!(!f.is_empty());
+                    }
+                    ref a => panic!("expected if-else, got {:?}", a),
+                }
+            }
+            ref a => panic!("expected sequence, got {:?}", a),
+        }
+    }
+
+    #[test]
+    fn while_loop() {
+        let mut ssa = function();
+        let (entry, exit) = (ssa.start_node(), ssa.exit_node());
+        let h = ssa.add_block(BBInfo { addr: 1 });
+        let body = ssa.add_block(BBInfo { addr: 2 });
+        let x = ssa.add_block(BBInfo { addr: 3 });
+        ssa.add_control_edge(entry, h, 2);
+        branch(&mut ssa, h, body, x);
+        ssa.add_control_edge(body, h, 2);
+        ssa.add_control_edge(x, exit, 2);
+
+        let ast = Dreamer::new(&ssa).run();
+        assert!(!has_goto(&ast));
+        assert!(has_loop(&ast));
+    }
+
+    #[test]
+    fn do_while() {
+        let mut ssa = function();
+        let (entry, exit) = (ssa.start_node(), ssa.exit_node());
+        let body = ssa.add_block(BBInfo { addr: 1 });
+        let latch = ssa.add_block(BBInfo { addr: 2 });
+        let x = ssa.add_block(BBInfo { addr: 3 });
+        ssa.add_control_edge(entry, body, 2);
+        branch(&mut ssa, body, latch, x);
+        branch(&mut ssa, latch, body, x);
+        ssa.add_control_edge(x, exit, 2);
 
-	fn acyclic_region(&mut self) {
-
-	}
+        let ast = Dreamer::new(&ssa).run();
+        assert!(!has_goto(&ast));
+        match ast {
+            AST::Seq(ref v) => {
+                match *v[1] {
+                    AST::Loop(lt, _, _) => assert_eq!(lt, LoopType::DoWhile),
+                    ref a => panic!("expected loop, got {:?}", a),
+                }
+            }
+            ref a => panic!("expected sequence, got {:?}", a),
+        }
+    }
 
-	fn cyclic_region(&mut self) {
-		unimplemented!();
-	}
+    #[test]
+    fn switch() {
+        let mut ssa = function();
+        let (entry, exit) = (ssa.start_node(), ssa.exit_node());
+        let a = ssa.add_block(BBInfo { addr: 1 });
+        let b = ssa.add_block(BBInfo { addr: 2 });
+        let c = ssa.add_block(BBInfo { addr: 3 });
+        let join = ssa.add_block(BBInfo { addr: 4 });
+        let sel = ssa.add_comment(entry, From::from(64 as u16), "x".to_owned());
+        ssa.mark_selector(sel, entry);
+        ssa.add_case_edge(entry, a, 0);
+        ssa.add_case_edge(entry, b, 1);
+        ssa.add_case_edge(entry, join, 2);
+        ssa.add_default_edge(entry, c);
+        ssa.add_control_edge(a, join, 2);
+        ssa.add_control_edge(b, join, 2);
+        ssa.add_control_edge(c, join, 2);
+        ssa.add_control_edge(join, exit, 2);
 
-	fn dfs(&mut self, i: NodeIndex) {
-
-		let direction = EdgeDirection::Outgoing;
-		let neighbors_iter = g.neighbors_directed(node, direction)
-			.collect::<Vec<NodeIndex>>();
-
-		for n in neighbors_iter.iter() {
-			self.dfs(g, n.clone());
-		}
-
-		self.visited.push(node.clone());
-		self.post_order.push(node.clone());	
-	}
-
-	pub fn run(&mut self) {
-
-	}
+        let ast = Dreamer::new(&ssa).run();
+        assert!(!has_goto(&ast));
+        match ast {
+            AST::Seq(ref v) => {
+                assert_eq!(v.len(), 3);
+                match *v[1] {
+                    AST::Switch(_, ref cases, ref default) => {
+                        assert_eq!(cases.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2]);
+                        assert!(!cases[&0].is_empty());
+                        assert!(cases[&2].is_empty());
+                        assert!(!default.is_empty());
+                    }
+                    ref a => panic!("expected switch, got {:?}", a),
+                }
+            }
+            ref a => panic!("expected sequence, got {:?}", a),
+        }
+    }
 }
@@ -9,24 +9,125 @@
 // SCF = Structured control flow
 
 use std::collections::BTreeMap;
+use petgraph::graph::NodeIndex;
 
-pub type AST_ = Box<AST>;
+pub mod dream;
 
-pub type RUnit = u32;
-pub type RCond = u32;
-pub type RExpr = u32;
+use middle::ssa::{SSA, SSAStorage};
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssastorage::NodeData;
 
+pub type AST_<B, V> = Box<AST<B, V>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoopType {
     While,
     DoWhile,
     ForEver,
 }
 
-pub enum AST {
-    Unit(RUnit),
+/// Boolean condition over selector values.
+///
+/// `Value(v)` holds when control leaves the block selected by `v` through
+/// its true edge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition<V> {
+    True,
+    Value(V),
+    Not(Box<Condition<V>>),
+    And(Vec<Condition<V>>),
+    Or(Vec<Condition<V>>),
+}
+
+impl<V> Condition<V> {
+    pub fn negate(self) -> Condition<V> {
+        match self {
+            Condition::Not(c) => *c,
+            c => Condition::Not(Box::new(c)),
+        }
+    }
+}
+
+/// Structured control flow tree.
+///
+/// `B` refers to basic blocks and `V` to the values selecting between
+/// branches, usually `SSA::ActionRef` and `SSA::ValueRef` respectively.
+#[derive(Clone, Debug)]
+pub enum AST<B, V> {
+    Unit(B),
+
+    Seq(Vec<AST_<B, V>>),
+    Cond(Condition<V>, AST_<B, V>, AST_<B, V>),
+    /// The condition is ignored for `LoopType::ForEver`.
+    Loop(LoopType, Condition<V>, AST_<B, V>),
+    Switch(V, BTreeMap<u64, AST_<B, V>>, AST_<B, V>),
+    /// Leaves the innermost loop.
+    Break,
+    /// Unstructured jump to a block. A jump to the exit node is a return.
+    Goto(B),
+}
+
+impl<B, V> AST<B, V> {
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            AST::Seq(ref v) => v.iter().all(|a| a.is_empty()),
+            _ => false,
+        }
+    }
+}
+
+fn block_addr(ssa: &SSAStorage, b: &NodeIndex) -> u64 {
+    match ssa.g[ssa.internal(b)] {
+        NodeData::BasicBlock(ref info) => info.addr,
+        _ => u64::max_value(),
+    }
+}
+
+/// Builds the trivial structure for `ssa`: Every basic block in address
+/// order, connected by `Goto`s where control does not fall through.
+///
+/// Used when no better structure is available.
+pub fn linearize(ssa: &SSAStorage) -> AST<NodeIndex, NodeIndex> {
+    let exit = ssa.exit_node();
+    let mut blocks = ssa.blocks();
+    blocks.sort_by(|a, b| block_addr(ssa, a).cmp(&block_addr(ssa, b)));
+
+    let jump = |target: NodeIndex, next: Option<NodeIndex>| -> AST<NodeIndex, NodeIndex> {
+        if target != exit && Some(target) == next {
+            AST::Seq(Vec::new())
+        } else {
+            AST::Goto(target)
+        }
+    };
 
-    Seq(Vec<AST_>),
-    Cond(RCond, AST_, AST_),
-    Loop(LoopType, RCond, AST_),
-    Switch(RExpr, BTreeMap<u64, AST_>, AST_),
+    let mut seq = Vec::new();
+    for (i, b) in blocks.iter().enumerate() {
+        let next = blocks.get(i + 1).cloned();
+        seq.push(Box::new(AST::Unit(*b)));
+        if let Some(sel) = ssa.selector_of(b) {
+            let (cases, default) = ssa.get_cases(&sel);
+            if cases.is_empty() {
+                let (false_branch, true_branch) = ssa.get_branches(&sel);
+                seq.push(Box::new(AST::Cond(Condition::Value(sel),
+                                            Box::new(jump(true_branch, next)),
+                                            Box::new(jump(false_branch, next)))));
+            } else {
+                let cases = cases.into_iter()
+                                 .map(|(case, target)| (case, Box::new(jump(target, next))))
+                                 .collect();
+                let default = if default == ssa.invalid_action() {
+                    AST::Seq(Vec::new())
+                } else {
+                    jump(default, next)
+                };
+                seq.push(Box::new(AST::Switch(sel, cases, Box::new(default))));
+            }
+        } else {
+            let succ = ssa.get_unconditional(b);
+            if succ != ssa.invalid_action() && succ != exit && Some(succ) != next {
+                seq.push(Box::new(AST::Goto(succ)));
+            }
+        }
+    }
+    AST::Seq(seq)
 }
@@ -9,8 +9,10 @@ extern crate radeco_lib;
 extern crate r2pipe;
 
 use radeco_lib::frontend::{parser, esilssa};
+use radeco_lib::frontend::offline::{self, Recording};
 use r2pipe::r2;
 use radeco_lib::middle::{cfg};
+use radeco_lib::middle::arch::Architecture;
 use radeco_lib::middle::dot;
 use radeco_lib::middle::ssa::SSAStorage;
 use radeco_lib::analysis::constant_propagation::constant;
@@ -19,6 +21,7 @@ use radeco_lib::middle::dce;
 use std::env;
 use std::io::prelude::*;
 use std::fs::{File, create_dir};
+use std::path::Path;
 
 fn write_file(fname: &str, res: String) {
     let mut file = File::create(fname).ok().expect("Error. Cannot create file!\n");
@@ -30,23 +33,32 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let fname = if args.len() >= 2 { &*args[1] } else { "./ex-bins/simple2" };
 
-    // Get a new r2 instance.
-    let mut r2 = r2::R2::new(Some(fname.to_owned())).unwrap();
+    // A directory holds a recording of the binary, see `frontend::offline`.
+    let (mut ops, r, endian) = if Path::new(fname).is_dir() {
+        let recording = Recording::load(fname, "sym.main").unwrap();
+        (recording.ops(), recording.reg_info, recording.endian)
+    } else {
+        // Get a new r2 instance.
+        let mut r2 = r2::R2::new(Some(fname.to_owned())).unwrap();
 
-    // Initialize with sane defaults.
-    r2.init();
+        // Initialize with sane defaults.
+        r2.init();
 
-    // Get Instructions for 'sym.main'
-    let func_info = r2.get_function("sym.main");
+        // Get Instructions for 'sym.main'
+        let func_info = r2.get_function("sym.main");
 
-    // Get the ops. We should handle error here. But for this example,
-    // Just panic is fine.
-    let mut ops = func_info.unwrap().ops.unwrap();
+        // Get the byte order of the binary.
+        r2.send("ij");
+        let endian = offline::parse_endian(&r2.recv()).unwrap_or(None);
+
+        // Get the ops. We should handle error here. But for this example,
+        // Just panic is fine.
+        (func_info.unwrap().ops.unwrap(), r2.get_reg_info().unwrap(), endian)
+    };
     println!("[*] Got ops.");
 
     // Initialize the parser with default configurations.
     let mut ssa = SSAStorage::new();
-    let r = r2.get_reg_info().unwrap();
     let cfg = {
         // limit scope of p
 
@@ -56,6 +68,7 @@ fn main() {
         // Get the register profile for the binary an hook it up with the parser.
 
         p.set_register_profile(&r); //, &mut ssa);
+        p.set_architecture(&Architecture::detect(&r).with_endian(endian));
 
         for op in ops.iter_mut() {
             p.parse_opinfo(op).ok();
@@ -7,13 +7,15 @@
 
 //! Converts the from ESIL/CFG representation to SSA.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use petgraph::graph::NodeIndex;
 use std::fmt::Debug;
 use std::convert::From;
 
 use r2pipe::structs::LRegInfo;
 
+use analysis::jumptable;
+use middle::callconv::CallingConvention;
 use middle::cfg::NodeData as CFGNodeData;
 use middle::cfg::EdgeType as CFGEdgeType;
 use middle::cfg::{BasicBlock, CFG};
@@ -21,7 +23,9 @@ use middle::dce;
 use middle::ir::{MInst, MOpcode, MVal, MValType};
 use middle::phiplacement::PhiPlacer;
 use middle::regfile::SubRegisterFile;
-use middle::ssa::{BBInfo, SSA, SSAExtra, SSAMod};
+use middle::summary::Summary;
+use middle::ssa::{BBInfo, SSA, SSAExtra, SSAMod, ValueType};
+use middle::ssa::ssa_traits::NodeType;
 use middle::ssa::verifier::{VerifiedAdd, Verify};
 
 pub type VarId = usize;
@@ -29,6 +33,13 @@ pub type VarId = usize;
 const ESIL_CUR: usize = 0;
 const ESIL_OLD: usize = 1;
 const LASTSZ: usize = 2;
+/// State of the memory. Loads read it, stores write it.
+pub const MEMORY: usize = 3;
+/// Index of the first register variable.
+pub const REGISTERS: usize = 4;
+/// Operand index of the first argument of a call. The target is operand 0
+/// and the memory it reads is operand 3.
+pub const ARGUMENTS: u8 = 4;
 
 pub struct SSAConstruction<'a, T>
     where T: 'a + Clone + Debug + SSAMod<BBInfo = BBInfo>
@@ -36,6 +47,15 @@ pub struct SSAConstruction<'a, T>
     pub phiplacer: PhiPlacer<'a, T>,
     pub regfile: SubRegisterFile,
     pub temps: HashMap<String, T::ValueRef>,
+    /// Convention assumed for calls. Without one, calls are opaque and do
+    /// not affect any register.
+    pub convention: Option<CallingConvention>,
+    /// Summaries of the called functions by address, used in place of the
+    /// convention.
+    pub summaries: HashMap<u64, Summary>,
+    /// The block and the target of every indirect jump, by the address of
+    /// the jump. Dead code elimination removes the targets, see `construct`.
+    pub indirect_jumps: BTreeMap<u64, (T::ActionRef, T::ValueRef)>,
 }
 
 impl<'a, T> SSAConstruction<'a, T>
@@ -47,18 +67,37 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
             phiplacer: PhiPlacer::new(ssa),
             regfile: SubRegisterFile::new(reg_info),
             temps: HashMap::new(),
+            convention: CallingConvention::for_registers(reg_info),
+            summaries: HashMap::new(),
+            indirect_jumps: BTreeMap::new(),
         };
         sc.phiplacer.add_variables(vec![
 			From::from(64 as u16),
 			From::from(64 as u16),
 			From::from(64 as u16),
+			From::from(0 as u16),
 			]);
 
         sc.phiplacer.add_variables(sc.regfile.whole_registers.clone());
         sc
     }
 
+    pub fn set_convention(&mut self, convention: Option<CallingConvention>) {
+        self.convention = convention;
+    }
+
+    pub fn set_summaries(&mut self, summaries: HashMap<u64, Summary>) {
+        self.summaries = summaries;
+    }
+
     pub fn run(&mut self, cfg: &CFG) {
+        self.construct(cfg);
+        dce::collect(self.phiplacer.ssa);
+    }
+
+    /// Constructs the SSA of `cfg` without removing dead code, which keeps
+    /// the targets of the indirect jumps.
+    pub fn construct(&mut self, cfg: &CFG) {
         let node_count = cfg.g.node_count();
         let mut blocks = Vec::<T::ActionRef>::with_capacity(node_count);
 
@@ -74,11 +113,13 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
             self.phiplacer.write_variable(block, 0, zero); // cur = 0
             self.phiplacer.write_variable(block, 1, zero); // old = 0
             self.phiplacer.write_variable(block, 2, zero); // lastsz = 0
+            let mem = self.phiplacer.ssa.add_comment(block, From::from(0 as u16), "mem".to_owned());
+            self.phiplacer.write_variable(block, MEMORY, mem);
 
             for (i, name) in self.regfile.whole_names.iter().enumerate() {
                 let reg = self.regfile.whole_registers[i];
                 let argnode = self.phiplacer.ssa.add_comment(block, reg, name.clone());
-                self.phiplacer.write_variable(block, i + 3, argnode);
+                self.phiplacer.write_variable(block, i + REGISTERS, argnode);
             }
 
             self.phiplacer.sync_register_state(block);
@@ -99,11 +140,18 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
             }
         }
 
+        let mut switches = BTreeMap::<usize, Vec<(u64, T::ActionRef)>>::new();
         for edge in cfg.g.raw_edges() {
             let i = match edge.weight.edge_type {
                 CFGEdgeType::False => 0,
                 CFGEdgeType::True => 1,
                 CFGEdgeType::Unconditional => 2,
+                CFGEdgeType::Case(case) => {
+                    switches.entry(edge.source().index())
+                            .or_insert_with(Vec::new)
+                            .push((case, blocks[edge.target().index()]));
+                    continue;
+                }
             };
 
             self.phiplacer.ssa.add_control_edge(blocks[edge.source().index()],
@@ -111,18 +159,56 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
                                                 i);
         }
 
+        for (source, cases) in switches {
+            self.add_switch(blocks[source], &cases);
+        }
+
         for &block in &blocks {
             self.phiplacer.seal_block(block);
         }
 
-        dce::collect(self.phiplacer.ssa);
+        // Record the registers at the end of every block, which the
+        // incremental updates of `SSAStorage` start from.
+        for i in cfg.bbs.values() {
+            self.phiplacer.sync_register_state(blocks[i.index()]);
+        }
+    }
+
+    // Makes the index of a jump through a table the selector of its block and
+    // branches to the target of every case. The index is bounded by the table,
+    // so the switch has no default.
+    fn add_switch(&mut self, block: T::ActionRef, cases: &[(u64, T::ActionRef)]) {
+        let target = self.indirect_jumps
+                         .values()
+                         .find(|&&(b, _)| b == block)
+                         .map(|&(_, target)| target);
+        let index = target.and_then(|target| {
+            jumptable::table_access(&*self.phiplacer.ssa, &target).map(|access| access.index)
+        });
+        let ref mut ssa = self.phiplacer.ssa;
+        match index {
+            // A table whose entries are all the same.
+            Some(_) if cases.iter().all(|&(_, target)| target == cases[0].1) => {
+                ssa.add_control_edge(block, cases[0].1, 2);
+            }
+            Some(index) => {
+                ssa.mark_selector(index, block);
+                for &(case, target) in cases {
+                    ssa.add_case_edge(block, target, case);
+                }
+            }
+            None => {
+                let exit = ssa.exit_node();
+                ssa.add_control_edge(block, exit, 2);
+            }
+        }
     }
 
     fn process_in(&mut self, block: T::ActionRef, mval: &MVal, addr: u64) -> T::ValueRef {
         match mval.val_type {
             MValType::Register => {
                 let phip = &mut self.phiplacer;
-                self.regfile.read_register(phip, 3, block, &mval.name, addr)
+                self.regfile.read_register(phip, REGISTERS, block, &mval.name, addr)
             }
             MValType::Temporary => self.temps[&mval.name],
             MValType::Internal => panic!("This value type should be eliminated during parsing"),
@@ -139,7 +225,7 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
         match mval.val_type { 
             MValType::Register => {
                 self.regfile.write_register(&mut self.phiplacer,
-                                            3,
+                                            REGISTERS,
                                             block,
                                             &mval.name,
                                             value,
@@ -165,12 +251,26 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
 
         let dsttype = match inst.dst.val_type {
             MValType::Null => From::from(0 as u16), // there is no ValueType::None?
+            _ if inst.opcode.is_float() => ValueType::Float { width: inst.dst.size },
             _ => From::from(inst.dst.size),
         };
 
+        let mut args = vec![n0, n1];
+        // The state of the memory is operand 3, and has to be there when the
+        // node is verified.
+        match inst.opcode {
+            MOpcode::OpLoad(..) | MOpcode::OpStore(..) | MOpcode::OpUnknown |
+            MOpcode::OpCall => {
+                let mem = self.phiplacer.read_variable(block, MEMORY);
+                args.push(self.phiplacer.ssa.invalid_value());
+                args.push(mem);
+            }
+            _ => {}
+        }
+
         let nn = {
             (*self.phiplacer.ssa)
-                .verified_add_op(block, inst.opcode, dsttype, &[n0, n1], Some(inst.addr.val))
+                .verified_add_op(block, inst.opcode, dsttype, &args, Some(inst.addr.val))
         };
 
         if inst.update_flags {
@@ -194,16 +294,155 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
         nn
     }
 
+    fn process_call(&mut self, block: T::ActionRef, inst: &MInst, target: T::ValueRef) {
+        let convention = match self.convention.clone() {
+            Some(cc) => cc,
+            None => {
+                let invalid = self.phiplacer.ssa.invalid_value();
+                let call = self.process_op(block, inst, target, invalid);
+                self.write_memory(block, call, inst.addr.val);
+                return;
+            }
+        };
+        let addr = inst.addr.val;
+        let summary = match self.phiplacer.ssa.get_node_data(&target).map(|nd| nd.nt) {
+            Ok(NodeType::Op(MOpcode::OpConst(a))) => self.summaries.get(&a).cloned(),
+            _ => None,
+        };
+        // Registers of the convention that are missing from the profile are
+        // ignored. The result of the call goes to the return register,
+        // unless the callee is known to modify only the floating point one.
+        let (args, clobbered, result) = {
+            let regfile = &self.regfile;
+            let known = |regs: &Vec<String>| {
+                regs.iter()
+                    .filter(|r| regfile.register_width(r).is_some())
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            let (args, modified, result) = match summary {
+                Some(ref summary) => {
+                    let sp = &convention.stack_pointer;
+                    // Arguments in the order of the convention, then the
+                    // others. The stack pointer is maintained below.
+                    let mut args = convention.args
+                                             .iter()
+                                             .filter(|r| summary.args.contains(*r))
+                                             .cloned()
+                                             .collect::<Vec<_>>();
+                    let others = summary.args
+                                        .iter()
+                                        .filter(|r| !args.contains(*r) && *r != sp)
+                                        .cloned()
+                                        .collect::<Vec<_>>();
+                    args.extend(others);
+                    let modified = summary.modified
+                                          .iter()
+                                          .filter(|r| *r != sp)
+                                          .cloned()
+                                          .collect::<Vec<_>>();
+                    let result = if summary.modified.contains(&convention.ret) {
+                        Some(convention.ret.clone())
+                    } else {
+                        match convention.float_ret {
+                            Some(ref f) if summary.modified.contains(f) => Some(f.clone()),
+                            _ => None,
+                        }
+                    };
+                    (args, modified, result)
+                }
+                None => {
+                    (convention.args.clone(),
+                     convention.modified(),
+                     Some(convention.ret.clone()))
+                }
+            };
+            let clobbered = modified.into_iter()
+                                    .filter(|r| Some(r) != result.as_ref())
+                                    .collect::<Vec<_>>();
+            let result = match result {
+                Some(ref r) if regfile.register_width(r).is_none() => None,
+                result => result,
+            };
+            (known(&args), known(&clobbered), result)
+        };
+
+        let mut values = Vec::new();
+        for reg in &args {
+            let phip = &mut self.phiplacer;
+            values.push(self.regfile.read_register(phip, REGISTERS, block, reg, addr));
+        }
+
+        let width = result.as_ref().and_then(|r| self.regfile.register_width(r)).unwrap_or(0);
+        let float = result.as_ref().map_or(false, |r| *r != convention.ret);
+        let vt = if float {
+            ValueType::Float { width: width }
+        } else {
+            From::from(width)
+        };
+        let call = self.phiplacer.ssa.add_op(block, MOpcode::OpCall, vt, Some(addr));
+        self.phiplacer.ssa.op_use(call, 0, target);
+        for (i, value) in values.into_iter().enumerate() {
+            self.phiplacer.ssa.op_use(call, ARGUMENTS + i as u8, value);
+        }
+        // Without a summary, the call may read and write any memory.
+        if summary.as_ref().map_or(true, |s| s.reads_memory) {
+            let mem = self.phiplacer.read_variable(block, MEMORY);
+            self.phiplacer.ssa.op_use(call, 3, mem);
+        }
+        if summary.as_ref().map_or(true, |s| s.writes_memory) {
+            self.write_memory(block, call, addr);
+        }
+
+        for reg in &clobbered {
+            let vt = From::from(self.regfile.register_width(reg).unwrap());
+            let undef = self.phiplacer.ssa.add_undefined(block, vt);
+            self.regfile.write_register(&mut self.phiplacer, REGISTERS, block, reg, undef, addr);
+        }
+        if let Some(ref result) = result {
+            self.regfile.write_register(&mut self.phiplacer, REGISTERS, block, result, call, addr);
+        }
+
+        // The callee pops the return address pushed by the call.
+        let sp = &convention.stack_pointer;
+        if let Some(width) = self.regfile.register_width(sp) {
+            if convention.return_address > 0 {
+                let phip = &mut self.phiplacer;
+                let old = self.regfile.read_register(phip, REGISTERS, block, sp, addr);
+                let size = self.phiplacer.ssa.add_const(block, convention.return_address);
+                let new = self.phiplacer
+                              .ssa
+                              .add_op(block, MOpcode::OpAdd, From::from(width), Some(addr));
+                self.phiplacer.ssa.op_use(new, 0, old);
+                self.phiplacer.ssa.op_use(new, 1, size);
+                self.regfile.write_register(&mut self.phiplacer, REGISTERS, block, sp, new, addr);
+            }
+        }
+    }
+
+    // The callee may change the memory, so the state after `call` is a new
+    // one, which follows the state before it like the state after a store.
+    fn write_memory(&mut self, block: T::ActionRef, call: T::ValueRef, addr: u64) {
+        let before = self.phiplacer.read_variable(block, MEMORY);
+        let after = self.phiplacer
+                        .ssa
+                        .add_op(block, MOpcode::OpMemory, From::from(0 as u16), Some(addr));
+        self.phiplacer.ssa.op_use(after, 0, call);
+        self.phiplacer.ssa.op_use(after, 3, before);
+        self.phiplacer.write_variable(block, MEMORY, after);
+    }
+
     fn process_block(&mut self, block: T::ActionRef, source: &BasicBlock) {
-        let mut machinestate = self.phiplacer.ssa.to_value(block);
         for ref instruction in &source.instructions {
             let n0 = self.process_in(block, &instruction.operand_1, instruction.addr.val);
             let n1 = self.process_in(block, &instruction.operand_2, instruction.addr.val);
 
             if instruction.opcode == MOpcode::OpJmp {
-                // TODO: In case of static jumps, this is trivial and does not need a selector.
-                // In case of dynamic jump, the jump targets have to be determined.
-                // self.ssa.g.add_edge(block, n0, SSAEdgeData::DynamicControl(0));
+                // The targets of an indirect jump are determined from the
+                // CFG, see `analysis::jumptable`.
+                if instruction.operand_1.as_literal.is_none() {
+                    self.indirect_jumps.insert(instruction.addr.val, (block, n0));
+                }
                 break;
             }
 
@@ -212,18 +451,227 @@ where T: 'a + Clone + Debug + Verify + SSAExtra +
                 continue;
             }
 
+            if instruction.opcode == MOpcode::OpCall {
+                self.process_call(block, instruction, n0);
+                continue;
+            }
+
             let nn = self.process_op(block, instruction, n0, n1);
 
-            if instruction.opcode == MOpcode::OpLoad {
-                self.phiplacer.ssa.op_use(nn, 3, machinestate);
+            match instruction.opcode {
+                MOpcode::OpStore(..) | MOpcode::OpUnknown => {
+                    self.phiplacer.write_variable(block, MEMORY, nn);
+                }
+                _ => {}
             }
 
-            if instruction.opcode == MOpcode::OpStore {
-                self.phiplacer.ssa.op_use(nn, 3, machinestate);
-                machinestate = nn;
+            self.process_out(block, &instruction, nn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use petgraph::graph::NodeIndex;
+    use r2pipe::structs::LOpInfo;
+    use rustc_serialize::json;
+    use frontend::offline::read_reg_info;
+    use frontend::parser::Parser;
+    use middle::cfg::CFG;
+    use middle::gvn;
+    use middle::ir::MOpcode;
+    use middle::ssa::{SSA, SSAStorage, ValueType};
+    use middle::ssa::ssa_traits::{NodeData, NodeType};
+    use middle::summary::Summary;
+
+    // The SSA of `ops`, given as ESIL and the type r2 reports, which are 4
+    // bytes long each and start at 0x1000. Calls use `summaries`.
+    fn construct_with(ops: &[(&str, &str)], summaries: HashMap<u64, Summary>) -> SSAStorage {
+        construct_in("./ex-bins/json/simple2/regs.json", ops, summaries)
+    }
+
+    // As `construct_with`, with the register profile at `regs`.
+    fn construct_in(regs: &str,
+                    ops: &[(&str, &str)],
+                    summaries: HashMap<u64, Summary>)
+                    -> SSAStorage {
+        let reg_info = read_reg_info(regs).unwrap();
+        let mut p = Parser::new(None);
+        p.set_register_profile(&reg_info);
+        for (i, &(esil, optype)) in ops.iter().enumerate() {
+            let op = format!("{{\"offset\": {}, \"esil\": \"{}\", \"optype\": \"{}\"}}",
+                             0x1000 + 4 * i,
+                             esil,
+                             optype);
+            p.parse_opinfo(&json::decode::<LOpInfo>(&op).unwrap()).unwrap();
+        }
+        let mut cfg = CFG::new();
+        cfg.build(&mut p.emit_insts());
+        let mut ssa = SSAStorage::new();
+        {
+            let mut con = SSAConstruction::new(&mut ssa, &reg_info);
+            con.set_summaries(summaries);
+            con.construct(&cfg);
+        }
+        ssa
+    }
+
+    fn construct(ops: &[(&str, &str)]) -> SSAStorage {
+        construct_with(ops, HashMap::new())
+    }
+
+    fn loads(ssa: &SSAStorage) -> Vec<NodeIndex> {
+        ssa.nodes()
+           .into_iter()
+           .filter(|n| {
+               match ssa.get_node_data(n).map(|nd| nd.nt) {
+                   Ok(NodeType::Op(MOpcode::OpLoad(..))) => true,
+                   _ => false,
+               }
+           })
+           .collect()
+    }
+
+    #[test]
+    fn load_across_call() {
+        // rbx is preserved by the call, so only the memory tells the loads
+        // apart.
+        let mut ssa = construct(&[("rbx,[8],rax,=", "mov"),
+                                  ("0x2000,rip,=", "call"),
+                                  ("rbx,[8],rcx,=", "mov")]);
+        gvn::run(&mut ssa);
+        assert_eq!(loads(&ssa).len(), 2);
+    }
+
+    #[test]
+    fn summarized_call() {
+        let ops = [("rbx,[8],rax,=", "mov"),
+                   ("0x2000,rip,=", "call"),
+                   ("rbx,[8],rcx,=", "mov")];
+        let summaries = |writes_memory| {
+            let mut summaries = HashMap::new();
+            summaries.insert(0x2000,
+                             Summary {
+                                 reads_memory: true,
+                                 writes_memory: writes_memory,
+                                 ..Summary::default()
+                             });
+            summaries
+        };
+
+        // The memory is the same after a call that does not write it.
+        let mut ssa = construct_with(&ops, summaries(false));
+        gvn::run(&mut ssa);
+        assert_eq!(loads(&ssa).len(), 1);
+        let mut ssa = construct_with(&ops, summaries(true));
+        gvn::run(&mut ssa);
+        assert_eq!(loads(&ssa).len(), 2);
+
+        let mut summaries = HashMap::new();
+        summaries.insert(0x2000, Summary::default());
+        let ssa = construct_with(&ops, summaries);
+        let call = ssa.nodes()
+                      .into_iter()
+                      .find(|n| {
+                          match ssa.get_node_data(n).map(|nd| nd.nt) {
+                              Ok(NodeType::Op(MOpcode::OpCall)) => true,
+                              _ => false,
+                          }
+                      })
+                      .unwrap();
+        assert!(ssa.get_sparse_operands(&call).iter().all(|&(i, _)| i != 3));
+    }
+
+    fn call(ssa: &SSAStorage) -> NodeIndex {
+        ssa.nodes()
+           .into_iter()
+           .find(|n| opcode(ssa, n) == Some(MOpcode::OpCall))
+           .unwrap()
+    }
+
+    #[test]
+    fn float_return() {
+        let ops = [("0x2000,rip,=", "call"), ("xmm0l,rbx,=", "mov"), ("zf,rcx,=", "mov")];
+        let regs = "./ex-bins/json/float/regs.json";
+
+        // Without a summary, the result is in rax and the flags are
+        // clobbered.
+        let ssa = construct_in(regs, &ops, HashMap::new());
+        assert_eq!(ssa.get_node_data(&call(&ssa)).unwrap().vt,
+                   ValueType::Integer { width: 64 });
+        assert!(ssa.nodes().iter().any(|n| {
+            match ssa.get_node_data(n) {
+                Ok(NodeData { nt: NodeType::Undefined, vt }) => vt.width() == 1,
+                _ => false,
             }
+        }));
+
+        // A callee that only modifies xmm0 returns a float in it.
+        let mut summaries = HashMap::new();
+        summaries.insert(0x2000,
+                         Summary {
+                             modified: vec!["xmm0".to_owned()],
+                             ..Summary::default()
+                         });
+        let ssa = construct_in(regs, &ops, summaries);
+        assert_eq!(ssa.get_node_data(&call(&ssa)).unwrap().vt,
+                   ValueType::Float { width: 128 });
+    }
 
-            self.process_out(block, &instruction, nn);
+    fn opcode(ssa: &SSAStorage, n: &NodeIndex) -> Option<MOpcode> {
+        match ssa.get_node_data(n).map(|nd| nd.nt) {
+            Ok(NodeType::Op(opc)) => Some(opc),
+            _ => None,
+        }
+    }
+
+    fn memory(ssa: &SSAStorage, n: &NodeIndex) -> NodeIndex {
+        ssa.get_sparse_operands(n).into_iter().find(|&(i, _)| i == 3).unwrap().1
+    }
+
+    #[test]
+    fn memory_chain() {
+        let ssa = construct(&[("rbx,[8],rax,=", "mov"),
+                              ("rax,rbx,=[8]", "mov"),
+                              ("rbx,[8],rcx,=", "mov")]);
+        let mut loads = loads(&ssa);
+        loads.sort();
+        assert_eq!(loads.len(), 2);
+        let before = memory(&ssa, &loads[0]);
+        let after = memory(&ssa, &loads[1]);
+        assert!(before != after);
+        match opcode(&ssa, &after) {
+            Some(MOpcode::OpStore(..)) => {}
+            opc => panic!("load after {:?}", opc),
+        }
+        assert_eq!(memory(&ssa, &after), before);
+    }
+
+    #[test]
+    fn memory_phi() {
+        // The store at 0x1004 is skipped if zf is set.
+        let ssa = construct(&[("zf,?{,0x1008,rip,=,}", "cjmp"),
+                              ("rax,rbx,=[8]", "mov"),
+                              ("rbx,[8],rcx,=", "mov")]);
+        let loads = loads(&ssa);
+        assert_eq!(loads.len(), 1);
+        let phi = memory(&ssa, &loads[0]);
+        match ssa.get_node_data(&phi).map(|nd| nd.nt) {
+            Ok(NodeType::Phi) => {}
+            _ => panic!("no phi for the memory at the join"),
         }
+        let operands = ssa.get_operands(&phi);
+        assert_eq!(operands.len(), 2);
+        let store = operands.iter()
+                            .find(|o| {
+                                match opcode(&ssa, o) {
+                                    Some(MOpcode::OpStore(..)) => true,
+                                    _ => false,
+                                }
+                            })
+                            .unwrap();
+        assert!(operands.contains(&memory(&ssa, store)));
     }
 }
@@ -0,0 +1,109 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reads the data of a binary, as the entries of jump tables.
+//!
+//! r2 implements `Memory` with `pxj`. A `MemoryMap` holds the regions saved
+//! in a recording, see `frontend::offline`.
+
+use std::collections::BTreeMap;
+
+use r2pipe::R2;
+use rustc_serialize::json::Json;
+
+use middle::arch::Endian;
+use middle::ir::WidthSpec;
+
+pub trait Memory {
+    /// Reads `size` bytes at `addr`, if all of them are mapped.
+    fn read(&mut self, addr: u64, size: usize) -> Option<Vec<u8>>;
+
+    /// Reads an integer of `width` bits at `addr`.
+    fn read_word(&mut self, addr: u64, width: WidthSpec, endian: Endian) -> Option<u64> {
+        let size = (width / 8) as usize;
+        if size == 0 || size > 8 {
+            return None;
+        }
+        let mut bytes = match self.read(addr, size) {
+            Some(bytes) => bytes,
+            None => return None,
+        };
+        if endian == Endian::Little {
+            bytes.reverse();
+        }
+        Some(bytes.iter().fold(0, |value, &b| value << 8 | b as u64))
+    }
+}
+
+impl Memory for R2 {
+    fn read(&mut self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        self.send(&format!("pxj {} @ {}", size, addr));
+        let bytes = match Json::from_str(&self.recv()) {
+            Ok(Json::Array(bytes)) => bytes,
+            _ => return None,
+        };
+        let bytes = bytes.iter().filter_map(|b| b.as_u64()).map(|b| b as u8).collect::<Vec<_>>();
+        if bytes.len() == size {
+            Some(bytes)
+        } else {
+            None
+        }
+    }
+}
+
+/// Regions of memory by their start address.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMap {
+    regions: BTreeMap<u64, Vec<u8>>,
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        MemoryMap { regions: BTreeMap::new() }
+    }
+
+    /// Maps `bytes` at `addr`. Regions are not merged, a read must lie in
+    /// one of them.
+    pub fn map(&mut self, addr: u64, bytes: Vec<u8>) {
+        self.regions.insert(addr, bytes);
+    }
+}
+
+impl Memory for MemoryMap {
+    fn read(&mut self, addr: u64, size: usize) -> Option<Vec<u8>> {
+        // The region starting last before `addr`.
+        let (start, bytes) = match self.regions.iter().rev().find(|&(&start, _)| start <= addr) {
+            Some(region) => region,
+            None => return None,
+        };
+        let offset = (addr - start) as usize;
+        if offset + size > bytes.len() {
+            return None;
+        }
+        Some(bytes[offset..offset + size].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::arch::Endian;
+
+    #[test]
+    fn read_words() {
+        let mut memory = MemoryMap::new();
+        memory.map(0x1000, vec![0x11, 0x22, 0x33, 0x44]);
+        memory.map(0x2000, vec![0x55]);
+        assert_eq!(memory.read_word(0x1000, 32, Endian::Little), Some(0x44332211));
+        assert_eq!(memory.read_word(0x1000, 32, Endian::Big), Some(0x11223344));
+        assert_eq!(memory.read_word(0x1002, 16, Endian::Little), Some(0x4433));
+        assert_eq!(memory.read(0x2000, 1), Some(vec![0x55]));
+        // Past the end of a region and before the first one.
+        assert_eq!(memory.read(0x1002, 4), None);
+        assert_eq!(memory.read(0x0fff, 1), None);
+    }
+}
@@ -11,5 +11,7 @@ pub use super::middle::ir::{Address, MAddr, MArity, MInst, MOpcode, MRegInfo, MV
 
 pub mod parser;
 pub mod esilssa;
+pub mod memory;
+pub mod offline;
 
 pub mod ssaconstructor;
@@ -0,0 +1,258 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Loads functions and register profiles that were saved from radare2 as
+//! JSON, so that they can be analyzed without a running radare2.
+//!
+//! A recorded binary is a directory holding the output of `aerpj` in
+//! `regs.json`, the output of `aflj` in `functions.json`, and the output of
+//! `pdfj @ <function>` in `<function>.json` for every recorded function.
+//! Regions of memory the functions read, as their jump tables, may be saved
+//! in `memory.json`, as a list of objects with the `offset` of the region
+//! and its `bytes` as output by `pxj`. The output of `ij` may be saved in
+//! `info.json`, for the byte order of the binary.
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use r2pipe::structs::{LFunctionInfo, LOpInfo, LRegInfo};
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json};
+
+use analysis::jumptable;
+use frontend::esilssa::SSAConstruction;
+use frontend::memory::{Memory, MemoryMap};
+use frontend::parser::Parser;
+use middle::arch::{Architecture, Endian};
+use middle::cfg::CFG;
+use middle::ssa::SSAStorage;
+
+/// File holding the register profile in a recorded binary.
+pub const REGISTER_PROFILE: &'static str = "regs.json";
+/// File listing the functions of a recorded binary.
+pub const FUNCTIONS: &'static str = "functions.json";
+/// File holding the regions of memory saved in a recorded binary.
+pub const MEMORY: &'static str = "memory.json";
+/// File holding the information on a recorded binary.
+pub const INFO: &'static str = "info.json";
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Syntax(json::ParserError),
+    Decode(json::DecoderError),
+    /// The file holds no function.
+    Empty,
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl From<json::ParserError> for LoadError {
+    fn from(e: json::ParserError) -> LoadError {
+        LoadError::Syntax(e)
+    }
+}
+
+impl From<json::DecoderError> for LoadError {
+    fn from(e: json::DecoderError) -> LoadError {
+        LoadError::Decode(e)
+    }
+}
+
+/// A function as listed by `aflj`.
+#[derive(Clone, Debug, RustcDecodable)]
+pub struct FunctionEntry {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A region of memory in `memory.json`.
+#[derive(Clone, Debug, RustcDecodable)]
+pub struct MemoryRegion {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// A function, the register profile and the memory of its binary.
+pub struct Recording {
+    pub function: LFunctionInfo,
+    pub reg_info: LRegInfo,
+    pub memory: MemoryMap,
+    /// Byte order of the binary, if it was recorded.
+    pub endian: Option<Endian>,
+}
+
+impl Recording {
+    /// Loads `function` from the recorded binary in `dir`.
+    pub fn load<P: AsRef<Path>>(dir: P, function: &str) -> Result<Recording, LoadError> {
+        let dir = dir.as_ref();
+        let reg_info = try!(read_reg_info(dir.join(REGISTER_PROFILE)));
+        let function = try!(read_function(dir.join(format!("{}.json", function))));
+        let memory = try!(read_memory(dir.join(MEMORY)));
+        let endian = try!(read_endian(dir.join(INFO)));
+        Ok(Recording {
+            function: function,
+            reg_info: reg_info,
+            memory: memory,
+            endian: endian,
+        })
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        Architecture::detect(&self.reg_info).with_endian(self.endian)
+    }
+
+    pub fn ops(&self) -> Vec<LOpInfo> {
+        self.function.ops.clone().unwrap_or(Vec::new())
+    }
+
+    pub fn cfg(&self) -> CFG {
+        build_cfg(&self.ops(),
+                  &self.reg_info,
+                  &self.architecture(),
+                  &mut self.memory.clone(),
+                  &BTreeSet::new())
+    }
+
+    pub fn ssa(&self) -> SSAStorage {
+        build_ssa(&self.cfg(), &self.reg_info)
+    }
+}
+
+/// Reads the output of `pdfj`.
+pub fn read_function<P: AsRef<Path>>(path: P) -> Result<LFunctionInfo, LoadError> {
+    let mut json = match try!(read_json(path)) {
+        // Older versions of radare2 wrap the function in an array.
+        Json::Array(mut functions) => {
+            if functions.is_empty() {
+                return Err(LoadError::Empty);
+            }
+            functions.swap_remove(0)
+        }
+        json => json,
+    };
+    // `type` is a keyword and is called `optype` in `LOpInfo`.
+    if let Some(&mut Json::Array(ref mut ops)) = json.as_object_mut()
+                                                     .and_then(|f| f.get_mut("ops")) {
+        for op in ops.iter_mut().filter_map(|op| op.as_object_mut()) {
+            if let Some(t) = op.remove("type") {
+                op.entry("optype".to_owned()).or_insert(t);
+            }
+        }
+    }
+    decode(json)
+}
+
+/// Reads the output of `aflj`.
+pub fn read_function_list<P: AsRef<Path>>(path: P) -> Result<Vec<FunctionEntry>, LoadError> {
+    let json = try!(read_json(path));
+    decode(json)
+}
+
+/// Parses the output of `aflj`, as received from r2.
+pub fn parse_function_list(s: &str) -> Result<Vec<FunctionEntry>, LoadError> {
+    let json = try!(Json::from_str(s));
+    decode(json)
+}
+
+/// Reads the output of `aerpj`.
+pub fn read_reg_info<P: AsRef<Path>>(path: P) -> Result<LRegInfo, LoadError> {
+    let json = try!(read_json(path));
+    decode(json)
+}
+
+/// Reads the regions of memory saved in `memory.json`. Without the file,
+/// no memory is mapped.
+pub fn read_memory<P: AsRef<Path>>(path: P) -> Result<MemoryMap, LoadError> {
+    let mut memory = MemoryMap::new();
+    if !path.as_ref().exists() {
+        return Ok(memory);
+    }
+    let json = try!(read_json(path));
+    let regions: Vec<MemoryRegion> = try!(decode(json));
+    for region in regions {
+        memory.map(region.offset, region.bytes);
+    }
+    Ok(memory)
+}
+
+/// Reads the byte order from the output of `ij` in `info.json`. Without the
+/// file, the byte order is not known.
+pub fn read_endian<P: AsRef<Path>>(path: P) -> Result<Option<Endian>, LoadError> {
+    if !path.as_ref().exists() {
+        return Ok(None);
+    }
+    let json = try!(read_json(path));
+    Ok(endian_of(&json))
+}
+
+/// Parses the byte order from the output of `ij`, as received from r2.
+pub fn parse_endian(s: &str) -> Result<Option<Endian>, LoadError> {
+    let json = try!(Json::from_str(s));
+    Ok(endian_of(&json))
+}
+
+/// Parses the ESIL of `ops` for `arch` and builds their control flow graph,
+/// as `Runner` does. Instructions that cannot be parsed have an unknown
+/// effect. Jump tables are read from `memory`. Calls to the functions at the
+/// addresses in `noreturn` end their blocks.
+pub fn build_cfg(ops: &[LOpInfo],
+                 reg_info: &LRegInfo,
+                 arch: &Architecture,
+                 memory: &mut Memory,
+                 noreturn: &BTreeSet<u64>)
+                 -> CFG {
+    let mut p = Parser::new(None);
+    p.set_register_profile(reg_info);
+    p.set_architecture(arch);
+    p.set_recovery(true);
+    for op in ops {
+        p.parse_opinfo(op).ok();
+    }
+    jumptable::build_cfg(&mut p.emit_insts(), reg_info, memory, noreturn)
+}
+
+/// Constructs the SSA of `cfg`, as `Runner` does.
+pub fn build_ssa(cfg: &CFG, reg_info: &LRegInfo) -> SSAStorage {
+    let mut ssa = SSAStorage::new();
+    {
+        let mut con = SSAConstruction::new(&mut ssa, reg_info);
+        con.run(cfg);
+    }
+    ssa
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+fn read_json<P: AsRef<Path>>(path: P) -> Result<Json, LoadError> {
+    let mut s = String::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_string(&mut s));
+    Ok(try!(Json::from_str(&s)))
+}
+
+// The byte order in the output of `ij`.
+fn endian_of(info: &Json) -> Option<Endian> {
+    info.find_path(&["bin", "endian"])
+        .and_then(|e| e.as_string())
+        .and_then(Endian::from_name)
+}
+
+fn decode<T: Decodable>(json: Json) -> Result<T, LoadError> {
+    let mut decoder = json::Decoder::new(json);
+    Ok(try!(Decodable::decode(&mut decoder)))
+}
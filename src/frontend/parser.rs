@@ -10,13 +10,14 @@
 use num::traits::Num;
 
 use std::collections::HashMap;
-use std::cmp;
+use std::{cmp, error, fmt};
 use regex::Regex;
 
 use r2pipe::structs::{LAliasInfo, LFlagInfo, LOpInfo, LRegInfo, LRegProfile};
 
 use frontend::{Address, MAddr, MInst, MOpcode, MRegInfo, MVal, MValType};
 use middle::ir::WidthSpec; // move WidthSpec to a different module?
+use middle::arch::{Architecture, Endian};
 
 // Macro to return a new hash given (key, value) tuples.
 // Example: hash![("foo", "bar"), ("bar", "baz")]
@@ -39,11 +40,62 @@ macro_rules! hex_to_i {
 	};
 }
 
-#[derive(Debug)]
-pub enum ParseError {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
     InvalidEsil,
     InvalidMOperator,
     InsufficientOperands,
+    /// The token is neither an operator, a register nor a number.
+    UnknownToken,
+    /// ESIL has no such internal variable.
+    InvalidInternal,
+}
+
+/// Failure to parse the ESIL of an instruction.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Address of the instruction.
+    pub addr: Address,
+    /// The ESIL of the instruction.
+    pub esil: String,
+    /// Index of the offending token among the comma separated tokens.
+    pub index: usize,
+    pub token: String,
+}
+
+impl ParseErrorKind {
+    fn description(&self) -> &'static str {
+        match *self {
+            ParseErrorKind::InvalidEsil => "invalid esil",
+            ParseErrorKind::InvalidMOperator => "invalid operator",
+            ParseErrorKind::InsufficientOperands => "insufficient operands",
+            ParseErrorKind::UnknownToken => "unknown token",
+            ParseErrorKind::InvalidInternal => "invalid internal variable",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} at 0x{:x}: token {} `{}` of `{}`",
+               self.kind.description(),
+               self.addr,
+               self.index,
+               self.token,
+               self.esil)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        self.kind.description()
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
 }
 
 #[allow(dead_code)]
@@ -62,6 +114,9 @@ pub struct Parser {
     opinfo: Option<LOpInfo>,
     tmp_index: u64,
     constants: HashMap<u64, MVal>,
+    endian: Endian,
+    recover: bool,
+    errors: Vec<ParseError>,
 }
 
 // Struct used to configure the Parser.
@@ -116,9 +171,27 @@ impl Parser {
             flags: flags,
             tmp_index: 0,
             constants: HashMap::new(),
+            endian: Endian::Little,
+            recover: false,
+            errors: Vec::new(),
         }
     }
 
+    /// In recovery mode, an instruction whose ESIL cannot be parsed is
+    /// replaced by an `OpUnknown` and the error is kept in `errors` instead
+    /// of being returned.
+    pub fn set_recovery(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    /// Errors recovered from since the parser was created.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Takes the registers from `reg_info`. The word size and byte order are
+    /// those `Architecture::detect` gives the profile until
+    /// `set_architecture` sets the ones of the binary.
     pub fn set_register_profile(&mut self, reg_info: &LRegInfo) {
         // , ssa: &'a mut SSAStorage) {
         // TODO: use SSA methods instead of SSAStorage methods
@@ -137,6 +210,15 @@ impl Parser {
         }
 
         self.alias_info = tmp.clone();
+        self.set_architecture(&Architecture::detect(reg_info));
+    }
+
+    /// Takes the word size and byte order from `arch`. The word size is the
+    /// width of constants and of memory accesses without an explicit size.
+    pub fn set_architecture(&mut self, arch: &Architecture) {
+        self.arch = arch.name.clone();
+        self.default_size = arch.word_size;
+        self.endian = arch.endian;
     }
 
     pub fn set_flags(&mut self, flags: &Vec<LFlagInfo>) {
@@ -164,61 +246,157 @@ impl Parser {
             None => self.addr + 1,
         };
 
+        // radare2 gives no ESIL for instructions without effect.
+        let nop = opinfo.optype.as_ref().map_or(false, |t| t == "nop");
         self.opinfo = Some(opinfo);
+        if nop && esil.is_empty() {
+            return Ok(());
+        }
         self.parse_str(&*esil)
     }
 
     pub fn parse_str(&mut self, esil: &str) -> Result<(), ParseError> {
-        if esil.len() == 0 {
-            return Err(ParseError::InvalidEsil);
+        let tokens: Vec<String> = esil.split(',').map(|x| x.to_string()).collect();
+        let insts = self.insts.len();
+        let stack = self.stack.clone();
+        let mut i = 0;
+        let result = if esil.len() == 0 {
+            Err(ParseErrorKind::InvalidEsil)
+        } else {
+            self.parse_tokens(&tokens, &mut i)
+        };
+        let kind = match result {
+            Ok(()) => return Ok(()),
+            Err(kind) => kind,
+        };
+
+        // `i` is past the offending token.
+        let index = if i > 0 { i - 1 } else { 0 };
+        let err = ParseError {
+            kind: kind,
+            addr: self.addr,
+            esil: esil.to_owned(),
+            index: index,
+            token: tokens.get(index).cloned().unwrap_or_default(),
+        };
+        if !self.recover {
+            return Err(err);
         }
 
-        let esil: Vec<String> = esil.split(',').map(|x| x.to_string()).collect();
-        for token in esil {
+        // Drop what was emitted for the instruction.
+        self.insts.truncate(insts);
+        self.stack = stack;
+        let null = MVal::null();
+        let inst = MOpcode::OpUnknown.to_inst(null.clone(),
+                                              null.clone(),
+                                              null,
+                                              Some(MAddr::new(self.addr)));
+        self.insts.push(inst);
+        self.errors.push(err);
+        Ok(())
+    }
+
+    // Parses `esil` as a part of the current instruction.
+    fn parse_expr(&mut self, esil: &str) -> Result<(), ParseErrorKind> {
+        let tokens: Vec<String> = esil.split(',').map(|x| x.to_string()).collect();
+        let mut i = 0;
+        self.parse_tokens(&tokens, &mut i)
+    }
+
+    // Parses the tokens from `*i` on. On error, `*i` is past the offending
+    // token.
+    fn parse_tokens(&mut self, esil: &[String], i: &mut usize) -> Result<(), ParseErrorKind> {
+        // Number of the open conditionals.
+        let mut depth = 0;
+        // Condition on which a repeated instruction stops, from `?{,BREAK,}`,
+        // and the index of the token following it.
+        let mut guard: Option<MVal> = None;
+        let mut body = 0;
+        while *i < esil.len() {
+            let token = esil[*i].clone();
+            *i += 1;
             if token.len() == 0 {
                 continue;
             }
+
+            // Commands that change the stack or the control flow of the
+            // expression itself.
+            match &*token {
+                "DUP" | "SWAP" | "PICK" | "RPICK" | "NUM" | "POP" | "CLEAR" => {
+                    try!(self.stack_command(&*token));
+                    continue;
+                }
+                // The remaining effects of the instruction are not known.
+                "TODO" => return Ok(()),
+                "BREAK" => {
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    // Only `?{,BREAK,}` on top of the expression can be
+                    // expressed: it guards a repeated instruction.
+                    let cond = try!(self.take_condition().ok_or(ParseErrorKind::InvalidEsil));
+                    *i = skip_conditional(esil, *i);
+                    depth -= 1;
+                    guard = Some(cond);
+                    body = *i;
+                    continue;
+                }
+                "GOTO" => {
+                    let target = try!(self.stack.pop().ok_or(ParseErrorKind::InsufficientOperands));
+                    // Only jumps back to the start of the instruction are
+                    // supported. They repeat the instruction.
+                    match target.as_literal {
+                        Some(t) if t as usize <= body => {}
+                        _ => return Err(ParseErrorKind::InvalidEsil),
+                    }
+                    let cond = if depth > 0 {
+                        Some(try!(self.take_condition().ok_or(ParseErrorKind::InvalidEsil)))
+                    } else {
+                        None
+                    };
+                    return self.add_repeat_inst(guard, cond);
+                }
+                _ => {}
+            }
+
             let op = match self.opset.get(&*token) {
                 Some(op) => op.clone(),
                 None => MOpcode::OpInvalid,
             };
 
             if op != MOpcode::OpInvalid {
+                match op {
+                    MOpcode::OpIf => depth += 1,
+                    MOpcode::OpCl if depth > 0 => depth -= 1,
+                    _ => {}
+                }
                 try!(self.add_inst(op));
                 continue;
             }
 
+            // Internal variables of ESIL, as `$z` or `$$`.
+            if let Some('$') = token.chars().nth(0) {
+                let v = MVal::new(token, self.default_size, MValType::Internal, 0, None);
+                self.stack.push(v);
+                continue;
+            }
+
             // If it contains atleast one alpha, it cannot be an operator.
             let re = Regex::new("[a-zA-Z]").unwrap();
             if re.is_match(&*token) {
-                let mut val_type = MValType::Unknown;
-                let mut val: Option<u64> = None;
-                let mut size: WidthSpec = self.default_size;
-                let mut reg_info: Option<MRegInfo> = None;
-                if let Some(r) = self.regset.get(&*token) {
-                    val_type = MValType::Register;
-                    let mut reg_info_ = self.new_mreg_info(r.clone());
+                let v = if let Some(r) = self.regset.get(&*token).cloned() {
+                    let mut reg_info = self.new_mreg_info(r.clone());
                     let alias = self.alias_info
-                                    .get(&reg_info_.reg)
+                                    .get(&reg_info.reg)
                                     .map(|x| x.role_str.clone())
                                     .unwrap_or_default();
-                    reg_info_.alias = alias;
-                    reg_info = Some(reg_info_.clone());
-                    size = r.size as WidthSpec;
+                    reg_info.alias = alias;
+                    MVal::new(token, r.size as WidthSpec, MValType::Register, 0, Some(reg_info))
                 } else if let Ok::<i64, _>(v) = hex_to_i!(token) {
                     // <u64>? will it be able to deal with negative numbers?
-                    val = Some(v as u64);
-                } else if let Some('$') = token.chars().nth(0) {
-                    val_type = MValType::Internal
+                    self.constant_value(v as u64)
                 } else {
-                    // TODO
-                    panic!("Proper error handling here");
-                }
-
-                let v = if let Some(cv) = val {
-                    self.constant_value(cv)
-                } else {
-                    MVal::new(token, size, val_type, 0, reg_info)
+                    return Err(ParseErrorKind::UnknownToken);
                 };
 
                 self.stack.push(v);
@@ -239,16 +417,16 @@ impl Parser {
                 if let Some(x) = self.stack.last() {
                     dst = x.clone();
                 } else {
-                    return Err(ParseError::InsufficientOperands);
-                }
-                let re = Regex::new(r"^(.|..)=$").unwrap();
-                let t = re.captures(&*token).unwrap().at(1).unwrap_or("");
-                if t.len() == 0 {
-                    return Err(ParseError::InvalidMOperator);
+                    return Err(ParseErrorKind::InsufficientOperands);
                 }
+                let re = Regex::new(r"^(.+)=$").unwrap();
+                let t = match re.captures(&*token).and_then(|c| c.at(1)) {
+                    Some(t) => t,
+                    None => return Err(ParseErrorKind::UnknownToken),
+                };
                 let op = match self.opset.get(t) {
                     Some(op) => op.clone(),
-                    None => return Err(ParseError::InvalidMOperator),
+                    None => return Err(ParseErrorKind::InvalidMOperator),
                 };
 
                 try!(self.add_inst(op));
@@ -258,31 +436,35 @@ impl Parser {
             }
 
             // Deal with memaccess 'composite' instructions.
-            let re = Regex::new(r"^(.|..)?(=)?\[([1248]?)\]$").unwrap();
-            let tokens = re.captures(&*token).unwrap();
+            let re = Regex::new(r"^(.*?)(=)?\[([0-9]*)\]$").unwrap();
+            let tokens = match re.captures(&*token) {
+                Some(tokens) => tokens,
+                None => return Err(ParseErrorKind::UnknownToken),
+            };
             let has_op = tokens.at(1).unwrap_or("");
             let eq = tokens.at(2).unwrap_or("");
-            let access_size = tokens.at(3).unwrap_or("");
-            let access_size = match access_size {
+            let access_size = match tokens.at(3).unwrap_or("") {
                 "" => self.default_size,
-                _ => access_size.parse::<WidthSpec>().unwrap() * 8,
+                "1" => 8,
+                "2" => 16,
+                "4" => 32,
+                "8" => 64,
+                "16" => 128,
+                _ => return Err(ParseErrorKind::InvalidMOperator),
             };
 
-            try!(self.add_inst(MOpcode::OpLoad));
-            // Set the correct size.
-            let mut x = self.stack.pop().unwrap();
-            self.add_narrow_inst(&mut x, access_size);
-            let tmp_dst1 = x.clone();
-            self.stack.push(x);
-
             // Simple 'peek' ([n])
             if eq.is_empty() {
+                if !has_op.is_empty() {
+                    return Err(ParseErrorKind::InvalidMOperator);
+                }
+                try!(self.add_load_inst(access_size));
                 continue;
             }
 
             // Simple 'poke' (=[n])
             if has_op.is_empty() {
-                try!(self.add_inst(MOpcode::OpStore));
+                try!(self.add_store_inst(access_size));
                 continue;
             }
 
@@ -290,12 +472,14 @@ impl Parser {
             let o = match self.opset.get(has_op) {
                 Some(x) => x.clone(),
                 // Return with error
-                None => return Err(ParseError::InvalidMOperator),
+                None => return Err(ParseErrorKind::InvalidMOperator),
             };
+            let addr = try!(self.stack.last().cloned().ok_or(ParseErrorKind::InsufficientOperands));
+            try!(self.add_load_inst(access_size));
             try!(self.add_inst(o));
             // Reassignment.
-            self.stack.push(tmp_dst1); // hope above add_inst doesn't invalidate dst1
-            try!(self.add_inst(MOpcode::OpStore));
+            self.stack.push(addr);
+            try!(self.add_store_inst(access_size));
         }
         Ok(())
     }
@@ -391,8 +575,155 @@ impl Parser {
         *op = dst;
     }
 
-    fn add_assign_inst(&mut self, op: MOpcode) -> Result<(), ParseError> {
-        let dst = try!(self.stack.pop().ok_or(ParseError::InsufficientOperands));
+    // Load from the address on top of the stack. The result has the width of
+    // the access.
+    fn add_load_inst(&mut self, size: WidthSpec) -> Result<(), ParseErrorKind> {
+        let endian = self.endian;
+        self.add_inst(MOpcode::OpLoad(size, endian))
+    }
+
+    // Store the value below the top of the stack to the address on top. The
+    // value is cast to the width of the access.
+    fn add_store_inst(&mut self, size: WidthSpec) -> Result<(), ParseErrorKind> {
+        let addr = try!(self.get_param());
+        let mut value = try!(self.get_param());
+        self.add_widen_inst(&mut value, size);
+        self.add_narrow_inst(&mut value, size);
+        let op = MOpcode::OpStore(size, self.endian);
+        let inst = op.to_inst(MVal::null(), addr, value, Some(MAddr::new(self.addr)));
+        self.insts.push(inst);
+        Ok(())
+    }
+
+    // Commands that only rearrange the stack.
+    fn stack_command(&mut self, command: &str) -> Result<(), ParseErrorKind> {
+        let len = self.stack.len();
+        match command {
+            "DUP" => {
+                let top = self.stack.last().cloned();
+                let top = try!(top.ok_or(ParseErrorKind::InsufficientOperands));
+                self.stack.push(top);
+            }
+            "SWAP" => {
+                if len < 2 {
+                    return Err(ParseErrorKind::InsufficientOperands);
+                }
+                self.stack.swap(len - 1, len - 2);
+            }
+            // `n,PICK` copies the n-th value from the top, starting at 1.
+            // `n,RPICK` copies the n-th value from the bottom, starting at 0.
+            "PICK" | "RPICK" => {
+                let n = try!(self.stack.pop().ok_or(ParseErrorKind::InsufficientOperands));
+                let n = try!(n.as_literal.ok_or(ParseErrorKind::InvalidEsil)) as usize;
+                let len = self.stack.len();
+                let index = if command == "PICK" {
+                    if n == 0 || n > len {
+                        return Err(ParseErrorKind::InsufficientOperands);
+                    }
+                    len - n
+                } else {
+                    if n >= len {
+                        return Err(ParseErrorKind::InsufficientOperands);
+                    }
+                    n
+                };
+                let v = self.stack[index].clone();
+                self.stack.push(v);
+            }
+            // Replaces a register by its value, which cannot be assigned to.
+            "NUM" => {
+                let v = try!(self.stack.pop().ok_or(ParseErrorKind::InsufficientOperands));
+                if v.val_type != MValType::Register {
+                    self.stack.push(v);
+                    return Ok(());
+                }
+                let tmp = self.get_tmp_register(v.size);
+                self.stack.push(v);
+                self.stack.push(tmp.clone());
+                try!(self.add_assign_inst(MOpcode::OpEq));
+                self.stack.push(tmp);
+            }
+            "POP" => {
+                try!(self.stack.pop().ok_or(ParseErrorKind::InsufficientOperands));
+            }
+            "CLEAR" => self.stack.clear(),
+            _ => return Err(ParseErrorKind::UnknownToken),
+        }
+        Ok(())
+    }
+
+    // Removes the innermost conditional if nothing but constants was emitted
+    // since it opened, and returns its condition.
+    fn take_condition(&mut self) -> Option<MVal> {
+        let pos = match self.insts.iter().rposition(|i| i.opcode == MOpcode::OpIf) {
+            Some(pos) => pos,
+            None => return None,
+        };
+        let only_constants = self.insts[pos + 1..].iter().all(|i| {
+            match i.opcode {
+                MOpcode::OpConst(_) => true,
+                _ => false,
+            }
+        });
+        if !only_constants || self.insts[pos].addr.val != self.addr {
+            return None;
+        }
+        Some(self.insts.remove(pos).operand_1)
+    }
+
+    // Jump back to the current instruction, which repeats it. The jump is
+    // taken unless `guard` holds and, if there is a `cond`, when it holds.
+    //
+    // ESIL checks the guard before each repetition, but the jump is at the
+    // end of the instruction, so at least one repetition is assumed.
+    fn add_repeat_inst(&mut self,
+                       guard: Option<MVal>,
+                       cond: Option<MVal>)
+                       -> Result<(), ParseErrorKind> {
+        let addr = MAddr::new(self.addr);
+        let mut taken = None;
+        if let Some(guard) = guard {
+            taken = Some(self.add_test_inst(MOpcode::OpCmp, guard));
+        }
+        if let Some(cond) = cond {
+            let holds = self.add_test_inst(MOpcode::OpLt, cond);
+            taken = match taken {
+                Some(t) => {
+                    let dst = self.get_tmp_register(1);
+                    let inst = MOpcode::OpAnd.to_inst(dst.clone(), t, holds, Some(addr.clone()));
+                    self.insts.push(inst);
+                    Some(dst)
+                }
+                None => Some(holds),
+            };
+        }
+
+        let target = self.constant_value(addr.val);
+        let inst = match taken {
+            Some(t) => MOpcode::OpCJmp.to_inst(MVal::null(), t, target, Some(addr)),
+            None => MOpcode::OpJmp.to_inst(MVal::null(), target, MVal::null(), Some(addr)),
+        };
+        self.insts.push(inst);
+        Ok(())
+    }
+
+    // Whether `v` is zero for `OpCmp`, or not zero for `OpLt`. The flags are
+    // not updated.
+    fn add_test_inst(&mut self, op: MOpcode, mut v: MVal) -> MVal {
+        let zero = self.constant_value(0);
+        self.add_widen_inst(&mut v, zero.size);
+        let (op1, op2) = match op {
+            MOpcode::OpLt => (zero, v),
+            _ => (v, zero),
+        };
+        let dst = self.get_tmp_register(1);
+        let inst = op.to_inst(dst.clone(), op1, op2, Some(MAddr::new(self.addr)));
+        self.insts.push(inst);
+        dst
+    }
+
+    fn add_assign_inst(&mut self, op: MOpcode) -> Result<(), ParseErrorKind> {
+        let dst = try!(self.stack.pop().ok_or(ParseErrorKind::InsufficientOperands));
         let mut op1 = try!(self.get_param());
 
         // Check the alias of dst. If it is the instruction pointer, the assignment
@@ -438,7 +769,7 @@ impl Parser {
         Ok(())
     }
 
-    fn add_inst(&mut self, op: MOpcode) -> Result<(), ParseError> {
+    fn add_inst(&mut self, op: MOpcode) -> Result<(), ParseErrorKind> {
         // Handle all the special cases.
         match op {
             MOpcode::OpCl => {
@@ -454,8 +785,10 @@ impl Parser {
                     MOpcode::OpDec => MOpcode::OpSub,
                     _ => unreachable!(),
                 };
-                let mut top = self.stack.len();
-                top -= 2;
+                if self.stack.is_empty() {
+                    return Err(ParseErrorKind::InsufficientOperands);
+                }
+                let top = self.stack.len() - 1;
                 let v = self.constant_value(1);
                 self.stack.insert(top, v);
                 try!(self.add_inst(_op));
@@ -501,10 +834,18 @@ impl Parser {
         }
 
         if op == MOpcode::OpGt || op == MOpcode::OpLt || op == MOpcode::OpGteq ||
-           op == MOpcode::OpLteq {
+           op == MOpcode::OpLteq || op == MOpcode::OpFCmp || op == MOpcode::OpFLt ||
+           op == MOpcode::OpFLteq {
             dst.size = 1;
         }
 
+        // Conversions determine the width of their result.
+        match op {
+            MOpcode::OpItoF(w) | MOpcode::OpSItoF(w) | MOpcode::OpFtoI(w) |
+            MOpcode::OpFConv(w) | MOpcode::OpLoad(w, _) => dst.size = w,
+            _ => {}
+        }
+
         let addr = MAddr::new(self.addr);
         let mut inst = op.to_inst(dst.clone(), op2, op1, Some(addr));
         inst.update_flags = update_flags;
@@ -519,9 +860,9 @@ impl Parser {
     }
 
     // correspons to r_anal_esil_get_parm
-    fn get_param(&mut self) -> Result<MVal, ParseError> {
+    fn get_param(&mut self) -> Result<MVal, ParseErrorKind> {
         let v = self.stack.pop();
-        let mv = try!(v.ok_or(ParseError::InsufficientOperands));
+        let mv = try!(v.ok_or(ParseErrorKind::InsufficientOperands));
         if mv.val_type != MValType::Internal {
             return Ok(mv);
         }
@@ -546,41 +887,41 @@ impl Parser {
                 let s = "==";
                 tmp_p.stack.push(self.constant_value(0));
                 tmp_p.stack.push(MVal::esilcur());
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
             }
             'b' => {
                 tmp_p.stack.push(MVal::esilold());
                 let s = format!("1,{},0x3f,&,0x3f,+,0x3f,&,2,<<,-", bit);
-                try!(tmp_p.parse_str(&*s));
+                try!(tmp_p.parse_expr(&*s));
                 let tmp_mval = tmp_p.stack.last().unwrap().clone();
-                try!(tmp_p.parse_str("&"));
+                try!(tmp_p.parse_expr("&"));
                 tmp_p.stack.push(tmp_mval);
                 tmp_p.stack.push(MVal::esilcur());
                 let s = "&,<";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
             }
             'c' => {
                 tmp_p.stack.push(MVal::esilcur());
                 let s = format!("1,{},0x3f,&,2,<<,-", bit);
-                try!(tmp_p.parse_str(&*s));
+                try!(tmp_p.parse_expr(&*s));
                 let tmp_mval = tmp_p.stack.last().unwrap().clone();
-                try!(tmp_p.parse_str("&"));
+                try!(tmp_p.parse_expr("&"));
                 tmp_p.stack.push(tmp_mval);
                 tmp_p.stack.push(MVal::esilold());
                 let s = "&,<";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
             }
             'p' => {
                 let mut s = "1,&,^".to_string();
                 tmp_p.stack.push(self.constant_value(1));
                 tmp_p.stack.push(MVal::esilcur());
-                try!(tmp_p.parse_str(&*s));
+                try!(tmp_p.parse_expr(&*s));
                 for i in 1..8 {
                     tmp_p.stack.push(self.constant_value(1));
                     tmp_p.stack.push(self.constant_value(i));
                     tmp_p.stack.push(MVal::esilcur());
                     s = format!(">>,&,^");
-                    try!(tmp_p.parse_str(&*s));
+                    try!(tmp_p.parse_expr(&*s));
                 }
             }
             'r' => {
@@ -596,16 +937,16 @@ impl Parser {
                     tmp_p.stack.push(self.constant_value(i));
                     tmp_p.stack.push(MVal::esillastsz());
                     let s = format!("-,0x3f,&,2,<<,-");
-                    try!(tmp_p.parse_str(&*s));
+                    try!(tmp_p.parse_expr(&*s));
                     let tmp_mval = tmp_p.stack.last().unwrap().clone();
-                    try!(tmp_p.parse_str("&"));
+                    try!(tmp_p.parse_expr("&"));
                     tmp_p.stack.push(tmp_mval);
                     tmp_p.stack.push(MVal::esilold());
                     let s = "&,<";
-                    try!(tmp_p.parse_str(s));
+                    try!(tmp_p.parse_expr(s));
                 }
                 let s = "^";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
             }
             's' => {
                 // !!((esil->cur & (0x1<<(esil->lastsz-1)))>>(esil->lastsz-1));
@@ -613,17 +954,17 @@ impl Parser {
                 tmp_p.stack.push(self.constant_value(1));
                 tmp_p.stack.push(MVal::esillastsz());
                 let s = "-".to_string();
-                try!(tmp_p.parse_str(&*s));
+                try!(tmp_p.parse_expr(&*s));
                 let v = tmp_p.stack.last().unwrap().clone();
                 let s = "1,<<,&";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
                 tmp_p.stack.insert(0, v.clone());
                 let s = ">>";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
                 let s = "0,==,!";
-                try!(tmp_p.parse_str(s));
+                try!(tmp_p.parse_expr(s));
             }
-            _ => panic!("Invalid internal variable!"),
+            _ => return Err(ParseErrorKind::InvalidInternal),
         }
 
         // Copy generated insts.
@@ -685,9 +1026,279 @@ fn map_esil_to_opset() -> HashMap<String, MOpcode> {
           ("-".to_owned(), MOpcode::OpSub),
           ("/".to_owned(), MOpcode::OpDiv),
           ("%".to_owned(), MOpcode::OpMod),
+          (">>>>".to_owned(), MOpcode::OpAsr),
+          ("<<<".to_owned(), MOpcode::OpRol),
+          (">>>".to_owned(), MOpcode::OpRor),
+          ("~/".to_owned(), MOpcode::OpSDiv),
+          ("~%".to_owned(), MOpcode::OpSMod),
           ("?{".to_owned(), MOpcode::OpIf),
           ("!".to_owned(), MOpcode::OpNot),
+          ("F+".to_owned(), MOpcode::OpFAdd),
+          ("F-".to_owned(), MOpcode::OpFSub),
+          ("F*".to_owned(), MOpcode::OpFMul),
+          ("F/".to_owned(), MOpcode::OpFDiv),
+          ("F==".to_owned(), MOpcode::OpFCmp),
+          ("F<".to_owned(), MOpcode::OpFLt),
+          ("F<=".to_owned(), MOpcode::OpFLteq),
+          ("I2D".to_owned(), MOpcode::OpItoF(64)),
+          ("S2D".to_owned(), MOpcode::OpSItoF(64)),
+          ("D2I".to_owned(), MOpcode::OpFtoI(64)),
+          ("F2D".to_owned(), MOpcode::OpFConv(64)),
+          ("D2F".to_owned(), MOpcode::OpFConv(32)),
           ("--".to_owned(), MOpcode::OpDec),
           ("++".to_owned(), MOpcode::OpInc),
           ("}".to_owned(), MOpcode::OpCl)]
 }
+
+// Index of the token after the `}` closing the conditional that contains the
+// token before `i`.
+fn skip_conditional(esil: &[String], mut i: usize) -> usize {
+    let mut depth = 1;
+    while i < esil.len() && depth > 0 {
+        match &*esil[i] {
+            "?{" => depth += 1,
+            "}" => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use frontend::offline::read_reg_info;
+    use middle::arch::{Architecture, Endian};
+    use middle::ir::{MInst, MOpcode, MValType};
+
+    fn parse(esil: &str) -> Result<Vec<MInst>, ParseError> {
+        let mut p = Parser::new(None);
+        p.set_register_profile(&read_reg_info("./ex-bins/json/simple2/regs.json").unwrap());
+        p.addr = 0x1000;
+        try!(p.parse_str(esil));
+        Ok(p.emit_insts())
+    }
+
+    // The instructions of `esil` that are not constants.
+    fn ops(esil: &str) -> Vec<MInst> {
+        parse(esil)
+            .unwrap()
+            .into_iter()
+            .filter(|i| {
+                match i.opcode {
+                    MOpcode::OpConst(_) => false,
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+
+    fn opcodes(esil: &str) -> Vec<MOpcode> {
+        ops(esil).iter().map(|i| i.opcode).collect()
+    }
+
+    #[test]
+    fn shifts_and_rotations() {
+        let insts = ops("4,rax,>>>>,rbx,=");
+        assert_eq!(insts[0].opcode, MOpcode::OpAsr);
+        assert_eq!(insts[0].operand_1.name, "rax");
+        assert_eq!(insts[0].operand_2.as_literal, Some(4));
+        assert_eq!(opcodes("4,rax,<<<,rbx,=")[0], MOpcode::OpRol);
+        assert_eq!(opcodes("4,rax,>>>,rbx,=")[0], MOpcode::OpRor);
+        assert_eq!(opcodes("4,rax,>>>>="), vec![MOpcode::OpAsr, MOpcode::OpEq]);
+        assert_eq!(opcodes("cl,rax,<<<="),
+                   vec![MOpcode::OpWiden(64), MOpcode::OpRol, MOpcode::OpEq]);
+    }
+
+    #[test]
+    fn signed_division() {
+        assert_eq!(opcodes("rbx,rax,~/,rax,="), vec![MOpcode::OpSDiv, MOpcode::OpEq]);
+        assert_eq!(opcodes("rbx,rax,~%,rdx,="), vec![MOpcode::OpSMod, MOpcode::OpEq]);
+    }
+
+    #[test]
+    fn compound_assignments() {
+        let cases = [("+=", MOpcode::OpAdd),
+                     ("-=", MOpcode::OpSub),
+                     ("*=", MOpcode::OpMul),
+                     ("/=", MOpcode::OpDiv),
+                     ("%=", MOpcode::OpMod),
+                     ("&=", MOpcode::OpAnd),
+                     ("|=", MOpcode::OpOr),
+                     ("^=", MOpcode::OpXor),
+                     ("<<=", MOpcode::OpLsl),
+                     (">>=", MOpcode::OpLsr)];
+        for &(token, opcode) in &cases {
+            let insts = ops(&format!("rbx,rax,{}", token));
+            assert_eq!(insts.len(), 2);
+            assert_eq!(insts[0].opcode, opcode);
+            assert_eq!(insts[0].operand_1.name, "rax");
+            assert_eq!(insts[0].operand_2.name, "rbx");
+            assert_eq!(insts[1].opcode, MOpcode::OpEq);
+            assert_eq!(insts[1].dst.name, "rax");
+        }
+
+        let insts = ops("rcx,--=");
+        assert_eq!(insts[0].opcode, MOpcode::OpSub);
+        assert_eq!(insts[0].operand_1.name, "rcx");
+        assert_eq!(insts[0].operand_2.as_literal, Some(1));
+        assert_eq!(insts[1].dst.name, "rcx");
+        assert_eq!(opcodes("rcx,++="), vec![MOpcode::OpAdd, MOpcode::OpEq]);
+    }
+
+    #[test]
+    fn stack_commands() {
+        let insts = ops("rax,rbx,SWAP,-,rcx,=");
+        assert_eq!(insts[0].operand_1.name, "rax");
+        assert_eq!(insts[0].operand_2.name, "rbx");
+
+        let insts = ops("rax,DUP,+,rcx,=");
+        assert_eq!(insts[0].operand_1.name, "rax");
+        assert_eq!(insts[0].operand_2.name, "rax");
+
+        let insts = ops("rax,rbx,rcx,2,PICK,+,rdx,=");
+        assert_eq!(insts[0].operand_1.name, "rbx");
+        assert_eq!(insts[0].operand_2.name, "rcx");
+
+        let insts = ops("rax,rbx,rcx,0,RPICK,+,rdx,=");
+        assert_eq!(insts[0].operand_1.name, "rax");
+
+        let insts = ops("rax,rbx,POP,rcx,=");
+        assert_eq!(insts.len(), 1);
+        assert_eq!(insts[0].operand_1.name, "rax");
+        assert_eq!(insts[0].dst.name, "rcx");
+
+        assert!(ops("rax,rbx,CLEAR").is_empty());
+        assert!(parse("rax,CLEAR,DUP").is_err());
+
+        // The value of the register is copied, so the assignment is to the
+        // copy.
+        let insts = ops("rax,NUM,rbx,SWAP,=");
+        assert_eq!(insts[0].operand_1.name, "rax");
+        assert_eq!(insts[0].dst.val_type, MValType::Temporary);
+        assert_eq!(insts[1].dst.name, insts[0].dst.name);
+    }
+
+    #[test]
+    fn current_address() {
+        let insts = parse("$$,rax,=").unwrap();
+        assert_eq!(insts[0].opcode, MOpcode::OpConst(0x1000));
+        assert_eq!(insts[1].operand_1.as_literal, Some(0x1000));
+    }
+
+    #[test]
+    fn memory_access() {
+        assert_eq!(opcodes("rax,[4],ebx,="),
+                   vec![MOpcode::OpLoad(32, Endian::Little), MOpcode::OpEq]);
+
+        let insts = ops("rdx,rsi,=[8]");
+        assert_eq!(insts.len(), 1);
+        assert_eq!(insts[0].opcode, MOpcode::OpStore(64, Endian::Little));
+        assert_eq!(insts[0].operand_1.name, "rsi");
+        assert_eq!(insts[0].operand_2.name, "rdx");
+
+        let insts = ops("rdx,rsi,+=[8]");
+        assert_eq!(insts.iter().map(|i| i.opcode).collect::<Vec<_>>(),
+                   vec![MOpcode::OpLoad(64, Endian::Little),
+                        MOpcode::OpAdd,
+                        MOpcode::OpStore(64, Endian::Little)]);
+        assert_eq!(insts[1].operand_2.name, "rdx");
+        assert_eq!(insts[2].operand_1.name, "rsi");
+        assert_eq!(insts[2].operand_2.name, insts[1].dst.name);
+
+        assert!(parse("rsi,[3]").is_err());
+
+        // The stored value is cast to the width of the access.
+        let insts = ops("rdx,rsi,=[2]");
+        assert_eq!(insts.iter().map(|i| i.opcode).collect::<Vec<_>>(),
+                   vec![MOpcode::OpNarrow(16), MOpcode::OpStore(16, Endian::Little)]);
+        assert_eq!(insts[1].operand_2.size, 16);
+    }
+
+    #[test]
+    fn big_endian() {
+        let mut p = Parser::new(None);
+        p.set_register_profile(&read_reg_info("./ex-bins/json/simple2/regs.json").unwrap());
+        p.set_architecture(&Architecture::x86_64().with_endian(Some(Endian::Big)));
+        p.parse_str("rax,[4],ebx,=,ebx,rax,=[2]").unwrap();
+        let insts = p.emit_insts();
+        assert_eq!(insts[0].opcode, MOpcode::OpLoad(32, Endian::Big));
+        assert_eq!(insts.last().unwrap().opcode, MOpcode::OpStore(16, Endian::Big));
+    }
+
+    #[test]
+    fn repeat() {
+        // rep stosb
+        let insts = ops("rcx,!,?{,BREAK,},al,rdi,=[1],1,rdi,+=,1,rcx,-=,0,GOTO");
+        assert!(insts.iter().all(|i| i.opcode != MOpcode::OpIf));
+        assert!(insts.iter().any(|i| i.opcode == MOpcode::OpStore(8, Endian::Little)));
+        let jump = insts.last().unwrap();
+        assert_eq!(jump.opcode, MOpcode::OpCJmp);
+        assert_eq!(jump.operand_2.as_literal, Some(0x1000));
+        assert_eq!(jump.operand_1.size, 1);
+
+        // The jump is taken while the condition holds.
+        let insts = ops("1,rcx,-=,rcx,?{,0,GOTO,}");
+        let jump = insts.last().unwrap();
+        assert_eq!(jump.opcode, MOpcode::OpCJmp);
+        assert_eq!(insts[insts.len() - 2].opcode, MOpcode::OpLt);
+
+        // Only jumps to the start are supported.
+        assert!(parse("rax,rbx,=,rcx,?{,3,GOTO,}").is_err());
+    }
+
+    #[test]
+    fn break_and_todo() {
+        let insts = ops("rax,rbx,=,BREAK,rcx,rdx,=");
+        assert_eq!(insts.len(), 1);
+        assert_eq!(insts[0].dst.name, "rbx");
+        assert_eq!(ops("rax,rbx,=,TODO,rcx,rdx,=").len(), 1);
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        assert!(parse("foo,rax,=").is_err());
+        assert!(parse("rax,rbx,@@").is_err());
+        assert!(parse("rax,rbx,==>").is_err());
+    }
+
+    #[test]
+    fn error_location() {
+        let err = parse("rax,rbx,foo,+,rcx,=").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnknownToken);
+        assert_eq!(err.addr, 0x1000);
+        assert_eq!(err.esil, "rax,rbx,foo,+,rcx,=");
+        assert_eq!(err.index, 2);
+        assert_eq!(err.token, "foo");
+        assert_eq!(err.to_string(),
+                   "unknown token at 0x1000: token 2 `foo` of `rax,rbx,foo,+,rcx,=`");
+
+        assert_eq!(parse("rax,+").unwrap_err().kind, ParseErrorKind::InsufficientOperands);
+        assert_eq!(parse("$q,rax,=").unwrap_err().kind, ParseErrorKind::InvalidInternal);
+        assert_eq!(parse("").unwrap_err().kind, ParseErrorKind::InvalidEsil);
+    }
+
+    #[test]
+    fn recovery() {
+        let mut p = Parser::new(None);
+        p.set_register_profile(&read_reg_info("./ex-bins/json/simple2/regs.json").unwrap());
+        p.set_recovery(true);
+        p.addr = 0x1000;
+        p.parse_str("rax,rbx,=").unwrap();
+        p.addr = 0x1003;
+        p.parse_str("1,rax,+,foo,=").unwrap();
+        p.addr = 0x1006;
+        p.parse_str("rbx,rcx,=").unwrap();
+
+        assert_eq!(p.errors().len(), 1);
+        assert_eq!(p.errors()[0].addr, 0x1003);
+        assert_eq!(p.errors()[0].token, "foo");
+        let insts = p.emit_insts();
+        let opcodes = insts.iter().map(|i| (i.addr.val, i.opcode)).collect::<Vec<_>>();
+        assert_eq!(opcodes,
+                   vec![(0x1000, MOpcode::OpEq),
+                        (0x1003, MOpcode::OpUnknown),
+                        (0x1006, MOpcode::OpEq)]);
+    }
+}
@@ -52,6 +52,7 @@ extern crate esil;
 
 #[macro_use]
 pub mod utils;
+#[macro_use]
 pub mod middle;
 pub mod analysis;
 pub mod frontend;
@@ -0,0 +1,295 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Describes the architecture a function was compiled for.
+//!
+//! An `Architecture` names the registers with a special role, the registers
+//! that merely collect the individual flags and the word size and byte
+//! order of the machine. Built-in profiles exist for x86, x86-64, ARM,
+//! AArch64, MIPS and PowerPC. `Architecture::detect` picks one for a
+//! register profile of radare2 and refines it with the aliases of the
+//! profile.
+//!
+//! A register profile does not tell the byte order, so the built-in profiles
+//! have the usual one of their architecture, and unknown architectures are
+//! little endian. The byte order radare2 reports for a binary replaces it,
+//! see `Architecture::with_endian`.
+
+use r2pipe::structs::LRegInfo;
+
+use middle::ir::WidthSpec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    /// Looks up a byte order by the name radare2 gives it.
+    pub fn from_name(name: &str) -> Option<Endian> {
+        match name {
+            "little" => Some(Endian::Little),
+            "big" => Some(Endian::Big),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Architecture {
+    pub name: String,
+    /// Width of the general purpose registers and of addresses.
+    pub word_size: WidthSpec,
+    pub endian: Endian,
+    pub pc: String,
+    pub sp: String,
+    /// Frame pointer, if the architecture has a conventional one.
+    pub bp: Option<String>,
+    /// Register holding the return value of a function.
+    pub ret: String,
+    /// Registers that combine the individual flags, as `rflags` on x86-64.
+    /// They are not modelled, the flags they contain are.
+    pub flag_registers: Vec<String>,
+}
+
+fn strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+impl Architecture {
+    pub fn x86() -> Architecture {
+        Architecture {
+            name: "x86".to_owned(),
+            word_size: 32,
+            endian: Endian::Little,
+            pc: "eip".to_owned(),
+            sp: "esp".to_owned(),
+            bp: Some("ebp".to_owned()),
+            ret: "eax".to_owned(),
+            flag_registers: strings(&["eflags", "flags"]),
+        }
+    }
+
+    pub fn x86_64() -> Architecture {
+        Architecture {
+            name: "x86_64".to_owned(),
+            word_size: 64,
+            endian: Endian::Little,
+            pc: "rip".to_owned(),
+            sp: "rsp".to_owned(),
+            bp: Some("rbp".to_owned()),
+            ret: "rax".to_owned(),
+            flag_registers: strings(&["rflags", "eflags", "flags"]),
+        }
+    }
+
+    /// 32 bit ARM. The flags are kept in `cpsr`.
+    pub fn arm() -> Architecture {
+        Architecture {
+            name: "arm".to_owned(),
+            word_size: 32,
+            endian: Endian::Little,
+            pc: "pc".to_owned(),
+            sp: "sp".to_owned(),
+            bp: Some("fp".to_owned()),
+            ret: "r0".to_owned(),
+            flag_registers: strings(&["cpsr"]),
+        }
+    }
+
+    pub fn aarch64() -> Architecture {
+        Architecture {
+            name: "aarch64".to_owned(),
+            word_size: 64,
+            endian: Endian::Little,
+            pc: "pc".to_owned(),
+            sp: "sp".to_owned(),
+            bp: Some("fp".to_owned()),
+            ret: "x0".to_owned(),
+            flag_registers: strings(&["nzcv", "pstate"]),
+        }
+    }
+
+    /// 32 bit MIPS, big endian as in most firmware. MIPS has no flags.
+    pub fn mips() -> Architecture {
+        Architecture {
+            name: "mips".to_owned(),
+            word_size: 32,
+            endian: Endian::Big,
+            pc: "pc".to_owned(),
+            sp: "sp".to_owned(),
+            bp: Some("fp".to_owned()),
+            ret: "v0".to_owned(),
+            flag_registers: Vec::new(),
+        }
+    }
+
+    /// 32 bit PowerPC. The condition register is modelled as a whole.
+    pub fn ppc() -> Architecture {
+        Architecture {
+            name: "ppc".to_owned(),
+            word_size: 32,
+            endian: Endian::Big,
+            pc: "pc".to_owned(),
+            sp: "r1".to_owned(),
+            bp: None,
+            ret: "r3".to_owned(),
+            flag_registers: Vec::new(),
+        }
+    }
+
+    /// Looks up a built-in profile by the name radare2 gives the
+    /// architecture and the number of bits.
+    pub fn from_name(name: &str, bits: WidthSpec) -> Option<Architecture> {
+        match (name, bits) {
+            ("x86", 64) | ("x86_64", _) => Some(Architecture::x86_64()),
+            ("x86", _) => Some(Architecture::x86()),
+            ("arm", 64) | ("aarch64", _) => Some(Architecture::aarch64()),
+            ("arm", _) => Some(Architecture::arm()),
+            ("mips", _) => {
+                let mut arch = Architecture::mips();
+                arch.word_size = if bits == 64 { 64 } else { 32 };
+                Some(arch)
+            }
+            ("ppc", _) => {
+                let mut arch = Architecture::ppc();
+                arch.word_size = if bits == 64 { 64 } else { 32 };
+                Some(arch)
+            }
+            _ => None,
+        }
+    }
+
+    /// Picks the architecture of a register profile. The registers given a
+    /// role by the aliases of the profile take precedence over those of the
+    /// built-in profile. Unknown architectures are described by the aliases
+    /// alone.
+    pub fn detect(reg_info: &LRegInfo) -> Architecture {
+        let has = |name: &str| reg_info.reg_info.iter().any(|r| r.name == name);
+        let alias = |role: &str| {
+            reg_info.alias_info
+                    .iter()
+                    .find(|a| a.role_str == role)
+                    .map(|a| a.reg.clone())
+        };
+
+        let mut arch = if has("rip") {
+            Architecture::x86_64()
+        } else if has("eip") {
+            Architecture::x86()
+        } else if has("x0") && has("x30") {
+            Architecture::aarch64()
+        } else if has("r1") && has("ctr") {
+            Architecture::ppc()
+        } else if has("r0") && has("lr") {
+            Architecture::arm()
+        } else if has("v0") && has("a0") {
+            Architecture::mips()
+        } else {
+            Architecture {
+                name: "unknown".to_owned(),
+                word_size: 32,
+                endian: Endian::Little,
+                pc: "pc".to_owned(),
+                sp: "sp".to_owned(),
+                bp: None,
+                ret: alias("A0").unwrap_or("r0".to_owned()),
+                flag_registers: Vec::new(),
+            }
+        };
+
+        if let Some(pc) = alias("PC") {
+            arch.pc = pc;
+        }
+        if let Some(sp) = alias("SP") {
+            arch.sp = sp;
+        }
+        if let Some(bp) = alias("BP") {
+            arch.bp = Some(bp);
+        }
+        if let Some(ret) = alias("R0") {
+            arch.ret = ret;
+        }
+        if let Some(sr) = alias("SR") {
+            if !arch.flag_registers.contains(&sr) {
+                arch.flag_registers.push(sr);
+            }
+        }
+        // The program counter is as wide as an address.
+        if let Some(pc) = reg_info.reg_info.iter().find(|r| r.name == arch.pc) {
+            arch.word_size = pc.size as WidthSpec;
+        }
+        arch
+    }
+
+    /// `self` with the byte order radare2 reports for the binary, if it is
+    /// known.
+    pub fn with_endian(mut self, endian: Option<Endian>) -> Architecture {
+        if let Some(endian) = endian {
+            self.endian = endian;
+        }
+        self
+    }
+
+    pub fn is_flag_register(&self, name: &str) -> bool {
+        self.flag_registers.iter().any(|r| r == name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use frontend::offline;
+
+    #[test]
+    fn detect_x86_64() {
+        let reg_info = offline::read_reg_info("./ex-bins/json/simple2/regs.json").unwrap();
+        let arch = Architecture::detect(&reg_info);
+        assert_eq!(arch.name, "x86_64");
+        assert_eq!(arch.word_size, 64);
+        assert_eq!(arch.pc, "rip");
+        assert_eq!(arch.sp, "rsp");
+        assert!(arch.is_flag_register("rflags"));
+        assert!(!arch.is_flag_register("zf"));
+    }
+
+    #[test]
+    fn detect_arm() {
+        let reg_info = offline::read_reg_info("./ex-bins/json/arm/regs.json").unwrap();
+        let arch = Architecture::detect(&reg_info);
+        assert_eq!(arch.name, "arm");
+        assert_eq!(arch.word_size, 32);
+        assert_eq!(arch.endian, Endian::Little);
+        assert_eq!(arch.pc, "pc");
+        assert!(arch.is_flag_register("cpsr"));
+    }
+
+    #[test]
+    fn detect_ppc() {
+        let reg_info = offline::read_reg_info("./ex-bins/json/ppc/regs.json").unwrap();
+        let arch = Architecture::detect(&reg_info);
+        assert_eq!(arch.name, "ppc");
+        assert_eq!(arch.sp, "r1");
+        assert_eq!(arch.ret, "r3");
+        assert_eq!(arch.endian, Endian::Big);
+        assert_eq!(offline::read_endian("./ex-bins/json/ppc/info.json").unwrap(),
+                   Some(Endian::Big));
+        assert_eq!(offline::read_endian("./ex-bins/json/arm/info.json").unwrap(), None);
+        assert_eq!(arch.with_endian(Some(Endian::Little)).endian, Endian::Little);
+        assert_eq!(Architecture::x86().with_endian(None).endian, Endian::Little);
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(Architecture::from_name("x86", 32), Some(Architecture::x86()));
+        assert_eq!(Architecture::from_name("arm", 64), Some(Architecture::aarch64()));
+        assert_eq!(Architecture::from_name("mips", 32).unwrap().endian, Endian::Big);
+        assert!(Architecture::from_name("z80", 8).is_none());
+        assert_eq!(Endian::from_name("big"), Some(Endian::Big));
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Describes how functions pass arguments and return values.
+//!
+//! During SSA construction a call consumes the values of the argument
+//! registers, leaves the caller-saved registers undefined and defines the
+//! return register with its result. A callee that is known to leave the
+//! return register alone but to modify the floating point return register
+//! defines the latter instead.
+
+use r2pipe::structs::LRegInfo;
+
+use middle::arch::Architecture;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallingConvention {
+    pub name: String,
+    /// Registers holding the arguments, in order. Further arguments are
+    /// passed on the stack.
+    pub args: Vec<String>,
+    /// Register holding the return value.
+    pub ret: String,
+    /// Register holding a floating point return value, if the convention
+    /// has one.
+    pub float_ret: Option<String>,
+    /// Registers that a call may modify, besides the return register.
+    pub clobbered: Vec<String>,
+    pub stack_pointer: String,
+    /// Bytes pushed by the call instruction, which are popped again when the
+    /// callee returns.
+    pub return_address: u64,
+}
+
+fn strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+impl CallingConvention {
+    /// System V AMD64 ABI.
+    pub fn sysv_x86_64() -> CallingConvention {
+        CallingConvention {
+            name: "amd64".to_owned(),
+            args: strings(&["rdi", "rsi", "rdx", "rcx", "r8", "r9"]),
+            ret: "rax".to_owned(),
+            float_ret: Some("xmm0".to_owned()),
+            clobbered: strings(&["rcx", "rdx", "rsi", "rdi", "r8", "r9", "r10", "r11", "cf",
+                                 "zf", "sf", "of", "pf", "xmm0", "xmm1", "xmm2", "xmm3", "xmm4",
+                                 "xmm5", "xmm6", "xmm7", "xmm8", "xmm9", "xmm10", "xmm11",
+                                 "xmm12", "xmm13", "xmm14", "xmm15"]),
+            stack_pointer: "rsp".to_owned(),
+            return_address: 8,
+        }
+    }
+
+    /// x86 cdecl. All arguments are passed on the stack, and floating point
+    /// values are returned on the x87 stack.
+    pub fn cdecl() -> CallingConvention {
+        CallingConvention {
+            name: "cdecl".to_owned(),
+            args: Vec::new(),
+            ret: "eax".to_owned(),
+            float_ret: Some("st0".to_owned()),
+            clobbered: strings(&["ecx", "edx", "cf", "zf", "sf", "of", "pf", "xmm0", "xmm1", "xmm2",
+                                 "xmm3", "xmm4", "xmm5", "xmm6", "xmm7"]),
+            stack_pointer: "esp".to_owned(),
+            return_address: 4,
+        }
+    }
+
+    /// ARM Procedure Call Standard. The return address is kept in `lr`.
+    pub fn aapcs() -> CallingConvention {
+        CallingConvention {
+            name: "arm32".to_owned(),
+            args: strings(&["r0", "r1", "r2", "r3"]),
+            ret: "r0".to_owned(),
+            float_ret: None,
+            clobbered: strings(&["r1", "r2", "r3", "r12", "lr"]),
+            stack_pointer: "sp".to_owned(),
+            return_address: 0,
+        }
+    }
+
+    /// Procedure Call Standard for AArch64. The return address is kept in
+    /// `x30`.
+    pub fn aapcs64() -> CallingConvention {
+        CallingConvention {
+            name: "arm64".to_owned(),
+            args: strings(&["x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7"]),
+            ret: "x0".to_owned(),
+            float_ret: None,
+            clobbered: strings(&["x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10",
+                                 "x11", "x12", "x13", "x14", "x15", "x16", "x17", "x18",
+                                 "x30"]),
+            stack_pointer: "sp".to_owned(),
+            return_address: 0,
+        }
+    }
+
+    /// MIPS o32. The return address is kept in `ra`.
+    pub fn o32() -> CallingConvention {
+        CallingConvention {
+            name: "o32".to_owned(),
+            args: strings(&["a0", "a1", "a2", "a3"]),
+            ret: "v0".to_owned(),
+            float_ret: None,
+            clobbered: strings(&["at", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3",
+                                 "t4", "t5", "t6", "t7", "t8", "t9", "ra"]),
+            stack_pointer: "sp".to_owned(),
+            return_address: 0,
+        }
+    }
+
+    /// Looks up a convention by its name in radare2.
+    pub fn from_name(name: &str) -> Option<CallingConvention> {
+        match name {
+            "amd64" | "sysv" => Some(CallingConvention::sysv_x86_64()),
+            "cdecl" => Some(CallingConvention::cdecl()),
+            "arm32" | "aapcs" => Some(CallingConvention::aapcs()),
+            "arm64" | "aapcs64" => Some(CallingConvention::aapcs64()),
+            "o32" | "mips" => Some(CallingConvention::o32()),
+            _ => None,
+        }
+    }
+
+    /// Picks the default convention for the architecture of a register
+    /// profile.
+    pub fn for_registers(reg_info: &LRegInfo) -> Option<CallingConvention> {
+        CallingConvention::for_architecture(&Architecture::detect(reg_info))
+    }
+
+    /// The default convention of `arch`.
+    pub fn for_architecture(arch: &Architecture) -> Option<CallingConvention> {
+        match &arch.name[..] {
+            "x86_64" => Some(CallingConvention::sysv_x86_64()),
+            "x86" => Some(CallingConvention::cdecl()),
+            "arm" => Some(CallingConvention::aapcs()),
+            "aarch64" => Some(CallingConvention::aapcs64()),
+            "mips" => Some(CallingConvention::o32()),
+            _ => None,
+        }
+    }
+
+    /// Every register a call may modify.
+    pub fn modified(&self) -> Vec<String> {
+        let mut regs = self.clobbered.clone();
+        regs.push(self.ret.clone());
+        if let Some(ref f) = self.float_ret {
+            if !regs.contains(f) {
+                regs.push(f.clone());
+            }
+        }
+        regs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        assert_eq!(CallingConvention::from_name("amd64"),
+                   Some(CallingConvention::sysv_x86_64()));
+        assert_eq!(CallingConvention::from_name("arm32").unwrap().args.len(), 4);
+        assert!(CallingConvention::from_name("fastcall").is_none());
+        assert_eq!(CallingConvention::for_architecture(&Architecture::mips()),
+                   Some(CallingConvention::o32()));
+        assert!(!CallingConvention::cdecl().modified().contains(&"ebx".to_owned()));
+        assert!(CallingConvention::cdecl().modified().contains(&"st0".to_owned()));
+        assert!(CallingConvention::sysv_x86_64().modified().contains(&"zf".to_owned()));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Call graph of a program.
+//!
+//! Every function is a node. An edge connects a caller to the target of each
+//! of its calls whose target is a constant. Targets that are not among the
+//! analyzed functions, such as imports, are added as external functions.
+
+use std::collections::{HashMap, HashSet};
+
+use petgraph::EdgeDirection;
+use petgraph::graph::{self, EdgeIndex, Graph, NodeIndex};
+
+use middle::dot::{DotAttrBlock, GraphDot};
+use middle::ir::MOpcode;
+use middle::ssa::{SSA, SSAStorage};
+use middle::ssa::ssa_traits::NodeType;
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub addr: u64,
+    /// Whether the function was not analyzed, only called.
+    pub external: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CallSite {
+    /// The `OpCall` node in the SSA of the caller.
+    pub call: NodeIndex,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+    pub g: Graph<Function, CallSite>,
+    functions: HashMap<u64, NodeIndex>,
+    /// Calls whose target is not a constant, per caller.
+    pub indirect: HashMap<NodeIndex, Vec<NodeIndex>>,
+}
+
+impl CallGraph {
+    pub fn new() -> CallGraph {
+        CallGraph {
+            g: Graph::new(),
+            functions: HashMap::new(),
+            indirect: HashMap::new(),
+        }
+    }
+
+    /// Adds an analyzed function. A function that was so far only known as
+    /// the target of a call stops being external.
+    pub fn add_function(&mut self, name: String, addr: u64) -> NodeIndex {
+        if let Some(&n) = self.functions.get(&addr) {
+            self.g[n].name = name;
+            self.g[n].external = false;
+            return n;
+        }
+        let n = self.g.add_node(Function {
+            name: name,
+            addr: addr,
+            external: false,
+        });
+        self.functions.insert(addr, n);
+        n
+    }
+
+    pub fn function_at(&self, addr: u64) -> Option<NodeIndex> {
+        self.functions.get(&addr).cloned()
+    }
+
+    /// Adds the calls made in `ssa`, the SSA of the function at `caller`.
+    pub fn add_calls(&mut self, caller: NodeIndex, ssa: &SSAStorage) {
+        let mut nodes = ssa.nodes();
+        nodes.sort();
+        for node in nodes {
+            match ssa.get_node_data(&node).map(|nd| nd.nt) {
+                Ok(NodeType::Op(MOpcode::OpCall)) => {}
+                _ => continue,
+            }
+            match call_target(ssa, node) {
+                Some(addr) => {
+                    let callee = match self.functions.get(&addr).cloned() {
+                        Some(callee) => callee,
+                        None => {
+                            let callee = self.g.add_node(Function {
+                                name: format!("fcn.{:08x}", addr),
+                                addr: addr,
+                                external: true,
+                            });
+                            self.functions.insert(addr, callee);
+                            callee
+                        }
+                    };
+                    self.g.add_edge(caller, callee, CallSite { call: node });
+                }
+                None => {
+                    self.indirect.entry(caller).or_insert_with(Vec::new).push(node);
+                }
+            }
+        }
+    }
+
+    pub fn callees(&self, f: NodeIndex) -> Vec<NodeIndex> {
+        let mut callees = self.g.neighbors(f).collect::<Vec<_>>();
+        callees.sort();
+        callees.dedup();
+        callees
+    }
+
+    pub fn callers(&self, f: NodeIndex) -> Vec<NodeIndex> {
+        let mut callers = self.g
+                              .neighbors_directed(f, EdgeDirection::Incoming)
+                              .collect::<Vec<_>>();
+        callers.sort();
+        callers.dedup();
+        callers
+    }
+
+    /// All functions, each after the functions it calls. Recursive calls are
+    /// ignored, so the functions of a cycle come in an arbitrary order.
+    pub fn bottom_up(&self) -> Vec<NodeIndex> {
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        for root in (0..self.g.node_count()).map(NodeIndex::new) {
+            if !visited.insert(root) {
+                continue;
+            }
+            // Post-order over the callees, without recursion.
+            let mut stack = vec![(root, self.callees(root))];
+            while !stack.is_empty() {
+                let next = stack.last_mut().unwrap().1.pop();
+                match next {
+                    Some(callee) => {
+                        if visited.insert(callee) {
+                            let callees = self.callees(callee);
+                            stack.push((callee, callees));
+                        }
+                    }
+                    None => {
+                        let (f, _) = stack.pop().unwrap();
+                        order.push(f);
+                    }
+                }
+            }
+        }
+        order
+    }
+}
+
+/// Address called by `call`, if it is a constant.
+pub fn call_target(ssa: &SSAStorage, call: NodeIndex) -> Option<u64> {
+    ssa.get_sparse_operands(&call)
+       .iter()
+       .find(|&&(i, _)| i == 0)
+       .and_then(|&(_, t)| ssa.get_node_data(&t).ok())
+       .and_then(|nd| {
+           match nd.nt {
+               NodeType::Op(MOpcode::OpConst(addr)) => Some(addr),
+               _ => None,
+           }
+       })
+}
+
+impl GraphDot for CallGraph {
+    type NodeIndex = NodeIndex;
+    type EdgeIndex = EdgeIndex;
+
+    fn configure(&self) -> String {
+        "digraph callgraph {\nsplines=\"true\";\n".to_owned()
+    }
+
+    fn node_count(&self) -> usize {
+        self.g.node_count()
+    }
+
+    fn edge_count(&self) -> usize {
+        self.g.edge_count()
+    }
+
+    fn node_index_new(i: usize) -> Self::NodeIndex {
+        graph::NodeIndex::new(i)
+    }
+
+    fn edge_index_new(i: usize) -> Self::EdgeIndex {
+        graph::EdgeIndex::new(i)
+    }
+
+    fn nodes(&self) -> Vec<Self::NodeIndex> {
+        (0..self.node_count()).map(|n| graph::NodeIndex::new(n)).collect()
+    }
+
+    fn edge_source(&self, edge: &Self::EdgeIndex) -> Self::NodeIndex {
+        self.g.raw_edges()[edge.index()].source()
+    }
+
+    fn edge_target(&self, edge: &Self::EdgeIndex) -> Self::NodeIndex {
+        self.g.raw_edges()[edge.index()].target()
+    }
+
+    fn edge_attrs(&self, edge: &Self::EdgeIndex) -> DotAttrBlock {
+        let edge = &self.g.raw_edges()[edge.index()];
+        DotAttrBlock::Raw(format!("n{} -> n{};\n", edge.source().index(), edge.target().index()))
+    }
+
+    fn node_attrs(&self, i: &Self::NodeIndex) -> DotAttrBlock {
+        let f = &self.g[*i];
+        let label = format!("\"{}\\n0x{:x}\"", f.name, f.addr);
+        let style = if f.external { "dashed" } else { "solid" };
+        DotAttrBlock::Hybrid(format!("n{}", i.index()),
+                             vec![("label".to_owned(), label),
+                                  ("shape".to_owned(), "box".to_owned()),
+                                  ("style".to_owned(), style.to_owned())])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    #[test]
+    fn direct_and_indirect_calls() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0x1000 });
+        ssa.mark_start_node(&entry);
+        let target = ssa.add_const(entry, 0x2000);
+        let call = ssa.add_op(entry, MOpcode::OpCall, From::from(64 as u16), None);
+        ssa.op_use(call, 0, target);
+        let external = ssa.add_const(entry, 0x3000);
+        let again = ssa.add_op(entry, MOpcode::OpCall, From::from(64 as u16), None);
+        ssa.op_use(again, 0, external);
+        let pointer = ssa.add_undefined(entry, From::from(64 as u16));
+        let indirect = ssa.add_op(entry, MOpcode::OpCall, From::from(64 as u16), None);
+        ssa.op_use(indirect, 0, pointer);
+
+        let mut cg = CallGraph::new();
+        let main = cg.add_function("main".to_owned(), 0x1000);
+        let f = cg.add_function("f".to_owned(), 0x2000);
+        cg.add_calls(main, &ssa);
+
+        let callees = cg.callees(main);
+        assert_eq!(callees.len(), 2);
+        assert!(callees.contains(&f));
+        let import = cg.function_at(0x3000).unwrap();
+        assert!(cg.g[import].external);
+        assert_eq!(cg.callers(f), vec![main]);
+        assert_eq!(cg.indirect[&main], vec![indirect]);
+
+        let order = cg.bottom_up();
+        let position = |n| order.iter().position(|&m| m == n).unwrap();
+        assert_eq!(order.len(), 3);
+        assert!(position(f) < position(main));
+        assert!(position(import) < position(main));
+    }
+}
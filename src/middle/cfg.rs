@@ -10,11 +10,18 @@
 //!
 //! Control Flow Graphs (CFG) aid in the analysis and recovery of the program
 //! structure.
+//!
+//! Indirect jumps lead to the exit, unless a `JumpTable` was found for them
+//! (see `analysis::jumptable`). A jump through a table ends a multi-way block
+//! with one `Case` edge for every entry of the table.
+//!
+//! A call to a function that does not return ends its block, which leads to
+//! the exit (see `analysis::noreturn`).
 
 use petgraph::graph::Graph;
 use petgraph::graph;
 use petgraph::Dfs;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use super::ir::*;
 use super::dot::{DotAttrBlock, GraphDot};
@@ -50,6 +57,30 @@ pub enum EdgeType {
     True,
     False,
     Unconditional,
+    /// Taken by a jump through a table if the index has this value.
+    Case(u64),
+}
+
+/// Targets of an indirect jump that are read from a table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JumpTable {
+    /// Address of the first entry.
+    pub base: u64,
+    /// Size of an entry in bits.
+    pub entry_size: WidthSpec,
+    /// The target for every index the jump may be taken with.
+    pub targets: BTreeMap<u64, Address>,
+}
+
+/// Why the targets of an indirect jump are not known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unresolved {
+    /// The target is not loaded from a table.
+    NoTable,
+    /// The index into the table is not bounded.
+    Unbounded,
+    /// The entry at this address cannot be read.
+    Unreadable(u64),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -74,6 +105,14 @@ pub struct CFG {
     pub entry: graph::NodeIndex,
     pub exit: graph::NodeIndex,
     pub bbs: BTreeMap<Address, graph::NodeIndex>,
+    /// Tables of the indirect jumps, by the address of the jump. They are
+    /// used by `build`.
+    pub jump_tables: BTreeMap<Address, JumpTable>,
+    /// Indirect jumps to an unknown target, by the address of the jump.
+    pub unresolved: BTreeMap<Address, Unresolved>,
+    /// Addresses of the functions that do not return. They are used by
+    /// `build`.
+    pub noreturn: BTreeSet<Address>,
 }
 
 impl BasicBlock {
@@ -138,6 +177,15 @@ impl EdgeData {
         EdgeData::new(BACKWARD, EdgeType::Unconditional, src_addr, dst_addr)
     }
 
+    pub fn new_case(src_addr: Address, dst_addr: Address, case: u64) -> EdgeData {
+        let direction = if src_addr > dst_addr {
+            BACKWARD
+        } else {
+            FORWARD
+        };
+        EdgeData::new(direction, EdgeType::Case(case), src_addr, dst_addr)
+    }
+
     pub fn new_true(src_addr: Address, dst_addr: Address) -> EdgeData {
         if src_addr > dst_addr {
             EdgeData::new_backward_true(src_addr, dst_addr)
@@ -176,6 +224,9 @@ impl CFG {
             entry: entry,
             exit: exit,
             bbs: BTreeMap::new(),
+            jump_tables: BTreeMap::new(),
+            unresolved: BTreeMap::new(),
+            noreturn: BTreeSet::new(),
         }
     }
 
@@ -195,23 +246,42 @@ impl CFG {
 
         while let Some(ref i) = insts_iter.next() {
             let inst = i.clone();
+            // The instruction after a call that does not return is only
+            // reached by jumping to it.
+            if self.is_noreturn_call(inst) {
+                if let Some(j) = insts_iter.peek() {
+                    if !self.bbs.contains_key(&(j.addr.val)) {
+                        let bb = self.add_new_block();
+                        self.bbs.insert(j.addr.val, bb);
+                    }
+                }
+                continue;
+            }
             let operand = match inst.opcode {
                 MOpcode::OpCJmp => &inst.operand_2,
                 MOpcode::OpJmp => &inst.operand_1,
                 _ => continue,
             };
-            // TODO: Resolve the address if it's not a constant.
-            let addr = match self.const_value_of(operand) {
-                Some(addr) => addr,
-                _ => continue,
+            let targets = match self.jump_tables.get(&inst.addr.val) {
+                Some(table) if inst.opcode == MOpcode::OpJmp => {
+                    table.targets.values().cloned().collect::<Vec<_>>()
+                }
+                _ => {
+                    match self.const_value_of(operand) {
+                        Some(addr) => vec![addr],
+                        _ => continue,
+                    }
+                }
             };
 
-            if !self.bbs.contains_key(&addr) {
-                if addr > last_addr.val || addr < first_addr.val {
-                    self.bbs.insert(addr, self.exit);
-                } else {
-                    let bb = self.add_new_block();
-                    self.bbs.insert(addr, bb);
+            for addr in targets {
+                if !self.bbs.contains_key(&addr) {
+                    if addr > last_addr.val || addr < first_addr.val {
+                        self.bbs.insert(addr, self.exit);
+                    } else {
+                        let bb = self.add_new_block();
+                        self.bbs.insert(addr, bb);
+                    }
                 }
             }
 
@@ -228,6 +298,12 @@ impl CFG {
         val.as_literal
     }
 
+    // Whether `inst` calls a function in `noreturn`.
+    fn is_noreturn_call(&self, inst: &MInst) -> bool {
+        inst.opcode == MOpcode::OpCall &&
+        self.const_value_of(&inst.operand_1).map_or(false, |addr| self.noreturn.contains(&addr))
+    }
+
     fn build_edges(&mut self,
                    current: graph::NodeIndex,
                    next: graph::NodeIndex,
@@ -235,6 +311,14 @@ impl CFG {
                    next_inst: MInst) {
         let exit = self.exit.clone();
         match inst.opcode {
+            MOpcode::OpJmp if self.jump_tables.contains_key(&inst.addr.val) => {
+                let targets = self.jump_tables[&inst.addr.val].targets.clone();
+                for (case, target_addr) in targets {
+                    let edge_data = EdgeData::new_case(inst.addr.val, target_addr, case);
+                    let target = *(self.bbs.get(&target_addr).unwrap_or(&exit));
+                    self.add_edge(current, target, edge_data);
+                }
+            }
             MOpcode::OpJmp => {
                 let target_addr = self.const_value_of(&inst.operand_1).unwrap_or(0); // unwrap?
                 let edge_data = EdgeData::new_forward_uncond(inst.addr.val, target_addr);
@@ -250,6 +334,9 @@ impl CFG {
                 let edge_data = EdgeData::new_false(inst.addr.val, next_inst.addr.val);
                 self.add_edge(current, next, edge_data);
             }
+            MOpcode::OpCall if self.is_noreturn_call(&inst) => {
+                self.add_edge(current, exit, EdgeData::new_forward_uncond(inst.addr.val, 0));
+            }
             _ => {
                 let edge_data = EdgeData::new_uncond(inst.addr.val, next_inst.addr.val);
                 self.add_edge(current, next, edge_data);
@@ -277,9 +364,14 @@ impl CFG {
                     }
                 }
                 None => {
-                    self.add_edge(current,
-                                  exit,
-                                  EdgeData::new_forward_uncond(inst.addr.val, 0));
+                    if inst.opcode == MOpcode::OpJmp &&
+                       self.jump_tables.contains_key(&inst.addr.val) {
+                        self.build_edges(current, exit, inst.clone(), inst.clone());
+                    } else {
+                        self.add_edge(current,
+                                      exit,
+                                      EdgeData::new_forward_uncond(inst.addr.val, 0));
+                    }
                 }
             }
 
@@ -292,6 +384,28 @@ impl CFG {
         self.mark_reachable();
     }
 
+    /// Whether the exit can be reached from the entry without calling a
+    /// function that does not return.
+    pub fn returns(&self) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![self.entry];
+        while let Some(n) = stack.pop() {
+            if n == self.exit {
+                return true;
+            }
+            if !visited.insert(n) {
+                continue;
+            }
+            if let NodeData::Block(ref block) = self.g[n] {
+                if block.instructions.last().map_or(false, |i| self.is_noreturn_call(i)) {
+                    continue;
+                }
+            }
+            stack.extend(self.g.neighbors(n));
+        }
+        false
+    }
+
     pub fn mark_reachable(&mut self) {
         let mut dfs = Dfs::new(&(self.g), self.entry);
         while let Some(n) = dfs.next(&self.g) {
@@ -370,9 +484,10 @@ impl GraphDot for CFG {
         let mut direction = "forward";
         let prefix = format!("n{} -> n{}", edge.source().index(), edge.target().index());
         let (color, label) = match wt.edge_type {
-            EdgeType::True => ("green", "label=T "),
-            EdgeType::False => ("red", "label=F "),
-            EdgeType::Unconditional => ("black", ""),
+            EdgeType::True => ("green", "label=T ".to_owned()),
+            EdgeType::False => ("red", "label=F ".to_owned()),
+            EdgeType::Unconditional => ("black", String::new()),
+            EdgeType::Case(case) => ("blue", format!("label={} ", case)),
         };
         if wt.direction == BACKWARD {
             direction = "back";
@@ -28,10 +28,14 @@ pub fn mark<T: Clone + SSAMod + SSAExtra>(ssa: &mut T) {
     let mut queue = VecDeque::<T::ValueRef>::new();
     for node in &nodes {
         if let Ok(ref result) = ssa.get_node_data(node) {
-            if let NodeType::Op(ref op) = result.nt {
-                if op.has_sideeffects() {
-                    ssa.mark(node);
-                }
+            let sideeffects = match result.nt {
+                NodeType::Op(ref op) => op.has_sideeffects(),
+                _ => false,
+            };
+            // Keep the operands of side effects and the selectors of
+            // branches alive as well.
+            if sideeffects || ssa.is_selector(node) {
+                queue.push_back(*node);
             }
         } else {
             ssa.mark(node);
@@ -48,7 +48,8 @@ impl fmt::Display for MInst {
             MOpcode::OpInc => format!("{} = {} + 1", self.dst, self.operand_1),
             MOpcode::OpDec => format!("{} = {} - 1", self.dst, self.operand_1),
             MOpcode::OpIf => format!("if ({}) {{", self.operand_1),
-            MOpcode::OpLoad => format!("{} = {}({})", self.dst, self.opcode, self.operand_1),
+            MOpcode::OpLoad(w, _) =>
+                format!("{} = {}({}, {})", self.dst, self.opcode, self.operand_1, w),
             MOpcode::OpNarrow(w) =>
                 format!("{} = {}({}, {})", self.dst, self.opcode, self.operand_1, w),
             MOpcode::OpWiden(w) =>
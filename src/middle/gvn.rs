@@ -0,0 +1,201 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Global value numbering.
+//!
+//! Merges operations that compute the same value, i.e. have the same
+//! opcode, type and operands. An operation is replaced by an equivalent one
+//! in the same block or in a dominating block.
+//!
+//! Operations with side effects are never merged. Loads take the memory
+//! state they read as an operand, so two loads are only merged if no store
+//! comes between them.
+
+use std::collections::HashMap;
+
+use analysis::dom::blocks::{dominates, dominators, reverse_post_order};
+use middle::ir::MOpcode;
+use middle::ssa::{SSAMod, ValueType};
+use middle::ssa::ssa_traits::NodeType;
+
+type Key<V> = (MOpcode, ValueType, Vec<(u8, V)>);
+
+/// Merges equivalent operations in `ssa`. Returns the number of operations
+/// replaced.
+pub fn run<T: SSAMod>(ssa: &mut T) -> usize {
+    let order = reverse_post_order(ssa);
+    let idom = dominators(ssa, &order);
+    let mut total = 0;
+    loop {
+        let merged = number(ssa, &order, &idom);
+        if merged == 0 {
+            break;
+        }
+        total += merged;
+    }
+    total
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// One pass over the blocks in reverse postorder, so that dominating blocks
+// are visited first.
+fn number<T: SSAMod>(ssa: &mut T, order: &[T::ActionRef], idom: &[usize]) -> usize {
+    let mut leaders = HashMap::<Key<T::ValueRef>, Vec<(T::ValueRef, usize)>>::new();
+    let mut merged = 0;
+    for (b, block) in order.iter().enumerate() {
+        for expr in ssa.exprs_in(block) {
+            let key = match key_of(ssa, &expr) {
+                Some(key) => key,
+                None => continue,
+            };
+            let leader = {
+                let swapped = swap(&key);
+                let mut keys = vec![&key];
+                if let Some(ref k) = swapped {
+                    keys.push(k);
+                }
+                keys.iter()
+                    .filter_map(|k| leaders.get(*k))
+                    .flat_map(|l| l.iter())
+                    .find(|&&(_, lb)| dominates(idom, lb, b))
+                    .map(|&(l, _)| l)
+            };
+            match leader {
+                // A node can only select for one block.
+                Some(l) if !ssa.is_selector(&expr) => {
+                    ssa.replace(expr, l);
+                    merged += 1;
+                }
+                _ => leaders.entry(key).or_insert_with(Vec::new).push((expr, b)),
+            }
+        }
+    }
+    merged
+}
+
+fn key_of<T: SSAMod>(ssa: &T, expr: &T::ValueRef) -> Option<Key<T::ValueRef>> {
+    let nd = match ssa.get_node_data(expr) {
+        Ok(nd) => nd,
+        Err(_) => return None,
+    };
+    let opc = match nd.nt {
+        NodeType::Op(opc) => opc,
+        _ => return None,
+    };
+    match opc {
+        MOpcode::OpNop | MOpcode::OpInvalid => return None,
+        _ if opc.has_sideeffects() => return None,
+        _ => {}
+    }
+    let mut operands = ssa.get_sparse_operands(expr);
+    operands.sort_by_key(|&(i, _)| i);
+    Some((opc, nd.vt, operands))
+}
+
+// The key with the operands exchanged, for commutative operations.
+fn swap<V: Copy>(key: &Key<V>) -> Option<Key<V>> {
+    match key.0 {
+        MOpcode::OpAdd | MOpcode::OpMul | MOpcode::OpAnd | MOpcode::OpOr | MOpcode::OpXor |
+        MOpcode::OpCmp => {}
+        _ => return None,
+    }
+    if key.2.len() != 2 {
+        return None;
+    }
+    let (a, b) = (key.2[0].1, key.2[1].1);
+    Some((key.0, key.1, vec![(0, b), (1, a)]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::arch::Endian;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+
+    #[test]
+    fn merge_in_dominated_blocks() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let left = ssa.add_block(BBInfo { addr: 0x10 });
+        let right = ssa.add_block(BBInfo { addr: 0x20 });
+        let join = ssa.add_block(BBInfo { addr: 0x30 });
+        ssa.add_control_edge(entry, left, 0);
+        ssa.add_control_edge(entry, right, 1);
+        ssa.add_control_edge(left, join, 2);
+        ssa.add_control_edge(right, join, 2);
+        ssa.add_control_edge(join, exit, 2);
+
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let one = ssa.add_const(entry, 1);
+        let a = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(a, 0, x);
+        ssa.op_use(a, 1, one);
+        let b = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(b, 0, one);
+        ssa.op_use(b, 1, x);
+        let c = ssa.add_op(left, MOpcode::OpAnd, From::from(64 as u16), None);
+        ssa.op_use(c, 0, x);
+        ssa.op_use(c, 1, b);
+        let d = ssa.add_op(right, MOpcode::OpAnd, From::from(64 as u16), None);
+        ssa.op_use(d, 0, x);
+        ssa.op_use(d, 1, a);
+        let sum = ssa.add_op(join, MOpcode::OpXor, From::from(64 as u16), None);
+        ssa.op_use(sum, 0, c);
+        ssa.op_use(sum, 1, d);
+
+        assert_eq!(run(&mut ssa), 1);
+        let operands = ssa.get_operands(&sum);
+        // `c` and `d` are in sibling blocks and stay apart.
+        assert!(operands[0] != operands[1]);
+        assert_eq!(ssa.get_operands(&operands[0])[1], ssa.get_operands(&operands[1])[1]);
+    }
+
+    #[test]
+    fn memory_order() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let mem = ssa.to_value(entry);
+        let addr = ssa.add_undefined(entry, From::from(64 as u16));
+        let load = ssa.add_op(entry,
+                              MOpcode::OpLoad(64, Endian::Little),
+                              From::from(64 as u16),
+                              None);
+        ssa.op_use(load, 0, addr);
+        ssa.op_use(load, 3, mem);
+        let store = ssa.add_op(entry,
+                               MOpcode::OpStore(64, Endian::Little),
+                               From::from(64 as u16),
+                               None);
+        ssa.op_use(store, 0, addr);
+        ssa.op_use(store, 1, load);
+        ssa.op_use(store, 3, mem);
+        let again = ssa.add_op(entry,
+                               MOpcode::OpLoad(64, Endian::Little),
+                               From::from(64 as u16),
+                               None);
+        ssa.op_use(again, 0, addr);
+        ssa.op_use(again, 3, store);
+        let twice = ssa.add_op(entry,
+                               MOpcode::OpStore(64, Endian::Little),
+                               From::from(64 as u16),
+                               None);
+        ssa.op_use(twice, 0, addr);
+        ssa.op_use(twice, 1, load);
+        ssa.op_use(twice, 3, mem);
+
+        assert_eq!(run(&mut ssa), 0);
+    }
+}
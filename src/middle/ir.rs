@@ -11,6 +11,8 @@
 
 use std::default::Default;
 
+use middle::arch::Endian;
+
 pub type Address = u64;
 pub type WidthSpec = u16;
 
@@ -40,7 +42,7 @@ pub enum MValType {
     Null,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum MOpcode {
     OpAdd,
     OpSub,
@@ -59,17 +61,55 @@ pub enum MOpcode {
     OpGteq,
     OpLsl,
     OpLsr,
+    /// Arithmetic shift right, which copies the sign bit.
+    OpAsr,
+    /// Rotation left within the width of the first operand.
+    OpRol,
+    /// Rotation right within the width of the first operand.
+    OpRor,
+    /// Division of signed integers, rounding towards zero.
+    OpSDiv,
+    /// Remainder of the division of signed integers. It has the sign of the
+    /// dividend.
+    OpSMod,
+    OpFAdd,
+    OpFSub,
+    OpFMul,
+    OpFDiv,
+    OpFCmp,
+    OpFLt,
+    OpFLteq,
+    /// Conversion of an unsigned integer to a float of the given width.
+    OpItoF(WidthSpec),
+    /// Conversion of a signed integer to a float of the given width.
+    OpSItoF(WidthSpec),
+    /// Conversion of a float to an integer of the given width, rounding
+    /// towards zero.
+    OpFtoI(WidthSpec),
+    /// Conversion of a float to a float of the given width.
+    OpFConv(WidthSpec),
     OpIf,
     OpJmp,
     OpCJmp,
+    /// Call of the target in operand 0. The memory it reads is operand 3,
+    /// the arguments follow (see `frontend::esilssa::ARGUMENTS`).
     OpCall,
-    OpLoad,
-    OpStore,
+    /// The memory after the call in operand 0. The memory before the call is
+    /// operand 3.
+    OpMemory,
+    /// Load of the given width and byte order from memory.
+    OpLoad(WidthSpec, Endian),
+    /// Store of the given width and byte order to memory. The stored value
+    /// has the width of the access.
+    OpStore(WidthSpec, Endian),
     OpNarrow(WidthSpec),
     OpWiden(WidthSpec),
     OpConst(u64),
     OpNop,
     OpInvalid,
+    /// An instruction whose ESIL could not be parsed. It may have changed the
+    /// memory.
+    OpUnknown,
     OpInc,
     OpDec,
     OpCl, // '}'
@@ -135,12 +175,27 @@ impl MOpcode {
         self.info().0
     }
 
+    /// Whether the result is a floating point value.
+    pub fn is_float(&self) -> bool {
+        match *self {
+            MOpcode::OpFAdd |
+            MOpcode::OpFSub |
+            MOpcode::OpFMul |
+            MOpcode::OpFDiv |
+            MOpcode::OpItoF(_) |
+            MOpcode::OpSItoF(_) |
+            MOpcode::OpFConv(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn has_sideeffects(&self) -> bool {
         match *self {
-            MOpcode::OpStore => true,
+            MOpcode::OpStore(..) => true,
             MOpcode::OpJmp => true,
             MOpcode::OpCJmp => true,
             MOpcode::OpCall => true,
+            MOpcode::OpUnknown => true,
             _ => false,
         }
     }
@@ -177,9 +232,27 @@ impl MOpcode {
             MOpcode::OpGteq => (">=", MArity::Binary),
             MOpcode::OpLsl => ("<<", MArity::Binary),
             MOpcode::OpLsr => (">>", MArity::Binary),
+            MOpcode::OpAsr => (">>>>", MArity::Binary),
+            MOpcode::OpRol => ("<<<", MArity::Binary),
+            MOpcode::OpRor => (">>>", MArity::Binary),
+            MOpcode::OpSDiv => ("~/", MArity::Binary),
+            MOpcode::OpSMod => ("~%", MArity::Binary),
+            MOpcode::OpFAdd => ("F+", MArity::Binary),
+            MOpcode::OpFSub => ("F-", MArity::Binary),
+            MOpcode::OpFMul => ("F*", MArity::Binary),
+            MOpcode::OpFDiv => ("F/", MArity::Binary),
+            MOpcode::OpFCmp => ("F==", MArity::Binary),
+            MOpcode::OpFLt => ("F<", MArity::Binary),
+            MOpcode::OpFLteq => ("F<=", MArity::Binary),
+            MOpcode::OpItoF(_) => ("itof", MArity::Unary),
+            MOpcode::OpSItoF(_) => ("sitof", MArity::Unary),
+            MOpcode::OpFtoI(_) => ("ftoi", MArity::Unary),
+            MOpcode::OpFConv(_) => ("fconv", MArity::Unary),
             MOpcode::OpIf => ("if", MArity::Unary),
-            MOpcode::OpLoad => ("load", MArity::Unary),
-            MOpcode::OpStore => ("store", MArity::Binary),
+            MOpcode::OpLoad(_, Endian::Little) => ("load", MArity::Unary),
+            MOpcode::OpLoad(_, Endian::Big) => ("load.be", MArity::Unary),
+            MOpcode::OpStore(_, Endian::Little) => ("store", MArity::Binary),
+            MOpcode::OpStore(_, Endian::Big) => ("store.be", MArity::Binary),
             MOpcode::OpNarrow(_) => ("narrow", MArity::Unary),
             MOpcode::OpWiden(_) => ("widen", MArity::Unary),
             MOpcode::OpJmp => ("jmp", MArity::Unary),
@@ -188,6 +261,8 @@ impl MOpcode {
             MOpcode::OpConst(_) => ("const", MArity::Zero),
             MOpcode::OpNop => ("nop", MArity::Zero),
             MOpcode::OpInvalid => ("invalid", MArity::Zero),
+            MOpcode::OpUnknown => ("unknown", MArity::Zero),
+            MOpcode::OpMemory => ("memory", MArity::Unary),
             MOpcode::OpInc => ("++", MArity::Unary),
             MOpcode::OpDec => ("--", MArity::Unary),
             MOpcode::OpCl => ("}", MArity::Zero),
@@ -196,6 +271,23 @@ impl MOpcode {
     }
 }
 
+/// For a load from the address of a store that reads only bytes the store
+/// wrote, the number of bits the stored value is shifted right by to give the
+/// loaded value. `None` if the load is wider than the store, or the accesses
+/// differ in byte order.
+pub fn forwarded_shift(store: MOpcode, load: MOpcode) -> Option<WidthSpec> {
+    match (store, load) {
+        (MOpcode::OpStore(sw, se), MOpcode::OpLoad(lw, le)) if se == le && lw <= sw => {
+            match se {
+                // The first bytes in memory are the least significant ones.
+                Endian::Little => Some(0),
+                Endian::Big => Some(sw - lw),
+            }
+        }
+        _ => None,
+    }
+}
+
 impl MRegInfo {
     pub fn new() -> MRegInfo {
         let def: MRegInfo = Default::default();
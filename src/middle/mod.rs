@@ -7,11 +7,19 @@
 
 //! Components processing data in SSA form.
 
+// Declared first, its macros are used by the other passes.
+#[macro_use]
+pub mod ssa;
+pub mod arch;
+pub mod callconv;
+pub mod callgraph;
 pub mod cfg;
 pub mod dce;
 pub mod display;
 pub mod dot;
+pub mod gvn;
 pub mod ir;
 pub mod phiplacement;
 pub mod regfile;
-pub mod ssa;
+pub mod simplify;
+pub mod summary;
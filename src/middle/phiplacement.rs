@@ -48,15 +48,17 @@ impl<'a, T: SSAMod<BBInfo=BBInfo> + 'a> PhiPlacer<'a, T> {
                                block: T::ActionRef,
                                variable: VarId,
                                value: T::ValueRef) {
-        assert_eq!(self.ssa.get_node_data(&value).unwrap().vt,
-                   self.variable_types[variable]);
+        assert_eq!(self.ssa.get_node_data(&value).unwrap().vt.width(),
+                   self.variable_types[variable].width());
         self.current_def[variable].insert(block, value);
     }
 
     pub fn read_variable(&mut self, block: T::ActionRef, variable: VarId) -> T::ValueRef {
-        self.current_def[variable].get(&block)
-            .map(|var| *var)
-            .unwrap_or(self.read_variable_recursive(variable, block))
+        let def = self.current_def[variable].get(&block).cloned();
+        match def {
+            Some(var) => var,
+            None => self.read_variable_recursive(variable, block),
+        }
     }
 
     fn read_variable_recursive(&mut self, variable: VarId, block: T::ActionRef) -> T::ValueRef {
@@ -91,6 +93,12 @@ impl<'a, T: SSAMod<BBInfo=BBInfo> + 'a> PhiPlacer<'a, T> {
         block
     }
 
+    /// Makes a block that is already part of the SSA known to the PhiPlacer,
+    /// so that variables can be placed in it.
+    pub fn add_existing_block(&mut self, block: T::ActionRef) {
+        self.incomplete_phis.insert(block, HashMap::new());
+    }
+
     pub fn seal_block(&mut self, block: T::ActionRef) {
         let inc = self.incomplete_phis[&block].clone(); // TODO: remove clone
         for (variable, node) in inc {
@@ -113,7 +121,9 @@ impl<'a, T: SSAMod<BBInfo=BBInfo> + 'a> PhiPlacer<'a, T> {
         self.try_remove_trivial_phi(phi)
     }
 
-    fn try_remove_trivial_phi(&mut self, phi: T::ValueRef) -> T::ValueRef {
+    /// Replaces `phi` by its operand if it merges a single value, and does
+    /// the same for the phis using it. Returns the value that replaces `phi`.
+    pub fn try_remove_trivial_phi(&mut self, phi: T::ValueRef) -> T::ValueRef {
         let undef = self.ssa.invalid_value();
         // The phi is unreachable or in the start block
         let mut same: T::ValueRef = undef;
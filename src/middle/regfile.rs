@@ -14,6 +14,7 @@ use std::convert::From;
 
 use r2pipe::structs::LRegInfo;
 
+use middle::arch::Architecture;
 use middle::ssa::{BBInfo, SSAMod, ValueType};
 use middle::ssa::verifier::VerifiedAdd;
 use middle::ir::{MOpcode, WidthSpec};
@@ -56,20 +57,42 @@ pub struct SubRegisterFile {
 impl SubRegisterFile {
     /// Creates a new SubRegisterFile based on a provided register profile.
     pub fn new(reg_info: &LRegInfo) -> SubRegisterFile {
+        SubRegisterFile::with_architecture(reg_info, &Architecture::detect(reg_info))
+    }
+
+    /// Creates a new SubRegisterFile for a register profile of `arch`. The
+    /// registers combining the flags are left out, the flags themselves
+    /// become whole registers.
+    pub fn with_architecture(reg_info: &LRegInfo, arch: &Architecture) -> SubRegisterFile {
         let mut slices = HashMap::new();
-        let mut events: Vec<SubRegister> = Vec::new();
+        // Offsets are relative to the arena of the register type. The flags
+        // share the arena of the general purpose registers, the fpu, mmx
+        // and xmm registers have their own.
+        let mut arenas: Vec<&str> = Vec::new();
+        let mut events: Vec<(usize, SubRegister)> = Vec::new();
         for (i, reg) in reg_info.reg_info.iter().enumerate() {
-            if reg.type_str == "fpu" {
-                continue;
-            } // st7 from "fpu" overlaps with zf from "gpr" (r2 bug?)
-            if reg.name.ends_with("flags") {
+            if arch.is_flag_register(&reg.name) {
                 continue;
-            } // HARDCODED x86
-            events.push(SubRegister::new(i, reg.offset, reg.size));
+            }
+            let arena = match &reg.type_str[..] {
+                "flg" => "gpr",
+                t => t,
+            };
+            let arena = match arenas.iter().position(|&a| a == arena) {
+                Some(a) => a,
+                None => {
+                    arenas.push(arena);
+                    arenas.len() - 1
+                }
+            };
+            events.push((arena, SubRegister::new(i, reg.offset, reg.size)));
         }
 
-        events.sort_by(|a, b| {
-            let o = a.shift.cmp(&b.shift);
+        events.sort_by(|&(arena_a, a), &(arena_b, b)| {
+            let o = match arena_a.cmp(&arena_b) {
+                Ordering::Equal => a.shift.cmp(&b.shift),
+                o => o,
+            };
             if let Ordering::Equal = o {
                 (b.width + b.shift).cmp(&(a.width + a.shift))
             } else {
@@ -78,13 +101,15 @@ impl SubRegisterFile {
         });
 
         let mut current = SubRegister::new(0, 0, 0);
+        let mut current_arena = 0;
         let mut whole: Vec<ValueType> = Vec::new();
         let mut names: Vec<String> = Vec::new();
-        for &ev in &events {
+        for &(arena, ev) in &events {
             let name = &reg_info.reg_info[ev.base].name;
             let cur_until = current.shift + current.width;
-            if ev.shift >= cur_until {
+            if arena != current_arena || ev.shift >= cur_until {
                 current = ev;
+                current_arena = arena;
                 whole.push(From::from(current.width));
                 names.push(name.clone());
             } else {
@@ -104,6 +129,12 @@ impl SubRegisterFile {
         }
     }
 
+    /// Width of the named (whole or partial) register, if the register
+    /// profile has it.
+    pub fn register_width(&self, name: &str) -> Option<WidthSpec> {
+        self.named_registers.get(name).map(|r| r.width as WidthSpec)
+    }
+
     /// Emit code for setting the specified register to the specified value.
     /// Will automatically insert code for shifting and masking in case of subregisters.
     /// This implies that it also tries to read the old value of the whole register.
@@ -130,9 +161,7 @@ impl SubRegisterFile {
         let info = &self.named_registers[var];
         let id = info.base + base;
 
-        let width = match phip.variable_types[id] {
-            ValueType::Integer { width } => width,
-        };
+        let width = phip.variable_types[id].width();
 
         if info.width >= width as usize {
             phip.write_variable(block, id, value);
@@ -143,11 +172,7 @@ impl SubRegisterFile {
         let vt = From::from(width);
         let opcode = MOpcode::OpWiden(width as WidthSpec);
 
-        if phip.ssa.get_node_data(&value).ok().map_or(0, |nd| {
-            match nd.vt {
-                ValueType::Integer{width} => width,
-            }
-        }) < width {
+        if phip.ssa.get_node_data(&value).ok().map_or(0, |nd| nd.vt.width()) < width {
             value = phip.ssa.verified_add_op(block, opcode, vt, &[value], Some(addr));
         }
 
@@ -163,16 +188,21 @@ impl SubRegisterFile {
             value = new_value;
         }
 
-        let fullval: u64 = !((!1u64) << (width - 1));
-        let maskval: u64 = ((!((!1u64) << (info.width - 1))) << info.shift) ^ fullval;
+        // The bits of the whole register outside of the subregister.
+        let maskvalue_node = if width > 64 {
+            wide_mask(phip, block, width, info, addr)
+        } else {
+            let fullval: u64 = !((!1u64) << (width - 1));
+            let maskval: u64 = ((!((!1u64) << (info.width - 1))) << info.shift) ^ fullval;
 
-        if maskval == 0 {
-            phip.write_variable(block, id, value);
-            return;
-        }
+            if maskval == 0 {
+                phip.write_variable(block, id, value);
+                return;
+            }
+            phip.ssa.add_const(block, maskval)
+        };
 
         let mut ov = phip.read_variable(block, id);
-        let maskvalue_node = phip.ssa.add_const(block, maskval);
         new_value = phip.ssa.verified_add_op(block,
                                              MOpcode::OpAnd,
                                              vt,
@@ -213,9 +243,7 @@ impl SubRegisterFile {
         let id = info.base + base;
         let mut value = phiplacer.read_variable(block, id);
 
-        let width = match phiplacer.variable_types[id] {
-            ValueType::Integer { width } => width,
-        };
+        let width = phiplacer.variable_types[id].width();
 
         if info.shift > 0 {
             let shift_amount_node = phiplacer.ssa.add_const(block, info.shift as u64);
@@ -239,3 +267,36 @@ impl SubRegisterFile {
         value
     }
 }
+
+// Mask clearing the bits of the subregister `info` in a whole register of
+// `width` bits, which is too wide for a constant.
+fn wide_mask<'a, T>(phip: &mut PhiPlacer<'a, T>,
+                    block: T::ActionRef,
+                    width: WidthSpec,
+                    info: &SubRegister,
+                    addr: u64)
+                    -> T::ValueRef
+    where T: 'a + SSAMod<BBInfo = BBInfo> + VerifiedAdd
+{
+    let ssa = &mut phip.ssa;
+    let sub = info.width as WidthSpec;
+    // All bits of the subregister set, at least 64 bits wide.
+    let mut ones = if sub <= 64 {
+        let c = if sub == 64 { !0 } else { (1 << sub) - 1 };
+        ssa.add_const(block, c)
+    } else {
+        let zero = ssa.add_const(block, 0);
+        let vt = From::from(sub);
+        let zero = ssa.verified_add_op(block, MOpcode::OpWiden(sub), vt, &[zero], Some(addr));
+        ssa.verified_add_op(block, MOpcode::OpNot, vt, &[zero], Some(addr))
+    };
+    let vt = From::from(width);
+    if ::std::cmp::max(sub, 64) < width {
+        ones = ssa.verified_add_op(block, MOpcode::OpWiden(width), vt, &[ones], Some(addr));
+    }
+    if info.shift > 0 {
+        let shift = ssa.add_const(block, info.shift as u64);
+        ones = ssa.verified_add_op(block, MOpcode::OpLsl, vt, &[ones, shift], Some(addr));
+    }
+    ssa.verified_add_op(block, MOpcode::OpNot, vt, &[ones], Some(addr))
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Algebraic simplification of expressions.
+//!
+//! Rewrites expressions into simpler, equivalent ones until nothing changes:
+//!
+//!  * Constants of commutative operations are moved to the right.
+//!  * Chains of `OpNarrow` and `OpWiden` are folded.
+//!  * Identities like `x + 0`, `x & mask` or `x ^ x` are removed.
+//!  * Constants of nested associative operations are combined.
+//!
+//! A replaced node is removed and its index refers to the replacement from
+//! then on, see `SSAMod::replace`. Operands that lose their last use are left
+//! in place; run `dce::collect` afterwards to remove them.
+
+use middle::ir::{MOpcode, WidthSpec};
+use middle::ssa::{SSAMod, ValueType};
+use middle::ssa::ssa_traits::NodeType;
+use middle::ssa::ssaquote::{Constants, Rule};
+
+/// Upper bound on the number of passes over the SSA.
+const MAX_PASSES: usize = 16;
+
+/// Simplifies all expressions in `ssa`. Returns the number of rewrites.
+pub fn run<T: SSAMod>(ssa: &mut T) -> usize {
+    let rules = rules();
+    let mut total = 0;
+    for _ in 0..MAX_PASSES {
+        let mut changed = 0;
+        for node in ssa.nodes() {
+            if simplify(ssa, &rules, node).is_some() {
+                changed += 1;
+            }
+        }
+        if changed == 0 {
+            break;
+        }
+        total += changed;
+    }
+    total
+}
+
+/// Simplifies the expression `node`, without looking at its uses. Returns
+/// the replacement if there was one.
+pub fn simplify<T: SSAMod>(ssa: &mut T,
+                           rules: &[Rule],
+                           node: T::ValueRef)
+                           -> Option<T::ValueRef> {
+    if !ssa.is_expr(&node) {
+        return None;
+    }
+    if let Some(n) = canonicalize(ssa, node) {
+        return Some(n);
+    }
+    if let Some(n) = fold_casts(ssa, node) {
+        return Some(n);
+    }
+    for rule in rules {
+        if let Some(n) = rule.apply(ssa, node) {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// The algebraic identities applied by `simplify`.
+pub fn rules() -> Vec<Rule> {
+    vec![
+        // Identities.
+        ssa_rule!((OpAdd x 0) => x),
+        ssa_rule!((OpSub x 0) => x),
+        ssa_rule!((OpOr x 0) => x),
+        ssa_rule!((OpXor x 0) => x),
+        ssa_rule!((OpMul x 1) => x),
+        ssa_rule!((OpDiv x 1) => x),
+        ssa_rule!((OpLsl x 0) => x),
+        ssa_rule!((OpLsr x 0) => x),
+        ssa_rule!((OpAnd x {|k: &Constants| k.mask()}) => x),
+        ssa_rule!((OpAnd x x) => x),
+        ssa_rule!((OpOr x x) => x),
+        ssa_rule!((OpNot (OpNot x)) => x),
+        // Constant results.
+        ssa_rule!((OpMul x 0) => 0),
+        ssa_rule!((OpAnd x 0) => 0),
+        ssa_rule!((OpOr x {|k: &Constants| k.mask()}) => {|k: &Constants| k.mask()}),
+        ssa_rule!((OpXor x x) => 0),
+        ssa_rule!((OpSub x x) => 0),
+        ssa_rule!((OpCmp x x) => 1),
+        // Shifts.
+        ssa_rule!((OpLsr (OpLsl x (const c)) (const c))
+                  => (OpAnd x {|k: &Constants| shr(k.mask(), k.get("c"))})),
+        ssa_rule!((OpLsl (OpLsr x (const c)) (const c))
+                  => (OpAnd x {|k: &Constants| shl(k.mask(), k.get("c")) & k.mask()})),
+        // Reassociation of constants.
+        ssa_rule!((OpAdd (OpAdd x (const a)) (const b))
+                  => (OpAdd x {|k: &Constants| k.get("a").wrapping_add(k.get("b")) & k.mask()})),
+        ssa_rule!((OpMul (OpMul x (const a)) (const b))
+                  => (OpMul x {|k: &Constants| k.get("a").wrapping_mul(k.get("b")) & k.mask()})),
+        ssa_rule!((OpAnd (OpAnd x (const a)) (const b))
+                  => (OpAnd x {|k: &Constants| k.get("a") & k.get("b")})),
+        ssa_rule!((OpOr (OpOr x (const a)) (const b))
+                  => (OpOr x {|k: &Constants| k.get("a") | k.get("b")})),
+        ssa_rule!((OpXor (OpXor x (const a)) (const b))
+                  => (OpXor x {|k: &Constants| k.get("a") ^ k.get("b")})),
+    ]
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+// Moves the constant operand of a commutative operation to the right.
+fn canonicalize<T: SSAMod>(ssa: &mut T, node: T::ValueRef) -> Option<T::ValueRef> {
+    let (opc, vt) = match op_of(ssa, &node) {
+        Some((opc, vt)) => (opc, vt),
+        None => return None,
+    };
+    match opc {
+        MOpcode::OpAdd | MOpcode::OpMul | MOpcode::OpAnd | MOpcode::OpOr | MOpcode::OpXor |
+        MOpcode::OpCmp => {}
+        _ => return None,
+    }
+    let (lhs, rhs) = match (operand(ssa, &node, 0), operand(ssa, &node, 1)) {
+        (Some(lhs), Some(rhs)) => (lhs, rhs),
+        _ => return None,
+    };
+    if !is_const(ssa, &lhs) || is_const(ssa, &rhs) {
+        return None;
+    }
+    Some(rebuild(ssa, node, opc, vt, &[rhs, lhs]))
+}
+
+// Folds casts of casts, and casts to the width the value already has.
+fn fold_casts<T: SSAMod>(ssa: &mut T, node: T::ValueRef) -> Option<T::ValueRef> {
+    let (opc, vt) = match op_of(ssa, &node) {
+        Some((opc, vt)) => (opc, vt),
+        None => return None,
+    };
+    let width = match opc {
+        MOpcode::OpNarrow(w) | MOpcode::OpWiden(w) => w,
+        _ => return None,
+    };
+    let inner = match operand(ssa, &node, 0) {
+        Some(inner) => inner,
+        None => return None,
+    };
+    let inner_width = match width_of(ssa, &inner) {
+        Some(w) => w,
+        None => return None,
+    };
+    if inner_width == width {
+        ssa.replace(node, inner);
+        return Some(inner);
+    }
+    let x = match (opc, op_of(ssa, &inner)) {
+        (MOpcode::OpNarrow(_), Some((MOpcode::OpWiden(_), _))) |
+        (MOpcode::OpNarrow(_), Some((MOpcode::OpNarrow(_), _))) |
+        (MOpcode::OpWiden(_), Some((MOpcode::OpWiden(_), _))) => {
+            match operand(ssa, &inner, 0) {
+                Some(x) => x,
+                None => return None,
+            }
+        }
+        _ => return None,
+    };
+    let x_width = match width_of(ssa, &x) {
+        Some(w) => w,
+        None => return None,
+    };
+    if x_width == width {
+        ssa.replace(node, x);
+        return Some(x);
+    }
+    let cast = if x_width < width {
+        MOpcode::OpWiden(width)
+    } else {
+        MOpcode::OpNarrow(width)
+    };
+    Some(rebuild(ssa, node, cast, vt, &[x]))
+}
+
+// Replaces `node` by a new operation in the same block.
+fn rebuild<T: SSAMod>(ssa: &mut T,
+                      node: T::ValueRef,
+                      opc: MOpcode,
+                      vt: ValueType,
+                      operands: &[T::ValueRef])
+                      -> T::ValueRef {
+    let block = ssa.get_block(&node);
+    let n = ssa.add_op(block, opc, vt, None);
+    for (i, op) in operands.iter().enumerate() {
+        ssa.op_use(n, i as u8, *op);
+    }
+    // The replacement also takes over as selector.
+    ssa.replace(node, n);
+    n
+}
+
+fn op_of<T: SSAMod>(ssa: &T, node: &T::ValueRef) -> Option<(MOpcode, ValueType)> {
+    match ssa.get_node_data(node) {
+        Ok(nd) => {
+            match nd.nt {
+                NodeType::Op(opc) => Some((opc, nd.vt)),
+                _ => None,
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+fn width_of<T: SSAMod>(ssa: &T, node: &T::ValueRef) -> Option<WidthSpec> {
+    ssa.get_node_data(node).ok().map(|nd| nd.vt.width())
+}
+
+fn is_const<T: SSAMod>(ssa: &T, node: &T::ValueRef) -> bool {
+    match op_of(ssa, node) {
+        Some((MOpcode::OpConst(_), _)) => true,
+        _ => false,
+    }
+}
+
+fn operand<T: SSAMod>(ssa: &T, node: &T::ValueRef, index: u8) -> Option<T::ValueRef> {
+    ssa.get_sparse_operands(node)
+       .iter()
+       .find(|&&(i, _)| i == index)
+       .map(|&(_, o)| o)
+}
+
+// Shifts by 64 bits or more leave nothing of the value.
+fn shr(v: u64, c: u64) -> u64 {
+    if c < 64 { v >> c } else { 0 }
+}
+
+fn shl(v: u64, c: u64) -> u64 {
+    if c < 64 { v << c } else { 0 }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::{MOpcode, WidthSpec};
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::CFGMod;
+    use middle::ssa::ssa_traits::NodeType;
+
+    fn opcode_of(ssa: &SSAStorage, v: &<SSAStorage as SSA>::ValueRef) -> Option<MOpcode> {
+        match ssa.get_node_data(v).unwrap().nt {
+            NodeType::Op(opc) => Some(opc),
+            _ => None,
+        }
+    }
+
+    fn setup() -> (SSAStorage, <SSAStorage as SSA>::ActionRef) {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        (ssa, entry)
+    }
+
+    #[test]
+    fn narrow_of_widen() {
+        let (mut ssa, entry) = setup();
+        let al = ssa.add_undefined(entry, From::from(8 as u16));
+        let widen = ssa.add_op(entry, MOpcode::OpWiden(64), From::from(64 as u16), None);
+        ssa.op_use(widen, 0, al);
+        let narrow = ssa.add_op(entry, MOpcode::OpNarrow(8), From::from(8 as u16), None);
+        ssa.op_use(narrow, 0, widen);
+        let narrow16 = ssa.add_op(entry, MOpcode::OpNarrow(16), From::from(16 as u16), None);
+        ssa.op_use(narrow16, 0, widen);
+        let not = ssa.add_op(entry, MOpcode::OpNot, From::from(8 as u16), None);
+        ssa.op_use(not, 0, narrow);
+        let not16 = ssa.add_op(entry, MOpcode::OpNot, From::from(16 as u16), None);
+        ssa.op_use(not16, 0, narrow16);
+
+        assert!(run(&mut ssa) > 0);
+        assert_eq!(ssa.get_operands(&not), vec![al]);
+        let cast = ssa.get_operands(&not16)[0];
+        assert_eq!(opcode_of(&ssa, &cast), Some(MOpcode::OpWiden(16)));
+        assert_eq!(ssa.get_operands(&cast), vec![al]);
+    }
+
+    #[test]
+    fn reassociate_constants() {
+        let (mut ssa, entry) = setup();
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let two = ssa.add_const(entry, 2);
+        let three = ssa.add_const(entry, 3);
+        // 2 + x
+        let inner = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(inner, 0, two);
+        ssa.op_use(inner, 1, x);
+        // (2 + x) + 3
+        let outer = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(outer, 0, inner);
+        ssa.op_use(outer, 1, three);
+        let not = ssa.add_op(entry, MOpcode::OpNot, From::from(64 as u16), None);
+        ssa.op_use(not, 0, outer);
+
+        run(&mut ssa);
+        let sum = ssa.get_operands(&not)[0];
+        assert_eq!(opcode_of(&ssa, &sum), Some(MOpcode::OpAdd));
+        let operands = ssa.get_operands(&sum);
+        assert_eq!(operands[0], x);
+        assert_eq!(opcode_of(&ssa, &operands[1]), Some(MOpcode::OpConst(5)));
+    }
+
+    #[test]
+    fn identity_mask() {
+        let (mut ssa, entry) = setup();
+        let x = ssa.add_undefined(entry, From::from(32 as u16));
+        let mask = ssa.add_const(entry, 0xffffffff);
+        let and = ssa.add_op(entry, MOpcode::OpAnd, From::from(32 as u16), None);
+        ssa.op_use(and, 0, mask);
+        ssa.op_use(and, 1, x);
+        let not = ssa.add_op(entry, MOpcode::OpNot, From::from(32 as u16), None);
+        ssa.op_use(not, 0, and);
+
+        run(&mut ssa);
+        assert_eq!(ssa.get_operands(&not), vec![x]);
+    }
+
+    #[test]
+    fn oversized_shift() {
+        let (mut ssa, entry) = setup();
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let c = ssa.add_const(entry, 64);
+        let shl = ssa.add_op(entry, MOpcode::OpLsl, From::from(64 as u16), None);
+        ssa.op_use(shl, 0, x);
+        ssa.op_use(shl, 1, c);
+        let shr = ssa.add_op(entry, MOpcode::OpLsr, From::from(64 as u16), None);
+        ssa.op_use(shr, 0, shl);
+        ssa.op_use(shr, 1, c);
+        let not = ssa.add_op(entry, MOpcode::OpNot, From::from(64 as u16), None);
+        ssa.op_use(not, 0, shr);
+
+        run(&mut ssa);
+        let value = ssa.get_operands(&not)[0];
+        assert_eq!(opcode_of(&ssa, &value), Some(MOpcode::OpConst(0)));
+    }
+}
@@ -103,6 +103,13 @@ pub trait CFG {
     /// Reference to the unconditional edge that flows out of the block
     fn next_edge_of(&self, i: &Self::ActionRef) -> Self::CFEdgeRef;
 
+    /// References to the edges of a switch with the case each one is taken
+    /// for, ordered by case
+    fn case_edges_of(&self, i: &Self::ActionRef) -> Vec<(u64, Self::CFEdgeRef)>;
+
+    /// Reference to the edge a switch takes if no case matches
+    fn default_edge_of(&self, i: &Self::ActionRef) -> Self::CFEdgeRef;
+
     /// Reference that represents an Invalid control flow edge.
     fn invalid_edge(&self) -> Self::CFEdgeRef;
 
@@ -127,6 +134,12 @@ pub trait CFGMod: CFG {
     /// Add a control edge between to basic blocks
     fn add_control_edge(&mut self, source: Self::ActionRef, target: Self::ActionRef, index: u8);
 
+    /// Add an edge that a switch takes if its selector is equal to `case`
+    fn add_case_edge(&mut self, source: Self::ActionRef, target: Self::ActionRef, case: u64);
+
+    /// Add the edge that a switch takes if no case matches
+    fn add_default_edge(&mut self, source: Self::ActionRef, target: Self::ActionRef);
+
     /// Will remove a block and all its associated data from the graph
     fn remove_block(&mut self, node: Self::ActionRef);
 }
@@ -16,6 +16,7 @@ pub enum SSAErr<T: SSA + Debug> {
     InvalidType(String),
     InvalidTarget(T::ActionRef, T::CFEdgeRef, T::ActionRef),
     InvalidControl(T::ActionRef, T::CFEdgeRef),
+    DuplicateCase(T::ActionRef, u64),
     WrongNumOperands(T::ValueRef, usize, usize),
     WrongNumEdges(T::ActionRef, usize, usize),
     NoSelector(T::ActionRef),
@@ -37,6 +38,9 @@ impl fmt::Display for SSAErr<SSAStorage> {
             SSAErr::InvalidControl(bi, ei) => {
                 format!("Block {:?} has invalid outgoing edge: {:?}", bi, ei)
             }
+            SSAErr::DuplicateCase(bi, case) => {
+                format!("Block {:?} has more than one edge for case {}", bi, case)
+            }
             SSAErr::WrongNumOperands(n, e, f) => {
                 format!("{:?} expected {} number of operands, found: {}", n, e, f)
             }
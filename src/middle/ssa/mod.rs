@@ -18,11 +18,11 @@ pub mod ssastorage;
 pub mod ssadot;
 pub mod verifier;
 pub mod error;
+pub mod update;
 mod bimap;
 
 pub use self::ssa_traits::{BBInfo, NodeData, SSA, SSAExtra, SSAMod, ValueType};
 pub use self::ssastorage::SSAStorage;
 
-// TODO: Fix/Complete SSAQuote before re enabling.
-// pub mod ssaquote;
-// pub use self::ssaquote::*;
+#[macro_use]
+pub mod ssaquote;
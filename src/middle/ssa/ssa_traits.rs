@@ -31,23 +31,61 @@ use std::fmt::Debug;
 use middle::ir;
 use super::cfg_traits::{CFG, CFGMod};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Type of a value. Values are constructed as `Integer`s, the other types are
+/// recovered by `analysis::types`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ValueType {
+    /// Integer of unknown signedness.
     Integer {
         width: ir::WidthSpec,
     },
+    Signed {
+        width: ir::WidthSpec,
+    },
+    /// Address of a value in memory.
+    Pointer {
+        width: ir::WidthSpec,
+    },
+    /// Truth value, one bit wide.
+    Bool,
+    Float {
+        width: ir::WidthSpec,
+    },
+}
+
+impl ValueType {
+    pub fn width(&self) -> ir::WidthSpec {
+        match *self {
+            ValueType::Integer { width } |
+            ValueType::Signed { width } |
+            ValueType::Pointer { width } |
+            ValueType::Float { width } => width,
+            ValueType::Bool => 1,
+        }
+    }
+
+    /// Whether both types have the same width, and therefore may be
+    /// assigned to each other.
+    pub fn compatible(&self, other: &ValueType) -> bool {
+        self.width() == other.width()
+    }
 }
 
 impl<'a> From<&'a str> for ValueType {
     fn from(v: &'a str) -> ValueType {
         let t = v.to_owned().chars().nth(0).unwrap();
-
-        let w = match t {
-            'i' => v[1..].parse::<u16>().unwrap(),
+        if t == 'b' {
+            return ValueType::Bool;
+        }
+
+        let w = v[1..].parse::<u16>().unwrap();
+        match t {
+            'i' => ValueType::Integer { width: w },
+            's' => ValueType::Signed { width: w },
+            'p' => ValueType::Pointer { width: w },
+            'f' => ValueType::Float { width: w },
             _ => unimplemented!(),
-        };
-
-        ValueType::Integer { width: w }
+        }
     }
 }
 
@@ -153,6 +191,11 @@ pub trait SSA: CFG {
     /// Get branches of a selector (false_branch, true_branch).
     fn get_branches(&self, i: &Self::ValueRef) -> (Self::ActionRef, Self::ActionRef);
 
+    /// Get the cases of a switch selector with their targets, ordered by case,
+    /// and the default target. The default is invalid if there is none, and
+    /// there are no cases for a selector of a conditional branch.
+    fn get_cases(&self, i: &Self::ValueRef) -> (Vec<(u64, Self::ActionRef)>, Self::ActionRef);
+
     /// Helper method that gets only the true branch.
     fn get_true_branch(&self, i: &Self::ValueRef) -> Self::ActionRef {
         self.get_branches(i).1
@@ -177,7 +220,8 @@ pub trait SSA: CFG {
         self.get_block(i)
     }
 
-    /// Get a node that has all register values at the beginning of the specified basic block as args
+    /// Get a node that has all register values at the end of the specified basic block, or at the
+    /// entry of the specified dynamic action, as args
     fn registers_at(&self, i: &Self::ActionRef) -> Self::ValueRef;
 
     fn invalid_value(&self) -> Self::ValueRef;
@@ -217,6 +261,9 @@ pub trait SSAMod: SSA + CFGMod {
                    msg: String)
                    -> Self::ValueRef;
 
+    /// Change the type of a value node. The width must not change.
+    fn set_value_type(&mut self, node: Self::ValueRef, vt: ValueType);
+
     /// Mark the node as selector for the control edges away from the specified basic block
     fn mark_selector(&mut self, node: Self::ValueRef, block: Self::ActionRef);
 
@@ -11,7 +11,7 @@ use petgraph::graph;
 
 use middle::dot::{DotAttrBlock, GraphDot};
 use super::ssastorage::{EdgeData, NodeData, SSAStorage};
-use super::ssa_traits::{BBInfo, SSA, SSAExtra, SSAMod, ValueType};
+use super::ssa_traits::{BBInfo, SSA, SSAExtra, SSAMod};
 
 /// ////////////////////////////////////////////////////////////////////////////
 /// / Implementation of GraphDot to emit Dot for SSAStorage.
@@ -99,21 +99,23 @@ impl GraphDot for SSAStorage {
             false
         };
         let attr = match edge.weight {
-            EdgeData::Control(_) if !target_is_bb => {
+            EdgeData::Control(_) | EdgeData::Case(_) | EdgeData::Default if !target_is_bb => {
                 vec![("color".to_string(), "red".to_string())]
             }
-            EdgeData::Control(i) => {
+            EdgeData::Control(_) | EdgeData::Case(_) | EdgeData::Default => {
                 // Determine the source and destination clusters.
                 let source_cluster = edge.source().index();
                 let dst_cluster = edge.target().index();
-                let (color, label) = match i {
-                    0 => ("red", "F"),
-                    1 => ("green", "T"),
-                    2 => ("blue", "U"),
+                let (color, label) = match edge.weight {
+                    EdgeData::Control(0) => ("red", "F".to_owned()),
+                    EdgeData::Control(1) => ("green", "T".to_owned()),
+                    EdgeData::Control(2) => ("blue", "U".to_owned()),
+                    EdgeData::Case(case) => ("orange", format!("{:#x}", case)),
+                    EdgeData::Default => ("orange", "D".to_owned()),
                     _ => unreachable!(),
                 };
                 vec![("color".to_string(), color.to_string()),
-                     ("label".to_string(), format!("{}", label.to_string())),
+                     ("label".to_string(), label),
                      ("ltail".to_string(), format!("cluster_{}", source_cluster)),
                      ("lhead".to_string(), format!("cluster_{}", dst_cluster)),
                      ("minlen".to_string(), format!("9"))]
@@ -143,7 +145,8 @@ impl GraphDot for SSAStorage {
         let mut prefix = String::new();
         prefix.push_str(&format!("n{}", i.index()));
         let attr = match *node {
-            NodeData::Op(opc, ValueType::Integer{width: w}) => {
+            NodeData::Op(opc, vt) => {
+                let w = vt.width();
                 // TODO
                 // self.extras_dump();
                 let addr = self.addr(i);
@@ -5,82 +5,569 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-// Eventually we want to specifiy replacements like this
-// Add(x, Neg(y)) -> Sub(x, y)
-// Sub(x, x) -> 0
-//
-// This file contains the datastructures to hold fragments like "Add(x, Neg(y))"
-//
-// variables = ["x", "y"]
-// nodes = [
-//     /*0*/ varindex=0 pattern=Any
-//     /*1*/ varindex=1 pattern=Any
-//     /*2*/ varindex=_ pattern=Op1(Neg, 1)
-//     /*3*/ varindex=_ pattern=Op2(Add, 0, 1)
-// ]
-
-use middle::ir;
-use super::ssa_traits::{SSA, SSAMod};
-
-struct Variable {
-	name: String,
+//! Pattern matching and rewriting of expression trees.
+//!
+//! An `SSAQuote` holds a fragment like `Add(x, Neg(y))` as a list of nodes
+//! in postorder, the last node being the root:
+//!
+//! ```text
+//! nodes = [
+//!     /*0*/ name="x" pattern=Any
+//!     /*1*/ name="y" pattern=Any
+//!     /*2*/ name=_   pattern=Op1(Neg, 1)
+//!     /*3*/ name=_   pattern=Op2(Add, 0, 2)
+//! ]
+//! ```
+//!
+//! Quotes are usually written with the `ssa_quote!` macro in prefix
+//! notation, and replacements with `ssa_rule!`:
+//!
+//! ```ignore
+//! // x ^ x -> 0
+//! let zero = ssa_rule!((OpXor x x) => 0);
+//! // (x << c) >> c -> x & (mask >> c)
+//! let shifts = ssa_rule!((OpLsr (OpLsl x (const c)) (const c))
+//!                        => (OpAnd x {|k: &Constants| k.mask() >> k.get("c")}));
+//! ```
+//!
+//! The forms understood by the macros are:
+//!
+//!  * `x` - Any value, bound to `x`. All occurrences must be the same value.
+//!  * `(const c)` - Any constant, its value bound to `c`.
+//!  * `5` - The constant 5.
+//!  * `{ f }` - A constant computed by the closure `f` from the bound
+//!  constants. In a pattern, only constants bound to its left are available.
+//!  * `(OpXor a b)`, `(OpNot a)` - An operation with the given operands.
+//!  Operands of commutative operations are matched in either order.
+
+use std::collections::HashMap;
+
+use middle::ir::{MOpcode, WidthSpec};
+use super::ssa_traits::{NodeType, SSA, SSAMod, ValueType};
+
+/// Index of a node in an `SSAQuote`.
+pub type Ref = u16;
+
+pub enum Pattern {
+    Any,
+    Const(u64),
+    ConstAny,
+    Computed(Box<Fn(&Constants) -> u64>),
+    Op0(MOpcode),
+    Op1(MOpcode, Ref),
+    Op2(MOpcode, Ref, Ref),
 }
 
-type Ref = u16;
+pub struct Node {
+    name: Option<String>,
+    pattern: Pattern,
+}
+
+pub struct SSAQuote {
+    nodes: Vec<Node>, // contains the quoted trees in postorder
+}
 
-enum Pattern {
-	Any,
-	Const(u8),
-	ConstAny,
-	Op0(ir::MOpcode),
-	Op1(ir::MOpcode, Ref),
-	Op2(ir::MOpcode, Ref, Ref),
+/// Constants bound by a match, and the width of the matched expression.
+#[derive(Clone, Debug)]
+pub struct Constants {
+    values: HashMap<String, u64>,
+    width: WidthSpec,
 }
 
-struct Node {
-	varindex: u8,
-	pattern:  Pattern
+impl Constants {
+    /// Value of the constant bound to `name`.
+    ///
+    /// Panics if there is none, as that is an error in the rule.
+    pub fn get(&self, name: &str) -> u64 {
+        match self.values.get(name) {
+            Some(v) => *v,
+            None => panic!("Constant `{}` is not bound", name),
+        }
+    }
+
+    pub fn width(&self) -> WidthSpec {
+        self.width
+    }
+
+    /// All ones in the width of the matched expression.
+    pub fn mask(&self) -> u64 {
+        if self.width >= 64 {
+            !0
+        } else {
+            (1 << self.width) - 1
+        }
+    }
 }
 
-struct SSAQuote {
-	variables: Vec<Variable>,
-	nodes:     Vec<Node>, // contains the quoted trees in postorder
+/// Values and constants bound by matching an `SSAQuote`.
+#[derive(Clone, Debug)]
+pub struct Binding<V: Copy + Eq> {
+    values: HashMap<String, V>,
+    constants: Constants,
+    /// Type of the matched root, which its replacement keeps.
+    vt: ValueType,
 }
 
-trait Binding {
-	fn get_valueref(&self, &SSA, &Variable) -> SSA::ValueRef;
-	fn get_value(&self, &SSA, &Variable) -> u64;
-	fn set_valueref(&mut self, &SSA, &Variable, SSA::ValueRef);
-	fn set_value(&mut self, &SSA, &Variable, u64);
+impl<V: Copy + Eq> Binding<V> {
+    pub fn new(vt: ValueType) -> Binding<V> {
+        Binding {
+            values: HashMap::new(),
+            constants: Constants {
+                values: HashMap::new(),
+                width: vt.width(),
+            },
+            vt: vt,
+        }
+    }
+
+    pub fn value(&self, name: &str) -> Option<V> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn constants(&self) -> &Constants {
+        &self.constants
+    }
+
+    /// Binds `name` to `v`. Fails if it is bound to another value.
+    fn bind_value(&mut self, name: &str, v: V) -> bool {
+        if let Some(old) = self.values.get(name) {
+            return *old == v;
+        }
+        self.values.insert(name.to_owned(), v);
+        true
+    }
+
+    /// Binds `name` to the constant `c`. Fails if it is bound to another one.
+    fn bind_constant(&mut self, name: &str, c: u64) -> bool {
+        if let Some(old) = self.constants.values.get(name) {
+            return *old == c;
+        }
+        self.constants.values.insert(name.to_owned(), c);
+        true
+    }
 }
 
 impl SSAQuote {
-	fn insert_into(&self, ssa: &mut SSAMod, block: T::ActionRef, binding: &Binding) {
-		let mut indices = Vec::with_capacity(self.nodes.len());
-		for node in &self.nodes {
-			let i = match node.pattern {
-				Any      => binding.get_valueref(ssa, &self.variables[node.varindex]),
-				Const(n) => ssa.add_const(block, n),
-				ConstAny => ssa.add_const(block, binding.get_value(ssa, &self.variables[node.varindex])),
-				Op0(op)  => ssa.add_op(block, op),
-				Op1(op, op1r) => {
-					let n = ssa.add_op(block, op);
-					ssa.op_use(n, 0, indices[op1r as usize]);
-					n
-				},
-				Op2(op, op1r, op2r) => {
-					let n = ssa.add_op(block, op);
-					ssa.op_use(n, 0, indices[op1r as usize]);
-					ssa.op_use(n, 1, indices[op2r as usize]);
-					n
-				},
-			};
-			indices.push(i);
-		}
-	}
-
-	fn extract_from(&self, ssa: &SSA, binding: &mut Binding) {
-		unimplemented!();
-		//for node in reverse &self.nodes
-	}
+    pub fn new() -> SSAQuote {
+        SSAQuote { nodes: Vec::new() }
+    }
+
+    pub fn any(&mut self, name: &str) -> Ref {
+        self.push(Some(name), Pattern::Any)
+    }
+
+    pub fn constant(&mut self, value: u64) -> Ref {
+        self.push(None, Pattern::Const(value))
+    }
+
+    pub fn const_any(&mut self, name: &str) -> Ref {
+        self.push(Some(name), Pattern::ConstAny)
+    }
+
+    pub fn computed<F>(&mut self, f: F) -> Ref
+        where F: Fn(&Constants) -> u64 + 'static
+    {
+        self.push(None, Pattern::Computed(Box::new(f)))
+    }
+
+    pub fn op0(&mut self, op: MOpcode) -> Ref {
+        self.push(None, Pattern::Op0(op))
+    }
+
+    pub fn op1(&mut self, op: MOpcode, a: Ref) -> Ref {
+        self.push(None, Pattern::Op1(op, a))
+    }
+
+    pub fn op2(&mut self, op: MOpcode, a: Ref, b: Ref) -> Ref {
+        self.push(None, Pattern::Op2(op, a, b))
+    }
+
+    /// Matches the quote against the expression tree rooted at `root`.
+    pub fn extract_from<T: SSA>(&self, ssa: &T, root: T::ValueRef) -> Option<Binding<T::ValueRef>> {
+        let vt = match ssa.get_node_data(&root) {
+            Ok(nd) => nd.vt,
+            Err(_) => return None,
+        };
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut binding = Binding::new(vt);
+        if self.match_node(ssa, self.root(), root, &mut binding) {
+            Some(binding)
+        } else {
+            None
+        }
+    }
+
+    /// Builds the quote in `block`, taking variables from `binding`. Returns
+    /// the root of the new tree.
+    pub fn insert_into<T: SSAMod>(&self,
+                                  ssa: &mut T,
+                                  block: T::ActionRef,
+                                  binding: &Binding<T::ValueRef>)
+                                  -> T::ValueRef {
+        let root = self.root();
+        let mut indices: Vec<T::ValueRef> = Vec::with_capacity(self.nodes.len());
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let vt = if idx == root as usize {
+                binding.vt
+            } else {
+                ValueType::Integer { width: self.width_of(ssa, node, &indices, binding) }
+            };
+            let i = match node.pattern {
+                Pattern::Any => {
+                    let name = node.name.as_ref().unwrap();
+                    match binding.value(name) {
+                        Some(v) => v,
+                        None => panic!("Value `{}` is not bound", name),
+                    }
+                }
+                Pattern::Const(n) => ssa.add_const(block, n),
+                Pattern::ConstAny => {
+                    let c = binding.constants.get(node.name.as_ref().unwrap());
+                    ssa.add_const(block, c)
+                }
+                Pattern::Computed(ref f) => ssa.add_const(block, f(&binding.constants)),
+                Pattern::Op0(op) => ssa.add_op(block, op, vt, None),
+                Pattern::Op1(op, a) => {
+                    let n = ssa.add_op(block, op, vt, None);
+                    ssa.op_use(n, 0, indices[a as usize]);
+                    n
+                }
+                Pattern::Op2(op, a, b) => {
+                    let n = ssa.add_op(block, op, vt, None);
+                    ssa.op_use(n, 0, indices[a as usize]);
+                    ssa.op_use(n, 1, indices[b as usize]);
+                    n
+                }
+            };
+            indices.push(i);
+        }
+        indices[root as usize]
+    }
+
+    /// ////////////////////////////////////////////////////////////////////////
+    /// / Helper functions.
+    /// ////////////////////////////////////////////////////////////////////////
+
+    fn push(&mut self, name: Option<&str>, pattern: Pattern) -> Ref {
+        self.nodes.push(Node {
+            name: name.map(|n| n.to_owned()),
+            pattern: pattern,
+        });
+        (self.nodes.len() - 1) as Ref
+    }
+
+    fn root(&self) -> Ref {
+        (self.nodes.len() - 1) as Ref
+    }
+
+    fn match_node<T: SSA>(&self,
+                          ssa: &T,
+                          idx: Ref,
+                          v: T::ValueRef,
+                          binding: &mut Binding<T::ValueRef>)
+                          -> bool {
+        let nt = match ssa.get_node_data(&v) {
+            Ok(nd) => nd.nt,
+            Err(_) => return false,
+        };
+        let node = &self.nodes[idx as usize];
+        match (&node.pattern, nt) {
+            (&Pattern::Any, _) => binding.bind_value(node.name.as_ref().unwrap(), v),
+            (&Pattern::Const(n), NodeType::Op(MOpcode::OpConst(c))) => n == c,
+            (&Pattern::ConstAny, NodeType::Op(MOpcode::OpConst(c))) => {
+                binding.bind_constant(node.name.as_ref().unwrap(), c)
+            }
+            (&Pattern::Computed(ref f), NodeType::Op(MOpcode::OpConst(c))) => {
+                f(&binding.constants) == c
+            }
+            (&Pattern::Op0(op), NodeType::Op(opc)) => op == opc,
+            (&Pattern::Op1(op, a), NodeType::Op(opc)) if op == opc => {
+                match operand(ssa, &v, 0) {
+                    Some(o) => self.match_node(ssa, a, o, binding),
+                    None => false,
+                }
+            }
+            (&Pattern::Op2(op, a, b), NodeType::Op(opc)) if op == opc => {
+                let (lhs, rhs) = match (operand(ssa, &v, 0), operand(ssa, &v, 1)) {
+                    (Some(lhs), Some(rhs)) => (lhs, rhs),
+                    _ => return false,
+                };
+                let mut attempt = binding.clone();
+                if self.match_node(ssa, a, lhs, &mut attempt) &&
+                   self.match_node(ssa, b, rhs, &mut attempt) {
+                    *binding = attempt;
+                    return true;
+                }
+                if !commutes(op) {
+                    return false;
+                }
+                let mut attempt = binding.clone();
+                if self.match_node(ssa, a, rhs, &mut attempt) &&
+                   self.match_node(ssa, b, lhs, &mut attempt) {
+                    *binding = attempt;
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    // Comparisons yield a single bit, other operations take the width of
+    // their first operand that is not a constant.
+    fn width_of<T: SSAMod>(&self,
+                           ssa: &T,
+                           node: &Node,
+                           indices: &[T::ValueRef],
+                           binding: &Binding<T::ValueRef>)
+                           -> WidthSpec {
+        let (op, operands) = match node.pattern {
+            Pattern::Op1(op, a) => (op, vec![a]),
+            Pattern::Op2(op, a, b) => (op, vec![a, b]),
+            _ => return binding.constants.width,
+        };
+        match op {
+            MOpcode::OpCmp | MOpcode::OpGt | MOpcode::OpLt | MOpcode::OpGteq |
+            MOpcode::OpLteq => return 1,
+            _ => {}
+        }
+        for o in operands {
+            if let Ok(nd) = ssa.get_node_data(&indices[o as usize]) {
+                if let NodeType::Op(MOpcode::OpConst(_)) = nd.nt {
+                    continue;
+                }
+                return nd.vt.width();
+            }
+        }
+        binding.constants.width
+    }
+}
+
+/// Rewrites expressions matching one quote into another.
+pub struct Rule {
+    from: SSAQuote,
+    to: SSAQuote,
+}
+
+impl Rule {
+    /// Creates a rule. Every variable used in `to` must be bound by `from`.
+    pub fn new(from: SSAQuote, to: SSAQuote) -> Rule {
+        Rule {
+            from: from,
+            to: to,
+        }
+    }
+
+    /// Replaces `v` if it matches the rule. Returns the replacement.
+    pub fn apply<T: SSAMod>(&self, ssa: &mut T, v: T::ValueRef) -> Option<T::ValueRef> {
+        let binding = match self.from.extract_from(ssa, v) {
+            Some(binding) => binding,
+            None => return None,
+        };
+        let block = ssa.get_block(&v);
+        let replacement = self.to.insert_into(ssa, block, &binding);
+        if replacement == v {
+            return None;
+        }
+        ssa.replace(v, replacement);
+        Some(replacement)
+    }
+}
+
+fn operand<T: SSA>(ssa: &T, v: &T::ValueRef, index: u8) -> Option<T::ValueRef> {
+    ssa.get_sparse_operands(v)
+       .iter()
+       .find(|&&(i, _)| i == index)
+       .map(|&(_, o)| o)
+}
+
+fn commutes(op: MOpcode) -> bool {
+    match op {
+        MOpcode::OpAdd | MOpcode::OpMul | MOpcode::OpAnd | MOpcode::OpOr | MOpcode::OpXor |
+        MOpcode::OpCmp => true,
+        _ => false,
+    }
+}
+
+/// Builds an `SSAQuote` from an expression in prefix notation. See the
+/// module documentation for the forms understood.
+#[macro_export]
+macro_rules! ssa_quote {
+    (@node $q:ident, (const $c:ident)) => {
+        $q.const_any(stringify!($c))
+    };
+    (@node $q:ident, ($op:ident $a:tt $b:tt)) => {{
+        let a = ssa_quote!(@node $q, $a);
+        let b = ssa_quote!(@node $q, $b);
+        $q.op2($crate::middle::ir::MOpcode::$op, a, b)
+    }};
+    (@node $q:ident, ($op:ident $a:tt)) => {{
+        let a = ssa_quote!(@node $q, $a);
+        $q.op1($crate::middle::ir::MOpcode::$op, a)
+    }};
+    (@node $q:ident, ($op:ident)) => {
+        $q.op0($crate::middle::ir::MOpcode::$op)
+    };
+    (@node $q:ident, { $f:expr }) => {
+        $q.computed($f)
+    };
+    (@node $q:ident, $v:ident) => {
+        $q.any(stringify!($v))
+    };
+    (@node $q:ident, $n:expr) => {
+        $q.constant($n)
+    };
+    ($e:tt) => {{
+        let mut quote = $crate::middle::ssa::ssaquote::SSAQuote::new();
+        ssa_quote!(@node quote, $e);
+        quote
+    }};
+}
+
+/// Builds a `Rule` from two quotes separated by `=>`.
+#[macro_export]
+macro_rules! ssa_rule {
+    ($from:tt => $to:tt) => {
+        $crate::middle::ssa::ssaquote::Rule::new(ssa_quote!($from), ssa_quote!($to))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage, ValueType};
+    use middle::ssa::cfg_traits::CFGMod;
+    use middle::ssa::ssa_traits::NodeType;
+    use middle::ssa::ssastorage::EdgeData;
+
+    fn const_of(ssa: &SSAStorage, v: &<SSAStorage as SSA>::ValueRef) -> Option<u64> {
+        match ssa.get_node_data(v).unwrap().nt {
+            NodeType::Op(MOpcode::OpConst(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn xor_self() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let y = ssa.add_undefined(entry, From::from(64 as u16));
+        let xor = ssa.add_op(entry, MOpcode::OpXor, From::from(64 as u16), None);
+        ssa.op_use(xor, 0, x);
+        ssa.op_use(xor, 1, x);
+        let other = ssa.add_op(entry, MOpcode::OpXor, From::from(64 as u16), None);
+        ssa.op_use(other, 0, x);
+        ssa.op_use(other, 1, y);
+        let add = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(add, 0, xor);
+        ssa.op_use(add, 1, other);
+
+        let rule = ssa_rule!((OpXor x x) => 0);
+        assert!(rule.apply(&mut ssa, other).is_none());
+        assert!(rule.apply(&mut ssa, xor).is_some());
+        let operands = ssa.get_operands(&add);
+        assert_eq!(const_of(&ssa, &operands[0]), Some(0));
+        assert_eq!(operands[1], other);
+    }
+
+    #[test]
+    fn shift_pair() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let three = ssa.add_const(entry, 3);
+        let shl = ssa.add_op(entry, MOpcode::OpLsl, From::from(64 as u16), None);
+        ssa.op_use(shl, 0, x);
+        ssa.op_use(shl, 1, three);
+        let shr = ssa.add_op(entry, MOpcode::OpLsr, From::from(64 as u16), None);
+        ssa.op_use(shr, 0, shl);
+        ssa.op_use(shr, 1, three);
+
+        let rule = ssa_rule!((OpLsr (OpLsl x (const c)) (const c))
+                             => (OpAnd x {|k: &Constants| k.mask() >> k.get("c")}));
+        let and = rule.apply(&mut ssa, shr).unwrap();
+        match ssa.get_node_data(&and).unwrap().nt {
+            NodeType::Op(MOpcode::OpAnd) => {}
+            ref nt => panic!("Expected OpAnd, found {:?}", nt),
+        }
+        let operands = ssa.get_operands(&and);
+        assert_eq!(operands[0], x);
+        assert_eq!(const_of(&ssa, &operands[1]), Some(0x1fffffffffffffff));
+    }
+
+    #[test]
+    fn keeps_type() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let p = ssa.add_undefined(entry, ValueType::Pointer { width: 64 });
+        let eight = ssa.add_const(entry, 8);
+        let four = ssa.add_const(entry, 4);
+        let inner = ssa.add_op(entry, MOpcode::OpAdd, ValueType::Pointer { width: 64 }, None);
+        ssa.op_use(inner, 0, p);
+        ssa.op_use(inner, 1, eight);
+        let outer = ssa.add_op(entry, MOpcode::OpAdd, ValueType::Pointer { width: 64 }, None);
+        ssa.op_use(outer, 0, inner);
+        ssa.op_use(outer, 1, four);
+
+        let rule = ssa_rule!((OpAdd (OpAdd x (const a)) (const b))
+                             => (OpAdd x {|k: &Constants| k.get("a").wrapping_add(k.get("b"))}));
+        let sum = rule.apply(&mut ssa, outer).unwrap();
+        assert_eq!(ssa.get_node_data(&sum).unwrap().vt, ValueType::Pointer { width: 64 });
+        assert_eq!(const_of(&ssa, &ssa.get_operands(&sum)[1]), Some(12));
+    }
+
+    #[test]
+    fn commuted_operands() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let x = ssa.add_undefined(entry, From::from(64 as u16));
+        let zero = ssa.add_const(entry, 0);
+        let add = ssa.add_op(entry, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(add, 0, zero);
+        ssa.op_use(add, 1, x);
+        let sub = ssa.add_op(entry, MOpcode::OpSub, From::from(64 as u16), None);
+        ssa.op_use(sub, 0, zero);
+        ssa.op_use(sub, 1, x);
+
+        let quote = ssa_quote!((OpAdd x 0));
+        assert_eq!(quote.extract_from(&ssa, add).unwrap().value("x"), Some(x));
+        assert!(ssa_quote!((OpSub x 0)).extract_from(&ssa, sub).is_none());
+    }
+
+    #[test]
+    fn rewritten_selector() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let c = ssa.add_undefined(entry, From::from(1 as u16));
+        let and = ssa.add_op(entry, MOpcode::OpAnd, From::from(1 as u16), None);
+        ssa.op_use(and, 0, c);
+        ssa.op_use(and, 1, c);
+        ssa.mark_selector(and, entry);
+
+        let rule = ssa_rule!((OpAnd c c) => c);
+        assert_eq!(rule.apply(&mut ssa, and), Some(c));
+        assert_eq!(ssa.selector_of(&entry), Some(c));
+        let block = ssa.internal(&entry);
+        let selectors = ssa.g
+                           .raw_edges()
+                           .iter()
+                           .filter(|e| {
+                               e.source() == block &&
+                               match e.weight {
+                                   EdgeData::Selector => true,
+                                   _ => false,
+                               }
+                           })
+                           .count();
+        assert_eq!(selectors, 1);
+    }
 }
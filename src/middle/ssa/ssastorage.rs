@@ -74,8 +74,8 @@ pub enum NodeData {
     /// by its associated RegisterState (which includes the instruction
     /// pointer).
     DynamicAction,
-    /// Represents the state of the register file at the moment of entry into
-    /// the associated action node.
+    /// Represents the state of the register file when control leaves the
+    /// associated basic block, or enters the associated dynamic action.
     RegisterState,
 }
 
@@ -85,6 +85,12 @@ pub enum EdgeData {
     /// Edge from action to action. Represents control flow. The number is
     /// used to distinguish true branch, false branch, etc.
     Control(u8),
+    /// Edge from action to action. Taken by a switch when its selector is
+    /// equal to the number. Several cases may lead to the same action.
+    Case(u64),
+    /// Edge from action to action. Taken by a switch when its selector
+    /// matches none of the cases. A switch has at most one.
+    Default,
     /// Edge from value or RegisterState to value. Represents data flow. The
     /// number describes the howmanyeth argument of the edge source is encoded
     /// by this edge.
@@ -95,13 +101,23 @@ pub enum EdgeData {
     /// successors to a value that decides which branch will be taken.
     Selector,
     /// Edge from action to RegisterState. Represents the values of all
-    /// registers at entry to the action.
+    /// registers at the end of a basic block or at entry to a dynamic action.
     RegisterState,
     /// Edge from Removed to value or from Unreachable to action. Created when
     /// calling replace() in stable indices mode.
     ReplacedBy,
 }
 
+impl EdgeData {
+    /// Whether the edge represents control flow between actions.
+    pub fn is_control(&self) -> bool {
+        match *self {
+            EdgeData::Control(_) | EdgeData::Case(_) | EdgeData::Default => true,
+            _ => false,
+        }
+    }
+}
+
 const CONTEDGE: EdgeData = EdgeData::ContainedInBB;
 
 /// A petgraph based SSA storage.
@@ -181,6 +197,10 @@ impl SSAStorage {
                         _ => panic!(),
                     }
                 }
+                // Any value may select the case of a switch.
+                EdgeData::Selector => {
+                    self.mark_selector(j, othernode_e);
+                }
                 _ => (),
             }
         }
@@ -252,9 +272,11 @@ impl SSAStorage {
                 if let EdgeData::Data(_) = self.g[edge] {
                     adjacent.push(self.external(&othernode));
                 }
-            } else {
-                if let EdgeData::Control(_) = self.g[edge] {
-                    adjacent.push(self.external(&othernode));
+            } else if self.g[edge].is_control() {
+                // Several cases of a switch may lead to the same block.
+                let other = self.external(&othernode);
+                if !adjacent.contains(&other) {
+                    adjacent.push(other);
                 }
             }
         }
@@ -301,6 +323,55 @@ impl SSAStorage {
         }
     }
 
+    /// Whether the block `i` ends in a switch.
+    pub fn is_switch(&self, i: NodeIndex) -> bool {
+        self.edges_of(&i).iter().any(|edge| {
+            match self.g[*edge] {
+                EdgeData::Case(_) | EdgeData::Default => true,
+                _ => false,
+            }
+        })
+    }
+
+    // Turns a switch that was left with a single target by the removal of
+    // some of its edges into an unconditional jump. The selector node is
+    // kept, as it is usually used elsewhere, e.g. by the load of a jump table.
+    fn repair_switch(&mut self, exi: NodeIndex) {
+        if self.succs_of(exi).len() > 1 {
+            return;
+        }
+        loop {
+            // Removing an edge may move another one, so the edges are looked
+            // up again every time.
+            let edges = self.edges_of(&exi);
+            if edges.len() <= 1 {
+                if let Some(edge) = edges.first() {
+                    *self.g.edge_weight_mut(*edge).unwrap() = EdgeData::Control(2);
+                }
+                break;
+            }
+            self.g.remove_edge(edges[edges.len() - 1]);
+        }
+        self.remove_selector(exi);
+    }
+
+    // Removes the selector edge of the block `exi`, leaving the selector to
+    // dead code elimination if it has no other use.
+    fn remove_selector(&mut self, exi: NodeIndex) {
+        let block = self.internal(&exi);
+        let mut seledge = None;
+        let mut walk = self.g.walk_edges_directed(block, EdgeDirection::Outgoing);
+        while let Some((edge, _)) = walk.next_neighbor(&self.g) {
+            if let EdgeData::Selector = self.g[edge] {
+                seledge = Some(edge);
+                break;
+            }
+        }
+        if let Some(edge) = seledge {
+            self.g.remove_edge(edge);
+        }
+    }
+
     pub fn is_action(&self, i: NodeIndex) -> bool {
         let action = self.internal(&i);
         match self.g[action] {
@@ -367,7 +438,7 @@ impl CFG for SSAStorage {
         let mut edges = Vec::<EdgeIndex>::new();
         let mut walk = self.g.walk_edges_directed(*i, EdgeDirection::Outgoing);
         while let Some((edge, _)) = walk.next_neighbor(&self.g) {
-            if let EdgeData::Control(_) = self.g[edge] {
+            if self.g[edge].is_control() {
                 edges.push(edge);
             }
         }
@@ -423,12 +494,32 @@ impl CFG for SSAStorage {
         return EdgeIndex::end();
     }
 
+    fn case_edges_of(&self, exi: &NodeIndex) -> Vec<(u64, EdgeIndex)> {
+        let mut cases = Vec::new();
+        for edge in self.edges_of(exi) {
+            if let EdgeData::Case(case) = self.g[edge] {
+                cases.push((case, edge));
+            }
+        }
+        cases.sort_by(|a, b| a.0.cmp(&b.0));
+        cases
+    }
+
+    fn default_edge_of(&self, exi: &NodeIndex) -> EdgeIndex {
+        for edge in self.edges_of(exi) {
+            if let EdgeData::Default = self.g[edge] {
+                return edge;
+            }
+        }
+        EdgeIndex::end()
+    }
+
     fn incoming_edges(&self, exi: &NodeIndex) -> Vec<EdgeIndex> {
         let i = &self.internal(exi);
         let mut edges = Vec::<EdgeIndex>::new();
         let mut walk = self.g.walk_edges_directed(*i, EdgeDirection::Incoming);
         while let Some((edge, _)) = walk.next_neighbor(&self.g) {
-            if let EdgeData::Control(_) = self.g[edge] {
+            if self.g[edge].is_control() {
                 edges.push(edge);
             }
         }
@@ -474,6 +565,14 @@ impl CFGMod for SSAStorage {
         self.insert_edge(source, target, EdgeData::Control(index));
     }
 
+    fn add_case_edge(&mut self, source: Self::ActionRef, target: Self::ActionRef, case: u64) {
+        self.insert_edge(source, target, EdgeData::Case(case));
+    }
+
+    fn add_default_edge(&mut self, source: Self::ActionRef, target: Self::ActionRef) {
+        self.insert_edge(source, target, EdgeData::Default);
+    }
+
     fn remove_block(&mut self, exi: Self::ActionRef) {
         assert!(self.is_block(exi));
 
@@ -498,19 +597,10 @@ impl CFGMod for SSAStorage {
 
         // block removal can make predecessors lose selectors
         for pred_e in preds {
-            if self.succs_of(pred_e).len() == 1 {
-                let pred = self.internal(&pred_e);
-                let mut walk = self.g.walk_edges_directed(pred, EdgeDirection::Incoming);
-                let mut seledge = None;
-                while let Some((edge, _)) = walk.next_neighbor(&self.g) {
-                    if let EdgeData::Selector = self.g[edge] {
-                        seledge = Some(edge);
-                        break;
-                    }
-                }
-                if let Some(edge) = seledge {
-                    self.g.remove_edge(edge);
-                }
+            if self.is_switch(pred_e) {
+                self.repair_switch(pred_e);
+            } else if self.succs_of(pred_e).len() == 1 {
+                self.remove_selector(pred_e);
             }
         }
     }
@@ -613,6 +703,21 @@ impl SSA for SSAStorage {
         (false_branch, true_branch)
     }
 
+    fn get_cases(&self, exi: &NodeIndex) -> (Vec<(u64, NodeIndex)>, NodeIndex) {
+        let block = self.selects_for(exi);
+        assert!(block != NodeIndex::end());
+        let cases = self.case_edges_of(&block)
+                        .iter()
+                        .map(|&(case, edge)| (case, self.target_of(&edge)))
+                        .collect();
+        let default = self.default_edge_of(&block);
+        if default == EdgeIndex::end() {
+            (cases, NodeIndex::end())
+        } else {
+            (cases, self.target_of(&default))
+        }
+    }
+
     fn registers_at(&self, exi: &NodeIndex) -> NodeIndex {
         assert!(self.is_action(*exi));
         let i = &self.internal(exi);
@@ -703,7 +808,7 @@ impl SSA for SSAStorage {
 
         let mut walk = self.g.walk_edges_directed(cur_block, EdgeDirection::Outgoing);
         while let Some((edge, othernode)) = walk.next_neighbor(&self.g) {
-            if let EdgeData::Control(_) = self.g[edge] {
+            if self.g[edge].is_control() {
                 return self.external(&othernode);
             }
         }
@@ -784,6 +889,20 @@ impl SSAMod for SSAStorage {
         n
     }
 
+    fn set_value_type(&mut self, node: NodeIndex, vt: ValueType) {
+        let node = self.internal(&node);
+        match self.g[node] {
+            NodeData::Op(_, ref mut t) |
+            NodeData::Phi(ref mut t, _) |
+            NodeData::Comment(ref mut t, _) |
+            NodeData::Undefined(ref mut t) => {
+                assert!(t.compatible(&vt));
+                *t = vt;
+            }
+            _ => panic!("Not a value node"),
+        }
+    }
+
     fn mark_selector(&mut self, node: Self::ValueRef, block: Self::ActionRef) {
         self.insert_edge(block, node, EdgeData::Selector);
     }
@@ -817,8 +936,10 @@ impl SSAMod for SSAStorage {
         }
 
         let src_node = self.source_of(i);
-        if let Some(selector) = self.selector_of(&src_node) {
-            self.remove(selector);
+        if let EdgeData::Case(_) | EdgeData::Default = self.g[*i] {
+            self.g.remove_edge(*i);
+            self.repair_switch(src_node);
+            return;
         }
 
         let other_edge = match self.g[*i] {
@@ -839,6 +960,9 @@ impl SSAMod for SSAStorage {
         }
 
         self.g.remove_edge(*i);
+        // The selector of a constant branch may have been replaced by a
+        // constant that is used elsewhere, so only the edge is removed.
+        self.remove_selector(src_node);
     }
 }
 
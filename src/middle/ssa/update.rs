@@ -0,0 +1,502 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Incremental updates of the control flow of an `SSAStorage`.
+//!
+//! The control flow of a function changes as its user learns more about it,
+//! e.g. that a call does not return or where an indirect jump goes. Instead
+//! of constructing the SSA again, the blocks are split and merged in place
+//! and the blocks whose predecessors changed are sealed again with the
+//! `PhiPlacer`.
+//!
+//! The register state of a basic block holds the registers at its end, as
+//! `SSAConstruction` records them. A block merges the registers at the end
+//! of its predecessors. Where a register reached a block with a single value
+//! before and with several ones now, a phi is placed and the uses dominated
+//! by the block are moved to it. Registers removed by dead code elimination
+//! are not used after the block and are not updated.
+//!
+//! SSA values do not record the register they were read from, so a value
+//! reaching a block in several registers, as after a copy, is taken to be
+//! read from the first of them.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use petgraph::EdgeDirection;
+use petgraph::graph::NodeIndex;
+
+use analysis::dom::blocks::{dominates, dominators, reverse_post_order};
+use middle::phiplacement::PhiPlacer;
+use super::ssa_traits::{BBInfo, SSA, SSAMod};
+use super::cfg_traits::{CFG, CFGMod};
+use super::ssastorage::{EdgeData, NodeData, SSAStorage};
+
+/// The values a block merges, by the index of the register in the register
+/// state. A register is merged by a value from before the block or by phis
+/// of the block.
+pub type EntryState = BTreeMap<u8, Vec<NodeIndex>>;
+
+impl SSAStorage {
+    /// The values merged at the entry of `block`. Registers that are dead at
+    /// the entry are left out.
+    pub fn entry_state(&self, block: NodeIndex) -> EntryState {
+        let mut state = EntryState::new();
+        if let Some(&pred) = self.preds_of(block).first() {
+            for (var, _) in self.get_sparse_operands(&self.registers_at(&pred)) {
+                if let Some(values) = self.entry_value(block, var) {
+                    state.insert(var, values);
+                }
+            }
+        }
+        state
+    }
+
+    /// Seals `block` again after its predecessors changed, given its
+    /// `entry_state` from before the change. Where the registers it merges
+    /// change, the blocks after it are updated as well.
+    pub fn reseal(&mut self, block: NodeIndex, state: &EntryState) {
+        let order = reverse_post_order(self);
+        let idom = dominators(self, &order);
+        let index = order.iter()
+                         .enumerate()
+                         .map(|(i, b)| (*b, i))
+                         .collect::<HashMap<_, _>>();
+
+        let mut worklist = state.iter()
+                                .map(|(&var, old)| (block, var, old.clone()))
+                                .collect::<VecDeque<_>>();
+        while let Some((block, var, old)) = worklist.pop_front() {
+            let b = match index.get(&block) {
+                Some(&b) if !self.preds_of(block).is_empty() => b,
+                // Unreachable blocks are left as they are.
+                _ => continue,
+            };
+            let old = old.iter().map(|v| self.canonical(*v)).collect::<Vec<_>>();
+            let new = self.merge_register(block, var, &old);
+
+            let phis = old.iter()
+                          .cloned()
+                          .filter(|v| self.is_phi_of(*v, block))
+                          .collect::<Vec<_>>();
+            if !phis.is_empty() {
+                // The uses of a phi all come after the block.
+                for phi in phis {
+                    let new = self.canonical(new);
+                    if phi != new {
+                        self.replace(phi, new);
+                    }
+                }
+                // The new phi may use the ones it replaced.
+                let new = self.canonical(new);
+                if self.is_phi_of(new, block) {
+                    PhiPlacer::new(self).try_remove_trivial_phi(new);
+                }
+                continue;
+            }
+
+            let value = old[0];
+            if new == value {
+                continue;
+            }
+            let region = (0..order.len())
+                             .filter(|&n| dominates(&idom, b, n))
+                             .map(|n| order[n])
+                             .collect::<Vec<_>>();
+
+            // The blocks the register flows into from the region, with what
+            // they merged before. Blocks inside the region only merge the
+            // register again if they have phis for it.
+            let mut next = Vec::<(NodeIndex, u8, Vec<NodeIndex>)>::new();
+            for &n in &region {
+                for succ in self.succs_of(n) {
+                    if next.iter().any(|&(s, _, _)| s == succ) {
+                        continue;
+                    }
+                    let inner = succ != block && region.contains(&succ);
+                    if let Some(values) = self.entry_value(succ, var) {
+                        if !inner || self.is_phi_of(values[0], succ) {
+                            next.push((succ, var, values));
+                        }
+                    }
+                }
+            }
+
+            for &n in &region {
+                for expr in self.exprs_in(&n) {
+                    self.replace_operand(expr, value, new);
+                }
+                if self.selector_of(&n) == Some(value) {
+                    self.take_selector(n);
+                    self.mark_selector(new, n);
+                }
+                if self.register_at_end(n, var) == Some(value) {
+                    let rs = self.registers_at(&n);
+                    self.set_operand(rs, var, Some(new));
+                }
+            }
+            worklist.extend(next);
+        }
+    }
+
+    /// Adds a control edge and seals `target` again. A conditional edge
+    /// needs a selector of `source`, see `SSAMod::mark_selector`.
+    pub fn insert_control_edge(&mut self, source: NodeIndex, target: NodeIndex, index: u8) {
+        let state = self.entry_state(target);
+        self.add_control_edge(source, target, index);
+        self.reseal(target, &state);
+    }
+
+    /// Removes the control edges from `source` to `target` and seals
+    /// `target` again. A branch left with a single target becomes
+    /// unconditional.
+    pub fn delete_control_edge(&mut self, source: NodeIndex, target: NodeIndex) {
+        let state = self.entry_state(target);
+        loop {
+            // Removing an edge may move another one, so the edges are looked
+            // up again every time.
+            let edge = self.edges_of(&source).into_iter().find(|e| self.target_of(e) == target);
+            match edge {
+                Some(edge) => self.remove_edge(&edge),
+                None => break,
+            }
+        }
+        self.reseal(target, &state);
+    }
+
+    /// Moves `tail`, the values at the end of `block`, to a new block at
+    /// `addr` that takes over the successors of `block`. The registers at the
+    /// end of `block` that hold a value of the tail are no longer known.
+    pub fn split_block(&mut self, block: NodeIndex, addr: u64, tail: &[NodeIndex]) -> NodeIndex {
+        assert!(self.is_block(block));
+        let tail = tail.iter().map(|v| self.canonical(*v)).collect::<Vec<_>>();
+        for value in &tail {
+            assert!(self.is_expr(value) && self.block_of(value) == block);
+        }
+        for expr in self.exprs_in(&block) {
+            if !tail.contains(&expr) {
+                assert!(self.args_of(expr).iter().all(|a| !tail.contains(a)),
+                        "The values before the split must not use the tail");
+            }
+        }
+
+        let new = self.add_block(BBInfo { addr: addr });
+        for &value in &tail {
+            self.move_value(value, new);
+        }
+        self.move_successors(block, new);
+        let (before, after) = (self.registers_at(&block), self.registers_at(&new));
+        for (var, value) in self.get_sparse_operands(&before) {
+            self.op_use(after, var, value);
+            if tail.contains(&value) {
+                self.set_operand(before, var, None);
+            }
+        }
+        self.add_control_edge(block, new, 2);
+        new
+    }
+
+    /// Merges `succ` into `block`, which must be its only predecessor and
+    /// have no other successor.
+    pub fn merge_blocks(&mut self, block: NodeIndex, succ: NodeIndex) {
+        assert!(block != succ && self.is_block(block) && self.is_block(succ));
+        assert_eq!(self.succs_of(block), vec![succ]);
+        assert_eq!(self.preds_of(succ), vec![block]);
+
+        // With a single predecessor, the phis merge a single value.
+        for phi in self.get_phis(&succ) {
+            let phi = self.canonical(phi);
+            if self.is_phi_of(phi, succ) {
+                PhiPlacer::new(self).try_remove_trivial_phi(phi);
+            }
+        }
+        for value in self.values_in(succ) {
+            self.move_value(value, block);
+        }
+
+        while let Some(&edge) = self.edges_of(&block).first() {
+            self.g.remove_edge(edge);
+        }
+        self.take_selector(block);
+        self.move_successors(succ, block);
+        let (rs, succ_rs) = (self.registers_at(&block), self.registers_at(&succ));
+        for (var, _) in self.get_sparse_operands(&rs) {
+            self.set_operand(rs, var, None);
+        }
+        for (var, value) in self.get_sparse_operands(&succ_rs) {
+            self.op_use(rs, var, value);
+        }
+        self.remove_block(succ);
+    }
+
+    /// ///////////////////////////////////////////////////////////////////////
+    /// / Helper functions.
+    /// ///////////////////////////////////////////////////////////////////////
+
+    // The values `block` merges for `var`, if the register is known at the
+    // end of all predecessors and used after `block`.
+    fn entry_value(&self, block: NodeIndex, var: u8) -> Option<Vec<NodeIndex>> {
+        let mut values = Vec::new();
+        for pred in self.preds_of(block) {
+            match self.register_at_end(pred, var) {
+                Some(value) => values.push(value),
+                None => return None,
+            }
+        }
+        if values.is_empty() {
+            return None;
+        }
+        if values.iter().all(|v| *v == values[0]) {
+            return Some(vec![values[0]]);
+        }
+        values.sort();
+        let phis = self.get_phis(&block)
+                       .into_iter()
+                       .filter(|phi| {
+                           let mut args = self.args_of(*phi);
+                           args.sort();
+                           args == values
+                       })
+                       .collect::<Vec<_>>();
+        if phis.is_empty() {
+            None
+        } else {
+            Some(phis)
+        }
+    }
+
+    // Merges the values of `var` at the end of the predecessors of `block`
+    // with the `PhiPlacer`. `old` holds the values merged before.
+    fn merge_register(&mut self, block: NodeIndex, var: u8, old: &[NodeIndex]) -> NodeIndex {
+        let vt = self.get_node_data(&old[0]).expect("No Data associated with this node!").vt;
+        let mut values = Vec::new();
+        for pred in self.preds_of(block) {
+            let value = match self.register_at_end(pred, var) {
+                Some(value) => value,
+                None => {
+                    // The register is not known at the end of the new
+                    // predecessor.
+                    let value = self.add_undefined(pred, vt);
+                    let rs = self.registers_at(&pred);
+                    self.op_use(rs, var, value);
+                    value
+                }
+            };
+            values.push((pred, value));
+        }
+
+        let mut placer = PhiPlacer::new(self);
+        placer.add_variables(vec![vt]);
+        placer.add_existing_block(block);
+        let phi = placer.read_variable(block, 0);
+        // Written after the read, as the block may be its own predecessor.
+        for (pred, value) in values {
+            placer.write_variable(pred, 0, value);
+        }
+        placer.seal_block(block);
+        placer.ssa.canonical(phi)
+    }
+
+    fn register_at_end(&self, block: NodeIndex, var: u8) -> Option<NodeIndex> {
+        let rs = self.registers_at(&block);
+        if rs == NodeIndex::end() {
+            return None;
+        }
+        self.get_sparse_operands(&rs).into_iter().find(|&(i, _)| i == var).map(|(_, v)| v)
+    }
+
+    // The node that `node` was replaced by, or `node` itself.
+    fn canonical(&self, node: NodeIndex) -> NodeIndex {
+        self.external(&self.internal(&node))
+    }
+
+    fn is_phi_of(&self, value: NodeIndex, block: NodeIndex) -> bool {
+        match self.g[self.internal(&value)] {
+            NodeData::Phi(_, _) => self.block_of(&value) == block,
+            _ => false,
+        }
+    }
+
+    // All values in `block`, including phis, comments and undefined values.
+    fn values_in(&self, block: NodeIndex) -> Vec<NodeIndex> {
+        let mut values = Vec::new();
+        let mut walk = self.g.walk_edges_directed(self.internal(&block), EdgeDirection::Incoming);
+        while let Some((edge, othernode)) = walk.next_neighbor(&self.g) {
+            if let EdgeData::ContainedInBB = self.g[edge] {
+                values.push(self.external(&othernode));
+            }
+        }
+        values
+    }
+
+    fn move_value(&mut self, value: NodeIndex, block: NodeIndex) {
+        let (n, b) = (self.internal(&value), self.internal(&block));
+        let mut contedge = None;
+        let mut walk = self.g.walk_edges_directed(n, EdgeDirection::Outgoing);
+        while let Some((edge, _)) = walk.next_neighbor(&self.g) {
+            if let EdgeData::ContainedInBB = self.g[edge] {
+                contedge = Some(edge);
+                break;
+            }
+        }
+        if let Some(edge) = contedge {
+            self.g.remove_edge(edge);
+        }
+        self.g.add_edge(n, b, EdgeData::ContainedInBB);
+    }
+
+    // Moves the control edges leaving `from` and its selector to `to`.
+    fn move_successors(&mut self, from: NodeIndex, to: NodeIndex) {
+        let edges = self.edges_of(&from)
+                        .iter()
+                        .map(|e| (self.internal(&self.target_of(e)), self.g[*e]))
+                        .collect::<Vec<_>>();
+        while let Some(&edge) = self.edges_of(&from).first() {
+            self.g.remove_edge(edge);
+        }
+        let source = self.internal(&to);
+        for (target, weight) in edges {
+            self.g.add_edge(source, target, weight);
+        }
+        if let Some(selector) = self.take_selector(from) {
+            self.mark_selector(selector, to);
+        }
+    }
+
+    // Removes the selector edge of `block` and returns the selector.
+    fn take_selector(&mut self, block: NodeIndex) -> Option<NodeIndex> {
+        let selector = self.selector_of(&block);
+        if let Some(selector) = selector {
+            let edge = self.g.find_edge(self.internal(&block), self.internal(&selector));
+            if let Some(edge) = edge {
+                self.g.remove_edge(edge);
+            }
+        }
+        selector
+    }
+
+    fn replace_operand(&mut self, node: NodeIndex, value: NodeIndex, replacement: NodeIndex) {
+        let indices = self.get_sparse_operands(&node)
+                          .into_iter()
+                          .filter(|&(_, o)| o == value)
+                          .map(|(i, _)| i)
+                          .collect::<Vec<_>>();
+        for i in indices {
+            self.set_operand(node, i, Some(replacement));
+        }
+    }
+
+    // Sets the `index`-th operand of `node`, or removes it.
+    fn set_operand(&mut self, node: NodeIndex, index: u8, value: Option<NodeIndex>) {
+        let n = self.internal(&node);
+        loop {
+            let mut operand = None;
+            let mut walk = self.g.walk_edges_directed(n, EdgeDirection::Outgoing);
+            while let Some((edge, _)) = walk.next_neighbor(&self.g) {
+                if let EdgeData::Data(i) = self.g[edge] {
+                    if i == index {
+                        operand = Some(edge);
+                        break;
+                    }
+                }
+            }
+            match operand {
+                Some(edge) => {
+                    self.g.remove_edge(edge);
+                }
+                None => break,
+            }
+        }
+        if let Some(value) = value {
+            self.op_use(node, index, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use middle::ir::MOpcode;
+    use middle::ssa::{BBInfo, SSA, SSAMod, SSAStorage};
+    use middle::ssa::cfg_traits::{CFG, CFGMod};
+    use middle::ssa::verifier::verify;
+    use petgraph::graph::NodeIndex;
+
+    // `entry` branches to `left`, which sets register 0 to 2, and to
+    // `right`, which doubles it. Both go on to the exit.
+    fn setup() -> (SSAStorage, Vec<NodeIndex>, Vec<NodeIndex>) {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.add_block(BBInfo { addr: 0 });
+        ssa.mark_start_node(&entry);
+        let exit = ssa.add_dynamic();
+        ssa.mark_exit_node(&exit);
+        let left = ssa.add_block(BBInfo { addr: 0x10 });
+        let right = ssa.add_block(BBInfo { addr: 0x20 });
+        ssa.add_control_edge(entry, left, 1);
+        ssa.add_control_edge(entry, right, 0);
+        ssa.add_control_edge(left, exit, 2);
+        ssa.add_control_edge(right, exit, 2);
+
+        let one = ssa.add_const(entry, 1);
+        let cond = ssa.add_undefined(entry, From::from(1 as u16));
+        ssa.mark_selector(cond, entry);
+        let two = ssa.add_const(left, 2);
+        let sum = ssa.add_op(right, MOpcode::OpAdd, From::from(64 as u16), None);
+        ssa.op_use(sum, 0, one);
+        ssa.op_use(sum, 1, one);
+        let phi = ssa.add_phi(exit, From::from(64 as u16));
+        ssa.phi_use(phi, two);
+        ssa.phi_use(phi, sum);
+        for &(block, value) in &[(entry, one), (left, two), (right, sum), (exit, phi)] {
+            let rs = ssa.registers_at(&block);
+            ssa.op_use(rs, 0, value);
+        }
+        (ssa, vec![entry, left, right, exit], vec![one, two, sum])
+    }
+
+    #[test]
+    fn redirect_edge() {
+        let (mut ssa, blocks, values) = setup();
+        let (left, right, exit) = (blocks[1], blocks[2], blocks[3]);
+        let (one, two, sum) = (values[0], values[1], values[2]);
+
+        ssa.delete_control_edge(left, exit);
+        assert!(ssa.get_phis(&exit).is_empty());
+        assert_eq!(ssa.get_sparse_operands(&ssa.registers_at(&exit)), vec![(0, sum)]);
+
+        ssa.insert_control_edge(left, right, 2);
+        let phis = ssa.get_phis(&right);
+        assert_eq!(phis.len(), 1);
+        let mut args = ssa.args_of(phis[0]);
+        args.sort();
+        let mut expected = vec![one, two];
+        expected.sort();
+        assert_eq!(args, expected);
+        assert_eq!(ssa.get_operands(&sum), vec![phis[0], phis[0]]);
+        assert_eq!(ssa.get_sparse_operands(&ssa.registers_at(&exit)), vec![(0, sum)]);
+        assert!(verify(&ssa).is_ok());
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let (mut ssa, blocks, values) = setup();
+        let (right, exit, sum) = (blocks[2], blocks[3], values[2]);
+
+        let tail = ssa.split_block(right, 0x28, &[sum]);
+        assert_eq!(ssa.block_of(&sum), tail);
+        assert_eq!(ssa.succs_of(right), vec![tail]);
+        assert_eq!(ssa.succs_of(tail), vec![exit]);
+        assert!(ssa.get_sparse_operands(&ssa.registers_at(&right)).is_empty());
+        assert_eq!(ssa.get_sparse_operands(&ssa.registers_at(&tail)), vec![(0, sum)]);
+        assert!(verify(&ssa).is_ok());
+
+        ssa.merge_blocks(right, tail);
+        assert_eq!(ssa.block_of(&sum), right);
+        assert_eq!(ssa.succs_of(right), vec![exit]);
+        assert_eq!(ssa.get_sparse_operands(&ssa.registers_at(&right)), vec![(0, sum)]);
+        assert!(!ssa.blocks().contains(&tail));
+        assert!(verify(&ssa).is_ok());
+    }
+}
@@ -9,6 +9,7 @@
 //! valid.
 //!
 //! This is only for verification and to catch potential mistakes.
+use std::collections::HashSet;
 use petgraph::graph::NodeIndex;
 
 use super::cfg_traits::CFG;
@@ -71,10 +72,14 @@ impl Verify for SSAStorage {
     fn verify_block(&self, block: &NodeIndex) -> VResult<Self> {
         let _ = self.node_count();
 
+        if self.is_switch(*block) {
+            return verify_switch(self, block);
+        }
+
         let edges = self.edges_of(block);
 
-        // Every BB can have a maximum of 2 Outgoing CFG Edges.
-        // TODO: Relax this assumption when we have support for switch.
+        // Every BB other than a switch can have a maximum of 2 Outgoing CFG
+        // Edges.
         check!(edges.len() < 3,
                SSAErr::WrongNumEdges(*block, 3, edges.len()));
 
@@ -156,12 +161,9 @@ impl Verify for SSAStorage {
         let i = &self.internal(exi);
         let node_data = &self.g[*i];
         match *node_data {
-            NodeData::Op(opcode, ValueType::Integer { width: w }) => {
-                let extract = |x: TNodeData| -> u16 {
-                    match x.vt {
-                        ValueType::Integer { width: w } => w,
-                    }
-                };
+            NodeData::Op(opcode, vt) => {
+                let w = vt.width();
+                let extract = |x: TNodeData| -> u16 { x.vt.width() };
 
                 let operands = self.get_operands(exi);
                 let op_len = operands.len();
@@ -171,8 +173,17 @@ impl Verify for SSAStorage {
                     MArity::Binary => 2,
                     _ => unreachable!(),
                 };
+                // Loads and stores also take the state of the memory.
+                let n = match opcode {
+                    MOpcode::OpLoad(..) | MOpcode::OpStore(..) | MOpcode::OpUnknown |
+                    MOpcode::OpMemory => n + 1,
+                    _ => n,
+                };
 
-                {
+                // Calls also take their arguments.
+                if opcode == MOpcode::OpCall {
+                    check!(op_len >= n, SSAErr::WrongNumOperands(*i, n, op_len));
+                } else {
                     check!(op_len == n, SSAErr::WrongNumOperands(*i, n, op_len));
                 }
 
@@ -194,10 +205,33 @@ impl Verify for SSAStorage {
                     MOpcode::OpGt |
                     MOpcode::OpLt |
                     MOpcode::OpLteq |
-                    MOpcode::OpGteq => {
+                    MOpcode::OpGteq |
+                    MOpcode::OpFCmp |
+                    MOpcode::OpFLt |
+                    MOpcode::OpFLteq => {
                         check!(w == 1, SSAErr::IncompatibleWidth(*i, 1, w));
                     }
-                    MOpcode::OpCall | MOpcode::OpStore => {}
+                    MOpcode::OpItoF(w0) |
+                    MOpcode::OpSItoF(w0) |
+                    MOpcode::OpFtoI(w0) |
+                    MOpcode::OpFConv(w0) => {
+                        check!(w == w0, SSAErr::IncompatibleWidth(*i, w, w0));
+                    }
+                    MOpcode::OpLsl | MOpcode::OpLsr | MOpcode::OpAsr | MOpcode::OpRol |
+                    MOpcode::OpRor => {
+                        // The shift amount may have any width.
+                        let w0 = self.get_node_data(&operands[0]).map(&extract).unwrap();
+                        check!(w0 == w, SSAErr::IncompatibleWidth(*i, w, w0));
+                    }
+                    MOpcode::OpLoad(w0, _) => {
+                        check!(w == w0, SSAErr::IncompatibleWidth(*i, w, w0));
+                    }
+                    MOpcode::OpStore(w0, _) => {
+                        // The stored value has the width of the access.
+                        let opw = self.get_node_data(&operands[1]).map(&extract).unwrap();
+                        check!(opw == w0, SSAErr::IncompatibleWidth(*i, opw, w0));
+                    }
+                    MOpcode::OpCall | MOpcode::OpUnknown | MOpcode::OpMemory => {}
                     _ => {
                         // All operands to an expr must have the same width.
                         let w0 = self.get_node_data(&operands[0]).map(&extract).unwrap();
@@ -215,6 +249,38 @@ impl Verify for SSAStorage {
     }
 }
 
+// Things to lookout for in a switch:
+//  * There must be a minimum of two edges.
+//  * There _must_ be a selector.
+//  * No two cases may have the same value.
+//  * There can be at most one default edge.
+//  * There can be no true, false or unconditional edge.
+fn verify_switch(ssa: &SSAStorage, block: &NodeIndex) -> VResult<SSAStorage> {
+    let edges = ssa.edges_of(block);
+    check!(edges.len() >= 2,
+           SSAErr::WrongNumEdges(*block, 2, edges.len()));
+
+    let mut cases = HashSet::new();
+    let mut default = false;
+    for edge in edges.iter() {
+        let target = ssa.target_of(edge);
+        check!(ssa.is_action(target), SSAErr::InvalidType("Block".to_owned()));
+        match ssa.g[*edge] {
+            EdgeData::Case(case) => {
+                check!(cases.insert(case), SSAErr::DuplicateCase(*block, case));
+            }
+            EdgeData::Default => {
+                check!(!default, SSAErr::InvalidControl(*block, *edge));
+                default = true;
+            }
+            _ => check!(false, SSAErr::InvalidControl(*block, *edge)),
+        }
+    }
+
+    check!(ssa.selector_of(block).is_some(), SSAErr::NoSelector(*block));
+    Ok(())
+}
+
 pub fn verify<T>(ssa: &T) -> VResult<T>
     where T: Verify + Debug
 {
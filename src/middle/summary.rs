@@ -0,0 +1,109 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Summaries of what a function reads, writes and returns.
+//!
+//! A summary is computed from the SSA of a function and used in place of the
+//! calling convention when constructing the SSA of its callers. Functions
+//! are therefore summarized bottom-up over the call graph.
+//!
+//! The registers a function saves on the stack and restores before it
+//! returns come back as loads, so the SSA does not show that they are
+//! preserved. Only the registers the calling convention lets a callee modify
+//! are therefore reported as modified.
+
+use r2pipe::structs::LRegInfo;
+
+use frontend::esilssa::{MEMORY, REGISTERS};
+use middle::callconv::CallingConvention;
+use middle::ir::MOpcode;
+use middle::regfile::SubRegisterFile;
+use middle::ssa::{SSA, SSAStorage};
+use middle::ssa::cfg_traits::CFG;
+use middle::ssa::ssastorage::NodeData;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Registers whose value at entry is used.
+    pub args: Vec<String>,
+    /// Registers whose value at exit may differ from the one at entry, and
+    /// which the calling convention does not preserve.
+    pub modified: Vec<String>,
+    /// Whether the function loads from memory. Calls and instructions of
+    /// unknown effect count as loads.
+    pub reads_memory: bool,
+    /// Whether the function stores to memory. Calls and instructions of
+    /// unknown effect count as stores.
+    pub writes_memory: bool,
+    /// Whether the function never returns to its caller. It is not known
+    /// from the SSA, see `CFG::returns`.
+    pub noreturn: bool,
+}
+
+impl Summary {
+    /// Summarizes the function in `ssa`, which was constructed by
+    /// `SSAConstruction` with the register profile `reg_info`.
+    pub fn compute(ssa: &SSAStorage, reg_info: &LRegInfo) -> Summary {
+        let names = SubRegisterFile::new(reg_info).whole_names;
+        // Without a convention, any register may be modified.
+        let modifiable = CallingConvention::for_registers(reg_info).map(|cc| cc.modified());
+        let mut summary = Summary::default();
+
+        let mut nodes = ssa.nodes();
+        nodes.sort();
+        for n in nodes {
+            match ssa.g[ssa.internal(&n)] {
+                NodeData::Comment(_, ref name) if names.contains(name) => {
+                    let used = ssa.uses_of(n).iter().any(|u| {
+                        match ssa.g[ssa.internal(u)] {
+                            NodeData::Op(_, _) | NodeData::Phi(_, _) => true,
+                            _ => false,
+                        }
+                    });
+                    if used {
+                        summary.args.push(name.clone());
+                    }
+                }
+                NodeData::Op(MOpcode::OpLoad(..), _) => summary.reads_memory = true,
+                NodeData::Op(MOpcode::OpStore(..), _) => summary.writes_memory = true,
+                NodeData::Op(MOpcode::OpCall, _) |
+                NodeData::Op(MOpcode::OpUnknown, _) => {
+                    summary.reads_memory = true;
+                    summary.writes_memory = true;
+                }
+                _ => {}
+            }
+        }
+
+        let exit = ssa.exit_node();
+        let state = ssa.registers_at(&exit);
+        for (i, value) in ssa.get_sparse_operands(&state) {
+            let i = i as usize;
+            let name = if i == MEMORY {
+                "mem"
+            } else if i >= REGISTERS && i - REGISTERS < names.len() {
+                &names[i - REGISTERS][..]
+            } else {
+                continue;
+            };
+            let unchanged = match ssa.g[ssa.internal(&value)] {
+                NodeData::Comment(_, ref c) => c == name,
+                _ => false,
+            };
+            if unchanged {
+                continue;
+            }
+            if i == MEMORY {
+                summary.writes_memory = true;
+            } else if modifiable.as_ref().map_or(true, |regs| regs.iter().any(|r| r == name)) {
+                summary.modified.push(name.to_owned());
+            }
+        }
+        summary.modified.sort();
+        summary
+    }
+}
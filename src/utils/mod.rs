@@ -10,25 +10,40 @@
 
 #[macro_use]
 pub mod logger;
+pub mod program;
 
+use std::collections::BTreeSet;
 use std::io::prelude::*;
 use std::fs;
 use std::fs::File;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use r2pipe::structs::{LOpInfo, LRegInfo};
 use r2pipe::R2;
 
 use frontend::parser::Parser;
 use frontend::esilssa::SSAConstruction;
+use frontend::memory::MemoryMap;
+use frontend::offline::{self, Recording};
+use middle::arch::{Architecture, Endian};
+use middle::callconv::CallingConvention;
 use middle::ir::MInst;
 use middle::cfg::CFG;
 use middle::dot;
 use middle::dce;
+use middle::gvn;
+use middle::simplify;
 use middle::ssa::SSAStorage;
 use analysis::constant_propagation::constant;
+use analysis::jumptable;
+use analysis::noreturn;
+use analysis::layout::Layouts;
+use analysis::stack::{self, StackFrame};
+use analysis::types::TypeInference;
 use middle::ssa::verifier;
+use backend::lang_c::CWriter;
+use backend::scf::dream::Dreamer;
 
 macro_rules! out {
 	($str: expr, $m: expr) => { if $m { println!($str) } }
@@ -37,15 +52,23 @@ macro_rules! out {
 #[derive(Clone, Copy, Debug)]
 pub enum Analysis {
     ConstProp,
+    StackVars,
+    Types,
+    Layout,
 }
 
 #[derive(Clone, Copy, Debug)]
 pub enum Pipeline {
     ReadFromR2,
+    /// Reads a function recorded from r2. The binary name is the directory of
+    /// the recording, see `frontend::offline`.
+    ReadFromJSON,
     ParseEsil,
     CFG,
     SSA,
     AnalyzeSSA(Analysis),
+    Simplify,
+    GVN,
     DCE,
     Verify,
     CWriter,
@@ -66,6 +89,9 @@ pub enum Pipeout {
     SSA {
         ssa: SSAStorage,
     },
+    C {
+        code: String,
+    },
 }
 
 impl Pipeout {
@@ -76,6 +102,7 @@ impl Pipeout {
             Pipeout::Instructions {i: _} => "ir",
             Pipeout::CFG {cfg: _} => "cfg",
             Pipeout::SSA {ssa: _} => "ssa",
+            Pipeout::C {code: _} => "c",
         };
         s.to_string()
     }
@@ -86,11 +113,19 @@ impl Pipeout {
 #[allow(dead_code)]
 pub struct State {
     r2: Option<R2>,
+    /// Memory of a recorded binary, read in place of r2.
+    memory: MemoryMap,
+    /// Addresses of the library functions that do not return.
+    noreturn: BTreeSet<u64>,
+    /// Byte order of the binary, if radare2 reports it.
+    endian: Option<Endian>,
     esil: Option<Vec<String>>,
     pub reg_info: Option<LRegInfo>,
     p: Option<Parser>,
     cfg: Option<CFG>,
     ssa: Option<SSAStorage>,
+    pub frame: Option<StackFrame<SSAStorage>>,
+    pub layouts: Option<Layouts<SSAStorage>>,
     pub pipeout: Option<Pipeout>,
 }
 
@@ -98,11 +133,16 @@ impl State {
     fn new() -> State {
         State {
             r2: None,
+            memory: MemoryMap::new(),
+            noreturn: BTreeSet::new(),
+            endian: None,
             esil: None,
             reg_info: None,
             p: None,
             cfg: None,
             ssa: None,
+            frame: None,
+            layouts: None,
             pipeout: None,
         }
     }
@@ -123,10 +163,18 @@ impl fmt::Display for Pipeline {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Pipeline::ReadFromR2 => write!(f, "{}", "r2"),
+            Pipeline::ReadFromJSON => write!(f, "{}", "Recorded r2 JSON"),
             Pipeline::ParseEsil => write!(f, "{}", "Parse ESIL"),
             Pipeline::CFG => write!(f, "{}", "Control Flow Graph Construction"),
             Pipeline::SSA => write!(f, "{}", "SSA Construction"),
-            Pipeline::AnalyzeSSA(_) => write!(f, "{}", "Constant Propagation"),
+            Pipeline::AnalyzeSSA(Analysis::ConstProp) => write!(f, "{}", "Constant Propagation"),
+            Pipeline::AnalyzeSSA(Analysis::StackVars) => {
+                write!(f, "{}", "Stack Variable Recovery")
+            }
+            Pipeline::AnalyzeSSA(Analysis::Types) => write!(f, "{}", "Type Inference"),
+            Pipeline::AnalyzeSSA(Analysis::Layout) => write!(f, "{}", "Struct Layout Recovery"),
+            Pipeline::Simplify => write!(f, "{}", "Expression Simplification"),
+            Pipeline::GVN => write!(f, "{}", "Global Value Numbering"),
             Pipeline::DCE => write!(f, "{}", "Dead Code Elimination"),
             Pipeline::Verify => write!(f, "{}", "Verify SSA"),
             Pipeline::CWriter => write!(f, "{}", "C Writer"),
@@ -206,6 +254,12 @@ impl Runner {
                 reg_info = r2.get_reg_info().unwrap();
                 let addr = self.addr.clone().unwrap();
                 func_info = r2.get_function(&*addr).unwrap();
+                r2.send("aflj");
+                if let Ok(list) = offline::parse_function_list(&r2.recv()) {
+                    self.state.noreturn = noreturn::known(&list);
+                }
+                r2.send("ij");
+                self.state.endian = offline::parse_endian(&r2.recv()).unwrap_or(None);
             }
             None => panic!("Unable to Initialize r2. Something is wrong!"),
         }
@@ -214,13 +268,34 @@ impl Runner {
         self.set_pipeout(&Pipeout::LOpInfo(func_info.ops.unwrap()));
     }
 
+    fn read_from_json(&mut self) {
+        out!("[*] Reading recorded JSON", self.verbose);
+        let dir = self.bin_name.clone().unwrap();
+        let addr = self.addr.clone().unwrap();
+        let recording = match Recording::load(&dir, &addr) {
+            Ok(r) => r,
+            Err(e) => panic!("Cannot load {} from {}: {:?}", addr, dir, e),
+        };
+
+        self.set_reg_info(&recording.reg_info);
+        self.state.memory = recording.memory.clone();
+        self.state.endian = recording.endian;
+        let functions = Path::new(&dir).join(offline::FUNCTIONS);
+        if let Ok(list) = offline::read_function_list(functions) {
+            self.state.noreturn = noreturn::known(&list);
+        }
+        self.set_pipeout(&Pipeout::LOpInfo(recording.ops()));
+    }
+
     fn parse_esil(&mut self) {
         let pipein = self.state.pipeout.clone().unwrap();
         out!("[*] Parsing ESIL", self.verbose);
         let mut p = Parser::new(None);
         if let Some(ref r) = self.state.reg_info {
             p.set_register_profile(r);
+            p.set_architecture(&Architecture::detect(r).with_endian(self.state.endian));
         }
+        p.set_recovery(true);
         match pipein {
             Pipeout::Esil(strs) => {
                 for _str in strs {
@@ -234,6 +309,11 @@ impl Runner {
             }
             _ => panic!("Incompatible type found in the pipeline!"),
         }
+        if self.verbose {
+            for e in p.errors() {
+                println!("[!] {}", e);
+            }
+        }
 
         let insts = p.emit_insts();
         let pipeout = Pipeout::Instructions { i: insts };
@@ -246,8 +326,30 @@ impl Runner {
         out!("[*] Starting CFG Construction", self.verbose);
         match pipein {
             Pipeout::Instructions {i: ref mut insts} => {
-                let mut cfg = CFG::new();
-                cfg.build(insts);
+                // Indirect jumps are resolved with the SSA, which needs the
+                // register profile.
+                let noreturn = self.state.noreturn.clone();
+                let cfg = match self.state.reg_info.clone() {
+                    Some(ref r) => {
+                        match self.state.r2 {
+                            Some(ref mut r2) => jumptable::build_cfg(insts, r, r2, &noreturn),
+                            None => {
+                                jumptable::build_cfg(insts, r, &mut self.state.memory, &noreturn)
+                            }
+                        }
+                    }
+                    None => {
+                        let mut cfg = CFG::new();
+                        cfg.noreturn = noreturn;
+                        cfg.build(insts);
+                        cfg
+                    }
+                };
+                if self.verbose {
+                    for (addr, reason) in &cfg.unresolved {
+                        println!("[!] Unresolved jump at 0x{:x}: {:?}", addr, reason);
+                    }
+                }
                 let pipeout = Pipeout::CFG { cfg: cfg.clone() };
                 self.set_pipeout(&pipeout);
                 self.state.cfg = Some(cfg);
@@ -291,11 +393,61 @@ impl Runner {
                 analyzer.analyze();
                 analyzer.emit_ssa()
             }
+            Analysis::StackVars => {
+                // Without a known stack pointer, nothing is promoted.
+                let sp = self.stack_pointer().and_then(|sp| stack::find_register(&ssa, &sp));
+                if let Some(sp) = sp {
+                    let mut frame = StackFrame::recover(&ssa, sp);
+                    frame.promote(&mut ssa);
+                    self.state.frame = Some(frame);
+                }
+                ssa
+            }
+            Analysis::Types => {
+                TypeInference::infer(&ssa).apply(&mut ssa);
+                ssa
+            }
+            Analysis::Layout => {
+                self.state.layouts = Some(Layouts::recover(&ssa));
+                ssa
+            }
         };
 
         self.set_pipeout(&Pipeout::SSA { ssa: ssa.clone() });
     }
 
+    fn simplify(&mut self) {
+        out!("[*] Simplifying Expressions", self.verbose);
+        let pipein = self.state.pipeout.clone().unwrap();
+        let mut ssa = if let Pipeout::SSA { ssa } = pipein {
+            ssa
+        } else {
+            panic!("Incompatible type found in the pipeline!");
+        };
+
+        {
+            simplify::run(&mut ssa);
+        }
+
+        self.set_pipeout(&Pipeout::SSA { ssa: ssa.clone() });
+    }
+
+    fn gvn(&mut self) {
+        out!("[*] Merging Equivalent Expressions", self.verbose);
+        let pipein = self.state.pipeout.clone().unwrap();
+        let mut ssa = if let Pipeout::SSA { ssa } = pipein {
+            ssa
+        } else {
+            panic!("Incompatible type found in the pipeline!");
+        };
+
+        {
+            gvn::run(&mut ssa);
+        }
+
+        self.set_pipeout(&Pipeout::SSA { ssa: ssa.clone() });
+    }
+
     fn dce(&mut self) {
         out!("[*] Running DCE", self.verbose);
         let pipein = self.state.pipeout.clone().unwrap();
@@ -326,19 +478,58 @@ impl Runner {
         }
     }
 
+    fn c_writer(&mut self) {
+        out!("[*] Writing C", self.verbose);
+        let pipein = self.state.pipeout.clone().unwrap();
+        let ssa = if let Pipeout::SSA { ssa } = pipein {
+            ssa
+        } else {
+            panic!("Incompatible type found in the pipeline!");
+        };
+
+        let ast = Dreamer::new(&ssa).run();
+        let name = self.addr.clone().unwrap_or(self.name.clone());
+        let code = {
+            let mut writer = CWriter::new(&ssa, name);
+            if let Some(ref frame) = self.state.frame {
+                writer.set_frame(frame);
+            }
+            if let Some(ref layouts) = self.state.layouts {
+                writer.set_layouts(layouts);
+            }
+            writer.emit(&ast)
+        };
+        self.set_pipeout(&Pipeout::C { code: code });
+    }
+
+    // Name of the stack pointer register, from the calling convention of the
+    // architecture or else the SP alias of the register profile.
+    fn stack_pointer(&self) -> Option<String> {
+        self.state.reg_info.as_ref().and_then(|r| {
+            CallingConvention::for_registers(r)
+                .map(|cc| cc.stack_pointer)
+                .or_else(|| {
+                    r.alias_info.iter().find(|a| a.role_str == "SP").map(|a| a.reg.clone())
+                })
+        })
+    }
+
     // TODO: Return Error. Never panic!()
     pub fn run(&mut self) {
         let pipe_iter = self.pipeline.clone();
         for stage in pipe_iter.iter() {
             match *stage {
                 Pipeline::ReadFromR2 => self.read_from_r2(),
+                Pipeline::ReadFromJSON => self.read_from_json(),
                 Pipeline::ParseEsil => self.parse_esil(),
                 Pipeline::CFG => self.construct_cfg(),
                 Pipeline::SSA => self.construct_ssa(),
                 Pipeline::AnalyzeSSA(ref a) => self.analyze(a),
+                Pipeline::Simplify => self.simplify(),
+                Pipeline::GVN => self.gvn(),
                 Pipeline::DCE => self.dce(),
                 Pipeline::Verify => self.verify(),
-                Pipeline::CWriter => unimplemented!(),
+                Pipeline::CWriter => self.c_writer(),
             }
             self.results.push(self.state.pipeout.clone().unwrap());
         }
@@ -395,6 +586,10 @@ impl Runner {
                     let tmp = dot::emit_dot(ssa);
                     write_out.push_str(&*tmp);
                 }
+                Pipeout::C {ref code} => {
+                    ext = "c";
+                    write_out.push_str(code);
+                }
             }
 
             // Format of output file name:
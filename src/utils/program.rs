@@ -0,0 +1,190 @@
+// Copyright (c) 2015, The Radare Project. All rights reserved.
+// See the COPYING file at the top-level directory of this distribution.
+// Licensed under the BSD 3-Clause License:
+// <http://opensource.org/licenses/BSD-3-Clause>
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Analysis of every function of a binary.
+//!
+//! Where `Runner` processes a single function, `Program` constructs the SSA
+//! of all functions r2 reports and connects them by a call graph. Functions
+//! are constructed bottom-up over the call graph, so that calls are modelled
+//! by the summary of their callee rather than by the calling convention.
+//! A function found not to return ends the blocks of its callers' calls to
+//! it, see `analysis::noreturn`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use petgraph::graph::NodeIndex;
+use r2pipe::R2;
+use r2pipe::structs::{LOpInfo, LRegInfo};
+
+use analysis::noreturn;
+use frontend::esilssa::SSAConstruction;
+use frontend::memory::Memory;
+use frontend::offline::{self, FunctionEntry, LoadError};
+use middle::arch::{Architecture, Endian};
+use middle::callgraph::CallGraph;
+use middle::cfg::CFG;
+use middle::dot;
+use middle::ssa::SSAStorage;
+use middle::summary::Summary;
+
+pub struct Function {
+    pub name: String,
+    pub addr: u64,
+    /// Node of the function in the call graph.
+    pub node: NodeIndex,
+    pub cfg: CFG,
+    pub ssa: SSAStorage,
+    pub summary: Summary,
+}
+
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub callgraph: CallGraph,
+    pub reg_info: LRegInfo,
+}
+
+impl Program {
+    /// Analyzes the functions r2 found in the binary it has opened.
+    pub fn from_r2(r2: &mut R2) -> Result<Program, LoadError> {
+        r2.send("aflj");
+        let list = try!(offline::parse_function_list(&r2.recv()));
+        r2.send("ij");
+        let endian = try!(offline::parse_endian(&r2.recv()));
+        // TODO: Error Handling
+        let reg_info = r2.get_reg_info().unwrap();
+        let mut sources = Vec::new();
+        for entry in list {
+            // Functions r2 cannot disassemble are left out.
+            if let Ok(info) = r2.get_function(&entry.name) {
+                sources.push((entry, info.ops.unwrap_or(Vec::new())));
+            }
+        }
+        Ok(Program::build(sources, reg_info, endian, r2))
+    }
+
+    /// Analyzes the functions of a binary recorded in `dir`, see
+    /// `frontend::offline`.
+    pub fn from_recording<P: AsRef<Path>>(dir: P) -> Result<Program, LoadError> {
+        let dir = dir.as_ref();
+        let list = try!(offline::read_function_list(dir.join(offline::FUNCTIONS)));
+        let reg_info = try!(offline::read_reg_info(dir.join(offline::REGISTER_PROFILE)));
+        let mut memory = try!(offline::read_memory(dir.join(offline::MEMORY)));
+        let endian = try!(offline::read_endian(dir.join(offline::INFO)));
+        let mut sources = Vec::new();
+        for entry in list {
+            let path = dir.join(format!("{}.json", entry.name));
+            let info = try!(offline::read_function(path));
+            sources.push((entry, info.ops.unwrap_or(Vec::new())));
+        }
+        Ok(Program::build(sources, reg_info, endian, &mut memory))
+    }
+
+    /// Constructs the SSA of every function from its ops and builds the call
+    /// graph. Memory is accessed in the byte order `endian`, if it is known,
+    /// and jump tables are read from `memory`.
+    ///
+    /// Calls to functions that do not return end their blocks. Library
+    /// functions are recognized by name, the others when they are
+    /// summarized, after which their callers are constructed again.
+    pub fn build(sources: Vec<(FunctionEntry, Vec<LOpInfo>)>,
+                 reg_info: LRegInfo,
+                 endian: Option<Endian>,
+                 memory: &mut Memory)
+                 -> Program {
+        let arch = Architecture::detect(&reg_info).with_endian(endian);
+        let mut callgraph = CallGraph::new();
+        let mut functions = Vec::new();
+        let mut ops_of = Vec::new();
+        let entries = sources.iter().map(|&(ref entry, _)| entry.clone()).collect::<Vec<_>>();
+        let mut noreturn = noreturn::known(&entries);
+        for (entry, ops) in sources {
+            let cfg = offline::build_cfg(&ops, &reg_info, &arch, memory, &noreturn);
+            let ssa = offline::build_ssa(&cfg, &reg_info);
+            let node = callgraph.add_function(entry.name.clone(), entry.offset);
+            functions.push(Function {
+                name: entry.name,
+                addr: entry.offset,
+                node: node,
+                cfg: cfg,
+                ssa: ssa,
+                summary: Summary::default(),
+            });
+            ops_of.push(ops);
+        }
+        link(&mut callgraph, &functions);
+
+        // Reconstruct every function once its callees are summarized.
+        let mut summaries = HashMap::new();
+        for node in callgraph.bottom_up() {
+            let i = match functions.iter().position(|f| f.node == node) {
+                Some(i) => i,
+                // External functions are not summarized.
+                None => continue,
+            };
+            let f = &mut functions[i];
+            if f.cfg.noreturn != noreturn {
+                f.cfg = offline::build_cfg(&ops_of[i], &reg_info, &arch, memory, &noreturn);
+            }
+            let mut ssa = SSAStorage::new();
+            {
+                let mut con = SSAConstruction::new(&mut ssa, &reg_info);
+                con.set_summaries(summaries.clone());
+                con.run(&f.cfg);
+            }
+            f.summary = Summary::compute(&ssa, &reg_info);
+            f.summary.noreturn = noreturn.contains(&f.addr) || !f.cfg.returns();
+            if f.summary.noreturn {
+                noreturn.insert(f.addr);
+            }
+            f.ssa = ssa;
+            summaries.insert(f.addr, f.summary.clone());
+        }
+
+        // The call sites of the graph point into the SSA of the first
+        // construction, which was replaced, so the graph is built again.
+        // Adding the functions in the same order gives them the same nodes,
+        // which keeps `Function::node` valid.
+        let mut callgraph = CallGraph::new();
+        for f in &functions {
+            let node = callgraph.add_function(f.name.clone(), f.addr);
+            assert_eq!(node, f.node);
+        }
+        link(&mut callgraph, &functions);
+        Program {
+            functions: functions,
+            callgraph: callgraph,
+            reg_info: reg_info,
+        }
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// The function at `node` of the call graph, if it was analyzed.
+    pub fn function_of(&self, node: NodeIndex) -> Option<&Function> {
+        self.functions.iter().find(|f| f.node == node)
+    }
+
+    pub fn callgraph_dot(&self) -> String {
+        dot::emit_dot(&self.callgraph)
+    }
+}
+
+/// ////////////////////////////////////////////////////////////////////////////
+/// / Helper functions.
+/// ////////////////////////////////////////////////////////////////////////////
+
+/// Adds the calls of `functions` to `callgraph`, which already holds them.
+/// All functions are known before the calls are resolved, so that only the
+/// targets outside of the binary are external.
+fn link(callgraph: &mut CallGraph, functions: &[Function]) {
+    for f in functions {
+        callgraph.add_calls(f.node, &f.ssa);
+    }
+}
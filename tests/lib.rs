@@ -9,6 +9,12 @@ extern crate radeco_lib;
 extern crate r2pipe;
 
 use radeco_lib::utils::{Pipeline, Runner, Pipeout, Analysis};
+use radeco_lib::utils::program::Program;
+use radeco_lib::middle::arch::Endian;
+use radeco_lib::middle::ir::MOpcode;
+use radeco_lib::middle::ssa::{SSA, ValueType};
+use radeco_lib::middle::ssa::cfg_traits::CFG;
+use radeco_lib::middle::ssa::ssa_traits::NodeType;
 use r2pipe::R2;
 
 #[test]
@@ -57,6 +63,40 @@ fn test_analysis1() {
 	test.output(None);
 }
 
+#[test]
+fn test_analysis1_cwriter() {
+	let esil = vec!["4,5,+".to_owned(), "6,*".to_owned(),
+	                "100,>,zf,=".to_owned(),
+					"5,rax,=".to_owned(),
+					"6,rbx,=".to_owned(),
+					"7,rbx,=".to_owned()
+	               ];
+
+	let test_name = "test2_cwriter".to_owned();
+	// Get a new r2 instance.
+    let mut r2 = R2::new(Some("./ex-bins/simple2".to_owned())).unwrap();
+    // Initialize with sane defaults.
+    r2.init();
+    let r = r2.get_reg_info().unwrap();
+
+	let pipeline = vec![
+		Pipeline::ParseEsil,
+		Pipeline::CFG,
+		Pipeline::SSA,
+		Pipeline::Verify,
+		Pipeline::AnalyzeSSA(Analysis::ConstProp),
+		Pipeline::DCE,
+		Pipeline::Verify,
+		Pipeline::CWriter
+	];
+
+	let mut test = Runner::new(test_name, None, None, true, pipeline, None);
+	test.state.pipeout = Some(Pipeout::Esil(esil));
+	test.state.reg_info = Some(r.clone());
+	test.run();
+	test.output(None);
+}
+
 #[test]
 fn test_analysis2() {
 	let test_name = "test_analysis".to_owned();
@@ -97,3 +137,161 @@ fn test_analysis2() {
 	//test.run();
 	//test.dump();
 //}
+
+#[test]
+fn test_offline_constprop() {
+	let test_name = "test_offline_constprop".to_owned();
+	let bin_name = Some("./ex-bins/json/constprop.o".to_owned());
+	let addr = Some("entry0".to_owned());
+	let pipeline = vec![
+		Pipeline::ReadFromJSON,
+		Pipeline::ParseEsil,
+		Pipeline::CFG,
+		Pipeline::SSA,
+		Pipeline::Verify,
+		Pipeline::AnalyzeSSA(Analysis::ConstProp),
+		Pipeline::DCE,
+		Pipeline::Verify,
+		Pipeline::CWriter
+	];
+	let mut test = Runner::new(test_name, bin_name, addr, false, pipeline, None);
+	test.run();
+	test.output(None);
+}
+
+#[test]
+fn test_offline_simple2() {
+	let test_name = "test_offline_simple2".to_owned();
+	let bin_name = Some("./ex-bins/json/simple2".to_owned());
+	let addr = Some("sym.main".to_owned());
+	let pipeline = vec![
+		Pipeline::ReadFromJSON,
+		Pipeline::ParseEsil,
+		Pipeline::CFG,
+		Pipeline::SSA,
+		Pipeline::Verify,
+		Pipeline::DCE,
+		Pipeline::CWriter
+	];
+	let mut test = Runner::new(test_name, bin_name, addr, false, pipeline, None);
+	test.run();
+	test.output(None);
+}
+
+#[test]
+fn test_offline_program() {
+	let program = Program::from_recording("./ex-bins/json/simple2").unwrap();
+	let main = program.function("sym.main").unwrap();
+	assert_eq!(main.addr, 0x4003c0);
+	assert!(program.callgraph.callees(main.node).is_empty());
+	assert!(program.callgraph_dot().starts_with("digraph callgraph"));
+}
+
+#[test]
+fn test_offline_summary() {
+	let program = Program::from_recording("./ex-bins/json/simple2").unwrap();
+	let summary = &program.function("sym.main").unwrap().summary;
+	assert!(summary.args.contains(&"rdi".to_owned()));
+	assert!(summary.args.contains(&"rsi".to_owned()));
+	assert!(summary.modified.contains(&"rax".to_owned()));
+	assert!(!summary.modified.contains(&"rbx".to_owned()));
+	assert!(summary.reads_memory);
+	assert!(summary.writes_memory);
+	assert!(!summary.noreturn);
+}
+
+#[test]
+fn test_offline_saved_registers() {
+	let program = Program::from_recording("./ex-bins/json/saved").unwrap();
+	let callee = &program.function("sym.callee").unwrap().summary;
+	assert!(callee.modified.contains(&"rax".to_owned()));
+	assert!(!callee.modified.contains(&"rbx".to_owned()));
+	assert!(!callee.modified.contains(&"rbp".to_owned()));
+
+	// rbx survives the call, so the sum uses the value of rsi.
+	let f = program.function("sym.caller").unwrap();
+	let add = f.ssa.nodes().into_iter().find(|n| {
+		match f.ssa.get_node_data(n).map(|nd| nd.nt) {
+			Ok(NodeType::Op(MOpcode::OpAdd)) => f.ssa.get_operands(n).iter().all(|o| {
+				match f.ssa.get_node_data(o).map(|nd| nd.nt) {
+					Ok(NodeType::Op(MOpcode::OpConst(_))) => false,
+					_ => true,
+				}
+			}),
+			_ => false,
+		}
+	}).unwrap();
+	assert!(f.ssa.get_operands(&add).iter().all(|o| {
+		match f.ssa.get_node_data(o).map(|nd| nd.nt) {
+			Ok(NodeType::Undefined) => false,
+			_ => true,
+		}
+	}));
+}
+
+#[test]
+fn test_offline_ppc() {
+	let program = Program::from_recording("./ex-bins/json/ppc").unwrap();
+	let f = program.function("sym.load").unwrap();
+	assert!(f.summary.args.contains(&"r4".to_owned()));
+	let big = f.ssa.nodes().into_iter().any(|n| {
+		match f.ssa.get_node_data(&n).map(|nd| nd.nt) {
+			Ok(NodeType::Op(MOpcode::OpLoad(32, Endian::Big))) => true,
+			_ => false,
+		}
+	});
+	assert!(big);
+}
+
+#[test]
+fn test_offline_float() {
+	let program = Program::from_recording("./ex-bins/json/float").unwrap();
+	let f = program.function("sym.scale").unwrap();
+	assert!(f.summary.args.contains(&"rdi".to_owned()));
+	assert!(f.summary.args.contains(&"xmm1".to_owned()));
+	assert!(f.summary.modified.contains(&"rax".to_owned()));
+	assert!(f.summary.modified.contains(&"xmm0".to_owned()));
+
+	let mul = f.ssa.nodes().into_iter().find(|n| {
+		match f.ssa.get_node_data(n).map(|nd| nd.nt) {
+			Ok(NodeType::Op(MOpcode::OpFMul)) => true,
+			_ => false,
+		}
+	}).unwrap();
+	assert_eq!(f.ssa.get_node_data(&mul).unwrap().vt, ValueType::Float { width: 64 });
+}
+
+#[test]
+fn test_offline_arm() {
+	let program = Program::from_recording("./ex-bins/json/arm").unwrap();
+	let f = program.function("sym.add").unwrap();
+	assert!(f.summary.args.contains(&"r0".to_owned()));
+	assert!(f.summary.args.contains(&"r1".to_owned()));
+	assert!(f.summary.modified.contains(&"r0".to_owned()));
+	// The flags of `cpsr` are registers of their own.
+	assert!(!f.summary.args.contains(&"cpsr".to_owned()));
+	assert!(!f.summary.modified.contains(&"cpsr".to_owned()));
+}
+
+#[test]
+fn test_offline_switch() {
+	let program = Program::from_recording("./ex-bins/json/arm").unwrap();
+	let f = program.function("sym.switch").unwrap();
+	// The table is read from memory.json, which is written by hand like the
+	// rest of the fixture, see ex-bins/json/README.md.
+	assert_eq!(f.cfg.jump_tables[&0x8104].targets.len(), 4);
+	assert!(f.cfg.unresolved.is_empty());
+	assert!(f.summary.args.contains(&"r0".to_owned()));
+	assert!(f.summary.modified.contains(&"r0".to_owned()));
+	// The jump is a switch over the index with an edge for every case.
+	let switches = f.ssa.blocks()
+	                .into_iter()
+	                .filter_map(|b| f.ssa.selector_of(&b))
+	                .map(|sel| f.ssa.get_cases(&sel).0)
+	                .filter(|cases| !cases.is_empty())
+	                .collect::<Vec<_>>();
+	assert_eq!(switches.len(), 1);
+	let cases = switches[0].iter().map(|&(case, _)| case).collect::<Vec<_>>();
+	assert_eq!(cases, vec![0, 1, 2, 3]);
+	assert_eq!(switches[0][1].1, switches[0][3].1);
+}